@@ -1,8 +1,11 @@
 use bitcoin;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
 use std::{fmt, str::FromStr, vec};
 
+use super::error::Error;
+
 /// A currency that custody understands. Can be extended in future.
 #[derive(
     Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, PartialOrd, Ord, Hash,
@@ -280,6 +283,21 @@ impl CurrencyAddress {
             CurrencyAddress::ERC20(v) => v.account.account.clone(),
         }
     }
+
+    /// Check that the address can receive withdrawals on the given bitcoin network.
+    /// Ethereum destinations must not be contracts of the supported tokens.
+    pub fn validate(&self, network: bitcoin::Network) -> Result<(), Error> {
+        let account = match self {
+            CurrencyAddress::BTC(addr) => return addr.validate(network),
+            CurrencyAddress::ETH(account) => account,
+            CurrencyAddress::ERC20(erc20) => &erc20.account,
+        };
+        account.validate()?;
+        if account.is_token_contract() {
+            return Err(Error::TokenContractAddress(account.account.clone()));
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for CurrencyAddress {
@@ -314,6 +332,19 @@ impl fmt::Display for BtcAddress {
     }
 }
 
+impl BtcAddress {
+    pub fn validate(&self, network: bitcoin::Network) -> Result<(), Error> {
+        let addr = bitcoin::Address::from_str(&self.addr)
+            .map_err(|_| Error::InvalidBtcAddress(self.addr.clone()))?;
+        // Testnet, signet and regtest share address prefixes, only mainnet is told apart
+        let is_main = |n| n == bitcoin::Network::Bitcoin;
+        if is_main(addr.network) != is_main(network) {
+            return Err(Error::BtcAddressWrongNetwork(self.addr.clone(), network.to_string()));
+        }
+        Ok(())
+    }
+}
+
 /// Validated ethereum account address
 #[derive(
     Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, PartialOrd, Ord, Hash,
@@ -328,6 +359,48 @@ impl fmt::Display for EthAccount {
     }
 }
 
+impl EthAccount {
+    /// Check the format and EIP-55 checksum. All lower or upper case addresses carry no checksum.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = || Error::InvalidEthAddress(self.account.clone());
+        let hex = self.account.strip_prefix("0x").ok_or_else(invalid)?;
+        if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let has_lower = hex.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = hex.chars().any(|c| c.is_ascii_uppercase());
+        if has_lower && has_upper && eip55_checksum(hex) != hex {
+            return Err(Error::EthAddressBadChecksum(self.account.clone()));
+        }
+        Ok(())
+    }
+
+    /// Whether the account is a contract of one of the supported tokens
+    pub fn is_token_contract(&self) -> bool {
+        Currency::supported_tokens()
+            .iter()
+            .any(|token| token.contract.eq_ignore_ascii_case(&self.account))
+    }
+}
+
+/// Mixed case form of 40 hex digits of an address as defined by EIP-55
+fn eip55_checksum(hex: &str) -> String {
+    let lower = hex.to_ascii_lowercase();
+    let hash = Keccak256::digest(lower.as_bytes());
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
 #[derive(
     Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq, PartialOrd, Ord, Hash,
 )]
@@ -430,6 +503,17 @@ impl Symbol {
         }
     }
 
+    /// Inverse of `symbol`. Unknown tickers are treated as ERC20 tokens
+    pub fn from_symbol(symbol: &str) -> Symbol {
+        match symbol {
+            "USD" => Symbol::USD,
+            "RUB" => Symbol::RUB,
+            "BTC" => Symbol::BTC,
+            "ETH" => Symbol::ETH,
+            ticker => Symbol::ERC20(ticker.to_owned()),
+        }
+    }
+
     pub fn is_crypto(&self) -> bool {
         match self {
             Symbol::USD => false,
@@ -670,4 +754,91 @@ impl From<(Currency, UnitInfo)> for UserUnitInfo{
             supported_units: uinfo.unit.supported().into_iter().map(|u| u.into()).collect()
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eth(account: &str) -> CurrencyAddress {
+        CurrencyAddress::ETH(EthAccount {
+            account: account.to_owned(),
+        })
+    }
+
+    fn btc(addr: &str) -> CurrencyAddress {
+        CurrencyAddress::BTC(BtcAddress {
+            addr: addr.to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_validate_eth() {
+        let network = bitcoin::Network::Bitcoin;
+        for account in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+        ] {
+            assert!(eth(account).validate(network).is_ok(), "{account}");
+        }
+        assert!(matches!(
+            eth("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").validate(network),
+            Err(Error::EthAddressBadChecksum(_))
+        ));
+        assert!(matches!(
+            eth("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").validate(network),
+            Err(Error::InvalidEthAddress(_))
+        ));
+        assert!(matches!(eth("0x5aAeb6").validate(network), Err(Error::InvalidEthAddress(_))));
+    }
+
+    #[test]
+    fn test_reject_token_contract() {
+        let usdt = match Currency::usdt_erc20() {
+            Currency::ERC20(token) => token,
+            _ => unreachable!(),
+        };
+        let erc20 = |account: &str| {
+            CurrencyAddress::ERC20(Erc20 {
+                token: usdt.clone(),
+                account: EthAccount {
+                    account: account.to_owned(),
+                },
+            })
+        };
+        let network = bitcoin::Network::Bitcoin;
+        assert!(matches!(
+            erc20(&usdt.contract.to_lowercase()).validate(network),
+            Err(Error::TokenContractAddress(_))
+        ));
+        assert!(matches!(eth(&usdt.contract).validate(network), Err(Error::TokenContractAddress(_))));
+        assert!(erc20("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").validate(network).is_ok());
+    }
+
+    #[test]
+    fn test_validate_btc() {
+        let mainnet = ["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"];
+        let testnet = ["mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"];
+        for addr in mainnet {
+            assert!(btc(addr).validate(bitcoin::Network::Bitcoin).is_ok(), "{addr}");
+            assert!(matches!(
+                btc(addr).validate(bitcoin::Network::Testnet),
+                Err(Error::BtcAddressWrongNetwork(_, _))
+            ));
+        }
+        for addr in testnet {
+            assert!(btc(addr).validate(bitcoin::Network::Testnet).is_ok(), "{addr}");
+            assert!(btc(addr).validate(bitcoin::Network::Regtest).is_ok(), "{addr}");
+            assert!(matches!(
+                btc(addr).validate(bitcoin::Network::Bitcoin),
+                Err(Error::BtcAddressWrongNetwork(_, _))
+            ));
+        }
+        assert!(matches!(
+            btc("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3").validate(bitcoin::Network::Bitcoin),
+            Err(Error::InvalidBtcAddress(_))
+        ));
+    }
+}
@@ -74,6 +74,62 @@ pub enum Error {
     ExchangeRateError(Currency),
     #[error("Malformed margin: {0}")]
     MalformedMargin(String),
+    #[error("Webhook {0} is not found")]
+    WebhookNotFound(uuid::Uuid),
+    #[error("Invalid webhook URL: {0}")]
+    WebhookInvalidUrl(String),
+    #[error("Webhook delivery {0} is not found")]
+    WebhookDeliveryNotFound(uuid::Uuid),
+    #[error("API key {0} is not found")]
+    ApiKeyNotFound(uuid::Uuid),
+    #[error("Invalid API key request: {0}")]
+    ApiKeyInvalid(String),
+    #[error("TOTP code is required")]
+    TotpRequired,
+    #[error("TOTP code is invalid")]
+    TotpInvalid,
+    #[error("TOTP is already enabled")]
+    TotpAlreadyEnabled,
+    #[error("TOTP is not enabled")]
+    TotpNotEnabled,
+    #[error("WebAuthn verification failed: {0}")]
+    WebauthnFailed(String),
+    #[error("Challenge is missing or expired")]
+    ChallengeExpired,
+    #[error("WebAuthn credential {0} is not found")]
+    WebauthnCredentialNotFound(String),
+    #[error("Exchange quote is missing or expired")]
+    ExchangeQuoteExpired,
+    #[error("No recent exchange rate for {0}/{1}, try again later")]
+    ExchangeRateStale(String, String),
+    #[error("Transfer recipient {0} is not found or doesn't have {1} enabled")]
+    TransferRecipientNotFound(String, Currency),
+    #[error("Can't transfer to yourself")]
+    TransferToSelf,
+    #[error("Withdrawals to {0} are not allowed, add it to the address book first")]
+    AddressNotAllowed(String),
+    #[error("Address book entry {0} is not found")]
+    AddressBookEntryNotFound(uuid::Uuid),
+    #[error("Invalid bitcoin address {0}")]
+    InvalidBtcAddress(String),
+    #[error("Bitcoin address {0} is not for {1} network")]
+    BtcAddressWrongNetwork(String, String),
+    #[error("Invalid ethereum address {0}")]
+    InvalidEthAddress(String),
+    #[error("Ethereum address {0} has wrong EIP-55 checksum")]
+    EthAddressBadChecksum(String),
+    #[error("{0} is a token contract, funds sent to it would be lost")]
+    TokenContractAddress(String),
+    #[error("Withdrawal request {0} is not found")]
+    WithdrawalRequestNotFound(uuid::Uuid),
+    #[error("Withdrawal request {0} can't be cancelled anymore")]
+    WithdrawalNotCancellable(uuid::Uuid),
+    #[error("Account is {0}")]
+    AccountNotActive(String),
+    #[error("Account change is not allowed: {0}")]
+    AccountChangeNotAllowed(String),
+    #[error("Too many wrong codes, try again in {0} minutes")]
+    TotpLocked(i64),
 }
 
 impl HexstodyError for Error {
@@ -112,6 +168,34 @@ impl HexstodyError for Error {
             Error::InvalidPhoneNumber => 27,
             Error::ExchangeRateError(_) => 28,
             Error::MalformedMargin(_) => 29,
+            Error::WebhookNotFound(_) => 30,
+            Error::WebhookInvalidUrl(_) => 31,
+            Error::WebhookDeliveryNotFound(_) => 32,
+            Error::ApiKeyNotFound(_) => 33,
+            Error::ApiKeyInvalid(_) => 34,
+            Error::TotpRequired => 35,
+            Error::TotpInvalid => 36,
+            Error::TotpAlreadyEnabled => 37,
+            Error::TotpNotEnabled => 38,
+            Error::WebauthnFailed(_) => 39,
+            Error::ChallengeExpired => 40,
+            Error::WebauthnCredentialNotFound(_) => 41,
+            Error::ExchangeQuoteExpired => 42,
+            Error::ExchangeRateStale(_, _) => 43,
+            Error::TransferRecipientNotFound(_, _) => 44,
+            Error::TransferToSelf => 45,
+            Error::AddressNotAllowed(_) => 46,
+            Error::AddressBookEntryNotFound(_) => 47,
+            Error::InvalidBtcAddress(_) => 48,
+            Error::BtcAddressWrongNetwork(_, _) => 49,
+            Error::InvalidEthAddress(_) => 50,
+            Error::EthAddressBadChecksum(_) => 51,
+            Error::TokenContractAddress(_) => 52,
+            Error::WithdrawalRequestNotFound(_) => 53,
+            Error::WithdrawalNotCancellable(_) => 54,
+            Error::AccountNotActive(_) => 55,
+            Error::AccountChangeNotAllowed(_) => 56,
+            Error::TotpLocked(_) => 57,
         }
     }
 
@@ -147,6 +231,34 @@ impl HexstodyError for Error {
             Error::InvalidPhoneNumber => 400,
             Error::ExchangeRateError(_) => 404,
             Error::MalformedMargin(_) => 400,
+            Error::WebhookNotFound(_) => 404,
+            Error::WebhookInvalidUrl(_) => 400,
+            Error::WebhookDeliveryNotFound(_) => 404,
+            Error::ApiKeyNotFound(_) => 404,
+            Error::ApiKeyInvalid(_) => 400,
+            Error::TotpRequired => 401,
+            Error::TotpInvalid => 403,
+            Error::TotpAlreadyEnabled => 400,
+            Error::TotpNotEnabled => 400,
+            Error::WebauthnFailed(_) => 403,
+            Error::ChallengeExpired => 403,
+            Error::WebauthnCredentialNotFound(_) => 404,
+            Error::ExchangeQuoteExpired => 410,
+            Error::ExchangeRateStale(_, _) => 503,
+            Error::TransferRecipientNotFound(_, _) => 404,
+            Error::TransferToSelf => 400,
+            Error::AddressNotAllowed(_) => 403,
+            Error::AddressBookEntryNotFound(_) => 404,
+            Error::InvalidBtcAddress(_) => 400,
+            Error::BtcAddressWrongNetwork(_, _) => 400,
+            Error::InvalidEthAddress(_) => 400,
+            Error::EthAddressBadChecksum(_) => 400,
+            Error::TokenContractAddress(_) => 400,
+            Error::WithdrawalRequestNotFound(_) => 404,
+            Error::WithdrawalNotCancellable(_) => 409,
+            Error::AccountNotActive(_) => 403,
+            Error::AccountChangeNotAllowed(_) => 409,
+            Error::TotpLocked(_) => 429,
         }
     }
 }
\ No newline at end of file
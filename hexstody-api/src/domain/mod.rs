@@ -1,8 +1,10 @@
 pub mod currency;
 pub mod error;
+pub mod rate;
 
 use std::str::FromStr;
 pub use currency::*;
+pub use rate::*;
 use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use regex::Regex;
@@ -0,0 +1,377 @@
+use std::fmt;
+use std::str::FromStr;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Number of decimal digits after the point kept by `Rate`
+pub const RATE_DECIMALS: u32 = 18;
+const SCALE: u128 = 10u128.pow(RATE_DECIMALS);
+
+/// How to round results that don't fit into the precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Rounding {
+    /// Towards zero. Amounts paid to users are rounded down in the house's favor
+    Down,
+    /// Away from zero
+    Up,
+    /// To the nearest value, halves are rounded up
+    Nearest,
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Rounding::Down
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RateError {
+    #[error("Malformed rate `{0}`")]
+    Malformed(String),
+    #[error("Rate `{0}` has more than 18 decimal digits")]
+    TooPrecise(String),
+    #[error("Rate `{0}` is out of range")]
+    OutOfRange(String),
+}
+
+/// Non negative fixed point decimal with 18 digits after the point.
+/// Used for exchange rates and margins, so the prices don't depend on float rounding.
+/// Serialized as a decimal string to keep all digits in JSON.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(u128);
+
+impl Rate {
+    pub const ZERO: Rate = Rate(0);
+    pub const ONE: Rate = Rate(SCALE);
+
+    /// Rate from the number of 10^-18 fractions
+    pub const fn from_raw(raw: u128) -> Rate {
+        Rate(raw)
+    }
+
+    pub const fn raw(&self) -> u128 {
+        self.0
+    }
+
+    pub fn from_integer(value: u64) -> Rate {
+        Rate(value as u128 * SCALE)
+    }
+
+    /// Convert the float using its shortest decimal representation, so `0.1` becomes exactly 0.1
+    pub fn from_f64(value: f64) -> Result<Rate, RateError> {
+        if !value.is_finite() || value < 0.0 {
+            return Err(RateError::OutOfRange(value.to_string()));
+        }
+        let repr = value.to_string();
+        match Rate::from_str(&repr) {
+            Err(RateError::TooPrecise(_)) => Rate::from_str_rounded(&repr, Rounding::Nearest),
+            res => res,
+        }
+    }
+
+    /// Approximate value for display and other places where precision is not important
+    pub fn to_f64(&self) -> f64 {
+        (self.0 / SCALE) as f64 + (self.0 % SCALE) as f64 / SCALE as f64
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(&self, other: Rate) -> Option<Rate> {
+        self.0.checked_add(other.0).map(Rate)
+    }
+
+    pub fn saturating_add(&self, other: Rate) -> Rate {
+        Rate(self.0.saturating_add(other.0))
+    }
+
+    /// Smallest integer not less than the rate, saturated to `u64`
+    pub fn ceil(&self) -> u64 {
+        let int = self.0 / SCALE + (self.0 % SCALE != 0) as u128;
+        u64::try_from(int).unwrap_or(u64::MAX)
+    }
+
+    /// Parse decimal string, rounding extra fraction digits
+    pub fn from_str_rounded(s: &str, rounding: Rounding) -> Result<Rate, RateError> {
+        let (int, frac) = s.trim().split_once('.').unwrap_or((s.trim(), ""));
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if !(digits(int) || (int.is_empty() && digits(frac))) || !(frac.is_empty() || digits(frac)) {
+            return Err(RateError::Malformed(s.to_owned()));
+        }
+        let out_of_range = || RateError::OutOfRange(s.to_owned());
+        let int_part = if int.is_empty() { 0 } else { int.parse::<u128>().map_err(|_| out_of_range())? };
+        let kept = frac.len().min(RATE_DECIMALS as usize);
+        let (frac_kept, frac_rest) = frac.split_at(kept);
+        let frac_part = if frac_kept.is_empty() { 0 } else { frac_kept.parse::<u128>().map_err(|_| out_of_range())? }
+            * 10u128.pow(RATE_DECIMALS - kept as u32);
+        let raw = int_part
+            .checked_mul(SCALE)
+            .and_then(|v| v.checked_add(frac_part))
+            .ok_or_else(out_of_range)?;
+        let has_rest = frac_rest.bytes().any(|b| b != b'0');
+        let round_up = match rounding {
+            Rounding::Down => false,
+            Rounding::Up => has_rest,
+            Rounding::Nearest => frac_rest.as_bytes().first().map_or(false, |b| *b >= b'5'),
+        };
+        if round_up {
+            raw.checked_add(1).map(Rate).ok_or_else(out_of_range)
+        } else {
+            Ok(Rate(raw))
+        }
+    }
+
+    /// Product of two rates
+    pub fn mul(&self, other: Rate, rounding: Rounding) -> Option<Rate> {
+        mul_div(self.0, other.0, SCALE, rounding).map(Rate)
+    }
+
+    /// Rate reduced by the margin given in whole percents. Margins over 100% give zero rate.
+    pub fn sub_percent(&self, percent: Rate, rounding: Rounding) -> Rate {
+        let hundred = 100 * SCALE;
+        let factor = hundred.saturating_sub(percent.0);
+        // Can't overflow since the factor is not greater than the divisor
+        Rate(mul_div(self.0, factor, hundred, rounding).unwrap_or(0))
+    }
+
+    pub fn abs_diff(&self, other: Rate) -> Rate {
+        Rate(self.0.abs_diff(other.0))
+    }
+
+    /// Value exactly in the middle between two rates, rounded down
+    pub fn midpoint(&self, other: Rate) -> Rate {
+        Rate((self.0 >> 1) + (other.0 >> 1) + (self.0 & other.0 & 1))
+    }
+
+    /// How many percents of `base` the rate is. `None` for zero base
+    pub fn percent_of(&self, base: Rate, rounding: Rounding) -> Option<Rate> {
+        if base.is_zero() {
+            return None;
+        }
+        mul_div(self.0, 100 * SCALE, base.0, rounding).map(Rate)
+    }
+
+    /// Value of the amount in minimal units given the rate of one whole coin,
+    /// e.g. fiat value of a withdrawal. `None` if the value is out of range.
+    pub fn value_of(&self, amount: u64, precision: u64, rounding: Rounding) -> Option<Rate> {
+        mul_div(self.0, amount as u128, precision as u128, rounding).map(Rate)
+    }
+
+    /// Convert amount in minimal units of one currency to minimal units of another.
+    /// Precisions are the number of minimal units in a whole coin.
+    pub fn convert(&self, amount: u64, from_precision: u64, to_precision: u64, rounding: Rounding) -> Option<u64> {
+        let scaled = amount as u128 * to_precision as u128;
+        let divisor = SCALE.checked_mul(from_precision as u128)?;
+        mul_div(scaled, self.0, divisor, rounding).and_then(|v| u64::try_from(v).ok())
+    }
+}
+
+/// `a * b / d` without intermediate overflow. Returns `None` if the result doesn't fit into `u128`.
+fn mul_div(a: u128, b: u128, d: u128, rounding: Rounding) -> Option<u128> {
+    let (hi, lo) = mul_wide(a, b);
+    let (quot, rem) = div_wide(hi, lo, d)?;
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => rem > 0,
+        Rounding::Nearest => rem >= d - rem,
+    };
+    if round_up {
+        quot.checked_add(1)
+    } else {
+        Some(quot)
+    }
+}
+
+/// Full 256 bit product as high and low halves
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    let mask = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & mask);
+    let (b_hi, b_lo) = (b >> 64, b & mask);
+    let ll = a_lo * b_lo;
+    let lh = a_lo * b_hi;
+    let hl = a_hi * b_lo;
+    let hh = a_hi * b_hi;
+    let mid = (ll >> 64) + (lh & mask) + (hl & mask);
+    let lo = (ll & mask) | (mid << 64);
+    let hi = hh + (lh >> 64) + (hl >> 64) + (mid >> 64);
+    (hi, lo)
+}
+
+/// Divide 256 bit number by `d`, returns quotient and remainder
+fn div_wide(hi: u128, lo: u128, d: u128) -> Option<(u128, u128)> {
+    if d == 0 || hi >= d {
+        return None;
+    }
+    let mut rem = hi;
+    let mut quot = 0u128;
+    for i in (0..128).rev() {
+        let carry = rem >> 127;
+        rem = (rem << 1) | ((lo >> i) & 1);
+        quot <<= 1;
+        if carry == 1 || rem >= d {
+            rem = rem.wrapping_sub(d);
+            quot |= 1;
+        }
+    }
+    Some((quot, rem))
+}
+
+impl FromStr for Rate {
+    type Err = RateError;
+
+    /// Parse decimal string. Digits beyond the precision are rejected, use `from_str_rounded` to round them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate = Rate::from_str_rounded(s, Rounding::Down)?;
+        if rate != Rate::from_str_rounded(s, Rounding::Up)? {
+            return Err(RateError::TooPrecise(s.to_owned()));
+        }
+        Ok(rate)
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let int = self.0 / SCALE;
+        let frac = self.0 % SCALE;
+        if frac == 0 {
+            write!(f, "{int}")
+        } else {
+            let frac = format!("{:0width$}", frac, width = RATE_DECIMALS as usize);
+            write!(f, "{int}.{}", frac.trim_end_matches('0'))
+        }
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    /// Accepts decimal strings and JSON numbers, as rates come as numbers from ticker providers
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RateVisitor;
+
+        impl<'de> de::Visitor<'de> for RateVisitor {
+            type Value = Rate;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("non negative decimal number or string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Rate, E> {
+                Rate::from_str_rounded(v, Rounding::Nearest).map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Rate, E> {
+                Ok(Rate::from_integer(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Rate, E> {
+                u64::try_from(v)
+                    .map(Rate::from_integer)
+                    .map_err(|_| E::custom(RateError::OutOfRange(v.to_string())))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Rate, E> {
+                Rate::from_f64(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(RateVisitor)
+    }
+}
+
+impl JsonSchema for Rate {
+    fn schema_name() -> String {
+        "Rate".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(s: &str) -> Rate {
+        Rate::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(rate("1"), Rate::ONE);
+        assert_eq!(rate("0.1").raw(), SCALE / 10);
+        assert_eq!(rate(".5").to_string(), "0.5");
+        assert_eq!(rate("20345.120000").to_string(), "20345.12");
+        assert_eq!(rate("0.000000000000000001").raw(), 1);
+        assert!(matches!(Rate::from_str("0.0000000000000000015"), Err(RateError::TooPrecise(_))));
+        assert_eq!(Rate::from_str_rounded("0.0000000000000000015", Rounding::Nearest).unwrap().raw(), 2);
+        assert!(matches!(Rate::from_str("-1"), Err(RateError::Malformed(_))));
+        assert!(matches!(Rate::from_str("1e5"), Err(RateError::Malformed(_))));
+        assert_eq!(Rate::from_f64(0.1).unwrap(), rate("0.1"));
+        assert!(Rate::from_f64(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(serde_json::to_string(&rate("1.5")).unwrap(), "\"1.5\"");
+        assert_eq!(serde_json::from_str::<Rate>("\"1.5\"").unwrap(), rate("1.5"));
+        assert_eq!(serde_json::from_str::<Rate>("1.5").unwrap(), rate("1.5"));
+        assert_eq!(serde_json::from_str::<Rate>("20000").unwrap(), rate("20000"));
+    }
+
+    #[test]
+    fn test_convert_rounding() {
+        // 1 BTC for 13.37 ETH with 18 decimals is too large for floats to be exact
+        let btc_eth = rate("13.370000000000000001");
+        let eth = 1_000_000_000_000_000_000;
+        assert_eq!(btc_eth.convert(100_000_000, 100_000_000, eth, Rounding::Down), Some(13_370_000_000_000_000_001));
+        // One third of a satoshi worth of ETH
+        let third = rate("0.333333333333333333");
+        assert_eq!(third.convert(1, 1, 1, Rounding::Down), Some(0));
+        assert_eq!(third.convert(1, 1, 1, Rounding::Up), Some(1));
+        assert_eq!(third.convert(1, 1, 1, Rounding::Nearest), Some(0));
+        assert_eq!(rate("0.5").convert(1, 1, 1, Rounding::Nearest), Some(1));
+        // Doesn't fit into u64
+        assert_eq!(rate("1000").convert(u64::MAX, 1, 1, Rounding::Down), None);
+        assert_eq!(Rate::ONE.convert(u64::MAX, eth, eth, Rounding::Down), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_value_of() {
+        // 0.03 BTC at 20000.1 USD
+        let value = rate("20000.1").value_of(3_000_000, 100_000_000, Rounding::Up).unwrap();
+        assert_eq!(value, rate("600.003"));
+        assert_eq!(value.ceil(), 601);
+        assert_eq!(rate("600").ceil(), 600);
+        // One wei at the rate with too many digits is rounded
+        let eth = 1_000_000_000_000_000_000;
+        assert_eq!(rate("0.5").value_of(1, eth, Rounding::Down).unwrap(), Rate::ZERO);
+        assert_eq!(rate("0.5").value_of(1, eth, Rounding::Up).unwrap().raw(), 1);
+        assert_eq!(value.checked_add(value), Some(rate("1200.006")));
+        assert_eq!(Rate::from_raw(u128::MAX).checked_add(Rate::from_raw(1)), None);
+    }
+
+    #[test]
+    fn test_margin() {
+        let rate_with_margin = rate("20000").sub_percent(rate("1.5"), Rounding::Down);
+        assert_eq!(rate_with_margin, rate("19700"));
+        assert_eq!(rate("1").sub_percent(rate("150"), Rounding::Down), Rate::ZERO);
+        let third = rate("1").sub_percent(rate("66.666666666666666667"), Rounding::Down);
+        assert_eq!(third, rate("0.333333333333333333"));
+        assert_eq!(rate("2").mul(rate("0.25"), Rounding::Down), Some(rate("0.5")));
+        assert_eq!(rate("3").abs_diff(rate("20")).percent_of(rate("20"), Rounding::Down), Some(rate("85")));
+        assert_eq!(rate("1").percent_of(Rate::ZERO, Rounding::Down), None);
+        assert_eq!(rate("3").midpoint(rate("4")), rate("3.5"));
+        assert_eq!(Rate::from_raw(1).midpoint(Rate::from_raw(2)), Rate::from_raw(1));
+    }
+}
@@ -22,9 +22,9 @@ use rocket_okapi::{
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::domain::{CurrencyTxId, Email, PhoneNumber, TgName, Unit, CurrencyUnit};
+use crate::domain::{CurrencyTxId, Email, PhoneNumber, Rate, Symbol, TgName, Unit, CurrencyUnit};
 
-use super::domain::currency::{BtcAddress, Currency, CurrencyAddress, Erc20Token};
+use super::domain::currency::{BtcAddress, Currency, CurrencyAddress, Erc20Token, Fiat};
 
 #[allow(non_snake_case)]
 #[derive(Debug, PartialEq, Serialize, Clone, Copy, Deserialize, JsonSchema)]
@@ -154,6 +154,8 @@ pub struct UserInfo {
     pub email: Option<Email>,
     pub phone: Option<PhoneNumber>,
     pub tg_name: Option<TgName>,
+    #[serde(default)]
+    pub status: AccountStatus,
 }
 
 #[allow(non_snake_case)]
@@ -245,22 +247,34 @@ impl Ord for BalanceItem {
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct DepositHistoryItem {
+    /// Stable identifier of the item, used by history cursors
+    #[serde(default)]
+    pub id: String,
     pub currency: Currency,
     pub date: DateTime<Utc>,
     pub value: u64,
     pub number_of_confirmations: u64,
     pub txid: CurrencyTxId,
     pub to_address: CurrencyAddress,
+    /// Value in fiat at the time of the deposit, if the rate was recorded then
+    #[serde(default)]
+    pub fiat_value: Option<TickerUsdRub>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct WithdrawalHistoryItem {
+    /// Stable identifier of the item, used by history cursors
+    #[serde(default)]
+    pub id: String,
     pub currency: Currency,
     pub date: DateTime<Utc>,
     pub value: u64,
     pub status: WithdrawalRequestStatus,
     //temp field to give txid for ETH and tokens while status not working
     pub txid: Option<CurrencyTxId>,
+    /// Value in fiat at the time of the withdrawal, if the rate was recorded then
+    #[serde(default)]
+    pub fiat_value: Option<TickerUsdRub>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -269,15 +283,204 @@ pub struct WithdrawalHistoryItem {
 pub enum HistoryItem {
     Deposit(DepositHistoryItem),
     Withdrawal(WithdrawalHistoryItem),
+    Exchange(ExchangeHistoryItem),
+    Transfer(TransferHistoryItem),
 }
 
 pub fn history_item_time(h: &HistoryItem) -> &DateTime<Utc> {
     match h {
         HistoryItem::Deposit(d) => &d.date,
         HistoryItem::Withdrawal(w) => &w.date,
+        HistoryItem::Exchange(e) => &e.date,
+        HistoryItem::Transfer(t) => &t.date,
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ExchangeHistoryItem {
+    pub id: Uuid,
+    pub currency_from: Currency,
+    pub currency_to: Currency,
+    pub amount_from: u64,
+    pub amount_to: u64,
+    /// When the order was completed, or created if it is not completed
+    pub date: DateTime<Utc>,
+    pub status: ExchangeStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransferHistoryItem {
+    pub id: Uuid,
+    pub currency: Currency,
+    pub amount: u64,
+    /// Username of the other side of the transfer
+    pub counterparty: String,
+    /// The user is the recipient
+    pub incoming: bool,
+    /// When the transfer was completed, or created if it is not completed
+    pub date: DateTime<Utc>,
+    pub status: TransferStatus,
+}
+
+/// Confirmations after which a deposit is counted in the finalized balance
+pub const DEPOSIT_FINALITY_CONFIRMATIONS: u64 = 3;
+
+impl HistoryItem {
+    pub fn id(&self) -> String {
+        match self {
+            HistoryItem::Deposit(d) => d.id.clone(),
+            HistoryItem::Withdrawal(w) => w.id.clone(),
+            HistoryItem::Exchange(e) => e.id.to_string(),
+            HistoryItem::Transfer(t) => t.id.to_string(),
+        }
+    }
+
+    pub fn item_type(&self) -> HistoryItemType {
+        match self {
+            HistoryItem::Deposit(_) => HistoryItemType::Deposit,
+            HistoryItem::Withdrawal(_) => HistoryItemType::Withdrawal,
+            HistoryItem::Exchange(_) => HistoryItemType::Exchange,
+            HistoryItem::Transfer(_) => HistoryItemType::Transfer,
+        }
+    }
+
+    pub fn status(&self) -> HistoryItemStatus {
+        match self {
+            HistoryItem::Deposit(d) if d.number_of_confirmations > DEPOSIT_FINALITY_CONFIRMATIONS => {
+                HistoryItemStatus::Completed
+            }
+            HistoryItem::Deposit(_) => HistoryItemStatus::Pending,
+            HistoryItem::Withdrawal(w) => match w.status {
+                WithdrawalRequestStatus::InProgress { .. } | WithdrawalRequestStatus::Confirmed => {
+                    HistoryItemStatus::Pending
+                }
+                WithdrawalRequestStatus::Completed { .. } => HistoryItemStatus::Completed,
+                // The funds are returned to the user as with rejections
+                WithdrawalRequestStatus::OpRejected
+                | WithdrawalRequestStatus::NodeRejected { .. }
+                | WithdrawalRequestStatus::Cancelled { .. } => HistoryItemStatus::Rejected,
+            },
+            HistoryItem::Exchange(e) => match e.status {
+                ExchangeStatus::InProgress { .. } => HistoryItemStatus::Pending,
+                ExchangeStatus::Completed => HistoryItemStatus::Completed,
+                ExchangeStatus::Rejected | ExchangeStatus::Expired => HistoryItemStatus::Rejected,
+            },
+            HistoryItem::Transfer(t) => match t.status {
+                TransferStatus::InProgress { .. } => HistoryItemStatus::Pending,
+                TransferStatus::Completed => HistoryItemStatus::Completed,
+                TransferStatus::Rejected => HistoryItemStatus::Rejected,
+            },
+        }
+    }
+
+    /// Exchanges involve both of their currencies
+    pub fn involves(&self, currency: &Currency) -> bool {
+        match self {
+            HistoryItem::Deposit(d) => d.currency == *currency,
+            HistoryItem::Withdrawal(w) => w.currency == *currency,
+            HistoryItem::Exchange(e) => e.currency_from == *currency || e.currency_to == *currency,
+            HistoryItem::Transfer(t) => t.currency == *currency,
+        }
+    }
+
+    pub fn cursor(&self) -> HistoryCursor {
+        HistoryCursor {
+            date: *history_item_time(self),
+            id: self.id(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryItemType {
+    Deposit,
+    Withdrawal,
+    Exchange,
+    Transfer,
+}
+
+/// Status common to history items of all types
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryItemStatus {
+    Pending,
+    Completed,
+    Rejected,
+}
+
+/// All fields are optional, missing ones match everything
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryFilter {
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    #[serde(default)]
+    pub item_type: Option<HistoryItemType>,
+    #[serde(default)]
+    pub status: Option<HistoryItemStatus>,
+    /// Inclusive start of the period
+    #[serde(default)]
+    pub from: Option<NaiveDateTime>,
+    /// Exclusive end of the period
+    #[serde(default)]
+    pub until: Option<NaiveDateTime>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, item: &HistoryItem) -> bool {
+        let date = history_item_time(item).naive_utc();
+        self.currency.as_ref().map_or(true, |c| item.involves(c))
+            && self.item_type.map_or(true, |t| item.item_type() == t)
+            && self.status.map_or(true, |s| item.status() == s)
+            && self.from.map_or(true, |from| date >= from)
+            && self.until.map_or(true, |until| date < until)
+    }
+}
+
+/// Position in the history, newest first. Items are ordered by date and then by ID,
+/// so new items don't shift the pages that are already loaded
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HistoryCursor {
+    pub date: DateTime<Utc>,
+    pub id: String,
+}
+
+impl fmt::Display for HistoryCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}|{}", self.date.to_rfc3339(), self.id)
+    }
+}
+
+impl std::str::FromStr for HistoryCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (date, id) = s.split_once('|').ok_or_else(|| format!("Malformed history cursor: {s}"))?;
+        let date = DateTime::parse_from_rfc3339(date)
+            .map_err(|e| format!("Malformed history cursor: {e}"))?
+            .with_timezone(&Utc);
+        Ok(HistoryCursor { date, id: id.to_owned() })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryRequest {
+    #[serde(default)]
+    pub filter: HistoryFilter,
+    /// `next_cursor` of the previous page, the newest items if omitted
+    #[serde(default)]
+    pub cursor: Option<String>,
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HistoryPage {
+    pub confirmations_config: ConfirmationsConfig,
+    pub items: Vec<HistoryItem>,
+    /// Cursor of the next page, `None` if this page is the last
+    pub next_cursor: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Balance {
     pub balances: Vec<BalanceItem>,
@@ -307,6 +510,9 @@ pub struct SignupEmail {
 pub struct SigninEmail {
     pub user: String,
     pub password: String,
+    /// TOTP or recovery code. Required if the user has TOTP enabled
+    #[serde(default)]
+    pub totp: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -318,7 +524,7 @@ pub struct PasswordChange {
 /// Auxiliary data type to display `WithdrawalRequest` on the page
 // NOTE: fields order must be the same as in 'ConfirmationData' struct
 // otherwise signature verification will fail
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct WithdrawalRequest {
     /// Request ID
     #[schemars(example = "example_uuid")]
@@ -338,12 +544,60 @@ pub struct WithdrawalRequest {
     /// Some request require manual confirmation
     #[schemars(example = "example_confirmation_status")]
     pub confirmation_status: WithdrawalRequestStatus,
+    /// Priority of the transaction chosen by the user
+    #[serde(default)]
+    pub fee_tier: FeeTier,
+    /// Fee charged to the user by the fee policy. `None` if the network fee is charged
+    #[serde(default)]
+    pub charged_fee: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct UserWithdrawRequest {
     pub address: CurrencyAddress,
     pub amount: u64,
+    /// TOTP or recovery code. Required if the user asked for it on withdrawals
+    #[serde(default)]
+    pub totp: Option<String>,
+    /// Priority of the BTC transaction, it also affects the charged fee
+    #[serde(default)]
+    pub fee_tier: FeeTier,
+}
+
+/// Saved withdrawal address of the user
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct AddressBookEntry {
+    pub id: Uuid,
+    pub address: CurrencyAddress,
+    pub label: String,
+    pub created_at: NaiveDateTime,
+    /// Withdrawals to the address are allowed since this time
+    pub available_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AddressBookRequest {
+    pub address: CurrencyAddress,
+    pub label: String,
+    /// TOTP or recovery code. If TOTP is enabled, the address is available at once
+    #[serde(default)]
+    pub totp: Option<String>,
+}
+
+/// Withdrawals only to saved addresses
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct WithdrawalAllowlistStatus {
+    pub enabled: bool,
+    /// The allow-list is switched off at this time
+    pub disabled_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WithdrawalAllowlistRequest {
+    pub enabled: bool,
+    /// TOTP or recovery code. If TOTP is enabled, the allow-list is switched off at once
+    #[serde(default)]
+    pub totp: Option<String>,
 }
 
 // NOTE: fields order must be the same as in 'WithdrawalRequest' struct
@@ -395,7 +649,7 @@ fn example_confirmation_status() -> WithdrawalRequestStatus {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
 #[serde(tag = "type")]
 pub enum WithdrawalRequestStatus {
     /// Number of confirmations minus number of rejections received
@@ -422,6 +676,10 @@ pub enum WithdrawalRequestStatus {
         /// Node
         reason: String,
     },
+    /// Cancelled by the user before operators confirmed it
+    Cancelled {
+        cancelled_at: NaiveDateTime,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, JsonSchema, FromFormField)]
@@ -432,6 +690,7 @@ pub enum WithdrawalFilter {
     Completed,
     OpRejected,
     NodeRejected,
+    Cancelled,
 }
 
 impl ToString for WithdrawalFilter {
@@ -443,6 +702,7 @@ impl ToString for WithdrawalFilter {
             WithdrawalFilter::Completed => "completed".to_owned(),
             WithdrawalFilter::OpRejected => "oprejected".to_owned(),
             WithdrawalFilter::NodeRejected => "noderejected".to_owned(),
+            WithdrawalFilter::Cancelled => "cancelled".to_owned(),
         }
     }
 }
@@ -628,6 +888,156 @@ fn example_block_height() -> Option<i64> {
     Some(12345)
 }
 
+/// Withdrawal priority chosen by the user
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeeTier {
+    Slow,
+    Normal,
+    Fast,
+}
+
+impl Default for FeeTier {
+    fn default() -> Self {
+        FeeTier::Normal
+    }
+}
+
+impl FeeTier {
+    pub fn all() -> [FeeTier; 3] {
+        [FeeTier::Slow, FeeTier::Normal, FeeTier::Fast]
+    }
+
+    /// Number of blocks the BTC transaction should be confirmed within.
+    /// Normal matches the default target of the node wallet.
+    pub fn conf_target(&self) -> u16 {
+        match self {
+            FeeTier::Slow => 24,
+            FeeTier::Normal => 6,
+            FeeTier::Fast => 2,
+        }
+    }
+}
+
+/// How the fee charged to the user for a withdrawal is calculated, in base units of the currency.
+/// Shares are in basis points, 1/100 of a percent.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum FeePolicy {
+    /// Fixed fee regardless of the amount and network conditions
+    Flat { fee: u64 },
+    /// Share of the withdrawn amount
+    Percent { basis_points: u64 },
+    /// Network fee estimate with a markup
+    NetworkFee { markup_basis_points: u64 },
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        FeePolicy::NetworkFee {
+            markup_basis_points: 0,
+        }
+    }
+}
+
+impl FeePolicy {
+    /// Fee to charge for withdrawing the amount. `None` if the policy depends on the network fee
+    /// and it is not known in advance, the network fee actually paid is charged then.
+    pub fn charge(&self, amount: u64, network_fee: Option<u64>) -> Option<u64> {
+        // Rounded up in favour of the house
+        let share = |value: u64, basis_points: u64| {
+            let share = (value as u128 * basis_points as u128 + 9_999) / 10_000;
+            u64::try_from(share).unwrap_or(u64::MAX)
+        };
+        match self {
+            FeePolicy::Flat { fee } => Some(*fee),
+            FeePolicy::Percent { basis_points } => Some(share(amount, *basis_points)),
+            FeePolicy::NetworkFee {
+                markup_basis_points,
+            } => network_fee.map(|fee| fee.saturating_add(share(fee, *markup_basis_points))),
+        }
+    }
+}
+
+/// Fee policy set for the currency
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct CurrencyFeePolicy {
+    pub currency: Currency,
+    pub policy: FeePolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WithdrawalFeeRequest {
+    pub currency: Currency,
+    pub amount: u64,
+}
+
+/// Fee of the withdrawal with the given priority
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct WithdrawalFee {
+    pub tier: FeeTier,
+    /// Target number of blocks for BTC, ignored by other currencies
+    pub blocks: u16,
+    /// Estimate of the fee paid to the network
+    pub network_fee: Option<u64>,
+    /// Fee charged to the user. `None` if the network fee actually paid is charged
+    pub fee: Option<u64>,
+}
+
+/// Kind of the operator's request recorded to the audit log
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatorActionType {
+    WithdrawalConfirm,
+    WithdrawalReject,
+    GenInvite,
+    LimitsConfirm,
+    LimitsReject,
+    SetAggregateLimits,
+    ExchangeConfirm,
+    ExchangeReject,
+    SetExchangeLimits,
+    GenExchangeAddress,
+    TransferConfirm,
+    TransferReject,
+    SetMargin,
+    SetFeeEstimates,
+    SetFeePolicy,
+    SettingsConfirm,
+    SettingsReject,
+    AccountChange,
+    AccountConfirm,
+    AccountReject,
+}
+
+/// Filter of the operators' audit log. Missing fields match any entry
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct OperatorAuditFilter {
+    /// Public key of the operator in the same format as in the log entries
+    pub operator: Option<String>,
+    pub action: Option<OperatorActionType>,
+    /// Entries recorded at this time or later
+    pub from: Option<NaiveDateTime>,
+    /// Entries recorded before this time
+    pub until: Option<NaiveDateTime>,
+}
+
+/// Operator's signed request recorded to the audit log. The signature can be
+/// verified again from the URL, nonce and payload
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct OperatorAuditEntry {
+    pub id: Uuid,
+    pub time: NaiveDateTime,
+    pub action: OperatorActionType,
+    /// Public key of the operator
+    pub operator: String,
+    /// Signed URL of the request
+    pub url: String,
+    pub signature: String,
+    pub nonce: u64,
+    /// Signed request body
+    pub payload: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct HotBalanceResponse {
     /// Total balance of the hot wallet in sat
@@ -662,6 +1072,9 @@ pub struct ConfirmedWithdrawal {
     pub confirmations: Vec<SignatureData>,
     /// Rejections received from operators
     pub rejections: Vec<SignatureData>,
+    /// Priority of the transaction chosen by the user
+    #[serde(default)]
+    pub fee_tier: FeeTier,
 }
 
 #[derive(Debug)]
@@ -765,10 +1178,25 @@ pub enum LimitSpan {
     Month,
 }
 
+impl LimitSpan {
+    /// Length of the rolling window the limit is applied to. Month is counted as 30 days.
+    pub fn window(&self) -> chrono::Duration {
+        match self {
+            LimitSpan::Day => chrono::Duration::days(1),
+            LimitSpan::Week => chrono::Duration::weeks(1),
+            LimitSpan::Month => chrono::Duration::days(30),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Limit {
+    /// Amount in base units of the currency or in whole units of the fiat
     pub amount: u64,
     pub span: LimitSpan,
+    /// Fiat currency the limit is set in. Withdrawals are converted at the rate of the request time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fiat: Option<Fiat>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
@@ -847,15 +1275,24 @@ pub struct LimitChangeOpResponse {
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Eq, JsonSchema)]
 pub struct LimitInfo {
     pub limit: Limit,
+    /// Amount withdrawn within the rolling window of the limit
     pub spent: u64,
 }
 
+impl LimitInfo {
+    /// Amount that still can be withdrawn without operators approval
+    pub fn remaining(&self) -> u64 {
+        self.limit.amount.saturating_sub(self.spent)
+    }
+}
+
 impl Default for LimitInfo {
     fn default() -> Self {
         Self {
             limit: Limit {
                 amount: 0,
                 span: LimitSpan::Day,
+                fiat: None,
             },
             spent: Default::default(),
         }
@@ -865,7 +1302,18 @@ impl Default for LimitInfo {
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct LimitApiResp {
     pub currency: Currency,
+    /// The limit with the least remaining amount
     pub limit_info: LimitInfo,
+    /// All limits of the currency, each of them is checked separately
+    pub limits: Vec<LimitInfo>,
+}
+
+/// Operator request to set limits over all currencies of the user
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AggregateLimitsReq {
+    pub user: String,
+    /// Fiat limits, the fiat is required
+    pub limits: Vec<Limit>,
 }
 
 impl PartialOrd for LimitApiResp {
@@ -920,6 +1368,8 @@ pub enum ExchangeStatus {
     InProgress {
         confirmations_minus_rejections: i16,
     },
+    /// Operators didn't decide before the quote expired, the funds are released
+    Expired,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
@@ -932,6 +1382,27 @@ pub struct ExchangeOrder {
     pub amount_to: u64,
     pub created_at: String,
     pub status: ExchangeStatus,
+    /// Operators must decide on the order before this time
+    pub expires_at: Option<NaiveDateTime>,
+    /// The order was under the exchange limits and executed without operators' decision
+    #[serde(default)]
+    pub auto_executed: bool,
+}
+
+/// Firm price for the exchange. Accepting it creates the exchange order.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ExchangeQuote {
+    pub id: Uuid,
+    pub currency_from: Currency,
+    pub currency_to: Currency,
+    pub amount_from: u64,
+    pub amount_to: u64,
+    /// Market rate of whole coins the price is based on
+    pub rate: Rate,
+    /// Margin in whole percents included into the price
+    pub margin: Rate,
+    /// The quote can't be accepted after this time
+    pub expires_at: NaiveDateTime,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
@@ -984,6 +1455,54 @@ impl<'a> FromUriParam<Query, &str> for ExchangeFilter {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(tag = "type")]
+pub enum TransferStatus {
+    Completed,
+    Rejected,
+    /// Number of confirmations minus number of rejections received
+    InProgress {
+        confirmations_minus_rejections: i16,
+    },
+}
+
+/// Off-chain transfer of funds between two users of the custody
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct InternalTransfer {
+    pub id: Uuid,
+    /// Sender's username
+    pub from: String,
+    /// Recipient's username
+    pub to: String,
+    pub currency: Currency,
+    pub amount: u64,
+    pub created_at: NaiveDateTime,
+    pub status: TransferStatus,
+    /// The transfer was under the sender's limits and executed without operators' decision
+    pub auto_executed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TransferRequest {
+    /// Recipient's username
+    pub to: String,
+    pub currency: Currency,
+    pub amount: u64,
+    /// TOTP or recovery code. Required if the user asked for it on withdrawals
+    #[serde(default)]
+    pub totp: Option<String>,
+}
+
+/// Transfer signed by the operator along with the decision
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct TransferConfirmationData {
+    pub id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub currency: Currency,
+    pub amount: u64,
+}
+
 impl<'a> FromUriParam<Query, &ExchangeFilter> for ExchangeFilter {
     type Target = ExchangeFilter;
 
@@ -992,6 +1511,32 @@ impl<'a> FromUriParam<Query, &ExchangeFilter> for ExchangeFilter {
     }
 }
 
+/// Max amount of a single order for the exchange pair, in units of `currency_from`
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ExchangePairLimit {
+    pub currency_from: Currency,
+    pub currency_to: Currency,
+    pub amount: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ExchangeCurrencyLimit {
+    pub currency: Currency,
+    pub amount: u64,
+}
+
+/// Limits under which exchange orders are executed without operators' decision.
+/// Amounts are counted in the currency the user exchanges from.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ExchangeLimits {
+    /// Orders for pairs without the limit always need operators' decision
+    pub pairs: Vec<ExchangePairLimit>,
+    /// Amount a single user can exchange automatically over the last 24 hours
+    pub user_daily: Vec<ExchangeCurrencyLimit>,
+    /// Amount all users can exchange automatically over the last 24 hours
+    pub daily: Vec<ExchangeCurrencyLimit>,
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct ExchangeBalanceItem {
     pub currency: Currency,
@@ -1005,11 +1550,197 @@ pub struct ExchangeAddress {
     pub qr_code_base64: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 pub struct MarginData {
     pub currency_from: Currency,
     pub currency_to: Currency,
-    pub margin: f64,
+    pub margin: Rate,
+}
+
+/// Estimates of the withdrawal transactions used to calculate network fees
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct FeeEstimates {
+    /// Estimate of bytes per tx for BTC
+    pub btc_bytes_per_tx: u64,
+    /// Gas limit for Ethereum transfer transaction
+    pub eth_tx_gas_limit: u64,
+    /// Gas limit for ERC20 transfer transaction
+    pub erc20_tx_gas_limit: u64,
+}
+
+impl FeeEstimates {
+    pub fn new() -> Self {
+        FeeEstimates {
+            btc_bytes_per_tx: 1024,
+            eth_tx_gas_limit: 21_000,
+            erc20_tx_gas_limit: 150_000,
+        }
+    }
+}
+
+impl Default for FeeEstimates {
+    fn default() -> Self {
+        FeeEstimates::new()
+    }
+}
+
+/// Setting that takes effect when enough operators confirm it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type")]
+pub enum SettingsChange {
+    /// Exchange margin of the pair in whole percents
+    Margin(MarginData),
+    FeeEstimates(FeeEstimates),
+    /// Withdrawal fee policy of the currency
+    FeePolicy(CurrencyFeePolicy),
+    /// Limits under which exchange orders are executed without operators' decision
+    ExchangeLimits(ExchangeLimits),
+    /// Fiat limits over withdrawals in all currencies of the user.
+    /// Needs as many confirmations as the change of the user's limit in one currency
+    AggregateLimits(AggregateLimitsReq),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(tag = "type")]
+pub enum SettingsChangeStatus {
+    Completed,
+    Rejected,
+    /// Number of confirmations minus number of rejections received
+    InProgress {
+        confirmations_minus_rejections: i16,
+    },
+}
+
+/// Settings change signed by the operator along with the decision
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct SettingsConfirmationData {
+    pub id: Uuid,
+    pub change: SettingsChange,
+}
+
+/// Settings change proposed by an operator
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct SettingsChangeRequest {
+    pub id: Uuid,
+    pub change: SettingsChange,
+    pub created_at: NaiveDateTime,
+    pub status: SettingsChangeStatus,
+    /// Public keys of the operators who confirmed the change, the proposer included
+    pub confirmations: Vec<String>,
+    /// Public keys of the operators who rejected the change
+    pub rejections: Vec<String>,
+}
+
+/// Lifecycle status of the user's account
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    /// Withdrawals, exchanges and sign in are blocked. Deposits are still credited
+    Frozen,
+    /// Balances are swept, the account can't be used anymore
+    Closed,
+}
+
+impl Default for AccountStatus {
+    fn default() -> Self {
+        AccountStatus::Active
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountStatus::Active => write!(f, "active"),
+            AccountStatus::Frozen => write!(f, "frozen"),
+            AccountStatus::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+/// Change of the account status that takes effect when enough operators confirm it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountAction {
+    Freeze,
+    Unfreeze,
+    /// Sweep all balances and close the account for good
+    Close,
+}
+
+impl AccountAction {
+    /// Status of the account after the action. `None` if the action is not allowed in the given status
+    pub fn next_status(&self, status: AccountStatus) -> Option<AccountStatus> {
+        match (self, status) {
+            (AccountAction::Freeze, AccountStatus::Active) => Some(AccountStatus::Frozen),
+            (AccountAction::Unfreeze, AccountStatus::Frozen) => Some(AccountStatus::Active),
+            (AccountAction::Close, AccountStatus::Active | AccountStatus::Frozen) => {
+                Some(AccountStatus::Closed)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AccountAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountAction::Freeze => write!(f, "freeze"),
+            AccountAction::Unfreeze => write!(f, "unfreeze"),
+            AccountAction::Close => write!(f, "close"),
+        }
+    }
+}
+
+/// Operator's proposal to change status of the user's account
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct AccountChangeProposal {
+    pub user: String,
+    pub action: AccountAction,
+    /// Why the change is proposed, shown to other operators
+    pub reason: String,
+}
+
+/// Account change signed by the operator along with the decision
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AccountConfirmationData {
+    pub id: Uuid,
+    pub user: String,
+    pub action: AccountAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(tag = "type")]
+pub enum AccountChangeStatus {
+    Completed,
+    Rejected,
+    /// Number of confirmations minus number of rejections received
+    InProgress {
+        confirmations_minus_rejections: i16,
+    },
+}
+
+/// Account change proposed by an operator
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AccountChangeRequest {
+    pub id: Uuid,
+    pub user: String,
+    pub action: AccountAction,
+    pub reason: String,
+    pub created_at: NaiveDateTime,
+    pub status: AccountChangeStatus,
+    /// Public keys of the operators who confirmed the change, the proposer included
+    pub confirmations: Vec<String>,
+    /// Public keys of the operators who rejected the change
+    pub rejections: Vec<String>,
+}
+
+/// Account of the user as operators see it
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct UserAccount {
+    pub user: String,
+    pub created_at: NaiveDateTime,
+    pub status: AccountStatus,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -1020,12 +1751,18 @@ pub struct ConfirmationsConfig {
     pub change_limit: i16,
     // Number of confirmations from operators required for exchange
     pub exchange: i16,
+    // Number of confirmations from operators required for change of margins and fee estimates
+    #[serde(default)]
+    pub settings: i16,
+    // Number of confirmations from operators required for freezing, unfreezing and closing accounts
+    #[serde(default)]
+    pub account: i16,
 }
 
 impl ConfirmationsConfig {
     // Returns maximum value among fields
     pub fn max(&self) -> i16 {
-        let items = [self.withdraw, self.change_limit, self.exchange];
+        let items = [self.withdraw, self.change_limit, self.exchange, self.settings, self.account];
         items
             .iter()
             .copied()
@@ -1077,4 +1814,554 @@ pub struct UnitTickedAmount{
     pub mul: u64,
     pub prec: u64,
     pub ticker: Option<TickerUsdRub>
-}
\ No newline at end of file
+}
+/// Event that touches user's account. Delivered to user's webhooks and event stream
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum UserEvent {
+    /// New deposit transaction or update of its confirmations
+    Deposit {
+        currency: Currency,
+        txid: CurrencyTxId,
+        address: CurrencyAddress,
+        amount: u64,
+        confirmations: u64,
+    },
+    /// Balance of the currency is changed
+    BalanceChanged {
+        currency: Currency,
+        /// Includes unconfirmed transactions
+        balance: u64,
+        /// Includes only finalized transactions
+        finalized_balance: u64,
+    },
+    /// Withdrawal request is created or got operator's decision
+    WithdrawalStatus {
+        id: Uuid,
+        currency: Currency,
+        amount: u64,
+        status: WithdrawalRequestStatus,
+    },
+    /// Withdrawal transaction is sent to the network
+    WithdrawalCompleted {
+        id: Uuid,
+        currency: Currency,
+        amount: u64,
+        txid: CurrencyTxId,
+    },
+    /// Withdrawal is rejected by the node
+    WithdrawalRejected {
+        id: Uuid,
+        currency: Currency,
+        amount: u64,
+        reason: String,
+    },
+    /// Operators made a decision on the limit change request
+    LimitChangeDecision {
+        id: Uuid,
+        currency: Currency,
+        limit: Limit,
+        status: LimitChangeStatus,
+    },
+    /// New exchange order is created
+    ExchangeCreated {
+        id: Uuid,
+        currency_from: Currency,
+        currency_to: Currency,
+        amount_from: u64,
+        amount_to: u64,
+    },
+    /// Operators made a decision on the exchange order or it was executed automatically
+    ExchangeDecision {
+        id: Uuid,
+        currency_from: Currency,
+        currency_to: Currency,
+        amount_from: u64,
+        amount_to: u64,
+        status: ExchangeStatus,
+    },
+    /// Internal transfer is created or got operators' decision. Sent to both users
+    Transfer {
+        id: Uuid,
+        currency: Currency,
+        amount: u64,
+        from: String,
+        to: String,
+        status: TransferStatus,
+    },
+}
+
+impl UserEvent {
+    /// Short name of the event, e.g. "deposit"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UserEvent::Deposit { .. } => "deposit",
+            UserEvent::BalanceChanged { .. } => "balanceChanged",
+            UserEvent::WithdrawalStatus { .. } => "withdrawalStatus",
+            UserEvent::WithdrawalCompleted { .. } => "withdrawalCompleted",
+            UserEvent::WithdrawalRejected { .. } => "withdrawalRejected",
+            UserEvent::LimitChangeDecision { .. } => "limitChangeDecision",
+            UserEvent::ExchangeCreated { .. } => "exchangeCreated",
+            UserEvent::ExchangeDecision { .. } => "exchangeDecision",
+            UserEvent::Transfer { .. } => "transfer",
+        }
+    }
+
+    /// Currencies which balance might be changed by the event
+    pub fn balance_currencies(&self) -> Vec<Currency> {
+        match self {
+            UserEvent::Deposit { currency, .. } => vec![currency.clone()],
+            UserEvent::WithdrawalStatus { currency, .. } => vec![currency.clone()],
+            UserEvent::WithdrawalRejected { currency, .. } => vec![currency.clone()],
+            UserEvent::ExchangeCreated { currency_from, .. } => vec![currency_from.clone()],
+            UserEvent::ExchangeDecision {
+                currency_from,
+                currency_to,
+                ..
+            } => vec![currency_from.clone(), currency_to.clone()],
+            UserEvent::Transfer { currency, .. } => vec![currency.clone()],
+            UserEvent::BalanceChanged { .. }
+            | UserEvent::WithdrawalCompleted { .. }
+            | UserEvent::LimitChangeDecision { .. } => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct WebhookRequest {
+    /// HTTPS endpoint to deliver events to
+    pub url: String,
+    /// Shared secret used to sign delivered events with HMAC-SHA256
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct WebhookInfo {
+    pub id: Uuid,
+    pub url: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// Body of the webhook POST request. The raw body is signed with
+/// HMAC-SHA256 and the signature is passed in 'X-Hexstody-Signature' header
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct WebhookPayload {
+    /// Delivery ID. Stays the same for retries and replays
+    pub id: Uuid,
+    /// When the event happened
+    pub created: NaiveDateTime,
+    pub event: UserEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, JsonSchema)]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl ToString for WebhookDeliveryStatus {
+    fn to_string(&self) -> String {
+        match self {
+            WebhookDeliveryStatus::Pending => "pending".to_owned(),
+            WebhookDeliveryStatus::Delivered => "delivered".to_owned(),
+            WebhookDeliveryStatus::Failed => "failed".to_owned(),
+        }
+    }
+}
+
+impl std::str::FromStr for WebhookDeliveryStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(WebhookDeliveryStatus::Pending),
+            "delivered" => Ok(WebhookDeliveryStatus::Delivered),
+            "failed" => Ok(WebhookDeliveryStatus::Failed),
+            _ => Err(format!("Unknown webhook delivery status: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub user: String,
+    pub url: String,
+    pub payload: WebhookPayload,
+    pub status: WebhookDeliveryStatus,
+    /// Number of delivery attempts made
+    pub attempts: u32,
+    /// Error of the last failed attempt
+    pub last_error: Option<String>,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+/// Event for operators. Carries the current state of the changed item,
+/// so the operator UI doesn't need to re-fetch everything.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+#[serde(rename_all = "camelCase")]
+pub enum OperatorEvent {
+    /// New or updated withdrawal request
+    WithdrawalRequest(WithdrawalRequest),
+    /// New or updated limit change request
+    LimitChangeRequest(LimitChangeOpResponse),
+    /// New or updated exchange order
+    ExchangeOrder(ExchangeOrder),
+    /// New or updated internal transfer
+    InternalTransfer(InternalTransfer),
+    /// Inconsistency between internal ledger and the node balances
+    ReconciliationAlert { currency: Currency, message: String },
+    /// Request to a node failed
+    NodeError { node: String, message: String },
+}
+
+impl OperatorEvent {
+    /// Short name of the event, e.g. "withdrawalRequest"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OperatorEvent::WithdrawalRequest(_) => "withdrawalRequest",
+            OperatorEvent::LimitChangeRequest(_) => "limitChangeRequest",
+            OperatorEvent::ExchangeOrder(_) => "exchangeOrder",
+            OperatorEvent::InternalTransfer(_) => "internalTransfer",
+            OperatorEvent::ReconciliationAlert { .. } => "reconciliationAlert",
+            OperatorEvent::NodeError { .. } => "nodeError",
+        }
+    }
+}
+
+/// Short-lived token to open operator's event stream.
+/// Browsers can't attach 'Signature-Data' header to the event source request.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EventStreamToken {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Permission granted to an API key. Every key can read account data.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ApiKeyScope {
+    /// Read balances, history and settings
+    ReadOnly,
+    /// Create withdrawal requests
+    Withdraw,
+    /// Create exchange orders
+    Exchange,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ApiKeyRequest {
+    /// Human readable name of the key
+    pub label: String,
+    /// Granted permissions. 'readOnly' is implied
+    pub scopes: Vec<ApiKeyScope>,
+    /// Addresses the key can be used from. Empty list allows any address
+    #[serde(default)]
+    pub allowed_ips: Vec<std::net::IpAddr>,
+    /// The key is rejected after this moment
+    pub expires_at: Option<NaiveDateTime>,
+    /// TOTP or recovery code. Required if the user asked for it on key creation
+    #[serde(default)]
+    pub totp: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ApiKeyInfo {
+    pub id: Uuid,
+    pub label: String,
+    /// First characters of the key to tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub allowed_ips: Vec<std::net::IpAddr>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    /// Time of the last authorized request since the server start. It is
+    /// not persisted, so it is empty after restart until the key is used again.
+    pub last_used: Option<NaiveDateTime>,
+}
+
+/// Newly created key. The key itself is shown only once, only its hash is stored.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ApiKeyCreated {
+    pub key: String,
+    pub info: ApiKeyInfo,
+}
+
+/// Secret for a new TOTP enrollment. Nothing is stored until the user
+/// confirms it with a code.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TotpSetup {
+    /// Base32 encoded secret
+    pub secret: String,
+    /// Secret in the form of 'otpauth://' URL for QR codes
+    pub otpauth_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TotpEnableRequest {
+    /// Secret from the setup step
+    pub secret: String,
+    /// Current code generated from the secret
+    pub code: String,
+    #[serde(default)]
+    pub require_for_withdrawal: bool,
+    #[serde(default)]
+    pub require_for_api_keys: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TotpCode {
+    /// TOTP or recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TotpStatus {
+    pub enabled: bool,
+    pub require_for_withdrawal: bool,
+    pub require_for_api_keys: bool,
+    pub recovery_codes_left: usize,
+}
+
+/// One-time recovery codes. They are shown only once.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TotpRecoveryCodesResponse {
+    pub codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebauthnRelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnUser {
+    /// Base64url encoded user handle
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebauthnCredParam {
+    #[serde(rename = "type")]
+    pub cred_type: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebauthnCredDescriptor {
+    #[serde(rename = "type")]
+    pub cred_type: String,
+    /// Base64url encoded credential ID
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnAuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// Options for 'navigator.credentials.create'. Binary fields are base64url encoded.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnRegisterOptions {
+    pub challenge: String,
+    pub rp: WebauthnRelyingParty,
+    pub user: WebauthnUser,
+    pub pub_key_cred_params: Vec<WebauthnCredParam>,
+    /// Milliseconds
+    pub timeout: u64,
+    pub attestation: String,
+    pub exclude_credentials: Vec<WebauthnCredDescriptor>,
+    pub authenticator_selection: WebauthnAuthenticatorSelection,
+}
+
+/// Result of 'navigator.credentials.create'. Binary fields are base64url encoded.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnRegisterRequest {
+    /// Human readable name of the authenticator
+    pub name: String,
+    pub id: String,
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// Options for 'navigator.credentials.get'. Binary fields are base64url encoded.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnLoginOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    /// Milliseconds
+    pub timeout: u64,
+    pub user_verification: String,
+}
+
+/// Result of 'navigator.credentials.get'. Binary fields are base64url encoded.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnLoginRequest {
+    pub id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebauthnCredentialInfo {
+    /// Base64url encoded credential ID
+    pub id: String,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+/// Signed in session of the user
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<std::net::IpAddr>,
+    /// The session the request is made from
+    pub current: bool,
+}
+
+/// Rate of the pair recorded at the given time
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct RateRecord {
+    pub from: Symbol,
+    pub to: Symbol,
+    pub rate: Rate,
+    pub time: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RateHistoryRequest {
+    pub from: Symbol,
+    pub to: Symbol,
+    /// Inclusive start of the period, from the first record if omitted
+    pub since: Option<NaiveDateTime>,
+    /// Exclusive end of the period, till now if omitted
+    pub until: Option<NaiveDateTime>,
+}
+
+/// Kind of a balance change in a statement
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementEntryKind {
+    Deposit,
+    Withdrawal,
+    WithdrawalFee,
+    ExchangeOut,
+    ExchangeIn,
+    TransferOut,
+    TransferIn,
+    /// Remaining balance taken off the closed account
+    ClosureSweep,
+}
+
+impl fmt::Display for StatementEntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatementEntryKind::Deposit => write!(f, "deposit"),
+            StatementEntryKind::Withdrawal => write!(f, "withdrawal"),
+            StatementEntryKind::WithdrawalFee => write!(f, "withdrawal_fee"),
+            StatementEntryKind::ExchangeOut => write!(f, "exchange_out"),
+            StatementEntryKind::ExchangeIn => write!(f, "exchange_in"),
+            StatementEntryKind::TransferOut => write!(f, "transfer_out"),
+            StatementEntryKind::TransferIn => write!(f, "transfer_in"),
+            StatementEntryKind::ClosureSweep => write!(f, "closure_sweep"),
+        }
+    }
+}
+
+/// Single balance change. Amounts are in minimal units of the currency
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct StatementEntry {
+    pub time: NaiveDateTime,
+    pub kind: StatementEntryKind,
+    /// Added to the balance
+    pub credit: u64,
+    /// Taken from the balance
+    pub debit: u64,
+    /// Balance after the entry
+    pub balance: u64,
+    /// Transaction hash, withdrawal or exchange ID
+    pub reference: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct CurrencyStatement {
+    pub currency: Currency,
+    pub opening_balance: u64,
+    pub closing_balance: u64,
+    /// Withdrawal fees paid in the period
+    pub fees: u64,
+    pub entries: Vec<StatementEntry>,
+}
+
+/// Balance changes of the user over the period `[from, until)`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct Statement {
+    pub user: String,
+    pub from: NaiveDateTime,
+    pub until: NaiveDateTime,
+    pub currencies: Vec<CurrencyStatement>,
+}
+
+impl Statement {
+    /// One row per entry, opening and closing balances are rows of their own
+    pub fn to_csv(&self) -> String {
+        let mut csv = "time,currency,type,credit,debit,balance,reference\n".to_owned();
+        for cs in self.currencies.iter() {
+            let ticker = csv_field(&cs.currency.ticker());
+            csv.push_str(&format!("{},{},opening_balance,,,{},\n", self.from, ticker, cs.opening_balance));
+            for e in cs.entries.iter() {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    e.time, ticker, e.kind, e.credit, e.debit, e.balance, csv_field(&e.reference)
+                ));
+            }
+            csv.push_str(&format!("{},{},closing_balance,,,{},\n", self.until, ticker, cs.closing_balance));
+        }
+        csv
+    }
+}
+
+/// Quote the field if it contains separators
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StatementRequest {
+    /// Inclusive start of the period
+    pub from: NaiveDateTime,
+    /// Exclusive end of the period
+    pub until: NaiveDateTime,
+}
+
+/// Operator's request of the user's statement
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UserStatementRequest {
+    pub user: String,
+    pub from: NaiveDateTime,
+    pub until: NaiveDateTime,
+}
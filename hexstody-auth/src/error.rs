@@ -1,12 +1,24 @@
 use hexstody_api::error::HexstodyError;
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Error, PartialEq)]
 pub enum Error {
     #[error("Action requires authentification")]
     AuthRequired,
     #[error("Authed user is not found in state!")]
     NoUserFound,
+    #[error("API key is expired")]
+    ApiKeyExpired,
+    #[error("API key doesn't have required scope")]
+    ApiKeyScopeDenied,
+    #[error("API key can't be used from this address")]
+    ApiKeyIpDenied,
+    #[error("Action requires signed in session, API keys are not accepted")]
+    SessionRequired,
+    #[error("Account is frozen, contact support")]
+    AccountFrozen,
+    #[error("Account is closed")]
+    AccountClosed,
 }
 
 impl HexstodyError for Error {
@@ -18,6 +30,12 @@ impl HexstodyError for Error {
         match self {
             Error::AuthRequired => 0,
             Error::NoUserFound => 1,
+            Error::ApiKeyExpired => 2,
+            Error::ApiKeyScopeDenied => 3,
+            Error::ApiKeyIpDenied => 4,
+            Error::SessionRequired => 5,
+            Error::AccountFrozen => 6,
+            Error::AccountClosed => 7,
         }
     }
 
@@ -25,6 +43,12 @@ impl HexstodyError for Error {
         match self {
             Error::AuthRequired => 403,
             Error::NoUserFound => 403,
+            Error::ApiKeyExpired => 401,
+            Error::ApiKeyScopeDenied => 403,
+            Error::ApiKeyIpDenied => 403,
+            Error::SessionRequired => 403,
+            Error::AccountFrozen => 403,
+            Error::AccountClosed => 403,
         }
     }
 }
\ No newline at end of file
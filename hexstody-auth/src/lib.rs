@@ -1,32 +1,74 @@
-use std::{future::Future, sync::Arc};
+use std::{collections::HashMap, future::Future, sync::Arc};
 
 use async_trait::async_trait;
-use rocket::{http::CookieJar, State};
+use chrono::{NaiveDateTime, Utc};
+use rocket::{http::{Cookie, CookieJar}, State};
 use hexstody_api::error as h_error;
+use hexstody_api::types::ApiKeyScope;
 
 pub mod error;
+pub mod session;
+pub mod throttle;
 pub mod types;
 
 use error::Error;
+use session::SessionStore;
+use throttle::FailureThrottle;
 use tokio::sync::{Mutex, MutexGuard};
-use types::ApiKey;
+use types::{ApiKey, ClientInfo};
+use uuid::Uuid;
 
 pub const AUTH_COOKIE: &str = "user_id";
 
 #[async_trait]
 pub trait HasAuth {
-    fn get_user_id_by_api_key(&self, api_key: ApiKey) -> Option<String>;
+    /// Find owner of the key and check that the key can be used for the scope.
+    /// Returns ID of the key and its owner.
+    fn get_user_id_by_api_key(&self, api_key: &ApiKey, scope: ApiKeyScope) -> Result<(Uuid, String), Error>;
+
+    /// Check that the account of the user can be used, e.g. it is not frozen or closed
+    fn check_user_access(&self, user_id: &str) -> Result<(), Error>;
 }
 
 pub trait HasUserInfo<I> {
     fn get_user_info(&self, user_id: &str) -> Option<I>;
 }
 
-/// Helper for implementing endpoints that require authentication
+/// Authentication data that is not derived from state updates, so it is
+/// kept out of the persisted state. Restart of the server signs out all users.
+#[derive(Debug, Default)]
+pub struct AuthStore {
+    pub sessions: SessionStore,
+    /// Time of the last authorized request by API key ID. Lost on restart
+    pub api_key_usage: HashMap<Uuid, NaiveDateTime>,
+    /// Wrong TOTP and recovery codes by users
+    pub second_factor_failures: FailureThrottle,
+}
+
+/// Helper for implementing endpoints that require authentication.
+/// API keys of any scope are accepted.
 pub async fn require_auth<F, Fut, S, R>(
-    cookies: &CookieJar<'_>, 
+    cookies: &CookieJar<'_>,
+    api_key: Option<ApiKey>,
+    auth_store: &Mutex<AuthStore>,
+    state: &State<Arc<Mutex<S>>>,
+    future: F
+) -> h_error::Result<R>
+where
+    S: Send + HasAuth,
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = h_error::Result<R>>,
+{
+    require_auth_scoped(cookies, api_key, auth_store, state, ApiKeyScope::ReadOnly, future).await
+}
+
+/// The same as 'require_auth', but API key must have the given scope
+pub async fn require_auth_scoped<F, Fut, S, R>(
+    cookies: &CookieJar<'_>,
     api_key: Option<ApiKey>,
+    auth_store: &Mutex<AuthStore>,
     state: &State<Arc<Mutex<S>>>,
+    scope: ApiKeyScope,
     future: F
 ) -> h_error::Result<R>
 where
@@ -34,20 +76,84 @@ where
     F: FnOnce(String) -> Fut,
     Fut: Future<Output = h_error::Result<R>>,
 {
-    if let Some(cookie) = cookies.get_private(AUTH_COOKIE) {
-        let user_id = cookie.value().to_string();
+    let user_id = if let Some(user_id) = session_user(cookies, auth_store).await {
+        user_id
+    } else if let Some(api_key) = api_key {
+        let (key_id, user_id) = state.lock().await.get_user_id_by_api_key(&api_key, scope)?;
+        auth_store.lock().await.api_key_usage.insert(key_id, Utc::now().naive_utc());
+        user_id
+    } else {
+        return Err(Error::AuthRequired.into());
+    };
+    state.lock().await.check_user_access(&user_id)?;
+    future(user_id).await
+}
+
+/// Helper for endpoints that manage the account itself, e.g. API keys.
+/// Only signed in users are allowed, API keys are rejected.
+pub async fn require_session<F, Fut, S, R>(
+    cookies: &CookieJar<'_>,
+    api_key: Option<ApiKey>,
+    auth_store: &Mutex<AuthStore>,
+    state: &State<Arc<Mutex<S>>>,
+    future: F
+) -> h_error::Result<R>
+where
+    S: Send + HasAuth,
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = h_error::Result<R>>,
+{
+    if let Some(user_id) = session_user(cookies, auth_store).await {
+        state.lock().await.check_user_access(&user_id)?;
         future(user_id).await
+    } else if api_key.is_some() {
+        Err(Error::SessionRequired.into())
     } else {
-        if let Some(api_key) = api_key {
-            if let Some(user_id) = state.lock().await.get_user_id_by_api_key(api_key) {
-                future(user_id).await
-            } else {
-                Err(Error::AuthRequired.into())
-            }
-        } else {
-            Err(Error::AuthRequired.into())
-        }
+        Err(Error::AuthRequired.into())
+    }
+}
+
+/// ID of the session stored in the auth cookie, the session might be already closed
+pub fn session_id(cookies: &CookieJar<'_>) -> Option<uuid::Uuid> {
+    let cookie = cookies.get_private(AUTH_COOKIE)?;
+    uuid::Uuid::parse_str(cookie.value()).ok()
+}
+
+/// Owner of the session from the auth cookie. The cookie of closed or expired session is removed.
+async fn session_user(cookies: &CookieJar<'_>, auth_store: &Mutex<AuthStore>) -> Option<String> {
+    let cookie = cookies.get_private(AUTH_COOKIE)?;
+    let user = auth_store
+        .lock()
+        .await
+        .sessions
+        .touch(cookie.value(), Utc::now().naive_utc());
+    if user.is_none() {
+        cookies.remove_private(Cookie::named(AUTH_COOKIE));
     }
+    user
+}
+
+/// Start new session of the user and set the auth cookie.
+/// Fails if the account can't be used, e.g. it is frozen.
+pub async fn start_session<S: Send + HasAuth>(
+    cookies: &CookieJar<'_>,
+    auth_store: &Mutex<AuthStore>,
+    state: &Mutex<S>,
+    user: &str,
+    client: ClientInfo,
+) -> Result<(), Error> {
+    state.lock().await.check_user_access(user)?;
+    let session = auth_store.lock().await.sessions.create(user, client, Utc::now().naive_utc());
+    cookies.add_private(Cookie::new(AUTH_COOKIE, session.id.to_string()));
+    Ok(())
+}
+
+/// Close the session from the auth cookie and remove the cookie
+pub async fn end_session(cookies: &CookieJar<'_>, auth_store: &Mutex<AuthStore>) {
+    if let Some(id) = session_id(cookies) {
+        auth_store.lock().await.sessions.remove(&id);
+    }
+    cookies.remove_private(Cookie::named(AUTH_COOKIE));
 }
 
 /// More specific helper than 'require_auth' as it also locks state
@@ -55,7 +161,25 @@ where
 pub async fn require_auth_user<F, S, I, Fut, R>(
     cookies: &CookieJar<'_>,
     api_key: Option<ApiKey>,
+    auth_store: &Mutex<AuthStore>,
+    state: &State<Arc<Mutex<S>>>,
+    future: F,
+) -> h_error::Result<R>
+where
+    S: Send + HasAuth + HasUserInfo<I>,
+    F: FnOnce(MutexGuard<S>, I) -> Fut,
+    Fut: Future<Output = h_error::Result<R>>,
+{
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::ReadOnly, future).await
+}
+
+/// The same as 'require_auth_user', but API key must have the given scope
+pub async fn require_auth_user_scoped<F, S, I, Fut, R>(
+    cookies: &CookieJar<'_>,
+    api_key: Option<ApiKey>,
+    auth_store: &Mutex<AuthStore>,
     state: &State<Arc<Mutex<S>>>,
+    scope: ApiKeyScope,
     future: F,
 ) -> h_error::Result<R>
 where
@@ -63,7 +187,7 @@ where
     F: FnOnce(MutexGuard<S>, I) -> Fut,
     Fut: Future<Output = h_error::Result<R>>,
 {
-    require_auth(cookies, api_key, state, |user_id| async move {
+    require_auth_scoped(cookies, api_key, auth_store, state, scope, |user_id| async move {
         {
             let state = state.lock().await;
             if let Some(user) = state.get_user_info(&user_id) {
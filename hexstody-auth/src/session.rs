@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::types::ClientInfo;
+
+/// Session is closed if there were no requests for this time
+pub const SESSION_IDLE_TIMEOUT_MINS: i64 = 60;
+/// Session is closed after this time regardless of the activity
+pub const SESSION_ABSOLUTE_TIMEOUT_HOURS: i64 = 12;
+
+/// Signed in browser session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub user: String,
+    pub created_at: NaiveDateTime,
+    /// Time of the last authenticated request
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+/// Sessions by their IDs. The ID is stored in the private auth cookie.
+///
+/// Sessions are kept in memory only, so restart of the server signs out all users.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionStore {
+    sessions: HashMap<Uuid, Session>,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        SessionStore::new(
+            Duration::minutes(SESSION_IDLE_TIMEOUT_MINS),
+            Duration::hours(SESSION_ABSOLUTE_TIMEOUT_HOURS),
+        )
+    }
+}
+
+impl SessionStore {
+    pub fn new(idle_timeout: Duration, absolute_timeout: Duration) -> Self {
+        SessionStore {
+            sessions: HashMap::new(),
+            idle_timeout,
+            absolute_timeout,
+        }
+    }
+
+    fn is_expired(&self, session: &Session, now: NaiveDateTime) -> bool {
+        now >= session.last_seen + self.idle_timeout
+            || now >= session.created_at + self.absolute_timeout
+    }
+
+    /// Start new session of the user. Expired sessions are dropped on the way.
+    pub fn create(&mut self, user: &str, client: ClientInfo, now: NaiveDateTime) -> Session {
+        self.prune(now);
+        let session = Session {
+            id: Uuid::new_v4(),
+            user: user.to_owned(),
+            created_at: now,
+            last_seen: now,
+            user_agent: client.user_agent,
+            ip: client.ip,
+        };
+        self.sessions.insert(session.id, session.clone());
+        session
+    }
+
+    /// Find owner of the active session and mark it as seen
+    pub fn touch(&mut self, id: &str, now: NaiveDateTime) -> Option<String> {
+        let id = Uuid::parse_str(id).ok()?;
+        let session = self.sessions.get(&id)?;
+        if self.is_expired(session, now) {
+            self.sessions.remove(&id);
+            return None;
+        }
+        let session = self.sessions.get_mut(&id)?;
+        session.last_seen = now;
+        Some(session.user.clone())
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Session> {
+        self.sessions.get(id)
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Session> {
+        self.sessions.remove(id)
+    }
+
+    /// Active sessions of the user, newest first
+    pub fn user_sessions(&self, user: &str, now: NaiveDateTime) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .values()
+            .filter(|s| s.user == user && !self.is_expired(s, now))
+            .cloned()
+            .collect();
+        sessions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        sessions
+    }
+
+    /// Close all sessions of the user except the given one. Returns number of closed sessions.
+    pub fn revoke_user_sessions(&mut self, user: &str, except: Option<&Uuid>) -> usize {
+        let before = self.sessions.len();
+        self.sessions
+            .retain(|id, s| s.user != user || Some(id) == except);
+        before - self.sessions.len()
+    }
+
+    /// Drop all expired sessions
+    pub fn prune(&mut self, now: NaiveDateTime) {
+        let (idle, absolute) = (self.idle_timeout, self.absolute_timeout);
+        self.sessions
+            .retain(|_, s| now < s.last_seen + idle && now < s.created_at + absolute);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            user_agent: Some("test".to_owned()),
+            ip: Some("127.0.0.1".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let mut store = SessionStore::new(Duration::minutes(10), Duration::hours(1));
+        let now = Utc::now().naive_utc();
+        let session = store.create("alice", client(), now);
+        let id = session.id.to_string();
+        assert_eq!(store.touch(&id, now + Duration::minutes(9)), Some("alice".to_owned()));
+        // Activity extends the session
+        assert_eq!(store.touch(&id, now + Duration::minutes(18)), Some("alice".to_owned()));
+        assert_eq!(store.touch(&id, now + Duration::minutes(29)), None);
+        assert!(store.get(&session.id).is_none());
+    }
+
+    #[test]
+    fn test_absolute_timeout() {
+        let mut store = SessionStore::new(Duration::minutes(10), Duration::minutes(25));
+        let now = Utc::now().naive_utc();
+        let id = store.create("alice", client(), now).id.to_string();
+        for minutes in [5, 10, 15, 20] {
+            assert!(store.touch(&id, now + Duration::minutes(minutes)).is_some());
+        }
+        assert_eq!(store.touch(&id, now + Duration::minutes(25)), None);
+    }
+
+    #[test]
+    fn test_revoke_other_sessions() {
+        let mut store = SessionStore::default();
+        let now = Utc::now().naive_utc();
+        let current = store.create("alice", client(), now);
+        let other = store.create("alice", client(), now);
+        let bob = store.create("bob", client(), now);
+        assert_eq!(store.user_sessions("alice", now).len(), 2);
+        assert_eq!(store.revoke_user_sessions("alice", Some(&current.id)), 1);
+        assert!(store.get(&current.id).is_some());
+        assert!(store.get(&other.id).is_none());
+        assert!(store.get(&bob.id).is_some());
+        assert_eq!(store.touch("not a session", now), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+
+/// Number of wrong codes in a row after which the user is locked out
+pub const MAX_FAILURES: u32 = 5;
+/// How long the user is locked out after too many wrong codes
+pub const LOCKOUT_MINS: i64 = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Failures {
+    count: u32,
+    last_at: NaiveDateTime,
+}
+
+/// Wrong second factor codes by users. Six digit codes can be guessed,
+/// so the checks are stopped for a while after several failures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureThrottle {
+    failures: HashMap<String, Failures>,
+    max_failures: u32,
+    lockout: Duration,
+}
+
+impl Default for FailureThrottle {
+    fn default() -> Self {
+        FailureThrottle::new(MAX_FAILURES, Duration::minutes(LOCKOUT_MINS))
+    }
+}
+
+impl FailureThrottle {
+    pub fn new(max_failures: u32, lockout: Duration) -> Self {
+        FailureThrottle {
+            failures: HashMap::new(),
+            max_failures,
+            lockout,
+        }
+    }
+
+    /// Time left until the user can try again. `None` if the user is not locked out.
+    pub fn locked_for(&self, user: &str, now: NaiveDateTime) -> Option<Duration> {
+        let failures = self.failures.get(user)?;
+        let unlock_at = failures.last_at + self.lockout;
+        if failures.count >= self.max_failures && now < unlock_at {
+            Some(unlock_at - now)
+        } else {
+            None
+        }
+    }
+
+    /// Count the wrong code. Failures older than the lockout time are forgotten.
+    pub fn record_failure(&mut self, user: &str, now: NaiveDateTime) {
+        let lockout = self.lockout;
+        let failures = self.failures.entry(user.to_owned()).or_insert(Failures {
+            count: 0,
+            last_at: now,
+        });
+        if now >= failures.last_at + lockout {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last_at = now;
+    }
+
+    /// Forget failures of the user after the correct code
+    pub fn reset(&mut self, user: &str) {
+        self.failures.remove(user);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_lockout() {
+        let mut throttle = FailureThrottle::new(3, Duration::minutes(10));
+        let now = Utc::now().naive_utc();
+        for _ in 0..2 {
+            throttle.record_failure("alice", now);
+        }
+        assert_eq!(throttle.locked_for("alice", now), None);
+        throttle.record_failure("alice", now + Duration::minutes(1));
+        assert_eq!(
+            throttle.locked_for("alice", now + Duration::minutes(2)),
+            Some(Duration::minutes(9))
+        );
+        assert_eq!(throttle.locked_for("bob", now), None);
+        assert_eq!(throttle.locked_for("alice", now + Duration::minutes(11)), None);
+        // Old failures are not counted after the lockout
+        throttle.record_failure("alice", now + Duration::minutes(12));
+        assert_eq!(throttle.locked_for("alice", now + Duration::minutes(12)), None);
+        throttle.record_failure("alice", now + Duration::minutes(13));
+        throttle.record_failure("alice", now + Duration::minutes(13));
+        assert!(throttle.locked_for("alice", now + Duration::minutes(13)).is_some());
+        throttle.reset("alice");
+        assert_eq!(throttle.locked_for("alice", now + Duration::minutes(13)), None);
+    }
+}
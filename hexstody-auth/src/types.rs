@@ -1,12 +1,30 @@
+use std::net::IpAddr;
+
 use async_trait::async_trait;
 use rocket::{http::Status, request::FromRequest, outcome::Outcome};
 use rocket_okapi::{okapi::openapi3::{Parameter, ParameterValue, Object}, request::{RequestHeaderInput, OpenApiFromRequest}};
-use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 
 use crate::error as error;
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Clone, Serialize, Deserialize)]
-pub struct ApiKey(String);
+/// API key passed in 'ApiKey' header together with the address of the caller
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub key: String,
+    pub client_ip: Option<IpAddr>,
+}
+
+impl ApiKey {
+    /// Hash of the key as it is stored in the state
+    pub fn hash(&self) -> String {
+        hash_api_key(&self.key)
+    }
+}
+
+/// Keys are long random strings, so plain SHA-256 is enough to store them
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
 
 #[async_trait]
 impl<'r> FromRequest<'r> for ApiKey {
@@ -16,8 +34,11 @@ impl<'r> FromRequest<'r> for ApiKey {
         let api_key = request.headers().get_one("ApiKey");
         match api_key {
           Some(api_key) => {
-            // check validity
-            Outcome::Success(ApiKey(api_key.to_string()))
+            // validity is checked against the state in 'require_auth'
+            Outcome::Success(ApiKey {
+                key: api_key.to_string(),
+                client_ip: request.client_ip(),
+            })
           },
           // token does not exist
           None => Outcome::Failure((Status::Unauthorized, error::Error::AuthRequired))
@@ -34,9 +55,9 @@ impl<'r> OpenApiFromRequest<'r> for ApiKey {
         let schema = gen.json_schema::<String>();
         let description = Some("Contains API key for authorization".to_owned(),
         );
-        let example = Some(serde_json::json!("123e4567-e89b-12d3-a456-426614174000"));
+        let example = Some(serde_json::json!("hxs_8f14e45fceea167a5a36dedd4bea2543"));
         Ok(RequestHeaderInput::Parameter(Parameter {
-            name: "Signature-Data".to_owned(),
+            name: "ApiKey".to_owned(),
             location: "header".to_owned(),
             description: description,
             required,
@@ -53,4 +74,33 @@ impl<'r> OpenApiFromRequest<'r> for ApiKey {
             extensions: Object::default(),
         }))
     }
-}
\ No newline at end of file
+}
+
+/// Metadata of the caller recorded in the session
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = error::Error;
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            user_agent: request.headers().get_one("User-Agent").map(|s| s.to_owned()),
+            ip: request.client_ip(),
+        })
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for ClientInfo {
+    fn from_request_input(
+        _gen: &mut rocket_okapi::gen::OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<rocket_okapi::request::RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
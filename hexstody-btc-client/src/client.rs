@@ -91,6 +91,22 @@ impl BtcClient {
         Ok(serde_json::from_str(&response)?)
     }
 
+    /// Fee rate for the transaction to be confirmed within the given number of blocks
+    pub async fn get_fees_for(&self, blocks: u16) -> Result<FeeResponse> {
+        let path = "/fees";
+        let endpoint = format!("{}{}?blocks={}", self.server, path, blocks);
+        let request = self.client.get(endpoint).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
     pub async fn withdraw_btc(&self, cw: ConfirmedWithdrawal) -> Result<WithdrawalResponse>{
         let path = "/withdraw";
         let endpoint = format!("{}{}", self.server, path);
@@ -70,11 +70,12 @@ async fn get_deposit_address(client: &State<Client>) -> error::Result<BtcAddress
     Ok(Json(address.into()))
 }
 
+/// Fee rate for the transaction to be confirmed within the given number of blocks, 2 by default
 #[openapi(tag = "fees")]
-#[get("/fees")]
-async fn get_fees(client: &State<Client>) -> Json<FeeResponse> {
+#[get("/fees?<blocks>")]
+async fn get_fees(client: &State<Client>, blocks: Option<u16>) -> Json<FeeResponse> {
     let est = client
-        .estimate_smart_fee(2, None)
+        .estimate_smart_fee(blocks.unwrap_or(2), None)
         .map_err(|e| error::Error::from(e));
     let res = FeeResponse {
         fee_rate: 5 * 1024, // default 5 sat/byte
@@ -164,7 +165,16 @@ async fn withdraw_btc(
                 let comment = cw.id.to_string();
                 let amount = bitcoin::Amount::from_sat(cw.amount);
                 let txid = client
-                    .send_to_address(&addr, amount, Some(&comment), None, None, None, None, None)
+                    .send_to_address(
+                        &addr,
+                        amount,
+                        Some(&comment),
+                        None,
+                        None,
+                        None,
+                        Some(cw.fee_tier.conf_target().into()),
+                        None,
+                    )
                     .map_err(|e| {
                         (
                             Status::InternalServerError,
@@ -264,7 +274,16 @@ async fn withdraw_btc_under_limit(
             let comment = cw.id.to_string();
             let amount = bitcoin::Amount::from_sat(cw.amount);
             let txid = client
-                .send_to_address(&addr, amount, Some(&comment), None, None, None, None, None)
+                .send_to_address(
+                    &addr,
+                    amount,
+                    Some(&comment),
+                    None,
+                    None,
+                    None,
+                    Some(cw.fee_tier.conf_target().into()),
+                    None,
+                )
                 .map_err(|e| {
                     (
                         Status::InternalServerError,
@@ -9,12 +9,16 @@ pub struct ConfirmationsConfig {
     pub change_limit: i16,
     // Number of confirmations from operators required for exchange
     pub exchange: i16,
+    // Number of confirmations from operators required for change of margins and fee estimates
+    pub settings: i16,
+    // Number of confirmations from operators required for freezing, unfreezing and closing accounts
+    pub account: i16,
 }
 
 impl ConfirmationsConfig {
     // Returns maximum value among fields
     pub fn max(&self) -> i16 {
-        let items = [self.withdraw, self.change_limit, self.exchange];
+        let items = [self.withdraw, self.change_limit, self.exchange, self.settings, self.account];
         items
             .iter()
             .copied()
@@ -28,4 +32,6 @@ pub const CONFIRMATIONS_CONFIG: ConfirmationsConfig = ConfirmationsConfig {
     withdraw: 2,
     change_limit: 2,
     exchange: 1,
+    settings: 2,
+    account: 2,
 };
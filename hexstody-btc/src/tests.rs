@@ -2,7 +2,7 @@ use bitcoin::Amount;
 use bitcoincore_rpc::RpcApi;
 use hexstody_api::domain::CurrencyAddress;
 use hexstody_api::types::{
-    ConfirmationData, ConfirmedWithdrawal, SignatureData, HotBalanceResponse,
+    ConfirmationData, ConfirmedWithdrawal, FeeTier, SignatureData, HotBalanceResponse,
 };
 use hexstody_btc_api::bitcoin::*;
 use hexstody_btc_api::events::*;
@@ -340,6 +340,7 @@ async fn process_withdrawal_request() {
             amount,
             confirmations,
             rejections,
+            fee_tier: FeeTier::Normal,
         };
         let resp = api.withdraw_btc(cw).await;
         assert!(resp.is_ok(), "Failed to post tx");
@@ -366,6 +367,7 @@ async fn withdraw_under_limit(){
             amount,
             confirmations: vec![],
             rejections: vec![],
+            fee_tier: FeeTier::Normal,
         };
         let resp = api.withdraw_under_limit(cw).await.expect("Failed to post tx");
         let HotBalanceResponse{balance} = api.get_hot_wallet_balance().await.expect("Failed to get balance");
@@ -446,6 +448,7 @@ async fn reject_withdrawal_request() {
             amount,
             confirmations,
             rejections,
+            fee_tier: FeeTier::Normal,
         };
         let resp = api.withdraw_btc(cw).await;
         assert!(resp.is_err(), "Failed to reject tx");
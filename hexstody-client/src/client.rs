@@ -6,6 +6,7 @@ use p256::ecdsa::SigningKey;
 use p256::ecdsa::signature::Signer;
 use p256::pkcs8::EncodePublicKey;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -109,6 +110,133 @@ impl HexstodyClient {
         Ok(serde_json::from_str(&response)?)
     }
 
+    /// Page of deposits, withdrawals and exchanges matching the filter
+    pub async fn get_history(&self, req: &HistoryRequest) -> Result<HistoryPage> {
+        let path = "/history";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(req).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    /// Fees charged for the withdrawal at each priority tier
+    pub async fn get_withdrawal_fees(&self, req: &WithdrawalFeeRequest) -> Result<Vec<WithdrawalFee>> {
+        let path = "/withdraw/fee";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(req).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    /// Cancel the withdrawal request that waits for operators
+    pub async fn cancel_withdrawal(&self, id: Uuid) -> Result<()> {
+        let path = "/withdraw/cancel";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(&id).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(())
+    }
+
+    /// Move funds to another user. Returns ID of the transfer
+    pub async fn transfer(&self, req: &TransferRequest) -> Result<Uuid> {
+        let path = "/transfer";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(req).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    pub async fn get_address_book(&self) -> Result<Vec<AddressBookEntry>> {
+        let path = "/address-book";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.get(endpoint).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    pub async fn add_address_book_entry(&self, req: &AddressBookRequest) -> Result<AddressBookEntry> {
+        let path = "/address-book";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(req).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    pub async fn remove_address_book_entry(&self, id: Uuid) -> Result<()> {
+        let path = "/address-book/remove";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(&id).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(())
+    }
+
+    pub async fn set_withdrawal_allowlist(
+        &self,
+        req: &WithdrawalAllowlistRequest,
+    ) -> Result<WithdrawalAllowlistStatus> {
+        let path = "/address-book/allowlist";
+        let endpoint = format!("{}{}", self.server, path);
+        let request = self.client.post(endpoint).json(req).build()?;
+        let response = self
+            .client
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        debug!("Response {path}: {}", response);
+        Ok(serde_json::from_str(&response)?)
+    }
+
     pub async fn get_deposit_address(&self, currency: Currency) -> Result<CurrencyAddress> {
         let path = "/deposit/address";
         let endpoint = format!("{}{}", self.server, path);
@@ -0,0 +1,13 @@
+create table webhook_deliveries(
+    id uuid primary key,
+    webhook_id uuid not null,
+    user_id text not null,
+    url text not null,
+    payload jsonb not null,
+    status text not null,
+    attempts integer not null,
+    last_error text,
+    created timestamp not null,
+    updated timestamp not null
+);
+create index webhook_deliveries_user_idx on webhook_deliveries(user_id, created);
@@ -0,0 +1,8 @@
+create table rate_history(
+    id bigserial primary key,
+    symbol_from text not null,
+    symbol_to text not null,
+    rate text not null,
+    created timestamp not null
+);
+create index rate_history_pair_idx on rate_history(symbol_from, symbol_to, created);
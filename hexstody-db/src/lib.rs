@@ -8,6 +8,7 @@ use sqlx::postgres::{PgPoolOptions, Postgres};
 use state::*;
 use update::results::UpdateResult;
 use std::sync::Arc;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::{Mutex, Notify};
 use update::*;
@@ -32,18 +33,25 @@ pub async fn update_worker(
     state: Arc<Mutex<State>>,
     state_notify: Arc<Notify>,
     mut update_receiver: Receiver<StateUpdate>,
-    update_resp_sender: Sender<UpdateResult>
+    update_resp_sender: Sender<UpdateResult>,
+    applied_sender: broadcast::Sender<StateUpdate>,
 ) {
     info!("Update state worker started");
     while let Some(i) = update_receiver.recv().await {
         debug!("Applying state update: {:?}", i);
+        if i.body.is_legacy() {
+            error!("Legacy state update is applied only on replay: {}", i.body.tag());
+            continue;
+        }
         {
             let mut mstate = state.lock().await;
             let mut copy_state = mstate.clone();
             match copy_state.apply_update(i.clone()) {
-                Ok(res) => match insert_update(&pool, i.body, Some(i.created)).await {
+                Ok(res) => match insert_update(&pool, i.body.clone(), Some(i.created)).await {
                     Ok(_) => {
                         *mstate = copy_state;
+                        // Error means there are no subscribers at the moment, that's fine
+                        let _ = applied_sender.send(i);
                         if let Some(update_result) = res {
                             if let Err(e) = update_resp_sender.send(update_result).await{
                                 error!("Failed to send an update result: {e}");
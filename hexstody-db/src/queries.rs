@@ -3,7 +3,11 @@ use super::update::*;
 use super::Pool;
 use chrono::prelude::*;
 use futures::StreamExt;
+use hexstody_api::domain::{Rate, Symbol};
+use hexstody_api::types::{RateRecord, WebhookDelivery, WebhookDeliveryStatus};
+use std::str::FromStr;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -15,6 +19,8 @@ pub enum Error {
     Encoding(#[from] serde_json::Error),
     #[error("Failed to reconstruct state: {0}")]
     StateInvalid(#[from] StateUpdateErr),
+    #[error("Malformed database row: {0}")]
+    MalformedRow(String),
 }
 
 /// Alias for a `Result` with the error type `self::Error`.
@@ -23,7 +29,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// Query all history of updates until we hit a snapshot or the begining of time
 pub async fn query_updates(pool: &Pool) -> Result<Vec<StateUpdate>> {
     let mut conn = pool.acquire().await?;
-    let res = sqlx::query!("select * from updates order by created desc")
+    let res = sqlx::query!("select * from updates order by created desc, id desc")
         .fetch(&mut conn)
         .fuse();
     futures::pin_mut!(res);
@@ -82,6 +88,183 @@ pub async fn query_state(network: Network, pool: &Pool) -> Result<State> {
     Ok(State::collect(network, updates.into_iter().rev())?)
 }
 
+/// Record new webhook delivery or overwrite status of existing one
+pub async fn upsert_webhook_delivery(pool: &Pool, delivery: &WebhookDelivery) -> Result<()> {
+    let payload = serde_json::to_value(&delivery.payload)?;
+    sqlx::query!(
+        "insert into webhook_deliveries (id, webhook_id, user_id, url, payload, status, attempts, last_error, created, updated)
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        on conflict (id) do update set status = $6, attempts = $7, last_error = $8, updated = $10",
+        delivery.id,
+        delivery.webhook_id,
+        delivery.user,
+        delivery.url,
+        payload,
+        delivery.status.to_string(),
+        delivery.attempts as i32,
+        delivery.last_error,
+        delivery.created,
+        delivery.updated
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Query user's webhook deliveries, newest first
+pub async fn query_webhook_deliveries(
+    pool: &Pool,
+    user: &str,
+    skip: i64,
+    take: i64,
+) -> Result<Vec<WebhookDelivery>> {
+    let rows = sqlx::query!(
+        "select * from webhook_deliveries where user_id = $1 order by created desc offset $2 limit $3",
+        user,
+        skip,
+        take
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(WebhookDelivery {
+                id: r.id,
+                webhook_id: r.webhook_id,
+                user: r.user_id,
+                url: r.url,
+                payload: serde_json::from_value(r.payload)?,
+                status: WebhookDeliveryStatus::from_str(&r.status).map_err(Error::MalformedRow)?,
+                attempts: r.attempts as u32,
+                last_error: r.last_error,
+                created: r.created,
+                updated: r.updated,
+            })
+        })
+        .collect()
+}
+
+/// Query deliveries that are not delivered and not failed yet, oldest first
+pub async fn query_pending_webhook_deliveries(pool: &Pool) -> Result<Vec<WebhookDelivery>> {
+    let rows = sqlx::query!(
+        "select * from webhook_deliveries where status = $1 order by created",
+        WebhookDeliveryStatus::Pending.to_string()
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(WebhookDelivery {
+                id: r.id,
+                webhook_id: r.webhook_id,
+                user: r.user_id,
+                url: r.url,
+                payload: serde_json::from_value(r.payload)?,
+                status: WebhookDeliveryStatus::from_str(&r.status).map_err(Error::MalformedRow)?,
+                attempts: r.attempts as u32,
+                last_error: r.last_error,
+                created: r.created,
+                updated: r.updated,
+            })
+        })
+        .collect()
+}
+
+/// Query single webhook delivery by ID
+pub async fn query_webhook_delivery(pool: &Pool, id: Uuid) -> Result<Option<WebhookDelivery>> {
+    let row = sqlx::query!("select * from webhook_deliveries where id = $1", id)
+        .fetch_optional(pool)
+        .await?;
+    row.map(|r| {
+        Ok(WebhookDelivery {
+            id: r.id,
+            webhook_id: r.webhook_id,
+            user: r.user_id,
+            url: r.url,
+            payload: serde_json::from_value(r.payload)?,
+            status: WebhookDeliveryStatus::from_str(&r.status).map_err(Error::MalformedRow)?,
+            attempts: r.attempts as u32,
+            last_error: r.last_error,
+            created: r.created,
+            updated: r.updated,
+        })
+    })
+    .transpose()
+}
+
+/// Append rates to the history
+pub async fn insert_rates(pool: &Pool, records: &[RateRecord]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for r in records {
+        sqlx::query!(
+            "insert into rate_history (symbol_from, symbol_to, rate, created) values ($1, $2, $3, $4)",
+            r.from.symbol(),
+            r.to.symbol(),
+            r.rate.to_string(),
+            r.time
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn rate_record(from: String, to: String, rate: String, time: NaiveDateTime) -> Result<RateRecord> {
+    Ok(RateRecord {
+        from: Symbol::from_symbol(&from),
+        to: Symbol::from_symbol(&to),
+        rate: Rate::from_str(&rate).map_err(|e| Error::MalformedRow(e.to_string()))?,
+        time,
+    })
+}
+
+/// Query rates of the pair recorded in the period, oldest first
+pub async fn query_rate_history(
+    pool: &Pool,
+    from: &Symbol,
+    to: &Symbol,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    take: i64,
+) -> Result<Vec<RateRecord>> {
+    let rows = sqlx::query!(
+        "select * from rate_history where symbol_from = $1 and symbol_to = $2
+        and ($3::timestamp is null or created >= $3) and ($4::timestamp is null or created < $4)
+        order by created asc limit $5",
+        from.symbol(),
+        to.symbol(),
+        since,
+        until,
+        take
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|r| rate_record(r.symbol_from, r.symbol_to, r.rate, r.created))
+        .collect()
+}
+
+/// Latest rate of the pair recorded not later than `at`
+pub async fn query_rate_at(
+    pool: &Pool,
+    from: &Symbol,
+    to: &Symbol,
+    at: NaiveDateTime,
+) -> Result<Option<RateRecord>> {
+    let row = sqlx::query!(
+        "select * from rate_history where symbol_from = $1 and symbol_to = $2 and created <= $3
+        order by created desc limit 1",
+        from.symbol(),
+        to.symbol(),
+        at
+    )
+    .fetch_optional(pool)
+    .await?;
+    row.map(|r| rate_record(r.symbol_from, r.symbol_to, r.rate, r.created))
+        .transpose()
+}
+
 #[cfg(test)]
 mod tests {
     #[sqlx_database_tester::test(
@@ -0,0 +1,138 @@
+use chrono::NaiveDateTime;
+use hexstody_api::types::{
+    AccountAction, AccountChangeRequest, AccountChangeStatus, AccountConfirmationData,
+    SignatureData, WithdrawalRequestDecisionType,
+};
+use p256::{ecdsa::Signature, PublicKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::update::signup::UserId;
+
+pub type AccountChangeId = Uuid;
+
+/// Body of the account change proposal. The proposer's signature counts as the first confirmation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AccountChangeUpd {
+    pub id: AccountChangeId,
+    pub user: UserId,
+    pub action: AccountAction,
+    /// Why the change is proposed
+    pub reason: String,
+    /// API URL wich was used to send the proposal
+    pub url: String,
+    /// Proposer's digital signature
+    pub signature: Signature,
+    /// Nonce that was generated during the proposal
+    pub nonce: u64,
+    /// Proposer's public key corresponding to the signing private key
+    pub public_key: PublicKey,
+}
+
+/// Account change waiting for operators' decision or already decided
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AccountChangeRecord {
+    pub id: AccountChangeId,
+    pub user: UserId,
+    pub action: AccountAction,
+    pub reason: String,
+    pub created_at: NaiveDateTime,
+    pub status: AccountChangeStatus,
+    pub confirmations: Vec<SignatureData>,
+    pub rejections: Vec<SignatureData>,
+}
+
+impl AccountChangeRecord {
+    /// New change confirmed by its proposer only
+    pub fn proposed(upd: AccountChangeUpd, created_at: NaiveDateTime) -> Self {
+        AccountChangeRecord {
+            id: upd.id,
+            user: upd.user,
+            action: upd.action,
+            reason: upd.reason,
+            created_at,
+            status: AccountChangeStatus::InProgress {
+                confirmations_minus_rejections: 1,
+            },
+            confirmations: vec![SignatureData {
+                signature: upd.signature,
+                nonce: upd.nonce,
+                public_key: upd.public_key,
+            }],
+            rejections: Vec::new(),
+        }
+    }
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, AccountChangeStatus::InProgress { .. })
+    }
+    pub fn has_confirmed(&self, pubkey: PublicKey) -> bool {
+        self.confirmations.iter().any(|sd| sd.public_key == pubkey)
+    }
+    pub fn has_rejected(&self, pubkey: PublicKey) -> bool {
+        self.rejections.iter().any(|sd| sd.public_key == pubkey)
+    }
+}
+
+impl From<AccountChangeRecord> for AccountChangeRequest {
+    fn from(rec: AccountChangeRecord) -> Self {
+        let keys = |sigs: Vec<SignatureData>| sigs.into_iter().map(|sd| sd.public_key.to_string()).collect();
+        AccountChangeRequest {
+            id: rec.id,
+            user: rec.user,
+            action: rec.action,
+            reason: rec.reason,
+            created_at: rec.created_at,
+            status: rec.status,
+            confirmations: keys(rec.confirmations),
+            rejections: keys(rec.rejections),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AccountChangeDecision {
+    /// Account change id
+    pub id: AccountChangeId,
+    /// User and action as the operator saw them, must match the proposed ones
+    pub user: UserId,
+    pub action: AccountAction,
+    /// API URL wich was used to send the decision
+    pub url: String,
+    /// Operator's digital signature
+    pub signature: Signature,
+    /// Nonce that was generated during decision
+    pub nonce: u64,
+    /// Operator's public key corresponding to the signing private key
+    pub public_key: PublicKey,
+    /// Decision type: confirm or reject
+    pub decision: WithdrawalRequestDecisionType,
+}
+
+impl
+    From<(
+        AccountConfirmationData,
+        SignatureData,
+        WithdrawalRequestDecisionType,
+        String,
+    )> for AccountChangeDecision
+{
+    fn from(
+        value: (
+            AccountConfirmationData,
+            SignatureData,
+            WithdrawalRequestDecisionType,
+            String,
+        ),
+    ) -> AccountChangeDecision {
+        AccountChangeDecision {
+            id: value.0.id,
+            user: value.0.user,
+            action: value.0.action,
+            url: value.3,
+            signature: value.1.signature,
+            nonce: value.1.nonce,
+            public_key: value.1.public_key,
+            decision: value.2,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use hexstody_api::types::{ApiKeyInfo, ApiKeyScope};
+use hexstody_auth::types::hash_api_key;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::update::api_key::ApiKeyCreate;
+use crate::update::signup::UserId;
+
+/// API key stored by the hash of the key
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ApiKeyRecord {
+    pub id: Uuid,
+    pub user: UserId,
+    pub prefix: String,
+    pub label: String,
+    pub scopes: Vec<ApiKeyScope>,
+    /// Empty list allows any address
+    pub allowed_ips: Vec<IpAddr>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl ApiKeyRecord {
+    /// Read access is granted to every key
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        scope == ApiKeyScope::ReadOnly || self.scopes.contains(&scope)
+    }
+
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires_at.map(|t| t <= now).unwrap_or(false)
+    }
+
+    pub fn is_ip_allowed(&self, ip: Option<IpAddr>) -> bool {
+        self.allowed_ips.is_empty() || ip.map(|ip| self.allowed_ips.contains(&ip)).unwrap_or(false)
+    }
+}
+
+impl From<ApiKeyCreate> for ApiKeyRecord {
+    fn from(req: ApiKeyCreate) -> Self {
+        ApiKeyRecord {
+            id: req.id,
+            user: req.user,
+            prefix: req.prefix,
+            label: req.label,
+            scopes: req.scopes,
+            allowed_ips: req.allowed_ips,
+            created_at: req.created_at,
+            expires_at: req.expires_at,
+        }
+    }
+}
+
+/// Length of the prefix of legacy keys, same as for the keys created by the API
+const LEGACY_KEY_PREFIX_LEN: usize = 8;
+
+/// Snapshots before hashed keys stored the plain keys mapped to the user IDs
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ApiKeysFormat {
+    Hashed(HashMap<String, ApiKeyRecord>),
+    Legacy(HashMap<String, UserId>),
+}
+
+/// Reads the keys of both formats. Legacy keys keep the access they had, so
+/// they get all scopes. The ID is derived from the hash to be the same on
+/// every restart, and the creation time is unknown, so it is the Unix epoch.
+pub(crate) fn deserialize_api_keys<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, ApiKeyRecord>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = match ApiKeysFormat::deserialize(deserializer)? {
+        ApiKeysFormat::Hashed(keys) => keys,
+        ApiKeysFormat::Legacy(keys) => keys
+            .into_iter()
+            .map(|(key, user)| {
+                let key_hash = hash_api_key(&key);
+                let record = ApiKeyRecord {
+                    id: Uuid::parse_str(&key_hash[..32]).unwrap_or_else(|_| Uuid::nil()),
+                    user,
+                    prefix: key.chars().take(LEGACY_KEY_PREFIX_LEN).collect(),
+                    label: "Legacy key".to_owned(),
+                    scopes: vec![ApiKeyScope::ReadOnly, ApiKeyScope::Withdraw, ApiKeyScope::Exchange],
+                    allowed_ips: vec![],
+                    created_at: NaiveDateTime::from_timestamp(0, 0),
+                    expires_at: None,
+                };
+                (key_hash, record)
+            })
+            .collect(),
+    };
+    Ok(keys)
+}
+
+/// The key with the time it was used last time. Usage is not an event,
+/// so it is tracked in memory by the API server.
+impl From<(ApiKeyRecord, Option<NaiveDateTime>)> for ApiKeyInfo {
+    fn from((rec, last_used): (ApiKeyRecord, Option<NaiveDateTime>)) -> Self {
+        ApiKeyInfo {
+            id: rec.id,
+            label: rec.label,
+            prefix: rec.prefix,
+            scopes: rec.scopes,
+            allowed_ips: rec.allowed_ips,
+            created_at: rec.created_at,
+            expires_at: rec.expires_at,
+            last_used,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use bitcoin_hashes::hex::ToHex;
+use chrono::NaiveDateTime;
+use hexstody_api::types::{OperatorAuditEntry, OperatorAuditFilter};
+use serde::{Deserialize, Serialize};
+
+use crate::update::audit::OperatorActionRec;
+
+/// Operator's request in the audit log with the time it was recorded
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct OperatorAuditRecord {
+    pub time: NaiveDateTime,
+    pub action: OperatorActionRec,
+}
+
+impl OperatorAuditRecord {
+    pub fn operator(&self) -> String {
+        self.action.signature_data.public_key.to_string()
+    }
+
+    pub fn matches(&self, filter: &OperatorAuditFilter) -> bool {
+        filter.operator.as_ref().map_or(true, |op| *op == self.operator())
+            && filter.action.map_or(true, |a| a == self.action.action)
+            && filter.from.map_or(true, |from| self.time >= from)
+            && filter.until.map_or(true, |until| self.time < until)
+    }
+}
+
+impl From<&OperatorAuditRecord> for OperatorAuditEntry {
+    fn from(rec: &OperatorAuditRecord) -> Self {
+        OperatorAuditEntry {
+            id: rec.action.id,
+            time: rec.time,
+            action: rec.action.action,
+            operator: rec.operator(),
+            url: rec.action.url.clone(),
+            signature: rec.action.signature_data.signature.as_ref().to_hex(),
+            nonce: rec.action.signature_data.nonce,
+            payload: rec.action.payload.clone(),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
-use hexstody_api::{domain::{Currency, CurrencyAddress}, types::{ExchangeStatus, SignatureData, ExchangeConfirmationData}};
+use chrono::NaiveDateTime;
+use hexstody_api::{domain::{Currency, CurrencyAddress, Rate}, types::{ExchangeStatus, SignatureData, ExchangeConfirmationData, ExchangeLimits}};
 use p256::{ecdsa::Signature, PublicKey};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
@@ -20,7 +21,29 @@ pub struct ExchangeOrderUpd {
     pub currency_to: Currency,
     pub amount_from: u64,
     pub amount_to: u64,
-    pub created_at: String
+    pub created_at: String,
+    /// Market rate of the accepted quote. Missing for orders created before quotes
+    #[serde(default)]
+    pub rate: Option<Rate>,
+    /// Margin in percents included into the quote
+    #[serde(default)]
+    pub margin: Option<Rate>,
+    /// Order expires and the held funds are released if operators don't decide before that
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Orders under the exchange limits are executed without operators' decision
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExchangeRequestType {
+    UnderLimit,
+    OverLimit,
+}
+
+impl Default for ExchangeRequestType {
+    fn default() -> Self {
+        ExchangeRequestType::OverLimit
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -34,7 +57,18 @@ pub struct ExchangeOrder {
     pub status: ExchangeStatus,
     pub created_at: String,
     pub confirmations: Vec<SignatureData>,
-    pub rejections: Vec<SignatureData>
+    pub rejections: Vec<SignatureData>,
+    #[serde(default)]
+    pub rate: Option<Rate>,
+    #[serde(default)]
+    pub margin: Option<Rate>,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub request_type: ExchangeRequestType,
+    /// When the order was completed. Missing for orders completed before it was recorded
+    #[serde(default)]
+    pub executed_at: Option<NaiveDateTime>,
 }
 
 impl ExchangeOrder {
@@ -47,6 +81,24 @@ impl ExchangeOrder {
     pub fn is_pending(&self) -> bool {
         matches!(self.status, ExchangeStatus::InProgress {..})
     }
+    pub fn is_auto_executed(&self) -> bool {
+        self.is_finalized() && self.request_type == ExchangeRequestType::UnderLimit
+    }
+    pub fn is_expired(&self) -> bool {
+        matches!(self.status, ExchangeStatus::Expired)
+    }
+    /// Parsed `created_at`, which is stored as displayed `DateTime<Utc>`
+    pub fn created_time(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(self.created_at.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S%.f").ok()
+    }
+    /// When the order was completed. Falls back to the creation time for old orders
+    pub fn completed_time(&self) -> Option<NaiveDateTime> {
+        self.executed_at.or_else(|| self.created_time())
+    }
+    /// Pending order that operators can no longer decide on at the given time
+    pub fn is_overdue(&self, now: NaiveDateTime) -> bool {
+        self.is_pending() && self.expires_at.map_or(false, |t| t <= now)
+    }
     pub fn has_confirmed(&self, pubkey: PublicKey) -> bool{
         self.confirmations.iter().any(|sd| sd.public_key == pubkey)
     }
@@ -55,18 +107,27 @@ impl ExchangeOrder {
     }
 
     pub fn into_exchange_upd(&self) -> ExchangeOrderUpd {
-        let ExchangeOrder { id, user, currency_from, currency_to, amount_from, amount_to, created_at, .. } = self.clone();
-        ExchangeOrderUpd { user, id, currency_from, currency_to, amount_from, amount_to, created_at }
+        let ExchangeOrder { id, user, currency_from, currency_to, amount_from, amount_to, created_at, rate, margin, expires_at, .. } = self.clone();
+        ExchangeOrderUpd { user, id, currency_from, currency_to, amount_from, amount_to, created_at, rate, margin, expires_at }
     }
 }
 
 impl From<ExchangeOrder> for hexstody_api::types::ExchangeOrder{
     fn from(eo: ExchangeOrder) -> Self {
-        let ExchangeOrder { id, user, currency_from, currency_to, amount_from, amount_to, status, created_at, .. } = eo;
-        hexstody_api::types::ExchangeOrder { user, id, currency_from, currency_to, amount_from, amount_to, status, created_at }
+        let auto_executed = eo.is_auto_executed();
+        let ExchangeOrder { id, user, currency_from, currency_to, amount_from, amount_to, status, created_at, expires_at, .. } = eo;
+        hexstody_api::types::ExchangeOrder { user, id, currency_from, currency_to, amount_from, amount_to, status, created_at, expires_at, auto_executed }
     }
 }
 
+/// Pending order that passed its expiry time
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ExchangeExpire {
+    pub user: String,
+    pub id: ExchangeOrderId,
+    pub currency_from: Currency,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum ExchangeDecisionType {
     Confirm,
@@ -140,7 +201,10 @@ pub struct ExchangeState{
     /// External deposits to exchange account
     pub deposits: Vec<Transaction>,
     /// We keep running balance, as to not calculate it each time
-    pub balances: HashMap<Currency, i64>
+    pub balances: HashMap<Currency, i64>,
+    /// Limits for orders executed without operators' decision
+    #[serde(default)]
+    pub limits: ExchangeLimits,
 }
 
 impl ExchangeState {
@@ -149,7 +213,8 @@ impl ExchangeState {
             exchanges: HashMap::new(),
             addresses: HashMap::new(),
             deposits: Vec::new(),
-            balances: Currency::supported().iter().map(|c| (c.clone(),0)).collect()
+            balances: Currency::supported().iter().map(|c| (c.clone(),0)).collect(),
+            limits: ExchangeLimits::default(),
         }
     }
 
@@ -159,6 +224,11 @@ impl ExchangeState {
         self.balances.entry(order.currency_to).and_modify(|v| *v -= order.amount_to as i64).or_insert(order.amount_to as i64);
     }
 
+    /// Whether the exchange account has enough of the currency to pay out
+    pub fn has_inventory(&self, currency: &Currency, amount: u64) -> bool {
+        self.balances.get(currency).map_or(false, |b| *b >= amount as i64)
+    }
+
     pub fn process_incoming_btc_tx(&mut self, upd_tx: BtcTransaction) {
         if upd_tx.amount >= 0 {
             for tx in self.deposits.iter_mut() {
@@ -0,0 +1,125 @@
+use chrono::prelude::*;
+use hexstody_api::domain::{BTCTxid, Currency, CurrencyAddress, CurrencyTxId, ETHTxid, Erc20, EthAccount};
+use hexstody_api::types::{
+    DepositHistoryItem, ExchangeHistoryItem, HistoryCursor, HistoryFilter, HistoryItem,
+    TransferHistoryItem, WithdrawalHistoryItem, WithdrawalRequestStatus,
+};
+
+use super::transaction::Transaction;
+use super::transfer::InternalTransfer;
+use super::user::{UserCurrencyInfo, UserInfo};
+use super::withdraw::WithdrawalRequest;
+
+fn deposit_item(info: &UserCurrencyInfo, tx: &Transaction) -> HistoryItem {
+    match tx {
+        Transaction::Btc(tx) => HistoryItem::Deposit(DepositHistoryItem {
+            id: format!("{}:{}", tx.txid, tx.vout),
+            currency: info.currency.clone(),
+            date: tx.timestamp,
+            value: tx.amount.unsigned_abs(),
+            number_of_confirmations: tx.confirmations,
+            txid: CurrencyTxId::BTC(BTCTxid { txid: tx.txid.to_string() }),
+            to_address: CurrencyAddress::from(tx.address.clone()),
+            fiat_value: None,
+        }),
+        Transaction::Eth(tx) => {
+            let account = EthAccount { account: tx.addr.clone() };
+            let to_address = match &info.currency {
+                Currency::ERC20(token) => CurrencyAddress::ERC20(Erc20 {
+                    token: token.clone(),
+                    account,
+                }),
+                _ => CurrencyAddress::ETH(account),
+            };
+            HistoryItem::Deposit(DepositHistoryItem {
+                id: tx.hash.clone(),
+                currency: info.currency.clone(),
+                date: Utc.timestamp(tx.timeStamp.parse().unwrap_or(0), 0),
+                value: tx.value.unsigned_abs(),
+                number_of_confirmations: tx.confirmations.max(0) as u64,
+                txid: CurrencyTxId::ETH(ETHTxid { txid: tx.hash.clone() }),
+                to_address,
+                fiat_value: None,
+            })
+        }
+    }
+}
+
+fn withdrawal_item(info: &UserCurrencyInfo, w: &WithdrawalRequest) -> HistoryItem {
+    let status: WithdrawalRequestStatus = w.status.clone().into();
+    let txid = match &status {
+        WithdrawalRequestStatus::Completed { txid, .. } => Some(txid.clone()),
+        _ => None,
+    };
+    HistoryItem::Withdrawal(WithdrawalHistoryItem {
+        id: w.id.to_string(),
+        currency: info.currency.clone(),
+        date: w.created_at,
+        value: w.amount,
+        status,
+        txid,
+        fiat_value: None,
+    })
+}
+
+fn transfer_item(t: &InternalTransfer, incoming: bool) -> HistoryItem {
+    HistoryItem::Transfer(TransferHistoryItem {
+        id: t.id,
+        currency: t.currency.clone(),
+        amount: t.amount,
+        counterparty: if incoming { t.from.clone() } else { t.to.clone() },
+        incoming,
+        date: DateTime::from_utc(t.time(), Utc),
+        status: t.status,
+    })
+}
+
+/// Deposits, withdrawals, exchanges and transfers of the user, newest first
+pub fn user_history(user: &UserInfo) -> Vec<HistoryItem> {
+    let mut items: Vec<HistoryItem> = vec![];
+    for info in user.currencies.values() {
+        items.extend(
+            info.unconfirmed_transactions()
+                // Withdrawals are tracked by withdrawal requests
+                .filter(|tx| !tx.is_withdraw())
+                .map(|tx| deposit_item(info, tx)),
+        );
+        items.extend(info.withdrawal_requests.values().map(|w| withdrawal_item(info, w)));
+        items.extend(info.exchange_requests.values().map(|o| {
+            let date = if o.is_finalized() { o.completed_time() } else { o.created_time() };
+            HistoryItem::Exchange(ExchangeHistoryItem {
+                id: o.id,
+                currency_from: o.currency_from.clone(),
+                currency_to: o.currency_to.clone(),
+                amount_from: o.amount_from,
+                amount_to: o.amount_to,
+                date: DateTime::from_utc(date.unwrap_or(user.created_at), Utc),
+                status: o.status,
+            })
+        }));
+        items.extend(info.outgoing_transfers.values().map(|t| transfer_item(t, false)));
+        items.extend(info.incoming_transfers.values().map(|t| transfer_item(t, true)));
+    }
+    items.sort_by(|a, b| b.cursor().cmp(&a.cursor()));
+    items
+}
+
+/// Up to `limit` items matching the filter that go after the cursor.
+/// Returns the cursor of the next page if there are more items
+pub fn history_page(
+    user: &UserInfo,
+    filter: &HistoryFilter,
+    after: Option<&HistoryCursor>,
+    limit: usize,
+) -> (Vec<HistoryItem>, Option<HistoryCursor>) {
+    let mut items = user_history(user)
+        .into_iter()
+        .filter(|item| after.map_or(true, |c| item.cursor() < *c))
+        .filter(|item| filter.matches(item));
+    let page: Vec<HistoryItem> = items.by_ref().take(limit).collect();
+    let next = match (page.last(), items.next()) {
+        (Some(last), Some(_)) => Some(last.cursor()),
+        _ => None,
+    };
+    (page, next)
+}
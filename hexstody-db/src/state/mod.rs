@@ -1,13 +1,25 @@
+pub mod account;
+pub mod api_key;
+pub mod audit;
 pub mod btc;
 pub mod exchange;
+pub mod history;
 pub mod network;
+pub mod settings;
+pub mod statement;
 pub mod transaction;
+pub mod transfer;
 pub mod user;
 pub mod withdraw;
 
+#[cfg(test)]
+mod replay_tests;
+
+pub use api_key::*;
 pub use btc::*;
 use chrono::prelude::*;
 use hexstody_auth::{HasUserInfo, HasAuth};
+use hexstody_auth::error::Error as AuthError;
 use hexstody_auth::types::ApiKey;
 use log::*;
 pub use network::*;
@@ -20,29 +32,46 @@ pub use user::*;
 use uuid::Uuid;
 pub use withdraw::*;
 
-use crate::update::limit::{LimitCancelData, LimitChangeData, LimitChangeDecision, LimitChangeUpd};
+use crate::update::address_book::{AddressBookAdd, AddressBookRemove, SetWithdrawalAllowlist};
+use crate::update::limit::{
+    AggregateLimitsUpd, LimitCancelData, LimitChangeData, LimitChangeDecision, LimitChangeUpd,
+};
 use crate::update::misc::{
     ConfigUpdateData, InviteRec, PasswordChangeUpd, SetLanguage, SetPublicKey, TokenAction,
     TokenUpdate, SetUnit,
 };
 use crate::update::signup::SignupAuth;
-use crate::update::withdrawal::{WithdrawCompleteInfo, WithdrawalRejectInfo};
+use crate::update::api_key::{ApiKeyCreate, ApiKeyRevoke};
+use crate::update::totp::{TotpDisable, TotpEnroll, TotpRecoveryCodeUsed, TotpRecoveryCodes};
+use crate::update::webauthn::{WebauthnCredentialAdd, WebauthnCredentialRemove, WebauthnCredentialUse};
+use crate::update::webhook::{WebhookRegister, WebhookRemove};
+use crate::update::withdrawal::{WithdrawCompleteInfo, WithdrawalCancelInfo, WithdrawalRejectInfo};
 
+use self::account::{AccountChangeDecision, AccountChangeId, AccountChangeRecord, AccountChangeUpd};
+use self::audit::OperatorAuditRecord;
 use self::exchange::{
-    ExchangeDecision, ExchangeDecisionType, ExchangeOrder, ExchangeOrderUpd, ExchangeState,
+    ExchangeDecision, ExchangeDecisionType, ExchangeExpire, ExchangeOrder, ExchangeOrderUpd,
+    ExchangeRequestType, ExchangeState,
 };
+use self::settings::{SettingsChangeDecision, SettingsChangeId, SettingsChangeRecord, SettingsChangeUpd};
+use self::transfer::{InternalTransfer, InternalTransferDecision, InternalTransferUpd, TransferId};
 
 use super::update::btc::BtcTxCancel;
+use super::update::eth::EthDepositUpd;
 use super::update::deposit::DepositAddress;
 use super::update::signup::{SignupInfo, UserId};
 use super::update::withdrawal::{
     WithdrawalRequestDecision, WithdrawalRequestDecisionInfo, WithdrawalRequestInfo,
 };
-use super::update::{results::UpdateResult, StateUpdate, UpdateBody};
+use super::update::{legacy_event_id, results::UpdateResult, StateUpdate, UpdateBody};
 use hexstody_api::domain::*;
 use hexstody_api::types::{
-    ConfirmationsConfig, ExchangeFilter, ExchangeOrder as ExchangeApiOrder, ExchangeStatus, Invite,
-    LimitChangeDecisionType, LimitChangeStatus, LimitInfo, LimitSpan, SignatureData,
+    AccountAction, AccountChangeRequest, AccountChangeStatus, AccountStatus,
+    ConfirmationsConfig, ExchangeCurrencyLimit, ExchangeFilter, ExchangeLimits,
+    ExchangeOrder as ExchangeApiOrder, ExchangeStatus, FeeEstimates, FeePolicy, Invite,
+    LimitChangeDecisionType, LimitChangeOpResponse, LimitChangeStatus, LimitInfo,
+    InternalTransfer as InternalTransferApi, OperatorAuditEntry, OperatorAuditFilter, OperatorEvent, SignatureData, ApiKeyScope, TransferStatus,
+    MarginData, SettingsChange, SettingsChangeRequest, SettingsChangeStatus, UserAccount, UserEvent,
     WithdrawalRequestDecisionType,
 };
 
@@ -51,6 +80,8 @@ pub const CONFIRMATIONS_CONFIG: ConfirmationsConfig = ConfirmationsConfig {
     withdraw: 2,
     change_limit: 2,
     exchange: 1,
+    settings: 2,
+    account: 2,
 };
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -65,8 +96,27 @@ pub struct State {
     pub invites: HashMap<Invite, InviteRec>,
     /// Special wallet for exchanges
     pub exchange_state: ExchangeState,
-    /// Map of api keys to user ids
-    pub api_keys: HashMap<ApiKey, UserId>
+    /// Map of api key hashes to the keys
+    #[serde(deserialize_with = "api_key::deserialize_api_keys")]
+    pub api_keys: HashMap<String, ApiKeyRecord>,
+    /// Withdrawal fee policies set by operators. Currencies without a policy charge the network fee
+    #[serde(default)]
+    pub fee_policies: HashMap<Currency, FeePolicy>,
+    /// Exchange margins in whole percents confirmed by operators. Pairs without a margin have zero margin
+    #[serde(default)]
+    pub margins: Vec<MarginData>,
+    /// Estimates of withdrawal transactions confirmed by operators
+    #[serde(default)]
+    pub fee_estimates: FeeEstimates,
+    /// Changes of margins and fee estimates proposed by operators
+    #[serde(default)]
+    pub settings_changes: HashMap<SettingsChangeId, SettingsChangeRecord>,
+    /// Freezes, unfreezes and closures of accounts proposed by operators
+    #[serde(default)]
+    pub account_changes: HashMap<AccountChangeId, AccountChangeRecord>,
+    /// Operators' signed requests, oldest first
+    #[serde(default)]
+    pub audit_log: Vec<OperatorAuditRecord>,
 }
 
 #[derive(Error, Debug, PartialEq)]
@@ -89,6 +139,10 @@ pub enum StateUpdateErr {
     WithdrawalRequestAlreadyConfirmed(WithdrawalRequestId),
     #[error("Withdrawal request {0} is already rejected")]
     WithdrawalRequestAlreadyRejected(WithdrawalRequestId),
+    #[error("Withdrawal request {0} is cancelled by the user")]
+    WithdrawalRequestCancelled(WithdrawalRequestId),
+    #[error("Withdrawal request {0} can't be cancelled anymore")]
+    WithdrawalRequestNotCancellable(WithdrawalRequestId),
     #[error("{0} is already enabled")]
     TokenAlreadyEnabled(Erc20Token),
     #[error("{0} is already disabled")]
@@ -111,6 +165,8 @@ pub enum StateUpdateErr {
     LimitAlreadyRejected,
     #[error("The spending is over the limit")]
     LimitOverflow,
+    #[error("Aggregate limit must be set in fiat")]
+    AggregateLimitNotFiat,
     #[error("User {0} doesn't have enough of currency {1}")]
     InsufficientFunds(UserId, Currency),
     #[error("User {0} doesn't have outstanding exchange request for {1}")]
@@ -121,8 +177,80 @@ pub enum StateUpdateErr {
     ExchangeAlreadyConfirmed,
     #[error("Exchange request already rejected")]
     ExchangeAlreadyRejected,
+    #[error("Exchange request {0} has expired")]
+    ExchangeExpired(Uuid),
+    #[error("Exchange request {0} is not due to expire")]
+    ExchangeNotExpired(Uuid),
     #[error("Unknown currency: {0}")]
     UnknownCurrency(String),
+    #[error("Webhook {0} already exists")]
+    WebhookAlreadyExists(Uuid),
+    #[error("Webhook {0} is not found")]
+    WebhookNotFound(Uuid),
+    #[error("API key {0} already exists")]
+    ApiKeyAlreadyExists(Uuid),
+    #[error("API key {0} is not found")]
+    ApiKeyNotFound(Uuid),
+    #[error("User {0} already has TOTP enabled")]
+    TotpAlreadyEnabled(UserId),
+    #[error("User {0} doesn't have TOTP enabled")]
+    TotpNotEnabled(UserId),
+    #[error("Recovery code of {0} is not found or already used")]
+    TotpRecoveryCodeNotFound(UserId),
+    #[error("WebAuthn credential {0} is already registered")]
+    WebauthnCredentialAlreadyExists(String),
+    #[error("WebAuthn credential {0} is not found")]
+    WebauthnCredentialNotFound(String),
+    #[error("User {0} can't transfer to themselves")]
+    SelfTransfer(UserId),
+    #[error("Transfer {0} already exists")]
+    TransferAlreadyExists(TransferId),
+    #[error("Transfer {0} is not found")]
+    TransferNotFound(TransferId),
+    #[error("Transfer {0} already signed by the operator")]
+    TransferAlreadySigned(TransferId),
+    #[error("Transfer {0} is already completed")]
+    TransferAlreadyCompleted(TransferId),
+    #[error("Transfer {0} is already rejected")]
+    TransferAlreadyRejected(TransferId),
+    #[error("Settings change {0} already exists")]
+    SettingsChangeAlreadyExists(SettingsChangeId),
+    #[error("Settings change {0} is not found")]
+    SettingsChangeNotFound(SettingsChangeId),
+    #[error("Settings change {0} differs from the one signed by the operator")]
+    SettingsChangeMismatch(SettingsChangeId),
+    #[error("Settings change {0} already signed by the operator")]
+    SettingsChangeAlreadySigned(SettingsChangeId),
+    #[error("Settings change {0} is already completed")]
+    SettingsChangeAlreadyCompleted(SettingsChangeId),
+    #[error("Settings change {0} is already rejected")]
+    SettingsChangeAlreadyRejected(SettingsChangeId),
+    #[error("Account change {0} already exists")]
+    AccountChangeAlreadyExists(AccountChangeId),
+    #[error("Account change {0} is not found")]
+    AccountChangeNotFound(AccountChangeId),
+    #[error("Account change {0} differs from the one signed by the operator")]
+    AccountChangeMismatch(AccountChangeId),
+    #[error("Account change {0} already signed by the operator")]
+    AccountChangeAlreadySigned(AccountChangeId),
+    #[error("Account change {0} is already completed")]
+    AccountChangeAlreadyCompleted(AccountChangeId),
+    #[error("Account change {0} is already rejected")]
+    AccountChangeAlreadyRejected(AccountChangeId),
+    #[error("Account of {0} already has a pending change")]
+    AccountChangePending(UserId),
+    #[error("Can't {1} the account of {0} which is {2}")]
+    AccountChangeNotAllowed(UserId, AccountAction, AccountStatus),
+    #[error("Account of {0} has pending deposits, withdrawals, exchanges or transfers")]
+    AccountHasPendingOperations(UserId),
+    #[error("Account of {0} is {1}")]
+    AccountNotActive(UserId, AccountStatus),
+    #[error("User {0} already saved address {1}")]
+    AddressAlreadySaved(UserId, CurrencyAddress),
+    #[error("Address book entry {0} is not found")]
+    AddressBookEntryNotFound(Uuid),
+    #[error("User {0} is not allowed to withdraw to {1}")]
+    WithdrawalAddressNotAllowed(UserId, CurrencyAddress),
 }
 
 impl HasUserInfo<UserInfo> for State{
@@ -132,8 +260,27 @@ impl HasUserInfo<UserInfo> for State{
 }
 
 impl HasAuth for State {
-    fn get_user_id_by_api_key(&self, api_key: ApiKey) -> Option<String> {
-        self.api_keys.get(&api_key).cloned()
+    fn get_user_id_by_api_key(&self, api_key: &ApiKey, scope: ApiKeyScope) -> Result<(Uuid, String), AuthError> {
+        let now = Utc::now().naive_utc();
+        let record = self.api_keys.get(&api_key.hash()).ok_or(AuthError::AuthRequired)?;
+        if record.is_expired(now) {
+            return Err(AuthError::ApiKeyExpired);
+        }
+        if !record.is_ip_allowed(api_key.client_ip) {
+            return Err(AuthError::ApiKeyIpDenied);
+        }
+        if !record.has_scope(scope) {
+            return Err(AuthError::ApiKeyScopeDenied);
+        }
+        Ok((record.id, record.user.clone()))
+    }
+
+    fn check_user_access(&self, user_id: &str) -> Result<(), AuthError> {
+        match self.users.get(user_id).map(|u| u.status) {
+            Some(AccountStatus::Frozen) => Err(AuthError::AccountFrozen),
+            Some(AccountStatus::Closed) => Err(AuthError::AccountClosed),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -145,8 +292,89 @@ impl State {
             btc_state: BtcState::new(network.btc()),
             invites: HashMap::new(),
             exchange_state: ExchangeState::new(),
-            api_keys: HashMap::new()
+            api_keys: HashMap::new(),
+            fee_policies: HashMap::new(),
+            margins: vec![],
+            fee_estimates: FeeEstimates::new(),
+            settings_changes: HashMap::new(),
+            account_changes: HashMap::new(),
+            audit_log: vec![],
+        }
+    }
+
+    /// Withdrawal fee policy of the currency
+    pub fn fee_policy(&self, currency: &Currency) -> FeePolicy {
+        self.fee_policies.get(currency).cloned().unwrap_or_default()
+    }
+
+    /// Exchange margin of the pair in whole percents
+    pub fn margin(&self, from: &Currency, to: &Currency) -> Rate {
+        self.margins
+            .iter()
+            .find(|m| m.currency_from == *from && m.currency_to == *to)
+            .map_or(Rate::ZERO, |m| m.margin)
+    }
+
+    /// Settings changes proposed by operators, newest first
+    pub fn get_settings_changes(&self) -> Vec<SettingsChangeRequest> {
+        let mut changes: Vec<SettingsChangeRecord> = self.settings_changes.values().cloned().collect();
+        changes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        changes.into_iter().map(|c| c.into()).collect()
+    }
+
+    /// Account changes proposed by operators, newest first
+    pub fn get_account_changes(&self) -> Vec<AccountChangeRequest> {
+        let mut changes: Vec<AccountChangeRecord> = self.account_changes.values().cloned().collect();
+        changes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        changes.into_iter().map(|c| c.into()).collect()
+    }
+
+    /// Statuses of all users' accounts ordered by user ID
+    pub fn get_user_accounts(&self) -> Vec<UserAccount> {
+        let mut accounts: Vec<UserAccount> = self
+            .users
+            .values()
+            .map(|u| UserAccount {
+                user: u.username.clone(),
+                created_at: u.created_at,
+                status: u.status,
+            })
+            .collect();
+        accounts.sort_by(|a, b| a.user.cmp(&b.user));
+        accounts
+    }
+
+    /// Whether the user's account has a change waiting for operators' decision
+    pub fn has_pending_account_change(&self, user: &UserId) -> bool {
+        self.account_changes.values().any(|c| c.user == *user && c.is_pending())
+    }
+
+    /// Check that the action can be applied to the user's account now.
+    /// Accounts with pending withdrawals, exchanges or transfers can't be closed,
+    /// as the funds of rejected ones would return to the closed account.
+    /// Unconfirmed deposits must be finalized first, so the whole balance is swept.
+    pub fn check_account_change(&self, user: &UserId, action: AccountAction) -> Result<(), StateUpdateErr> {
+        let uinfo = self
+            .users
+            .get(user)
+            .ok_or_else(|| StateUpdateErr::UserNotFound(user.clone()))?;
+        if action.next_status(uinfo.status).is_none() {
+            return Err(StateUpdateErr::AccountChangeNotAllowed(user.clone(), action, uinfo.status));
+        }
+        if action == AccountAction::Close && uinfo.has_pending_operations() {
+            return Err(StateUpdateErr::AccountHasPendingOperations(user.clone()));
         }
+        Ok(())
+    }
+
+    /// Operators' audit log entries matching the filter, newest first
+    pub fn operator_audit(&self, filter: &OperatorAuditFilter) -> Vec<OperatorAuditEntry> {
+        self.audit_log
+            .iter()
+            .rev()
+            .filter(|rec| rec.matches(filter))
+            .map(|rec| rec.into())
+            .collect()
     }
 
     /// Find user by attached deposit address
@@ -192,11 +420,15 @@ impl State {
             }
             UpdateBody::Snapshot(snaphsot) => {
                 *self = snaphsot;
+                self.users
+                    .values_mut()
+                    .flat_map(|u| u.currencies.values_mut())
+                    .for_each(|cinfo| cinfo.upgrade_legacy_limit());
                 self.last_changed = update.created;
                 Ok(None)
             }
             UpdateBody::CreateWithdrawalRequest(withdrawal_request) => {
-                let res = self.with_new_withdrawal_request(withdrawal_request)?;
+                let res = self.with_new_withdrawal_request(update.created, withdrawal_request)?;
                 self.last_changed = update.created;
                 info!("Res: {:?}", res);
                 Ok(res)
@@ -216,6 +448,11 @@ impl State {
                 self.last_changed = update.created;
                 Ok(None)
             }
+            UpdateBody::CancelWithdrawalRequest(cancel_info) => {
+                self.cancel_withdrawal_request(cancel_info)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
             UpdateBody::DepositAddress(dep_address) => {
                 self.with_deposit_address(dep_address)?;
                 self.last_changed = update.created;
@@ -239,6 +476,21 @@ impl State {
                 self.last_changed = update.created;
                 Ok(None)
             }
+            UpdateBody::EthDeposit(upd) => {
+                self.with_eth_deposit(upd)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::InternalTransfer(req) => {
+                self.add_internal_transfer(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::InternalTransferDecision(req) => {
+                self.apply_transfer_decision(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
             UpdateBody::UpdateTokens(token_update) => {
                 self.update_tokens(token_update)?;
                 self.last_changed = update.created;
@@ -250,7 +502,7 @@ impl State {
                 Ok(None)
             }
             UpdateBody::LimitsChangeRequest(req) => {
-                self.insert_limits_req(req)?;
+                self.insert_limits_req(update.created, req)?;
                 self.last_changed = update.created;
                 Ok(None)
             }
@@ -264,8 +516,14 @@ impl State {
                 self.last_changed = update.created;
                 Ok(None)
             }
-            UpdateBody::ClearLimits(span) => {
-                self.clear_limits(span)?;
+            UpdateBody::SetAggregateLimits(req) => {
+                self.set_aggregate_limits(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            // Legacy: limits are applied over rolling windows now, periodic resets are
+            // replayed as no-ops and the update worker refuses new ones, see `UpdateBody::is_legacy`
+            UpdateBody::ClearLimits(_) => {
                 self.last_changed = update.created;
                 Ok(None)
             }
@@ -295,13 +553,61 @@ impl State {
                 Ok(None)
             }
             UpdateBody::ExchangeDecision(req) => {
-                let b = self.apply_exchange_decision(&req)?;
+                let b = self.apply_exchange_decision(&req, update.created)?;
                 if b {
                     self.add_incoming_exchange(req)?;
                 }
                 self.last_changed = update.created;
                 Ok(None)
             }
+            UpdateBody::ExchangeExpired(req) => {
+                self.expire_exchange(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::SetExchangeLimits(limits) => {
+                self.exchange_state.limits = limits;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::SetFeePolicy(req) => {
+                self.fee_policies.insert(req.currency, req.policy);
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::SettingsChangeRequest(req) => {
+                self.insert_settings_change(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::SettingsChangeDecision(req) => {
+                self.apply_settings_decision(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::AccountChangeRequest(req) => {
+                self.insert_account_change(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::AccountChangeDecision(req) => {
+                self.apply_account_decision(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::OperatorAction(action) => {
+                self.audit_log.push(OperatorAuditRecord {
+                    time: update.created,
+                    action,
+                });
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::ExchangeAutoExecuted(req) => {
+                self.auto_execute_exchange(req, update.created)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
             UpdateBody::ExchangeAddress(req) => {
                 self.set_exchange_address(req)?;
                 self.last_changed = update.created;
@@ -312,6 +618,76 @@ impl State {
                 self.last_changed = update.created;
                 Ok(None)
             },
+            UpdateBody::RegisterWebhook(req) => {
+                self.register_webhook(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::RemoveWebhook(req) => {
+                self.remove_webhook(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::CreateApiKey(req) => {
+                self.create_api_key(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::RevokeApiKey(req) => {
+                self.revoke_api_key(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::TotpEnroll(req) => {
+                self.totp_enroll(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::TotpDisable(req) => {
+                self.totp_disable(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::TotpRecoveryCodes(req) => {
+                self.set_totp_recovery_codes(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::TotpRecoveryCodeUsed(req) => {
+                self.use_totp_recovery_code(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::WebauthnCredentialAdd(req) => {
+                self.add_webauthn_credential(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::WebauthnCredentialRemove(req) => {
+                self.remove_webauthn_credential(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::WebauthnCredentialUse(req) => {
+                self.use_webauthn_credential(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::AddressBookAdd(req) => {
+                self.add_address_book_entry(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::AddressBookRemove(req) => {
+                self.remove_address_book_entry(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
+            UpdateBody::SetWithdrawalAllowlist(req) => {
+                self.set_withdrawal_allowlist(req)?;
+                self.last_changed = update.created;
+                Ok(None)
+            }
         }
     }
 
@@ -338,21 +714,44 @@ impl State {
     /// Apply new withdrawal request update
     fn with_new_withdrawal_request(
         &mut self,
+        timestamp: NaiveDateTime,
         withdrawal_request_info: WithdrawalRequestInfo,
     ) -> Result<Option<UpdateResult>, StateUpdateErr> {
-        let withdrawal_request: WithdrawalRequest =
-            (chrono::offset::Utc::now(), withdrawal_request_info.clone()).into();
+        let created_at = withdrawal_request_info.created_at.unwrap_or(timestamp);
+        let withdrawal_request: WithdrawalRequest = (
+            DateTime::<Utc>::from_utc(created_at, Utc),
+            withdrawal_request_info.clone(),
+        )
+            .into();
         info!("withdrawal_request: {:?}", withdrawal_request);
         if let Some(user) = self.users.get_mut(&withdrawal_request.user) {
+            if !user.is_active() {
+                return Err(StateUpdateErr::AccountNotActive(withdrawal_request.user, user.status));
+            }
+            if !user.can_withdraw_to(&withdrawal_request.address, created_at) {
+                return Err(StateUpdateErr::WithdrawalAddressNotAllowed(
+                    withdrawal_request.user,
+                    withdrawal_request.address,
+                ));
+            }
             let currency = withdrawal_request.address.currency();
+            let rates = &withdrawal_request_info.rates;
+            let fits_aggregate =
+                user.fits_aggregate_limits(&currency, withdrawal_request.amount, rates, created_at);
             if let Some(cur_info) = user.currencies.get_mut(&currency) {
                 match withdrawal_request.request_type {
                     WithdrawalRequestType::UnderLimit => {
-                        if cur_info.limit_info.limit.amount
-                            < cur_info.limit_info.spent + withdrawal_request.amount
+                        if !fits_aggregate
+                            || !cur_info.fits_limits(withdrawal_request.amount, rates, created_at)
                         {
                             return Err(StateUpdateErr::LimitOverflow);
                         } else {
+                            cur_info.add_limit_spend(LimitSpend {
+                                id: withdrawal_request_info.id,
+                                amount: withdrawal_request.amount,
+                                spent_at: created_at,
+                                rates: rates.clone(),
+                            });
                             cur_info
                                 .withdrawal_requests
                                 .insert(withdrawal_request_info.id, withdrawal_request.clone());
@@ -451,6 +850,11 @@ impl State {
                     withdrawal_request.id,
                 ))
             }
+            WithdrawalRequestStatus::Cancelled { .. } => {
+                return Err(StateUpdateErr::WithdrawalRequestCancelled(
+                    withdrawal_request.id,
+                ))
+            }
             WithdrawalRequestStatus::InProgress {
                 confirmations_minus_rejections: n,
             } => match withdrawal_request_decision.decision_type {
@@ -564,6 +968,20 @@ impl State {
         }
     }
 
+    /// Record deposit to ETH or ERC20 account of the user
+    fn with_eth_deposit(&mut self, upd: EthDepositUpd) -> Result<(), StateUpdateErr> {
+        let user = self
+            .users
+            .get_mut(&upd.user)
+            .ok_or_else(|| StateUpdateErr::UserNotFound(upd.user.clone()))?;
+        let cinfo = user
+            .currencies
+            .get_mut(&upd.currency)
+            .ok_or_else(|| StateUpdateErr::UserMissingCurrency(upd.user.clone(), upd.currency.clone()))?;
+        cinfo.update_eth_tx(&upd.tx);
+        Ok(())
+    }
+
     /// Apply cancel of BTC transaction
     fn with_btc_tx_cancel(&mut self, tx: BtcTxCancel) -> Result<(), StateUpdateErr> {
         let address = CurrencyAddress::BTC(BtcAddress {
@@ -650,6 +1068,310 @@ impl State {
         None
     }
 
+    /// Collect events for users that are touched by the update.
+    /// Should be called on the state with the update already applied.
+    pub fn user_events(&self, body: &UpdateBody) -> Vec<(UserId, UserEvent)> {
+        let mut events = self.account_events(body);
+        let mut touched: Vec<(UserId, Currency)> = vec![];
+        for (user, event) in events.iter() {
+            for currency in event.balance_currencies() {
+                let key = (user.clone(), currency);
+                if !touched.contains(&key) {
+                    touched.push(key);
+                }
+            }
+        }
+        for (user, currency) in touched {
+            let cinfo = self
+                .users
+                .get(&user)
+                .and_then(|u| u.currencies.get(&currency));
+            if let Some(cinfo) = cinfo {
+                let event = UserEvent::BalanceChanged {
+                    currency,
+                    balance: cinfo.balance(),
+                    finalized_balance: cinfo.finalized_balance(),
+                };
+                events.push((user, event));
+            }
+        }
+        events
+    }
+
+    /// Events that are directly caused by the update
+    fn account_events(&self, body: &UpdateBody) -> Vec<(UserId, UserEvent)> {
+        match body {
+            UpdateBody::UpdateBtcTx(tx) if tx.amount >= 0 => {
+                let address = CurrencyAddress::BTC(BtcAddress {
+                    addr: tx.address.to_string(),
+                });
+                self.find_user_address(&address)
+                    .map(|user| {
+                        let event = UserEvent::Deposit {
+                            currency: Currency::BTC,
+                            txid: tx.txid.into(),
+                            address,
+                            amount: tx.amount as u64,
+                            confirmations: tx.confirmations,
+                        };
+                        vec![(user, event)]
+                    })
+                    .unwrap_or_default()
+            }
+            UpdateBody::EthDeposit(upd) => {
+                let address = match &upd.currency {
+                    Currency::ERC20(token) => CurrencyAddress::ERC20(Erc20 {
+                        token: token.clone(),
+                        account: EthAccount { account: upd.tx.addr.clone() },
+                    }),
+                    _ => CurrencyAddress::ETH(EthAccount { account: upd.tx.addr.clone() }),
+                };
+                let event = UserEvent::Deposit {
+                    currency: upd.currency.clone(),
+                    txid: CurrencyTxId::ETH(ETHTxid { txid: upd.tx.hash.clone() }),
+                    address,
+                    amount: upd.tx.value.max(0) as u64,
+                    confirmations: upd.tx.confirmations.max(0) as u64,
+                };
+                vec![(upd.user.clone(), event)]
+            }
+            UpdateBody::InternalTransfer(InternalTransferUpd {
+                id, from, currency, ..
+            })
+            | UpdateBody::InternalTransferDecision(InternalTransferDecision {
+                id, from, currency, ..
+            }) => self
+                .get_transfer(from, currency, id)
+                .map(|t| {
+                    let event = UserEvent::Transfer {
+                        id: t.id,
+                        currency: t.currency.clone(),
+                        amount: t.amount,
+                        from: t.from.clone(),
+                        to: t.to.clone(),
+                        status: t.status,
+                    };
+                    // The recipient sees the transfer only when the funds are moved
+                    if t.is_completed() {
+                        vec![(t.from.clone(), event.clone()), (t.to.clone(), event)]
+                    } else {
+                        vec![(t.from.clone(), event)]
+                    }
+                })
+                .unwrap_or_default(),
+            UpdateBody::CreateWithdrawalRequest(WithdrawalRequestInfo { id, .. })
+            | UpdateBody::WithdrawalRequestDecision(WithdrawalRequestDecisionInfo {
+                request_id: id,
+                ..
+            })
+            | UpdateBody::CancelWithdrawalRequest(WithdrawalCancelInfo { id, .. }) => self
+                .get_withdrawal_request(*id)
+                .map(|req| {
+                    let event = UserEvent::WithdrawalStatus {
+                        id: req.id,
+                        currency: req.address.currency(),
+                        amount: req.amount,
+                        status: req.status.into(),
+                    };
+                    vec![(req.user, event)]
+                })
+                .unwrap_or_default(),
+            UpdateBody::WithdrawalRequestComplete(info) => self
+                .get_withdrawal_request(info.id)
+                .map(|req| {
+                    let event = UserEvent::WithdrawalCompleted {
+                        id: req.id,
+                        currency: req.address.currency(),
+                        amount: req.amount,
+                        txid: info.txid.clone(),
+                    };
+                    vec![(req.user, event)]
+                })
+                .unwrap_or_default(),
+            UpdateBody::WithdrawalRequestNodeRejected(info) => self
+                .get_withdrawal_request(info.id)
+                .map(|req| {
+                    let event = UserEvent::WithdrawalRejected {
+                        id: req.id,
+                        currency: req.address.currency(),
+                        amount: req.amount,
+                        reason: info.reason.clone(),
+                    };
+                    vec![(req.user, event)]
+                })
+                .unwrap_or_default(),
+            UpdateBody::LimitChangeDecision(decision) => {
+                let status = self
+                    .users
+                    .get(&decision.user)
+                    .and_then(|user| user.limit_change_requests.get(&decision.currency))
+                    .filter(|req| req.id == decision.id)
+                    .map(|req| req.status.clone())
+                    // Finalized requests are removed from the user's info
+                    .unwrap_or_else(|| match decision.decision_type {
+                        LimitChangeDecisionType::Confirm => LimitChangeStatus::Completed,
+                        LimitChangeDecisionType::Reject => LimitChangeStatus::Rejected,
+                    });
+                let event = UserEvent::LimitChangeDecision {
+                    id: decision.id,
+                    currency: decision.currency.clone(),
+                    limit: decision.requested_limit.clone(),
+                    status,
+                };
+                vec![(decision.user.clone(), event)]
+            }
+            UpdateBody::ExchangeAutoExecuted(order) => self
+                .users
+                .get(&order.user)
+                .and_then(|user| user.currencies.get(&order.currency_from))
+                .and_then(|cinfo| cinfo.exchange_requests.get(&order.id))
+                .map(|order| {
+                    // Orders over the limits are left for operators
+                    let event = if order.is_auto_executed() {
+                        UserEvent::ExchangeDecision {
+                            id: order.id,
+                            currency_from: order.currency_from.clone(),
+                            currency_to: order.currency_to.clone(),
+                            amount_from: order.amount_from,
+                            amount_to: order.amount_to,
+                            status: order.status,
+                        }
+                    } else {
+                        UserEvent::ExchangeCreated {
+                            id: order.id,
+                            currency_from: order.currency_from.clone(),
+                            currency_to: order.currency_to.clone(),
+                            amount_from: order.amount_from,
+                            amount_to: order.amount_to,
+                        }
+                    };
+                    vec![(order.user.clone(), event)]
+                })
+                .unwrap_or_default(),
+            UpdateBody::ExchangeRequest(order) => {
+                let event = UserEvent::ExchangeCreated {
+                    id: order.id,
+                    currency_from: order.currency_from.clone(),
+                    currency_to: order.currency_to.clone(),
+                    amount_from: order.amount_from,
+                    amount_to: order.amount_to,
+                };
+                vec![(order.user.clone(), event)]
+            }
+            UpdateBody::ExchangeDecision(ExchangeDecision {
+                user,
+                id,
+                currency_from,
+                ..
+            })
+            | UpdateBody::ExchangeExpired(ExchangeExpire {
+                user,
+                id,
+                currency_from,
+            }) => self
+                .users
+                .get(user)
+                .and_then(|user| user.currencies.get(currency_from))
+                .and_then(|cinfo| cinfo.exchange_requests.get(id))
+                .map(|order| {
+                    let event = UserEvent::ExchangeDecision {
+                        id: order.id,
+                        currency_from: order.currency_from.clone(),
+                        currency_to: order.currency_to.clone(),
+                        amount_from: order.amount_from,
+                        amount_to: order.amount_to,
+                        status: order.status,
+                    };
+                    vec![(order.user.clone(), event)]
+                })
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// Collect events for operators caused by the update.
+    /// Should be called on the state with the update already applied.
+    pub fn operator_events(&self, body: &UpdateBody) -> Vec<OperatorEvent> {
+        let withdrawal_event = |id: &WithdrawalRequestId| {
+            self.get_withdrawal_request(*id)
+                .map(|req| OperatorEvent::WithdrawalRequest(req.into()))
+        };
+        let event = match body {
+            UpdateBody::CreateWithdrawalRequest(info) => withdrawal_event(&info.id),
+            UpdateBody::WithdrawalRequestDecision(info) => withdrawal_event(&info.request_id),
+            UpdateBody::WithdrawalRequestComplete(info) => withdrawal_event(&info.id),
+            UpdateBody::WithdrawalRequestNodeRejected(info) => withdrawal_event(&info.id),
+            UpdateBody::CancelWithdrawalRequest(info) => withdrawal_event(&info.id),
+            UpdateBody::LimitsChangeRequest(req) => self.users.get(&req.user).and_then(|uinfo| {
+                uinfo
+                    .limit_change_requests
+                    .get(&req.currency)
+                    .map(|req| OperatorEvent::LimitChangeRequest(uinfo.limit_change_op_response(req)))
+            }),
+            UpdateBody::LimitChangeDecision(decision) => self.users.get(&decision.user).map(|uinfo| {
+                let response = match uinfo.limit_change_requests.get(&decision.currency) {
+                    Some(req) if req.id == decision.id => uinfo.limit_change_op_response(req),
+                    // Finalized requests are removed from the user's info
+                    _ => LimitChangeOpResponse {
+                        id: decision.id,
+                        user: decision.user.clone(),
+                        created_at: decision.created_at.clone(),
+                        currency: decision.currency.clone(),
+                        current_limit: uinfo
+                            .currencies
+                            .get(&decision.currency)
+                            .map(|cinfo| cinfo.current_limit(&decision.requested_limit.span))
+                            .unwrap_or_else(|| LimitInfo::default().limit),
+                        requested_limit: decision.requested_limit.clone(),
+                        status: match decision.decision_type {
+                            LimitChangeDecisionType::Confirm => LimitChangeStatus::Completed,
+                            LimitChangeDecisionType::Reject => LimitChangeStatus::Rejected,
+                        },
+                    },
+                };
+                OperatorEvent::LimitChangeRequest(response)
+            }),
+            UpdateBody::ExchangeRequest(ExchangeOrderUpd {
+                user,
+                id,
+                currency_from,
+                ..
+            })
+            | UpdateBody::ExchangeAutoExecuted(ExchangeOrderUpd {
+                user,
+                id,
+                currency_from,
+                ..
+            })
+            | UpdateBody::ExchangeDecision(ExchangeDecision {
+                user,
+                id,
+                currency_from,
+                ..
+            })
+            | UpdateBody::ExchangeExpired(ExchangeExpire {
+                user,
+                id,
+                currency_from,
+            }) => self
+                .users
+                .get(user)
+                .and_then(|uinfo| uinfo.currencies.get(currency_from))
+                .and_then(|cinfo| cinfo.exchange_requests.get(id))
+                .map(|order| OperatorEvent::ExchangeOrder(order.clone().into())),
+            UpdateBody::InternalTransfer(InternalTransferUpd {
+                id, from, currency, ..
+            })
+            | UpdateBody::InternalTransferDecision(InternalTransferDecision {
+                id, from, currency, ..
+            }) => self
+                .get_transfer(from, currency, id)
+                .map(|t| OperatorEvent::InternalTransfer(t.clone().into())),
+            _ => None,
+        };
+        event.into_iter().collect()
+    }
+
     pub fn set_withdrawal_request_completed(
         &mut self,
         withdrawal_confirmed_info: WithdrawCompleteInfo,
@@ -687,11 +1409,37 @@ impl State {
                         req.status = stat;
                     }
                 }
+                info.remove_limit_spend(&reject_info.id);
             }
         }
         Ok(())
     }
 
+    /// Cancel the request on behalf of its owner. The amount is returned to the balance
+    /// and doesn't count against the limits anymore.
+    fn cancel_withdrawal_request(&mut self, info: WithdrawalCancelInfo) -> Result<(), StateUpdateErr> {
+        let user = self
+            .users
+            .get_mut(&info.user)
+            .ok_or_else(|| StateUpdateErr::UserNotFound(info.user.clone()))?;
+        let cur_info = user
+            .currencies
+            .get_mut(&info.currency)
+            .ok_or_else(|| StateUpdateErr::UserMissingCurrency(info.user.clone(), info.currency.clone()))?;
+        let req = cur_info
+            .withdrawal_requests
+            .get_mut(&info.id)
+            .ok_or_else(|| StateUpdateErr::WithdrawalRequestNotFound(info.user.clone(), info.id))?;
+        if !req.is_cancellable() {
+            return Err(StateUpdateErr::WithdrawalRequestNotCancellable(info.id));
+        }
+        req.status = WithdrawalRequestStatus::Cancelled {
+            cancelled_at: info.cancelled_at,
+        };
+        cur_info.remove_limit_spend(&info.id);
+        Ok(())
+    }
+
     pub fn update_tokens(&mut self, token_update: TokenUpdate) -> Result<(), StateUpdateErr> {
         let TokenUpdate {
             user,
@@ -740,16 +1488,24 @@ impl State {
         }
     }
 
-    fn insert_limits_req(&mut self, req: LimitChangeUpd) -> Result<(), StateUpdateErr> {
+    fn insert_limits_req(
+        &mut self,
+        timestamp: NaiveDateTime,
+        req: LimitChangeUpd,
+    ) -> Result<(), StateUpdateErr> {
         let cur = req.currency.clone();
         match self.users.get_mut(&req.user) {
             Some(usr) => match usr.currencies.get_mut(&cur) {
                 None => Err(StateUpdateErr::UserMissingCurrency(req.user, cur)),
                 Some(_) => {
+                    let id = req.id.unwrap_or_else(|| {
+                        legacy_event_id(timestamp, &format!("limit:{}:{}", req.user, cur))
+                    });
+                    let created_at = req.created_at.unwrap_or(timestamp);
                     let data = LimitChangeData {
-                        id: Uuid::new_v4(),
+                        id,
                         user: usr.username.clone(),
-                        created_at: chrono::offset::Utc::now().to_string(),
+                        created_at: DateTime::<Utc>::from_utc(created_at, Utc).to_string(),
                         status: LimitChangeStatus::InProgress {
                             confirmations_minus_rejections: 0,
                         },
@@ -815,10 +1571,7 @@ impl State {
                                     if n == CONFIRMATIONS_CONFIG.change_limit - m {
                                         req.status = LimitChangeStatus::Completed;
                                         if let Some(cinfo) = usr.currencies.get_mut(&lcd.currency) {
-                                            cinfo.limit_info = LimitInfo {
-                                                limit: lcd.requested_limit.clone(),
-                                                spent: 0,
-                                            }
+                                            cinfo.set_limit(lcd.requested_limit.clone());
                                         }
                                         let _ = usr.limit_change_requests.remove(&lcd.currency);
                                     } else {
@@ -863,74 +1616,285 @@ impl State {
         }
     }
 
-    fn clear_limits(&mut self, span: LimitSpan) -> Result<(), StateUpdateErr> {
-        for (_, uinfo) in self.users.iter_mut() {
-            for curinfo in uinfo.currencies.values_mut() {
-                if curinfo.limit_info.limit.span == span {
-                    curinfo.limit_info.spent = 0;
-                }
+
+    fn insert_settings_change(
+        &mut self,
+        req: SettingsChangeUpd,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        if self.settings_changes.contains_key(&req.id) {
+            return Err(StateUpdateErr::SettingsChangeAlreadyExists(req.id));
+        }
+        if let SettingsChange::AggregateLimits(limits) = &req.change {
+            if limits.limits.iter().any(|l| l.fiat.is_none()) {
+                return Err(StateUpdateErr::AggregateLimitNotFiat);
+            }
+            if !self.users.contains_key(&limits.user) {
+                return Err(StateUpdateErr::UserNotFound(limits.user.clone()));
             }
         }
+        let change = SettingsChangeRecord::proposed(req, now);
+        if change.required_confirmations() <= 1 {
+            self.complete_settings_change(change);
+        } else {
+            self.settings_changes.insert(change.id, change);
+        }
         Ok(())
     }
 
-    fn set_language(&mut self, req: SetLanguage) -> Result<(), StateUpdateErr> {
-        match self.users.get_mut(&req.user) {
-            Some(uinfo) => {
-                uinfo.config.language = req.language;
-                Ok(())
+    fn complete_settings_change(&mut self, mut change: SettingsChangeRecord) {
+        change.status = SettingsChangeStatus::Completed;
+        match &change.change {
+            SettingsChange::Margin(margin) => {
+                self.margins.retain(|m| {
+                    m.currency_from != margin.currency_from || m.currency_to != margin.currency_to
+                });
+                self.margins.push(margin.clone());
+            }
+            SettingsChange::FeeEstimates(estimates) => self.fee_estimates = estimates.clone(),
+            SettingsChange::FeePolicy(req) => {
+                self.fee_policies.insert(req.currency.clone(), req.policy.clone());
+            }
+            SettingsChange::ExchangeLimits(limits) => self.exchange_state.limits = limits.clone(),
+            // The user is checked when the change is proposed
+            SettingsChange::AggregateLimits(req) => {
+                if let Some(uinfo) = self.users.get_mut(&req.user) {
+                    uinfo.aggregate_limits = req.limits.clone();
+                }
             }
-            None => Err(StateUpdateErr::UserNotFound(req.user)),
         }
+        self.settings_changes.insert(change.id, change);
     }
 
-    fn update_user_config(&mut self, req: ConfigUpdateData) -> Result<(), StateUpdateErr> {
-        let ConfigUpdateData {
-            user,
-            email,
-            phone,
-            tg_name,
-        } = req;
-        match self.users.get_mut(&user) {
-            Some(uinfo) => {
-                if let Some(email) = email {
-                    uinfo.config.email = email.ok();
+    fn apply_settings_decision(&mut self, req: SettingsChangeDecision) -> Result<(), StateUpdateErr> {
+        let change = self
+            .settings_changes
+            .get_mut(&req.id)
+            .ok_or(StateUpdateErr::SettingsChangeNotFound(req.id))?;
+        if change.change != req.change {
+            return Err(StateUpdateErr::SettingsChangeMismatch(req.id));
+        }
+        let sdata = SignatureData {
+            signature: req.signature,
+            nonce: req.nonce,
+            public_key: req.public_key,
+        };
+        let n = match change.status {
+            SettingsChangeStatus::Completed => return Err(StateUpdateErr::SettingsChangeAlreadyCompleted(req.id)),
+            SettingsChangeStatus::Rejected => return Err(StateUpdateErr::SettingsChangeAlreadyRejected(req.id)),
+            SettingsChangeStatus::InProgress {
+                confirmations_minus_rejections: n,
+            } => n,
+        };
+        match req.decision {
+            WithdrawalRequestDecisionType::Confirm => {
+                if change.has_confirmed(req.public_key) {
+                    return Err(StateUpdateErr::SettingsChangeAlreadySigned(req.id));
                 }
-                if let Some(phone) = phone {
-                    uinfo.config.phone = phone.ok();
+                let m = if change.has_rejected(req.public_key) { 2 } else { 1 };
+                change.rejections.retain(|x| x.public_key != req.public_key);
+                change.confirmations.push(sdata);
+                if n + m >= change.required_confirmations() {
+                    let change = change.clone();
+                    self.complete_settings_change(change);
+                } else {
+                    change.status = SettingsChangeStatus::InProgress {
+                        confirmations_minus_rejections: n + m,
+                    };
                 }
-                if let Some(tg_name) = tg_name {
-                    uinfo.config.tg_name = tg_name.ok();
+            }
+            WithdrawalRequestDecisionType::Reject => {
+                if change.has_rejected(req.public_key) {
+                    return Err(StateUpdateErr::SettingsChangeAlreadySigned(req.id));
+                }
+                let m = if change.has_confirmed(req.public_key) { 2 } else { 1 };
+                change.confirmations.retain(|x| x.public_key != req.public_key);
+                change.rejections.push(sdata);
+                if n - m <= -change.required_confirmations() {
+                    change.status = SettingsChangeStatus::Rejected;
+                } else {
+                    change.status = SettingsChangeStatus::InProgress {
+                        confirmations_minus_rejections: n - m,
+                    };
                 }
-                Ok(())
             }
-            None => Err(StateUpdateErr::UserNotFound(user)),
         }
+        Ok(())
     }
 
-    fn change_password(&mut self, req: PasswordChangeUpd) -> Result<(), StateUpdateErr> {
-        let PasswordChangeUpd { user, new_password } = req;
-        match self.users.get_mut(&user) {
-            Some(uinfo) => {
-                uinfo.auth = SignupAuth::Password(new_password);
-                Ok(())
-            }
-            None => Err(StateUpdateErr::UserNotFound(user)),
+    fn insert_account_change(
+        &mut self,
+        req: AccountChangeUpd,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        if self.account_changes.contains_key(&req.id) {
+            return Err(StateUpdateErr::AccountChangeAlreadyExists(req.id));
+        }
+        if self.has_pending_account_change(&req.user) {
+            return Err(StateUpdateErr::AccountChangePending(req.user));
         }
+        self.check_account_change(&req.user, req.action)?;
+        let change = AccountChangeRecord::proposed(req, now);
+        if CONFIRMATIONS_CONFIG.account <= 1 {
+            self.complete_account_change(change, now);
+        } else {
+            self.account_changes.insert(change.id, change);
+        }
+        Ok(())
     }
 
-    fn set_user_public_key(&mut self, req: SetPublicKey) -> Result<(), StateUpdateErr> {
-        let SetPublicKey { user, public_key } = req;
-        match self.users.get_mut(&user) {
-            Some(uinfo) => {
-                uinfo.public_key = public_key;
-                Ok(())
+    /// Apply the confirmed change. The change must be checked with `check_account_change` before
+    fn complete_account_change(&mut self, mut change: AccountChangeRecord, now: NaiveDateTime) {
+        change.status = AccountChangeStatus::Completed;
+        if let Some(uinfo) = self.users.get_mut(&change.user) {
+            if let Some(status) = change.action.next_status(uinfo.status) {
+                uinfo.status = status;
+            }
+            if change.action == AccountAction::Close {
+                uinfo.sweep_balances(now);
             }
-            None => Err(StateUpdateErr::UserNotFound(user)),
         }
+        self.account_changes.insert(change.id, change);
     }
 
-    fn add_exchange_request(&mut self, req: ExchangeOrderUpd) -> Result<(), StateUpdateErr> {
+    fn apply_account_decision(
+        &mut self,
+        req: AccountChangeDecision,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        let change = self
+            .account_changes
+            .get(&req.id)
+            .ok_or(StateUpdateErr::AccountChangeNotFound(req.id))?;
+        if change.user != req.user || change.action != req.action {
+            return Err(StateUpdateErr::AccountChangeMismatch(req.id));
+        }
+        let n = match change.status {
+            AccountChangeStatus::Completed => return Err(StateUpdateErr::AccountChangeAlreadyCompleted(req.id)),
+            AccountChangeStatus::Rejected => return Err(StateUpdateErr::AccountChangeAlreadyRejected(req.id)),
+            AccountChangeStatus::InProgress {
+                confirmations_minus_rejections: n,
+            } => n,
+        };
+        let sdata = SignatureData {
+            signature: req.signature,
+            nonce: req.nonce,
+            public_key: req.public_key,
+        };
+        match req.decision {
+            WithdrawalRequestDecisionType::Confirm => {
+                if change.has_confirmed(req.public_key) {
+                    return Err(StateUpdateErr::AccountChangeAlreadySigned(req.id));
+                }
+                let m = if change.has_rejected(req.public_key) { 2 } else { 1 };
+                let completes = n + m >= CONFIRMATIONS_CONFIG.account;
+                // The account could have changed since the proposal, e.g. a new withdrawal was requested
+                if completes {
+                    self.check_account_change(&req.user, req.action)?;
+                }
+                let mut change = change.clone();
+                change.rejections.retain(|x| x.public_key != req.public_key);
+                change.confirmations.push(sdata);
+                if completes {
+                    self.complete_account_change(change, now);
+                } else {
+                    change.status = AccountChangeStatus::InProgress {
+                        confirmations_minus_rejections: n + m,
+                    };
+                    self.account_changes.insert(change.id, change);
+                }
+            }
+            WithdrawalRequestDecisionType::Reject => {
+                if change.has_rejected(req.public_key) {
+                    return Err(StateUpdateErr::AccountChangeAlreadySigned(req.id));
+                }
+                let m = if change.has_confirmed(req.public_key) { 2 } else { 1 };
+                let mut change = change.clone();
+                change.confirmations.retain(|x| x.public_key != req.public_key);
+                change.rejections.push(sdata);
+                if n - m <= -CONFIRMATIONS_CONFIG.account {
+                    change.status = AccountChangeStatus::Rejected;
+                } else {
+                    change.status = AccountChangeStatus::InProgress {
+                        confirmations_minus_rejections: n - m,
+                    };
+                }
+                self.account_changes.insert(change.id, change);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_aggregate_limits(&mut self, req: AggregateLimitsUpd) -> Result<(), StateUpdateErr> {
+        if req.limits.iter().any(|l| l.fiat.is_none()) {
+            return Err(StateUpdateErr::AggregateLimitNotFiat);
+        }
+        match self.users.get_mut(&req.user) {
+            Some(uinfo) => {
+                uinfo.aggregate_limits = req.limits;
+                Ok(())
+            }
+            None => Err(StateUpdateErr::UserNotFound(req.user)),
+        }
+    }
+
+    fn set_language(&mut self, req: SetLanguage) -> Result<(), StateUpdateErr> {
+        match self.users.get_mut(&req.user) {
+            Some(uinfo) => {
+                uinfo.config.language = req.language;
+                Ok(())
+            }
+            None => Err(StateUpdateErr::UserNotFound(req.user)),
+        }
+    }
+
+    fn update_user_config(&mut self, req: ConfigUpdateData) -> Result<(), StateUpdateErr> {
+        let ConfigUpdateData {
+            user,
+            email,
+            phone,
+            tg_name,
+        } = req;
+        match self.users.get_mut(&user) {
+            Some(uinfo) => {
+                if let Some(email) = email {
+                    uinfo.config.email = email.ok();
+                }
+                if let Some(phone) = phone {
+                    uinfo.config.phone = phone.ok();
+                }
+                if let Some(tg_name) = tg_name {
+                    uinfo.config.tg_name = tg_name.ok();
+                }
+                Ok(())
+            }
+            None => Err(StateUpdateErr::UserNotFound(user)),
+        }
+    }
+
+    fn change_password(&mut self, req: PasswordChangeUpd) -> Result<(), StateUpdateErr> {
+        let PasswordChangeUpd { user, new_password } = req;
+        match self.users.get_mut(&user) {
+            Some(uinfo) => {
+                uinfo.auth = SignupAuth::Password(new_password);
+                Ok(())
+            }
+            None => Err(StateUpdateErr::UserNotFound(user)),
+        }
+    }
+
+    fn set_user_public_key(&mut self, req: SetPublicKey) -> Result<(), StateUpdateErr> {
+        let SetPublicKey { user, public_key } = req;
+        match self.users.get_mut(&user) {
+            Some(uinfo) => {
+                uinfo.public_key = public_key;
+                Ok(())
+            }
+            None => Err(StateUpdateErr::UserNotFound(user)),
+        }
+    }
+
+    fn add_exchange_request(&mut self, req: ExchangeOrderUpd) -> Result<(), StateUpdateErr> {
         let ExchangeOrderUpd {
             user,
             currency_from,
@@ -939,11 +1903,17 @@ impl State {
             amount_to,
             id,
             created_at,
+            rate,
+            margin,
+            expires_at,
         } = req;
         let uinfo = self
             .users
             .get_mut(&user)
             .ok_or(StateUpdateErr::UserNotFound(user.clone()))?;
+        if !uinfo.is_active() {
+            return Err(StateUpdateErr::AccountNotActive(user, uinfo.status));
+        }
         let cinfo =
             uinfo
                 .currencies
@@ -952,7 +1922,7 @@ impl State {
                     user.clone(),
                     currency_from.clone(),
                 ))?;
-        if cinfo.balance() < amount_from {
+        if cinfo.finalized_balance() < amount_from {
             return Err(StateUpdateErr::InsufficientFunds(
                 user.clone(),
                 currency_from.clone(),
@@ -971,6 +1941,11 @@ impl State {
             confirmations: Vec::new(),
             rejections: Vec::new(),
             created_at,
+            rate,
+            margin,
+            expires_at,
+            request_type: ExchangeRequestType::OverLimit,
+            executed_at: None,
         };
         cinfo.exchange_requests.insert(id, order);
         Ok(())
@@ -978,7 +1953,11 @@ impl State {
 
     /// Returns true if we need to update the balance for the target currency
     /// This is required since we can't borrow user info as mutable twice
-    fn apply_exchange_decision(&mut self, req: &ExchangeDecision) -> Result<bool, StateUpdateErr> {
+    fn apply_exchange_decision(
+        &mut self,
+        req: &ExchangeDecision,
+        now: NaiveDateTime,
+    ) -> Result<bool, StateUpdateErr> {
         let user = req.user.clone();
         let currency_from = req.currency_from.clone();
         let uinfo = self
@@ -1006,9 +1985,14 @@ impl State {
             nonce: req.nonce,
             public_key: req.public_key,
         };
+        // The quote is no longer valid, even if the expiry is not recorded yet
+        if exchange.is_overdue(now) {
+            return Err(StateUpdateErr::ExchangeExpired(req.id));
+        }
         match exchange.status {
             ExchangeStatus::Completed => Err(StateUpdateErr::ExchangeAlreadyConfirmed),
             ExchangeStatus::Rejected => Err(StateUpdateErr::ExchangeAlreadyRejected),
+            ExchangeStatus::Expired => Err(StateUpdateErr::ExchangeExpired(req.id)),
             ExchangeStatus::InProgress {
                 confirmations_minus_rejections: n,
             } => match req.decision {
@@ -1029,6 +2013,7 @@ impl State {
                         exchange.confirmations.push(sdata);
                         if n == CONFIRMATIONS_CONFIG.exchange - m {
                             exchange.status = ExchangeStatus::Completed;
+                            exchange.executed_at = Some(now);
                             self.exchange_state
                                 .process_order(exchange.into_exchange_upd());
                             Ok(true)
@@ -1069,6 +2054,120 @@ impl State {
         }
     }
 
+    /// Whether the order fits the exchange limits and the exchange account can pay it out
+    pub fn can_auto_execute(&self, order: &ExchangeOrderUpd, now: NaiveDateTime) -> bool {
+        let ExchangeLimits {
+            pairs,
+            user_daily,
+            daily,
+        } = &self.exchange_state.limits;
+        let fits_pair = pairs.iter().any(|l| {
+            l.currency_from == order.currency_from
+                && l.currency_to == order.currency_to
+                && order.amount_from <= l.amount
+        });
+        let day_ago = now - chrono::Duration::days(1);
+        let exchanged = |uinfo: &UserInfo| {
+            uinfo
+                .currencies
+                .get(&order.currency_from)
+                .map_or(0, |cinfo| cinfo.auto_exchanged_since(day_ago))
+        };
+        let fits = |limits: &[ExchangeCurrencyLimit], exchanged: u64| {
+            limits
+                .iter()
+                .filter(|l| l.currency == order.currency_from)
+                .all(|l| exchanged + order.amount_from <= l.amount)
+        };
+        let user_exchanged = self.users.get(&order.user).map_or(0, exchanged);
+        let total_exchanged = self.users.values().map(exchanged).sum();
+        fits_pair
+            && fits(user_daily, user_exchanged)
+            && fits(daily, total_exchanged)
+            && self
+                .exchange_state
+                .has_inventory(&order.currency_to, order.amount_to)
+    }
+
+    /// Execute the order without operators' decision. If the limits were exhausted
+    /// since the order was accepted, it is left for operators as a usual order.
+    fn auto_execute_exchange(
+        &mut self,
+        req: ExchangeOrderUpd,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        if !self.can_auto_execute(&req, now) {
+            return self.add_exchange_request(req);
+        }
+        let uinfo = self
+            .users
+            .get(&req.user)
+            .ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        if !uinfo.currencies.contains_key(&req.currency_to) {
+            return Err(StateUpdateErr::UserMissingCurrency(
+                req.user.clone(),
+                req.currency_to.clone(),
+            ));
+        }
+        self.add_exchange_request(req.clone())?;
+        let uinfo = self
+            .users
+            .get_mut(&req.user)
+            .ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        if let Some(order) = uinfo
+            .currencies
+            .get_mut(&req.currency_from)
+            .and_then(|cinfo| cinfo.exchange_requests.get_mut(&req.id))
+        {
+            order.status = ExchangeStatus::Completed;
+            order.request_type = ExchangeRequestType::UnderLimit;
+            order.executed_at = Some(now);
+            self.exchange_state.process_order(order.into_exchange_upd());
+        }
+        if let Some(cinfo) = uinfo.currencies.get_mut(&req.currency_to) {
+            cinfo.incoming_exchange_requests.insert(req.id, req.amount_to);
+        }
+        Ok(())
+    }
+
+    /// Release funds held by the pending order that passed its expiry time
+    fn expire_exchange(
+        &mut self,
+        req: ExchangeExpire,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        let uinfo = self
+            .users
+            .get_mut(&req.user)
+            .ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        let cinfo = uinfo.currencies.get_mut(&req.currency_from).ok_or(
+            StateUpdateErr::UserMissingCurrency(req.user.clone(), req.currency_from.clone()),
+        )?;
+        let exchange = cinfo.exchange_requests.get_mut(&req.id).ok_or(
+            StateUpdateErr::UserMissingExchange(req.user.clone(), req.currency_from.clone()),
+        )?;
+        if !exchange.is_overdue(now) {
+            return Err(StateUpdateErr::ExchangeNotExpired(req.id));
+        }
+        exchange.status = ExchangeStatus::Expired;
+        Ok(())
+    }
+
+    /// Pending exchange orders that passed their expiry time
+    pub fn overdue_exchanges(&self, now: NaiveDateTime) -> Vec<ExchangeExpire> {
+        self.users
+            .values()
+            .flat_map(|uinfo| uinfo.currencies.values())
+            .flat_map(|cinfo| cinfo.exchange_requests.values())
+            .filter(|order| order.is_overdue(now))
+            .map(|order| ExchangeExpire {
+                user: order.user.clone(),
+                id: order.id,
+                currency_from: order.currency_from.clone(),
+            })
+            .collect()
+    }
+
     fn add_incoming_exchange(&mut self, req: ExchangeDecision) -> Result<(), StateUpdateErr> {
         let uinfo = self
             .users
@@ -1090,6 +2189,181 @@ impl State {
             .collect()
     }
 
+    /// Move funds to another user. Transfers under the sender's limits are completed at once,
+    /// others hold the funds until operators decide
+    fn add_internal_transfer(
+        &mut self,
+        req: InternalTransferUpd,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        if req.from == req.to {
+            return Err(StateUpdateErr::SelfTransfer(req.from));
+        }
+        let recipient = self
+            .users
+            .get(&req.to)
+            .ok_or(StateUpdateErr::UserNotFound(req.to.clone()))?;
+        // Frozen accounts still receive funds, closed ones don't
+        if recipient.status == AccountStatus::Closed {
+            return Err(StateUpdateErr::AccountNotActive(req.to.clone(), recipient.status));
+        }
+        if !recipient.currencies.contains_key(&req.currency) {
+            return Err(StateUpdateErr::UserMissingCurrency(
+                req.to.clone(),
+                req.currency.clone(),
+            ));
+        }
+        let uinfo = self
+            .users
+            .get_mut(&req.from)
+            .ok_or(StateUpdateErr::UserNotFound(req.from.clone()))?;
+        if !uinfo.is_active() {
+            return Err(StateUpdateErr::AccountNotActive(req.from.clone(), uinfo.status));
+        }
+        let fits_aggregate =
+            uinfo.fits_aggregate_limits(&req.currency, req.amount, &req.rates, req.created_at);
+        let cinfo = uinfo.currencies.get_mut(&req.currency).ok_or(
+            StateUpdateErr::UserMissingCurrency(req.from.clone(), req.currency.clone()),
+        )?;
+        if cinfo.outgoing_transfers.contains_key(&req.id) {
+            return Err(StateUpdateErr::TransferAlreadyExists(req.id));
+        }
+        if cinfo.finalized_balance() < req.amount {
+            return Err(StateUpdateErr::InsufficientFunds(
+                req.from.clone(),
+                req.currency.clone(),
+            ));
+        }
+        let mut transfer = InternalTransfer::from(req.clone());
+        if req.request_type == WithdrawalRequestType::UnderLimit {
+            if !fits_aggregate || !cinfo.fits_limits(req.amount, &req.rates, req.created_at) {
+                return Err(StateUpdateErr::LimitOverflow);
+            }
+            cinfo.add_limit_spend(LimitSpend {
+                id: req.id,
+                amount: req.amount,
+                spent_at: req.created_at,
+                rates: req.rates,
+            });
+            transfer.status = TransferStatus::Completed;
+            transfer.executed_at = Some(now);
+        }
+        cinfo.outgoing_transfers.insert(transfer.id, transfer.clone());
+        if transfer.is_completed() {
+            self.credit_transfer(transfer);
+        }
+        Ok(())
+    }
+
+    /// Recipient's currency is checked before the transfer is completed
+    fn credit_transfer(&mut self, transfer: InternalTransfer) {
+        if let Some(cinfo) = self
+            .users
+            .get_mut(&transfer.to)
+            .and_then(|uinfo| uinfo.currencies.get_mut(&transfer.currency))
+        {
+            cinfo.incoming_transfers.insert(transfer.id, transfer);
+        }
+    }
+
+    /// Transfer as it is stored with the sender
+    pub fn get_transfer(
+        &self,
+        from: &UserId,
+        currency: &Currency,
+        id: &TransferId,
+    ) -> Option<&InternalTransfer> {
+        self.users
+            .get(from)
+            .and_then(|uinfo| uinfo.currencies.get(currency))
+            .and_then(|cinfo| cinfo.outgoing_transfers.get(id))
+    }
+
+    /// Count operator's decision on the transfer over the limits. The same quorum as for withdrawals is required
+    fn apply_transfer_decision(
+        &mut self,
+        req: InternalTransferDecision,
+        now: NaiveDateTime,
+    ) -> Result<(), StateUpdateErr> {
+        let to = self
+            .get_transfer(&req.from, &req.currency, &req.id)
+            .map(|t| t.to.clone())
+            .ok_or(StateUpdateErr::TransferNotFound(req.id))?;
+        let recipient_ready = self
+            .users
+            .get(&to)
+            .map_or(false, |uinfo| uinfo.currencies.contains_key(&req.currency));
+        let transfer = self
+            .users
+            .get_mut(&req.from)
+            .and_then(|uinfo| uinfo.currencies.get_mut(&req.currency))
+            .and_then(|cinfo| cinfo.outgoing_transfers.get_mut(&req.id))
+            .ok_or(StateUpdateErr::TransferNotFound(req.id))?;
+        let sdata = SignatureData {
+            signature: req.signature,
+            nonce: req.nonce,
+            public_key: req.public_key,
+        };
+        let n = match transfer.status {
+            TransferStatus::Completed => return Err(StateUpdateErr::TransferAlreadyCompleted(req.id)),
+            TransferStatus::Rejected => return Err(StateUpdateErr::TransferAlreadyRejected(req.id)),
+            TransferStatus::InProgress {
+                confirmations_minus_rejections: n,
+            } => n,
+        };
+        match req.decision {
+            WithdrawalRequestDecisionType::Confirm => {
+                if transfer.has_confirmed(req.public_key) {
+                    return Err(StateUpdateErr::TransferAlreadySigned(req.id));
+                }
+                if !recipient_ready {
+                    return Err(StateUpdateErr::UserMissingCurrency(to, req.currency));
+                }
+                let m = if transfer.has_rejected(req.public_key) { 2 } else { 1 };
+                transfer.rejections.retain(|x| x.public_key != req.public_key);
+                transfer.confirmations.push(sdata);
+                if n + m >= CONFIRMATIONS_CONFIG.withdraw {
+                    transfer.status = TransferStatus::Completed;
+                    transfer.executed_at = Some(now);
+                    let transfer = transfer.clone();
+                    self.credit_transfer(transfer);
+                } else {
+                    transfer.status = TransferStatus::InProgress {
+                        confirmations_minus_rejections: n + m,
+                    };
+                }
+            }
+            WithdrawalRequestDecisionType::Reject => {
+                if transfer.has_rejected(req.public_key) {
+                    return Err(StateUpdateErr::TransferAlreadySigned(req.id));
+                }
+                let m = if transfer.has_confirmed(req.public_key) { 2 } else { 1 };
+                transfer.confirmations.retain(|x| x.public_key != req.public_key);
+                transfer.rejections.push(sdata);
+                if n - m <= -CONFIRMATIONS_CONFIG.withdraw {
+                    transfer.status = TransferStatus::Rejected;
+                } else {
+                    transfer.status = TransferStatus::InProgress {
+                        confirmations_minus_rejections: n - m,
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Transfers of all users, newest first
+    pub fn get_internal_transfers(&self) -> Vec<InternalTransferApi> {
+        let mut transfers: Vec<&InternalTransfer> = self
+            .users
+            .values()
+            .flat_map(|uinfo| uinfo.currencies.values())
+            .flat_map(|cinfo| cinfo.outgoing_transfers.values())
+            .collect();
+        transfers.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        transfers.into_iter().map(|t| t.clone().into()).collect()
+    }
+
     fn set_exchange_address(&mut self, req: CurrencyAddress) -> Result<(), StateUpdateErr> {
         self.exchange_state
             .addresses
@@ -1104,6 +2378,157 @@ impl State {
         cinfo.unit = req.unit;
         Ok(())
     }
+
+    fn register_webhook(&mut self, req: WebhookRegister) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        if uinfo.webhooks.contains_key(&req.id) {
+            return Err(StateUpdateErr::WebhookAlreadyExists(req.id));
+        }
+        uinfo.webhooks.insert(req.id, req.into());
+        Ok(())
+    }
+
+    fn remove_webhook(&mut self, req: WebhookRemove) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        uinfo.webhooks.remove(&req.id).ok_or(StateUpdateErr::WebhookNotFound(req.id))?;
+        Ok(())
+    }
+
+    fn create_api_key(&mut self, req: ApiKeyCreate) -> Result<(), StateUpdateErr> {
+        if !self.users.contains_key(&req.user) {
+            return Err(StateUpdateErr::UserNotFound(req.user));
+        }
+        if self.api_keys.values().any(|k| k.id == req.id) || self.api_keys.contains_key(&req.key_hash) {
+            return Err(StateUpdateErr::ApiKeyAlreadyExists(req.id));
+        }
+        self.api_keys.insert(req.key_hash.clone(), req.into());
+        Ok(())
+    }
+
+    fn revoke_api_key(&mut self, req: ApiKeyRevoke) -> Result<(), StateUpdateErr> {
+        let before = self.api_keys.len();
+        self.api_keys.retain(|_, k| !(k.id == req.id && k.user == req.user));
+        if self.api_keys.len() == before {
+            return Err(StateUpdateErr::ApiKeyNotFound(req.id));
+        }
+        Ok(())
+    }
+
+    fn totp_enroll(&mut self, req: TotpEnroll) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        if uinfo.totp.is_some() {
+            return Err(StateUpdateErr::TotpAlreadyEnabled(req.user));
+        }
+        uinfo.totp = Some(req.into());
+        Ok(())
+    }
+
+    fn totp_disable(&mut self, req: TotpDisable) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        uinfo.totp.take().ok_or(StateUpdateErr::TotpNotEnabled(req.user))?;
+        Ok(())
+    }
+
+    fn set_totp_recovery_codes(&mut self, req: TotpRecoveryCodes) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        let totp = uinfo.totp.as_mut().ok_or(StateUpdateErr::TotpNotEnabled(req.user))?;
+        totp.recovery_codes = req.recovery_codes;
+        Ok(())
+    }
+
+    fn use_totp_recovery_code(&mut self, req: TotpRecoveryCodeUsed) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        let totp = uinfo.totp.as_mut().ok_or(StateUpdateErr::TotpNotEnabled(req.user.clone()))?;
+        let before = totp.recovery_codes.len();
+        totp.recovery_codes.retain(|h| *h != req.code_hash);
+        if totp.recovery_codes.len() == before {
+            return Err(StateUpdateErr::TotpRecoveryCodeNotFound(req.user));
+        }
+        Ok(())
+    }
+
+    fn add_webauthn_credential(&mut self, req: WebauthnCredentialAdd) -> Result<(), StateUpdateErr> {
+        // Credential IDs are globally unique, the same authenticator can't be shared
+        if self.find_webauthn_credential(&req.credential_id).is_some() {
+            return Err(StateUpdateErr::WebauthnCredentialAlreadyExists(req.credential_id));
+        }
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        uinfo.webauthn_credentials.insert(req.credential_id.clone(), req.into());
+        Ok(())
+    }
+
+    fn remove_webauthn_credential(&mut self, req: WebauthnCredentialRemove) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        uinfo
+            .webauthn_credentials
+            .remove(&req.credential_id)
+            .ok_or(StateUpdateErr::WebauthnCredentialNotFound(req.credential_id))?;
+        Ok(())
+    }
+
+    fn use_webauthn_credential(&mut self, req: WebauthnCredentialUse) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        let cred = uinfo
+            .webauthn_credentials
+            .get_mut(&req.credential_id)
+            .ok_or(StateUpdateErr::WebauthnCredentialNotFound(req.credential_id))?;
+        cred.sign_count = req.sign_count;
+        cred.last_used = Some(req.used_at);
+        Ok(())
+    }
+
+    fn add_address_book_entry(&mut self, req: AddressBookAdd) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        if uinfo.address_book.values().any(|a| a.address == req.address) {
+            return Err(StateUpdateErr::AddressAlreadySaved(req.user, req.address));
+        }
+        uinfo.address_book.insert(req.id, req.into());
+        Ok(())
+    }
+
+    fn remove_address_book_entry(&mut self, req: AddressBookRemove) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        uinfo
+            .address_book
+            .remove(&req.id)
+            .ok_or(StateUpdateErr::AddressBookEntryNotFound(req.id))?;
+        Ok(())
+    }
+
+    /// Switching the allow-list on takes effect at once, switching it off at `effective_at`
+    fn set_withdrawal_allowlist(&mut self, req: SetWithdrawalAllowlist) -> Result<(), StateUpdateErr> {
+        let uinfo = self.users.get_mut(&req.user).ok_or(StateUpdateErr::UserNotFound(req.user.clone()))?;
+        if req.enabled {
+            uinfo.allowlist = WithdrawalAllowlist {
+                enabled: true,
+                disabled_at: None,
+            };
+        } else if uinfo.allowlist.enabled {
+            // Repeated requests don't postpone the pending switch off
+            let at = uinfo.allowlist.disabled_at.map_or(req.effective_at, |t| t.min(req.effective_at));
+            uinfo.allowlist.disabled_at = Some(at);
+        }
+        Ok(())
+    }
+
+    /// Find owner and the credential by base64url encoded credential ID
+    pub fn find_webauthn_credential(&self, credential_id: &str) -> Option<(&UserInfo, &WebauthnCredential)> {
+        self.users.values().find_map(|u| {
+            u.webauthn_credentials.get(credential_id).map(|c| (u, c))
+        })
+    }
+
+    /// API keys of the user sorted by creation time
+    pub fn user_api_keys(&self, user: &UserId) -> Vec<ApiKeyRecord> {
+        let mut keys: Vec<ApiKeyRecord> = self
+            .api_keys
+            .values()
+            .filter(|k| &k.user == user)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.created_at);
+        keys
+    }
 }
 
 impl Default for State {
@@ -1125,8 +2550,11 @@ mod tests {
     use super::*;
     use crate::queries::*;
     use crate::update::signup::{SignupAuth, SignupInfo};
+    use crate::update::withdrawal::FiatRates;
     use crate::update::StateUpdate;
+    use chrono::Duration;
     use hexstody_api::domain::{BtcAddress, CurrencyAddress};
+    use hexstody_api::types::{ExchangePairLimit, FeeTier, Limit, LimitSpan};
 
     async fn apply_state_update(
         update: StateUpdate,
@@ -1140,9 +2568,55 @@ mod tests {
         return update.created;
     }
 
-    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "./migrations"))]
-    async fn test_signup_update() {
-        let mut state = State::default();
+    /// Add a signed up user with the given BTC balance to the state.
+    /// The balance comes from an exchange, so it is finalized at once.
+    fn add_user(state: &mut State, name: &str, btc_balance: u64, now: NaiveDateTime) {
+        let invite = Invite {
+            invite: Uuid::new_v4(),
+        };
+        let mut user = UserInfo::new(name, invite, SignupAuth::Lightning, now);
+        if btc_balance > 0 {
+            let btc = user.currencies.get_mut(&Currency::BTC).unwrap();
+            btc.incoming_exchange_requests.insert(Uuid::new_v4(), btc_balance);
+        }
+        state.users.insert(name.to_owned(), user);
+    }
+
+    /// Settings change signed by the single operator
+    fn settings_proposal(id: Uuid, key: &SecretKey, change: SettingsChange) -> UpdateBody {
+        UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+            id,
+            change,
+            url: "test".to_owned(),
+            signature: SigningKey::from(key.clone()).sign(b"test"),
+            nonce: 0,
+            public_key: key.public_key(),
+        })
+    }
+
+    /// Propose the settings change and confirm it by another operator
+    fn change_settings(state: &mut State, change: SettingsChange, now: NaiveDateTime) {
+        let id = Uuid::new_v4();
+        let proposer = SecretKey::random(&mut OsRng);
+        let confirmer = SecretKey::random(&mut OsRng);
+        let propose = settings_proposal(id, &proposer, change.clone());
+        let confirm = SettingsChangeDecision {
+            id,
+            change,
+            url: "test".to_owned(),
+            signature: SigningKey::from(confirmer.clone()).sign(b"test"),
+            nonce: 0,
+            public_key: confirmer.public_key(),
+            decision: WithdrawalRequestDecisionType::Confirm,
+        };
+        for body in [propose, UpdateBody::SettingsChangeDecision(confirm)] {
+            state.apply_update(StateUpdate { created: now, body }).unwrap();
+        }
+    }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "./migrations"))]
+    async fn test_signup_update() {
+        let mut state = State::default();
         let invite = Invite {
             invite: Uuid::new_v4(),
         };
@@ -1211,6 +2685,10 @@ mod tests {
             }),
             amount: 1,
             request_type: WithdrawalRequestType::OverLimit,
+            created_at: Some(Utc::now().naive_utc()),
+            rates: FiatRates::new(),
+            fee_tier: FeeTier::Normal,
+            charged_fee: None,
         };
         let _ = apply_state_update(
             StateUpdate::new(UpdateBody::Signup(signup_info.clone())),
@@ -1250,7 +2728,9 @@ mod tests {
                 },
                 confirmations: vec![],
                 rejections: vec![],
-                request_type: WithdrawalRequestType::OverLimit
+                request_type: WithdrawalRequestType::OverLimit,
+                fee_tier: FeeTier::Normal,
+                charged_fee: None,
             }
         );
     }
@@ -1285,6 +2765,10 @@ mod tests {
             }),
             amount: 1,
             request_type: WithdrawalRequestType::OverLimit,
+            created_at: Some(Utc::now().naive_utc()),
+            rates: FiatRates::new(),
+            fee_tier: FeeTier::Normal,
+            charged_fee: None,
         };
         let _ = apply_state_update(
             StateUpdate::new(UpdateBody::Signup(signup_info.clone())),
@@ -1360,4 +2844,1306 @@ mod tests {
             )]
         );
     }
+
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "./migrations"))]
+    async fn test_api_key_lifecycle() {
+        let mut state = State::default();
+        let invite = Invite {
+            invite: Uuid::new_v4(),
+        };
+        let invite_rec = InviteRec {
+            invite: invite.clone(),
+            invitor: String::new(),
+            label: String::new(),
+        };
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::GenInvite(invite_rec.clone())),
+            &mut state,
+            &pool,
+        )
+        .await;
+        let signup_info = SignupInfo {
+            username: "Alice".to_owned(),
+            invite,
+            auth: SignupAuth::Lightning,
+        };
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::Signup(signup_info.clone())),
+            &mut state,
+            &pool,
+        )
+        .await;
+        let allowed_ip: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let key = "hxs_test_key".to_owned();
+        let create = ApiKeyCreate {
+            id: Uuid::new_v4(),
+            user: signup_info.username.clone(),
+            key_hash: hexstody_auth::types::hash_api_key(&key),
+            prefix: key[..8].to_owned(),
+            label: "bot".to_owned(),
+            scopes: vec![ApiKeyScope::Exchange],
+            allowed_ips: vec![allowed_ip],
+            created_at: Utc::now().naive_utc(),
+            expires_at: None,
+        };
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::CreateApiKey(create.clone())),
+            &mut state,
+            &pool,
+        )
+        .await;
+        // Only the hash is persisted
+        let mut state = query_state(Network::Regtest, &pool).await.unwrap();
+        assert!(!state.api_keys.contains_key(&key));
+
+        let api_key = ApiKey {
+            key: key.clone(),
+            client_ip: Some(allowed_ip),
+        };
+        assert_eq!(
+            state.get_user_id_by_api_key(&api_key, ApiKeyScope::Exchange),
+            Ok((create.id, signup_info.username.clone()))
+        );
+        assert_eq!(
+            state.get_user_id_by_api_key(&api_key, ApiKeyScope::Withdraw),
+            Err(AuthError::ApiKeyScopeDenied)
+        );
+        let other_ip = ApiKey {
+            key: key.clone(),
+            client_ip: Some("10.0.0.2".parse().unwrap()),
+        };
+        assert_eq!(
+            state.get_user_id_by_api_key(&other_ip, ApiKeyScope::ReadOnly),
+            Err(AuthError::ApiKeyIpDenied)
+        );
+
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::RevokeApiKey(ApiKeyRevoke {
+                id: create.id,
+                user: signup_info.username.clone(),
+            })),
+            &mut state,
+            &pool,
+        )
+        .await;
+        assert_eq!(
+            state.get_user_id_by_api_key(&api_key, ApiKeyScope::ReadOnly),
+            Err(AuthError::AuthRequired)
+        );
+    }
+    #[sqlx_database_tester::test(pool(variable = "pool", migrations = "./migrations"))]
+    async fn test_webauthn_credential_lifecycle() {
+        let mut state = State::default();
+        let invite = Invite {
+            invite: Uuid::new_v4(),
+        };
+        let invite_rec = InviteRec {
+            invite: invite.clone(),
+            invitor: String::new(),
+            label: String::new(),
+        };
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::GenInvite(invite_rec.clone())),
+            &mut state,
+            &pool,
+        )
+        .await;
+        let signup_info = SignupInfo {
+            username: "Alice".to_owned(),
+            invite,
+            auth: SignupAuth::Lightning,
+        };
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::Signup(signup_info.clone())),
+            &mut state,
+            &pool,
+        )
+        .await;
+        let add = WebauthnCredentialAdd {
+            user: signup_info.username.clone(),
+            credential_id: "Y3JlZGVudGlhbA".to_owned(),
+            name: "laptop".to_owned(),
+            public_key: SecretKey::random(&mut OsRng).public_key(),
+            sign_count: 0,
+            created_at: Utc::now().naive_utc(),
+        };
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::WebauthnCredentialAdd(add.clone())),
+            &mut state,
+            &pool,
+        )
+        .await;
+        let used_at = Utc::now().naive_utc();
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::WebauthnCredentialUse(WebauthnCredentialUse {
+                user: signup_info.username.clone(),
+                credential_id: add.credential_id.clone(),
+                sign_count: 5,
+                used_at,
+            })),
+            &mut state,
+            &pool,
+        )
+        .await;
+        // Signature counter survives the restart
+        let mut state = query_state(Network::Regtest, &pool).await.unwrap();
+        let (owner, cred) = state.find_webauthn_credential(&add.credential_id).unwrap();
+        assert_eq!(owner.username, signup_info.username);
+        assert_eq!(cred.sign_count, 5);
+        assert_eq!(cred.last_used, Some(used_at));
+        assert_eq!(cred.public_key, add.public_key);
+
+        let _ = apply_state_update(
+            StateUpdate::new(UpdateBody::WebauthnCredentialRemove(WebauthnCredentialRemove {
+                user: signup_info.username.clone(),
+                credential_id: add.credential_id.clone(),
+            })),
+            &mut state,
+            &pool,
+        )
+        .await;
+        assert!(state.find_webauthn_credential(&add.credential_id).is_none());
+    }
+
+    #[test]
+    fn test_rolling_window_limits() {
+        let mut info = UserCurrencyInfo::new(Currency::BTC);
+        info.set_limit(Limit {
+            amount: 100,
+            span: LimitSpan::Day,
+            fiat: None,
+        });
+        info.set_limit(Limit {
+            amount: 150,
+            span: LimitSpan::Month,
+            fiat: None,
+        });
+        assert_eq!(info.limits.len(), 2);
+        let rates = FiatRates::new();
+        let start = NaiveDate::from_ymd(2022, 1, 1).and_hms(23, 59, 0);
+        assert!(info.fits_limits(100, &rates, start));
+        info.add_limit_spend(LimitSpend {
+            id: Uuid::new_v4(),
+            amount: 100,
+            spent_at: start,
+            rates: rates.clone(),
+        });
+        // Midnight doesn't reset the daily limit
+        assert!(!info.fits_limits(1, &rates, start + Duration::minutes(2)));
+        let next_day = start + Duration::days(1);
+        // Daily limit is free again, but the monthly one is still counted
+        assert!(info.fits_limits(50, &rates, next_day));
+        assert!(!info.fits_limits(51, &rates, next_day));
+        assert_eq!(info.limit_info(next_day).limit.span, LimitSpan::Month);
+        assert!(info.fits_limits(100, &rates, start + Duration::days(30)));
+    }
+
+    #[test]
+    fn test_fiat_and_aggregate_limits() {
+        use std::str::FromStr;
+
+        let invite = Invite {
+            invite: Uuid::new_v4(),
+        };
+        let mut user = UserInfo::new("Alice", invite, SignupAuth::Lightning, Utc::now().naive_utc());
+        let day_usd = Limit {
+            amount: 1000,
+            span: LimitSpan::Day,
+            fiat: Some(Fiat::USD),
+        };
+        user.aggregate_limits = vec![day_usd.clone()];
+        let btc = user.currencies.get_mut(&Currency::BTC).unwrap();
+        btc.limits = vec![day_usd];
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        let btc_rates = FiatRates::from([(Fiat::USD, Rate::from_integer(20_000))]);
+        // 0.03 BTC is 600 USD
+        assert!(btc.fits_limits(3_000_000, &btc_rates, now));
+        assert!(!btc.fits_limits(6_000_000, &btc_rates, now));
+        // Fiat limit can't be checked without the rate
+        assert!(!btc.fits_limits(1, &FiatRates::new(), now));
+        btc.add_limit_spend(LimitSpend {
+            id: Uuid::new_v4(),
+            amount: 3_000_000,
+            spent_at: now,
+            rates: btc_rates,
+        });
+        assert_eq!(btc.limit_info(now).spent, 600);
+        // Spends in BTC are counted against the aggregate limit of ETH withdrawals
+        let eth_rates = FiatRates::from([(Fiat::USD, Rate::from_integer(1_000))]);
+        let half_eth = 500_000_000_000_000_000;
+        assert!(!user.fits_aggregate_limits(&Currency::ETH, half_eth, &eth_rates, now));
+        assert!(user.fits_aggregate_limits(&Currency::ETH, half_eth / 2, &eth_rates, now));
+        assert_eq!(user.aggregate_limit_infos(now)[0].spent, 600);
+        // Float rates of old events are read exactly, so replay checks the same values
+        let recorded: FiatRates = serde_json::from_str(r#"{"USD": 20000.1}"#).unwrap();
+        assert_eq!(recorded[&Fiat::USD], Rate::from_str("20000.1").unwrap());
+        let btc = &user.currencies[&Currency::BTC];
+        assert_eq!(btc.fiat_value(3_000_000, recorded[&Fiat::USD]), Rate::from_str("600.003").ok());
+    }
+
+    #[test]
+    fn test_aggregate_limits_quorum() {
+        use hexstody_api::types::AggregateLimitsReq;
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 0, now);
+        let at = |body: UpdateBody| StateUpdate { created: now, body };
+        let operator = SecretKey::random(&mut OsRng);
+        let limits = AggregateLimitsReq {
+            user: "Alice".to_owned(),
+            limits: vec![Limit {
+                amount: 1000,
+                span: LimitSpan::Day,
+                fiat: Some(Fiat::USD),
+            }],
+        };
+        let mut no_fiat = limits.clone();
+        no_fiat.limits[0].fiat = None;
+        assert_eq!(
+            state.apply_update(at(settings_proposal(Uuid::new_v4(), &operator, SettingsChange::AggregateLimits(no_fiat)))),
+            Err(StateUpdateErr::AggregateLimitNotFiat)
+        );
+        // Limits signed by a single operator don't take effect
+        state
+            .apply_update(at(settings_proposal(Uuid::new_v4(), &operator, SettingsChange::AggregateLimits(limits.clone()))))
+            .unwrap();
+        assert!(state.users["Alice"].aggregate_limits.is_empty());
+        change_settings(&mut state, SettingsChange::AggregateLimits(limits.clone()), now);
+        assert_eq!(state.users["Alice"].aggregate_limits, limits.limits);
+    }
+
+    #[test]
+    fn test_exchange_expiry_releases_funds() {
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 1000, now);
+        let order = ExchangeOrderUpd {
+            id: Uuid::new_v4(),
+            user: "Alice".to_owned(),
+            currency_from: Currency::BTC,
+            currency_to: Currency::ETH,
+            amount_from: 600,
+            amount_to: 6000,
+            created_at: now.to_string(),
+            rate: Some(Rate::from_integer(10)),
+            margin: Some(Rate::ZERO),
+            expires_at: Some(now + Duration::minutes(60)),
+        };
+        let at = |created: NaiveDateTime, body: UpdateBody| StateUpdate { created, body };
+        state
+            .apply_update(at(now, UpdateBody::ExchangeRequest(order.clone())))
+            .unwrap();
+        let btc_info = |state: &State| state.users["Alice"].currencies[&Currency::BTC].clone();
+        assert_eq!(btc_info(&state).held_balance(), 600);
+        assert_eq!(btc_info(&state).balance(), 400);
+        // Held funds can't be exchanged twice
+        let second = ExchangeOrderUpd {
+            id: Uuid::new_v4(),
+            ..order.clone()
+        };
+        assert!(state
+            .apply_update(at(now, UpdateBody::ExchangeRequest(second)))
+            .is_err());
+
+        let expire = ExchangeExpire {
+            user: order.user.clone(),
+            id: order.id,
+            currency_from: order.currency_from.clone(),
+        };
+        assert_eq!(
+            state.apply_update(at(now + Duration::minutes(59), UpdateBody::ExchangeExpired(expire.clone()))),
+            Err(StateUpdateErr::ExchangeNotExpired(order.id))
+        );
+        let expiry = now + Duration::minutes(60);
+        assert_eq!(state.overdue_exchanges(expiry), vec![expire.clone()]);
+        // Operators can't confirm the order once the quote is stale
+        let secret_key = SecretKey::random(&mut OsRng);
+        let decision = ExchangeDecision {
+            user: order.user.clone(),
+            id: order.id,
+            currency_from: order.currency_from.clone(),
+            currency_to: order.currency_to.clone(),
+            amount_from: order.amount_from,
+            amount_to: order.amount_to,
+            url: "test".to_owned(),
+            signature: SigningKey::from(secret_key.clone()).sign(b"test"),
+            nonce: 0,
+            public_key: secret_key.public_key(),
+            decision: ExchangeDecisionType::Confirm,
+        };
+        assert_eq!(
+            state.apply_update(at(expiry, UpdateBody::ExchangeDecision(decision))),
+            Err(StateUpdateErr::ExchangeExpired(order.id))
+        );
+        state
+            .apply_update(at(expiry, UpdateBody::ExchangeExpired(expire)))
+            .unwrap();
+        assert_eq!(btc_info(&state).held_balance(), 0);
+        assert_eq!(btc_info(&state).balance(), 1000);
+        assert!(state.overdue_exchanges(expiry).is_empty());
+    }
+
+    #[test]
+    fn test_exchange_auto_execution() {
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 2000, now);
+        state.exchange_state.balances.insert(Currency::ETH, 10_000);
+        let at = |created: NaiveDateTime, body: UpdateBody| StateUpdate { created, body };
+        let limits = ExchangeLimits {
+            pairs: vec![ExchangePairLimit {
+                currency_from: Currency::BTC,
+                currency_to: Currency::ETH,
+                amount: 500,
+            }],
+            user_daily: vec![ExchangeCurrencyLimit {
+                currency: Currency::BTC,
+                amount: 700,
+            }],
+            daily: vec![],
+        };
+        // Limits signed by a single operator don't take effect
+        let operator = SecretKey::random(&mut OsRng);
+        let proposal = settings_proposal(Uuid::new_v4(), &operator, SettingsChange::ExchangeLimits(limits.clone()));
+        state.apply_update(at(now, proposal)).unwrap();
+        assert_eq!(state.exchange_state.limits, ExchangeLimits::default());
+        change_settings(&mut state, SettingsChange::ExchangeLimits(limits.clone()), now);
+        assert_eq!(state.exchange_state.limits, limits);
+        let order = |amount_from: u64| ExchangeOrderUpd {
+            id: Uuid::new_v4(),
+            user: "Alice".to_owned(),
+            currency_from: Currency::BTC,
+            currency_to: Currency::ETH,
+            amount_from,
+            amount_to: amount_from * 10,
+            created_at: now.to_string(),
+            rate: Some(Rate::from_integer(10)),
+            margin: Some(Rate::ZERO),
+            expires_at: Some(now + Duration::minutes(60)),
+        };
+        // Over the pair limit
+        assert!(!state.can_auto_execute(&order(600), now));
+        // Exchange account doesn't have enough ETH
+        assert!(!state.can_auto_execute(
+            &ExchangeOrderUpd {
+                amount_to: 20_000,
+                ..order(400)
+            },
+            now
+        ));
+
+        let first = order(400);
+        assert!(state.can_auto_execute(&first, now));
+        state
+            .apply_update(at(now, UpdateBody::ExchangeAutoExecuted(first.clone())))
+            .unwrap();
+        let user = &state.users["Alice"];
+        assert!(user.currencies[&Currency::BTC].exchange_requests[&first.id].is_auto_executed());
+        assert_eq!(user.currencies[&Currency::BTC].balance(), 1600);
+        assert_eq!(user.currencies[&Currency::ETH].balance(), 4000);
+        assert_eq!(state.exchange_state.balances[&Currency::BTC], 400);
+        assert_eq!(state.exchange_state.balances[&Currency::ETH], 6000);
+
+        // Daily limit of the user is exhausted, the order is left for operators
+        let second = order(400);
+        assert!(!state.can_auto_execute(&second, now));
+        state
+            .apply_update(at(now, UpdateBody::ExchangeAutoExecuted(second.clone())))
+            .unwrap();
+        let pending = &state.users["Alice"].currencies[&Currency::BTC].exchange_requests[&second.id];
+        assert!(pending.is_pending());
+        assert!(!pending.is_auto_executed());
+
+        assert!(state.can_auto_execute(&order(400), now + Duration::days(1)));
+    }
+
+    #[test]
+    fn test_user_statement() {
+        use super::exchange::{ExchangeOrder, ExchangeRequestType};
+        use super::statement::user_statement;
+        use hexstody_api::types::{ExchangeStatus, StatementEntryKind};
+
+        let invite = Invite {
+            invite: Uuid::new_v4(),
+        };
+        let start = NaiveDate::from_ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let mut user = UserInfo::new("Alice", invite, SignupAuth::Lightning, start);
+        let order = |from: Currency, to: Currency, amount_from: u64, amount_to: u64, status: ExchangeStatus, at: NaiveDateTime| {
+            ExchangeOrder {
+                id: Uuid::new_v4(),
+                user: "Alice".to_owned(),
+                currency_from: from,
+                currency_to: to,
+                amount_from,
+                amount_to,
+                status,
+                created_at: at.to_string(),
+                confirmations: vec![],
+                rejections: vec![],
+                rate: None,
+                margin: None,
+                expires_at: None,
+                request_type: ExchangeRequestType::OverLimit,
+                executed_at: Some(at),
+            }
+        };
+        let day = |d: u32| NaiveDate::from_ymd(2022, 1, d).and_hms(12, 0, 0);
+        let pending = ExchangeStatus::InProgress { confirmations_minus_rejections: 0 };
+        for o in [
+            order(Currency::usdt_erc20(), Currency::BTC, 10_000, 1000, ExchangeStatus::Completed, day(1)),
+            order(Currency::BTC, Currency::ETH, 100, 1000, ExchangeStatus::Completed, day(10)),
+            order(Currency::BTC, Currency::ETH, 50, 500, pending, day(11)),
+            order(Currency::BTC, Currency::ETH, 70, 700, ExchangeStatus::Rejected, day(12)),
+            order(Currency::BTC, Currency::ETH, 30, 300, ExchangeStatus::Completed, day(25)),
+        ] {
+            user.currencies
+                .get_mut(&o.currency_from)
+                .unwrap()
+                .exchange_requests
+                .insert(o.id, o);
+        }
+
+        let statement = user_statement(&user, day(5), day(20));
+        let btc = statement.currencies.iter().find(|c| c.currency == Currency::BTC).unwrap();
+        assert_eq!(btc.opening_balance, 1000);
+        assert_eq!(btc.closing_balance, 850);
+        assert_eq!(
+            btc.entries.iter().map(|e| (e.kind, e.debit, e.balance)).collect::<Vec<_>>(),
+            vec![(StatementEntryKind::ExchangeOut, 100, 900), (StatementEntryKind::ExchangeOut, 50, 850)]
+        );
+        let eth = statement.currencies.iter().find(|c| c.currency == Currency::ETH).unwrap();
+        assert_eq!(eth.opening_balance, 0);
+        assert_eq!(eth.entries.len(), 1);
+        assert_eq!(eth.entries[0].kind, StatementEntryKind::ExchangeIn);
+        assert_eq!(eth.closing_balance, 1000);
+
+        let csv = statement.to_csv();
+        assert!(csv.starts_with("time,currency,type,credit,debit,balance,reference\n"));
+        assert!(csv.contains(&format!("{},BTC,closing_balance,,,850,", day(20))));
+    }
+
+    #[test]
+    fn test_user_history_page() {
+        use super::exchange::{ExchangeOrder, ExchangeRequestType};
+        use super::history::{history_page, user_history};
+        use super::transaction::EthTransaction;
+        use hexstody_api::types::{
+            ExchangeStatus, HistoryCursor, HistoryFilter, HistoryItem, HistoryItemStatus, HistoryItemType,
+        };
+
+        let invite = Invite {
+            invite: Uuid::new_v4(),
+        };
+        let start = NaiveDate::from_ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let mut user = UserInfo::new("Alice", invite, SignupAuth::Lightning, start);
+        let day = |d: u32| NaiveDate::from_ymd(2022, 1, d).and_hms(12, 0, 0);
+        let deposit = |confirmations: i64| EthTransaction {
+            blockNumber: "1".to_owned(),
+            timeStamp: day(2).timestamp().to_string(),
+            hash: "0xdeposit".to_owned(),
+            from: "0xsender".to_owned(),
+            to: "0xalice".to_owned(),
+            value: 5000,
+            tokenName: "".to_owned(),
+            gas: "21000".to_owned(),
+            gasPrice: "1".to_owned(),
+            contractAddress: "".to_owned(),
+            confirmations,
+            addr: "0xalice".to_owned(),
+        };
+        let eth = user.currencies.get_mut(&Currency::ETH).unwrap();
+        eth.update_eth_tx(&deposit(1));
+        eth.update_eth_tx(&deposit(10));
+        assert_eq!(eth.transactions.len(), 1);
+
+        let pending = ExchangeStatus::InProgress { confirmations_minus_rejections: 0 };
+        for (d, status) in [(3, ExchangeStatus::Completed), (4, pending), (5, ExchangeStatus::Rejected)] {
+            let o = ExchangeOrder {
+                id: Uuid::new_v4(),
+                user: "Alice".to_owned(),
+                currency_from: Currency::ETH,
+                currency_to: Currency::BTC,
+                amount_from: 1000,
+                amount_to: 10,
+                status,
+                created_at: day(d).to_string(),
+                confirmations: vec![],
+                rejections: vec![],
+                rate: None,
+                margin: None,
+                expires_at: None,
+                request_type: ExchangeRequestType::OverLimit,
+                executed_at: Some(day(d)),
+            };
+            user.currencies
+                .get_mut(&Currency::ETH)
+                .unwrap()
+                .exchange_requests
+                .insert(o.id, o);
+        }
+
+        let all = user_history(&user);
+        assert_eq!(all.len(), 4);
+        assert!(all.windows(2).all(|w| w[0].cursor() > w[1].cursor()));
+        assert!(matches!(all.last(), Some(HistoryItem::Deposit(d)) if d.number_of_confirmations == 10));
+
+        let (page1, next) = history_page(&user, &HistoryFilter::default(), None, 3);
+        assert_eq!(page1.len(), 3);
+        let next: HistoryCursor = next.unwrap().to_string().parse().unwrap();
+        let (page2, last) = history_page(&user, &HistoryFilter::default(), Some(&next), 3);
+        assert_eq!(page2.len(), 1);
+        assert_eq!(page2[0].id(), all[3].id());
+        assert_eq!(last, None);
+
+        let filter = HistoryFilter {
+            currency: Some(Currency::BTC),
+            item_type: Some(HistoryItemType::Exchange),
+            status: Some(HistoryItemStatus::Completed),
+            ..HistoryFilter::default()
+        };
+        let (items, _) = history_page(&user, &filter, None, 10);
+        assert_eq!(items.len(), 1);
+        let since = HistoryFilter {
+            from: Some(day(4)),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(history_page(&user, &since, None, 10).0.len(), 2);
+    }
+
+    #[test]
+    fn test_internal_transfer() {
+        use super::history::user_history;
+        use super::transfer::InternalTransferDecision;
+        use hexstody_api::types::{HistoryItem, WithdrawalRequestDecisionType};
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 2000, now);
+        add_user(&mut state, "Bob", 0, now);
+        let alice_btc = state.users.get_mut("Alice").unwrap().currencies.get_mut(&Currency::BTC).unwrap();
+        alice_btc.limits = vec![Limit {
+            amount: 500,
+            span: LimitSpan::Day,
+            fiat: None,
+        }];
+        let at = |created: NaiveDateTime, body: UpdateBody| StateUpdate { created, body };
+        let transfer = |to: &str, amount: u64, request_type: WithdrawalRequestType| InternalTransferUpd {
+            id: Uuid::new_v4(),
+            from: "Alice".to_owned(),
+            to: to.to_owned(),
+            currency: Currency::BTC,
+            amount,
+            created_at: now,
+            request_type,
+            rates: FiatRates::new(),
+        };
+        let balance = |state: &State, user: &str| state.users[user].currencies[&Currency::BTC].balance();
+
+        let under = transfer("Bob", 300, WithdrawalRequestType::UnderLimit);
+        let body = UpdateBody::InternalTransfer(under.clone());
+        state.apply_update(at(now, body.clone())).unwrap();
+        assert_eq!(balance(&state, "Alice"), 1700);
+        assert_eq!(balance(&state, "Bob"), 300);
+        let events = state.user_events(&body);
+        assert!(events.iter().any(|(u, e)| u == "Bob" && matches!(e, UserEvent::Transfer { .. })));
+        assert!(events.iter().any(|(u, e)| u == "Bob" && matches!(e, UserEvent::BalanceChanged { balance: 300, .. })));
+
+        assert_eq!(
+            state.apply_update(at(now, UpdateBody::InternalTransfer(transfer("Bob", 300, WithdrawalRequestType::UnderLimit)))),
+            Err(StateUpdateErr::LimitOverflow)
+        );
+        assert_eq!(
+            state.apply_update(at(now, UpdateBody::InternalTransfer(transfer("Alice", 100, WithdrawalRequestType::OverLimit)))),
+            Err(StateUpdateErr::SelfTransfer("Alice".to_owned()))
+        );
+        assert_eq!(
+            state.apply_update(at(now, UpdateBody::InternalTransfer(transfer("Bob", 5000, WithdrawalRequestType::OverLimit)))),
+            Err(StateUpdateErr::InsufficientFunds("Alice".to_owned(), Currency::BTC))
+        );
+
+        // Over the limits the funds are held until operators decide
+        let over = transfer("Bob", 1000, WithdrawalRequestType::OverLimit);
+        state.apply_update(at(now, UpdateBody::InternalTransfer(over.clone()))).unwrap();
+        assert_eq!(balance(&state, "Alice"), 700);
+        assert_eq!(balance(&state, "Bob"), 300);
+        let operators = [SecretKey::random(&mut OsRng), SecretKey::random(&mut OsRng)];
+        let decide = |t: &InternalTransferUpd, key: &SecretKey, decision: WithdrawalRequestDecisionType| {
+            UpdateBody::InternalTransferDecision(InternalTransferDecision {
+                id: t.id,
+                from: t.from.clone(),
+                currency: t.currency.clone(),
+                url: "test".to_owned(),
+                signature: SigningKey::from(key.clone()).sign(b"test"),
+                nonce: 0,
+                public_key: key.public_key(),
+                decision,
+            })
+        };
+        state
+            .apply_update(at(now, decide(&over, &operators[0], WithdrawalRequestDecisionType::Confirm)))
+            .unwrap();
+        assert_eq!(
+            state.apply_update(at(now, decide(&over, &operators[0], WithdrawalRequestDecisionType::Confirm))),
+            Err(StateUpdateErr::TransferAlreadySigned(over.id))
+        );
+        assert_eq!(balance(&state, "Bob"), 300);
+        state
+            .apply_update(at(now, decide(&over, &operators[1], WithdrawalRequestDecisionType::Confirm)))
+            .unwrap();
+        assert_eq!(balance(&state, "Alice"), 700);
+        assert_eq!(balance(&state, "Bob"), 1300);
+
+        let rejected = transfer("Bob", 500, WithdrawalRequestType::OverLimit);
+        state.apply_update(at(now, UpdateBody::InternalTransfer(rejected.clone()))).unwrap();
+        assert_eq!(balance(&state, "Alice"), 200);
+        for key in operators.iter() {
+            state
+                .apply_update(at(now, decide(&rejected, key, WithdrawalRequestDecisionType::Reject)))
+                .unwrap();
+        }
+        assert_eq!(balance(&state, "Alice"), 700);
+        assert_eq!(balance(&state, "Bob"), 1300);
+
+        let bob_history = user_history(&state.users["Bob"]);
+        assert_eq!(bob_history.len(), 2);
+        assert!(bob_history
+            .iter()
+            .all(|item| matches!(item, HistoryItem::Transfer(t) if t.incoming && t.counterparty == "Alice")));
+        assert_eq!(user_history(&state.users["Alice"]).len(), 3);
+        assert_eq!(state.get_internal_transfers().len(), 3);
+    }
+
+    #[test]
+    fn test_withdrawal_allowlist() {
+        use crate::update::address_book::{AddressBookAdd, AddressBookRemove, SetWithdrawalAllowlist};
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 0, now);
+        let at = |created: NaiveDateTime, body: UpdateBody| StateUpdate { created, body };
+        let btc = |addr: &str| {
+            CurrencyAddress::BTC(BtcAddress {
+                addr: addr.to_owned(),
+            })
+        };
+        let saved = btc("bc1qsaved");
+        let other = btc("bc1qother");
+        let can_withdraw = |state: &State, address: &CurrencyAddress, time: NaiveDateTime| {
+            state.users["Alice"].can_withdraw_to(address, time)
+        };
+        assert!(can_withdraw(&state, &other, now));
+
+        let add = AddressBookAdd {
+            id: Uuid::new_v4(),
+            user: "Alice".to_owned(),
+            address: saved.clone(),
+            label: "Cold wallet".to_owned(),
+            created_at: now,
+            available_at: now + Duration::hours(24),
+        };
+        state.apply_update(at(now, UpdateBody::AddressBookAdd(add.clone()))).unwrap();
+        let duplicate = AddressBookAdd {
+            id: Uuid::new_v4(),
+            ..add.clone()
+        };
+        assert_eq!(
+            state.apply_update(at(now, UpdateBody::AddressBookAdd(duplicate))),
+            Err(StateUpdateErr::AddressAlreadySaved("Alice".to_owned(), saved.clone()))
+        );
+
+        let set = |enabled: bool, effective_at: NaiveDateTime| {
+            UpdateBody::SetWithdrawalAllowlist(SetWithdrawalAllowlist {
+                user: "Alice".to_owned(),
+                enabled,
+                effective_at,
+            })
+        };
+        state.apply_update(at(now, set(true, now))).unwrap();
+        assert!(!can_withdraw(&state, &other, now));
+        // The saved address is usable only after the cool-down
+        assert!(!can_withdraw(&state, &saved, now));
+        let later = now + Duration::hours(25);
+        assert!(can_withdraw(&state, &saved, later));
+        assert!(!can_withdraw(&state, &other, later));
+
+        // Switching off is delayed, and a repeated request doesn't postpone it
+        state.apply_update(at(now, set(false, now + Duration::hours(24)))).unwrap();
+        state.apply_update(at(now, set(false, now + Duration::hours(48)))).unwrap();
+        assert!(!can_withdraw(&state, &other, now + Duration::hours(1)));
+        assert!(can_withdraw(&state, &other, later));
+
+        state.apply_update(at(now, set(true, now))).unwrap();
+        assert!(!can_withdraw(&state, &other, later));
+        let remove = AddressBookRemove {
+            id: add.id,
+            user: "Alice".to_owned(),
+        };
+        state.apply_update(at(now, UpdateBody::AddressBookRemove(remove.clone()))).unwrap();
+        assert!(!can_withdraw(&state, &saved, later));
+        assert_eq!(
+            state.apply_update(at(now, UpdateBody::AddressBookRemove(remove))),
+            Err(StateUpdateErr::AddressBookEntryNotFound(add.id))
+        );
+    }
+
+    #[test]
+    fn test_cancel_withdrawal_request() {
+        use crate::update::withdrawal::WithdrawalCancelInfo;
+        use hexstody_api::types::WithdrawalRequestDecisionType;
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 2000, now);
+        let alice_btc = state.users.get_mut("Alice").unwrap().currencies.get_mut(&Currency::BTC).unwrap();
+        alice_btc.limits = vec![Limit {
+            amount: 500,
+            span: LimitSpan::Day,
+            fiat: None,
+        }];
+        let at = |created: NaiveDateTime, body: UpdateBody| StateUpdate { created, body };
+        let withdrawal = |amount: u64, request_type: WithdrawalRequestType| WithdrawalRequestInfo {
+            id: Uuid::new_v4(),
+            user: "Alice".to_owned(),
+            address: CurrencyAddress::BTC(BtcAddress {
+                addr: "bc1qpv8tczdsft9lmlz4nhz8058jdyl96velqqlwgj".to_owned(),
+            }),
+            amount,
+            request_type,
+            created_at: Some(now),
+            rates: FiatRates::new(),
+            fee_tier: FeeTier::Normal,
+            charged_fee: None,
+        };
+        let cancel = |id: WithdrawalRequestId| {
+            UpdateBody::CancelWithdrawalRequest(WithdrawalCancelInfo {
+                id,
+                user: "Alice".to_owned(),
+                currency: Currency::BTC,
+                cancelled_at: now,
+            })
+        };
+        let btc_info = |state: &State| state.users["Alice"].currencies[&Currency::BTC].clone();
+
+        let over = withdrawal(1000, WithdrawalRequestType::OverLimit);
+        state.apply_update(at(now, UpdateBody::CreateWithdrawalRequest(over.clone()))).unwrap();
+        assert_eq!(btc_info(&state).balance(), 1000);
+        let body = cancel(over.id);
+        state.apply_update(at(now, body.clone())).unwrap();
+        assert_eq!(btc_info(&state).balance(), 2000);
+        assert_eq!(btc_info(&state).finalized_balance(), 2000);
+        assert!(btc_info(&state).withdrawal_requests[&over.id].is_cancelled());
+        assert!(matches!(
+            state.operator_events(&body).as_slice(),
+            [OperatorEvent::WithdrawalRequest(req)] if req.id == over.id
+        ));
+        assert_eq!(
+            state.apply_update(at(now, cancel(over.id))),
+            Err(StateUpdateErr::WithdrawalRequestNotCancellable(over.id))
+        );
+        let key = SecretKey::random(&mut OsRng);
+        let decision = WithdrawalRequestDecisionInfo {
+            user_id: "Alice".to_owned(),
+            currency: Currency::BTC,
+            request_id: over.id,
+            url: "test".to_owned(),
+            signature: SigningKey::from(key.clone()).sign(b"test"),
+            nonce: 0,
+            public_key: key.public_key(),
+            decision_type: WithdrawalRequestDecisionType::Confirm,
+        };
+        assert_eq!(
+            state.apply_update(at(now, UpdateBody::WithdrawalRequestDecision(decision))),
+            Err(StateUpdateErr::WithdrawalRequestCancelled(over.id))
+        );
+
+        // Under limit requests are sent to the node at once
+        let under = withdrawal(300, WithdrawalRequestType::UnderLimit);
+        state.apply_update(at(now, UpdateBody::CreateWithdrawalRequest(under.clone()))).unwrap();
+        assert_eq!(
+            state.apply_update(at(now, cancel(under.id))),
+            Err(StateUpdateErr::WithdrawalRequestNotCancellable(under.id))
+        );
+        assert_eq!(btc_info(&state).limit_info(now).spent, 300);
+        assert!(matches!(
+            state.apply_update(at(now, cancel(Uuid::new_v4()))),
+            Err(StateUpdateErr::WithdrawalRequestNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn test_fee_policy() {
+        use hexstody_api::types::CurrencyFeePolicy;
+
+        assert_eq!(FeePolicy::Flat { fee: 700 }.charge(10_000, Some(300)), Some(700));
+        assert_eq!(FeePolicy::Percent { basis_points: 25 }.charge(10_001, None), Some(26));
+        assert_eq!(FeePolicy::NetworkFee { markup_basis_points: 1000 }.charge(10_000, Some(301)), Some(332));
+        assert_eq!(FeePolicy::NetworkFee { markup_basis_points: 0 }.charge(10_000, None), None);
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 2000, now);
+        assert_eq!(state.fee_policy(&Currency::BTC), FeePolicy::default());
+
+        let policy = FeePolicy::Flat { fee: 100 };
+        let change = SettingsChange::FeePolicy(CurrencyFeePolicy {
+            currency: Currency::BTC,
+            policy: policy.clone(),
+        });
+        change_settings(&mut state, change, now);
+        assert_eq!(state.fee_policy(&Currency::BTC), policy);
+        assert_eq!(state.fee_policy(&Currency::ETH), FeePolicy::default());
+
+        let req = WithdrawalRequestInfo {
+            id: Uuid::new_v4(),
+            user: "Alice".to_owned(),
+            address: CurrencyAddress::BTC(BtcAddress {
+                addr: "bc1qpv8tczdsft9lmlz4nhz8058jdyl96velqqlwgj".to_owned(),
+            }),
+            amount: 500,
+            request_type: WithdrawalRequestType::OverLimit,
+            created_at: Some(now),
+            rates: FiatRates::new(),
+            fee_tier: FeeTier::Fast,
+            charged_fee: policy.charge(500, Some(30)),
+        };
+        state
+            .apply_update(StateUpdate { created: now, body: UpdateBody::CreateWithdrawalRequest(req.clone()) })
+            .unwrap();
+        let btc_info = &state.users["Alice"].currencies[&Currency::BTC];
+        assert_eq!(btc_info.balance(), 1400);
+        assert_eq!(btc_info.withdrawal_requests[&req.id].fee_tier, FeeTier::Fast);
+    }
+
+    #[test]
+    fn test_operator_audit() {
+        use crate::update::audit::OperatorActionRec;
+        use hexstody_api::types::{OperatorActionType, OperatorAuditFilter};
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        let alice_key = SecretKey::random(&mut OsRng);
+        let bob_key = SecretKey::random(&mut OsRng);
+        let action = |key: &SecretKey, action: OperatorActionType, payload: &str| OperatorActionRec {
+            id: Uuid::new_v4(),
+            action,
+            url: "test".to_owned(),
+            signature_data: SignatureData {
+                signature: SigningKey::from(key.clone()).sign(payload.as_bytes()),
+                nonce: 0,
+                public_key: key.public_key(),
+            },
+            payload: payload.to_owned(),
+        };
+        let actions = vec![
+            (now, action(&alice_key, OperatorActionType::GenInvite, "{\"label\":\"Bob\"}")),
+            (now + Duration::hours(1), action(&bob_key, OperatorActionType::SetMargin, "{}")),
+            (now + Duration::hours(2), action(&alice_key, OperatorActionType::SetMargin, "{}")),
+        ];
+        for (created, action) in actions.iter().cloned() {
+            state
+                .apply_update(StateUpdate { created, body: UpdateBody::OperatorAction(action) })
+                .unwrap();
+        }
+        let ids = |filter: OperatorAuditFilter| -> Vec<Uuid> {
+            state.operator_audit(&filter).into_iter().map(|e| e.id).collect()
+        };
+
+        assert_eq!(
+            ids(OperatorAuditFilter::default()),
+            vec![actions[2].1.id, actions[1].1.id, actions[0].1.id]
+        );
+        assert_eq!(
+            ids(OperatorAuditFilter {
+                operator: Some(alice_key.public_key().to_string()),
+                ..Default::default()
+            }),
+            vec![actions[2].1.id, actions[0].1.id]
+        );
+        assert_eq!(
+            ids(OperatorAuditFilter {
+                action: Some(OperatorActionType::SetMargin),
+                until: Some(now + Duration::hours(2)),
+                ..Default::default()
+            }),
+            vec![actions[1].1.id]
+        );
+        assert_eq!(
+            ids(OperatorAuditFilter {
+                from: Some(now + Duration::minutes(1)),
+                ..Default::default()
+            }),
+            vec![actions[2].1.id, actions[1].1.id]
+        );
+        let entries = state.operator_audit(&OperatorAuditFilter::default());
+        let entry = &entries[2];
+        assert_eq!(entry.time, now);
+        assert_eq!(entry.payload, "{\"label\":\"Bob\"}");
+        assert_eq!(entry.operator, alice_key.public_key().to_string());
+    }
+
+    #[test]
+    fn test_settings_change() {
+        use hexstody_api::types::{
+            CurrencyFeePolicy, FeeEstimates, MarginData, SettingsChange, SettingsChangeStatus,
+        };
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        let alice_key = SecretKey::random(&mut OsRng);
+        let bob_key = SecretKey::random(&mut OsRng);
+        let at = |body: UpdateBody| StateUpdate { created: now, body };
+        let propose = |id: Uuid, key: &SecretKey, change: SettingsChange| {
+            UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+                id,
+                change,
+                url: "test".to_owned(),
+                signature: SigningKey::from(key.clone()).sign(b"test"),
+                nonce: 0,
+                public_key: key.public_key(),
+            })
+        };
+        let decide = |id: Uuid, key: &SecretKey, change: SettingsChange, decision| {
+            UpdateBody::SettingsChangeDecision(SettingsChangeDecision {
+                id,
+                change,
+                url: "test".to_owned(),
+                signature: SigningKey::from(key.clone()).sign(b"test"),
+                nonce: 0,
+                public_key: key.public_key(),
+                decision,
+            })
+        };
+        let margin = "1.5".parse::<Rate>().unwrap();
+        let margin_change = SettingsChange::Margin(MarginData {
+            currency_from: Currency::BTC,
+            currency_to: Currency::ETH,
+            margin,
+        });
+
+        let id = Uuid::new_v4();
+        state.apply_update(at(propose(id, &alice_key, margin_change.clone()))).unwrap();
+        assert_eq!(state.margin(&Currency::BTC, &Currency::ETH), Rate::ZERO);
+        assert_eq!(
+            state.apply_update(at(decide(id, &alice_key, margin_change.clone(), WithdrawalRequestDecisionType::Confirm))),
+            Err(StateUpdateErr::SettingsChangeAlreadySigned(id))
+        );
+        let other = SettingsChange::FeeEstimates(FeeEstimates::new());
+        assert_eq!(
+            state.apply_update(at(decide(id, &bob_key, other, WithdrawalRequestDecisionType::Confirm))),
+            Err(StateUpdateErr::SettingsChangeMismatch(id))
+        );
+        state
+            .apply_update(at(decide(id, &bob_key, margin_change.clone(), WithdrawalRequestDecisionType::Confirm)))
+            .unwrap();
+        assert_eq!(state.margin(&Currency::BTC, &Currency::ETH), margin);
+        assert_eq!(state.margin(&Currency::ETH, &Currency::BTC), Rate::ZERO);
+        assert_eq!(state.get_settings_changes()[0].status, SettingsChangeStatus::Completed);
+        assert_eq!(
+            state.apply_update(at(decide(id, &bob_key, margin_change, WithdrawalRequestDecisionType::Reject))),
+            Err(StateUpdateErr::SettingsChangeAlreadyCompleted(id))
+        );
+
+        // The proposer can take the change back
+        let estimates = SettingsChange::FeeEstimates(FeeEstimates {
+            btc_bytes_per_tx: 300,
+            ..FeeEstimates::new()
+        });
+        let id = Uuid::new_v4();
+        state.apply_update(at(propose(id, &alice_key, estimates.clone()))).unwrap();
+        state
+            .apply_update(at(decide(id, &alice_key, estimates.clone(), WithdrawalRequestDecisionType::Reject)))
+            .unwrap();
+        state
+            .apply_update(at(decide(id, &bob_key, estimates, WithdrawalRequestDecisionType::Reject)))
+            .unwrap();
+        assert_eq!(state.settings_changes[&id].status, SettingsChangeStatus::Rejected);
+        assert_eq!(state.fee_estimates, FeeEstimates::new());
+
+        let policy = FeePolicy::Percent { basis_points: 50 };
+        let fee_policy = SettingsChange::FeePolicy(CurrencyFeePolicy {
+            currency: Currency::ETH,
+            policy: policy.clone(),
+        });
+        let id = Uuid::new_v4();
+        state.apply_update(at(propose(id, &alice_key, fee_policy.clone()))).unwrap();
+        assert_eq!(state.fee_policy(&Currency::ETH), FeePolicy::default());
+        state
+            .apply_update(at(decide(id, &bob_key, fee_policy, WithdrawalRequestDecisionType::Confirm)))
+            .unwrap();
+        assert_eq!(state.fee_policy(&Currency::ETH), policy);
+        assert_eq!(state.fee_policy(&Currency::BTC), FeePolicy::default());
+    }
+
+    #[test]
+    fn test_account_lifecycle() {
+        use crate::state::account::{AccountChangeDecision, AccountChangeUpd};
+        use crate::update::withdrawal::WithdrawalCancelInfo;
+        use std::str::FromStr;
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 2000, now);
+        add_user(&mut state, "Bob", 0, now);
+        let op_key = SecretKey::random(&mut OsRng);
+        let other_key = SecretKey::random(&mut OsRng);
+        let at = |body: UpdateBody| StateUpdate { created: now, body };
+        let propose = |id: Uuid, action: AccountAction| {
+            UpdateBody::AccountChangeRequest(AccountChangeUpd {
+                id,
+                user: "Alice".to_owned(),
+                action,
+                reason: "test".to_owned(),
+                url: "test".to_owned(),
+                signature: SigningKey::from(op_key.clone()).sign(b"test"),
+                nonce: 0,
+                public_key: op_key.public_key(),
+            })
+        };
+        let confirm = |id: Uuid, action: AccountAction| {
+            UpdateBody::AccountChangeDecision(AccountChangeDecision {
+                id,
+                user: "Alice".to_owned(),
+                action,
+                url: "test".to_owned(),
+                signature: SigningKey::from(other_key.clone()).sign(b"test"),
+                nonce: 0,
+                public_key: other_key.public_key(),
+                decision: WithdrawalRequestDecisionType::Confirm,
+            })
+        };
+        let withdrawal = WithdrawalRequestInfo {
+            id: Uuid::new_v4(),
+            user: "Alice".to_owned(),
+            address: CurrencyAddress::BTC(BtcAddress {
+                addr: "bc1qpv8tczdsft9lmlz4nhz8058jdyl96velqqlwgj".to_owned(),
+            }),
+            amount: 1000,
+            request_type: WithdrawalRequestType::OverLimit,
+            created_at: Some(now),
+            rates: FiatRates::new(),
+            fee_tier: FeeTier::Normal,
+            charged_fee: None,
+        };
+        state
+            .apply_update(at(UpdateBody::CreateWithdrawalRequest(withdrawal.clone())))
+            .unwrap();
+
+        let freeze = Uuid::new_v4();
+        state.apply_update(at(propose(freeze, AccountAction::Freeze))).unwrap();
+        assert_eq!(state.users["Alice"].status, AccountStatus::Active);
+        assert_eq!(
+            state.apply_update(at(propose(Uuid::new_v4(), AccountAction::Close))),
+            Err(StateUpdateErr::AccountChangePending("Alice".to_owned()))
+        );
+        assert_eq!(
+            state.apply_update(at(confirm(freeze, AccountAction::Close))),
+            Err(StateUpdateErr::AccountChangeMismatch(freeze))
+        );
+        state.apply_update(at(confirm(freeze, AccountAction::Freeze))).unwrap();
+        assert_eq!(state.users["Alice"].status, AccountStatus::Frozen);
+        assert_eq!(state.check_user_access("Alice"), Err(AuthError::AccountFrozen));
+        let another = WithdrawalRequestInfo {
+            id: Uuid::new_v4(),
+            ..withdrawal.clone()
+        };
+        assert_eq!(
+            state.apply_update(at(UpdateBody::CreateWithdrawalRequest(another))),
+            Err(StateUpdateErr::AccountNotActive("Alice".to_owned(), AccountStatus::Frozen))
+        );
+        let transfer = InternalTransferUpd {
+            id: Uuid::new_v4(),
+            from: "Alice".to_owned(),
+            to: "Bob".to_owned(),
+            currency: Currency::BTC,
+            amount: 100,
+            created_at: now,
+            request_type: WithdrawalRequestType::UnderLimit,
+            rates: FiatRates::new(),
+        };
+        assert_eq!(
+            state.apply_update(at(UpdateBody::InternalTransfer(transfer))),
+            Err(StateUpdateErr::AccountNotActive("Alice".to_owned(), AccountStatus::Frozen))
+        );
+        assert_eq!(
+            state.apply_update(at(propose(Uuid::new_v4(), AccountAction::Freeze))),
+            Err(StateUpdateErr::AccountChangeNotAllowed(
+                "Alice".to_owned(),
+                AccountAction::Freeze,
+                AccountStatus::Frozen
+            ))
+        );
+        // Funds of the pending withdrawal would return to the closed account if it is rejected
+        assert_eq!(
+            state.apply_update(at(propose(Uuid::new_v4(), AccountAction::Close))),
+            Err(StateUpdateErr::AccountHasPendingOperations("Alice".to_owned()))
+        );
+        state
+            .apply_update(at(UpdateBody::CancelWithdrawalRequest(WithdrawalCancelInfo {
+                id: withdrawal.id,
+                user: "Alice".to_owned(),
+                currency: Currency::BTC,
+                cancelled_at: now,
+            })))
+            .unwrap();
+
+        // Unconfirmed deposit is not swept, so it blocks the closure until it is finalized
+        let deposit = BtcTransaction {
+            txid: bitcoin::Txid::from_str(
+                "5a1dfb3ab2b6b7dd3da1c9ee0e2c4c1f8a1b7c2ef0f77e1f2d5a3cce2a7f1b11",
+            )
+            .unwrap(),
+            vout: 0,
+            address: bitcoin::Address::from_str("bc1qpv8tczdsft9lmlz4nhz8058jdyl96velqqlwgj").unwrap(),
+            confirmations: 1,
+            amount: 500,
+            timestamp: DateTime::from_utc(now, Utc),
+            conflicts: vec![],
+            fee: None,
+        };
+        let alice_btc = state.users.get_mut("Alice").unwrap().currencies.get_mut(&Currency::BTC).unwrap();
+        alice_btc.update_btc_tx(&deposit);
+        assert_eq!(alice_btc.balance(), 2500);
+        assert_eq!(alice_btc.finalized_balance(), 2000);
+        assert_eq!(
+            state.apply_update(at(propose(Uuid::new_v4(), AccountAction::Close))),
+            Err(StateUpdateErr::AccountHasPendingOperations("Alice".to_owned()))
+        );
+        let alice_btc = state.users.get_mut("Alice").unwrap().currencies.get_mut(&Currency::BTC).unwrap();
+        alice_btc.update_btc_tx(&BtcTransaction {
+            confirmations: 4,
+            ..deposit
+        });
+
+        let close = Uuid::new_v4();
+        state.apply_update(at(propose(close, AccountAction::Close))).unwrap();
+        state.apply_update(at(confirm(close, AccountAction::Close))).unwrap();
+        let alice = &state.users["Alice"];
+        assert_eq!(alice.status, AccountStatus::Closed);
+        assert_eq!(alice.currencies[&Currency::BTC].balance(), 0);
+        assert_eq!(alice.currencies[&Currency::BTC].finalized_balance(), 0);
+        assert_eq!(alice.currencies[&Currency::BTC].swept(), 2500);
+        assert_eq!(state.check_user_access("Alice"), Err(AuthError::AccountClosed));
+        assert_eq!(state.get_account_changes().len(), 2);
+        assert_eq!(
+            state.apply_update(at(propose(Uuid::new_v4(), AccountAction::Unfreeze))),
+            Err(StateUpdateErr::AccountChangeNotAllowed(
+                "Alice".to_owned(),
+                AccountAction::Unfreeze,
+                AccountStatus::Closed
+            ))
+        );
+    }
+
+    #[test]
+    fn test_totp_recovery_code_used() {
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 0, now);
+        let at = |body: UpdateBody| StateUpdate { created: now, body };
+        let enroll = TotpEnroll {
+            user: "Alice".to_owned(),
+            secret: "JBSWY3DPEHPK3PXP".to_owned(),
+            recovery_codes: vec!["first".to_owned(), "second".to_owned()],
+            require_for_withdrawal: true,
+            require_for_api_keys: false,
+            enrolled_at: now,
+        };
+        state.apply_update(at(UpdateBody::TotpEnroll(enroll))).unwrap();
+        let used = TotpRecoveryCodeUsed {
+            user: "Alice".to_owned(),
+            code_hash: "first".to_owned(),
+        };
+        state.apply_update(at(UpdateBody::TotpRecoveryCodeUsed(used.clone()))).unwrap();
+        let totp = state.users["Alice"].totp.as_ref().unwrap();
+        assert_eq!(totp.recovery_codes, vec!["second".to_owned()]);
+        // The second request with the same code is rejected
+        assert_eq!(
+            state.apply_update(at(UpdateBody::TotpRecoveryCodeUsed(used))),
+            Err(StateUpdateErr::TotpRecoveryCodeNotFound("Alice".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_legacy_snapshot_limits() {
+        use crate::update::{UpdateTag, CURRENT_BODY_VERSION};
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 0, now);
+        // Token keys can't be encoded in JSON maps
+        state.exchange_state.balances.clear();
+        state
+            .users
+            .get_mut("Alice")
+            .unwrap()
+            .currencies
+            .retain(|c, _| *c == Currency::BTC);
+
+        // Snapshot made before several limits per currency
+        let mut snapshot = serde_json::to_value(&state).unwrap();
+        let btc = snapshot["users"]["Alice"]["currencies"]["BTC"].as_object_mut().unwrap();
+        btc.remove("limits");
+        btc.remove("limit_spends");
+        btc.insert(
+            "limit_info".to_owned(),
+            serde_json::json!({"limit": {"amount": 5000, "span": "Week"}, "spent": 300}),
+        );
+        let body = UpdateTag::from_tag("snapshot", CURRENT_BODY_VERSION, snapshot).unwrap();
+        let mut restored = State::default();
+        restored.apply_update(StateUpdate { created: now, body }).unwrap();
+        let btc_info = &restored.users["Alice"].currencies[&Currency::BTC];
+        assert_eq!(
+            btc_info.limits,
+            vec![Limit {
+                amount: 5000,
+                span: LimitSpan::Week,
+                fiat: None,
+            }]
+        );
+        // The old field is not written to new snapshots
+        let snapshot = serde_json::to_value(&restored).unwrap();
+        assert!(snapshot["users"]["Alice"]["currencies"]["BTC"].get("limit_info").is_none());
+    }
+
+    #[test]
+    fn test_legacy_snapshot_api_keys() {
+        use crate::update::{UpdateTag, CURRENT_BODY_VERSION};
+
+        let mut state = State::default();
+        let now = NaiveDate::from_ymd(2022, 1, 1).and_hms(12, 0, 0);
+        add_user(&mut state, "Alice", 0, now);
+        // Token keys can't be encoded in JSON maps
+        state.exchange_state.balances.clear();
+        state
+            .users
+            .get_mut("Alice")
+            .unwrap()
+            .currencies
+            .retain(|c, _| *c == Currency::BTC);
+
+        // Snapshot made before hashed keys
+        let key = "hxs_legacykey";
+        let mut snapshot = serde_json::to_value(&state).unwrap();
+        snapshot["api_keys"] = serde_json::json!({ key: "Alice" });
+        let body = UpdateTag::from_tag("snapshot", CURRENT_BODY_VERSION, snapshot).unwrap();
+        let mut restored = State::default();
+        restored.apply_update(StateUpdate { created: now, body }).unwrap();
+        let key_hash = hexstody_auth::types::hash_api_key(key);
+        let record = &restored.api_keys[&key_hash];
+        assert_eq!(record.user, "Alice");
+        assert_eq!(record.prefix, "hxs_lega");
+        assert!(record.has_scope(ApiKeyScope::Withdraw));
+        assert!(record.has_scope(ApiKeyScope::Exchange));
+        // The plain key is not written to new snapshots
+        let snapshot = serde_json::to_string(&restored).unwrap();
+        assert!(!snapshot.contains(key));
+
+        // New snapshots are read back as is
+        let snapshot = serde_json::to_value(&restored).unwrap();
+        let body = UpdateTag::from_tag("snapshot", CURRENT_BODY_VERSION, snapshot).unwrap();
+        let mut reloaded = State::default();
+        reloaded.apply_update(StateUpdate { created: now, body }).unwrap();
+        assert_eq!(reloaded.api_keys, restored.api_keys);
+    }
 }
@@ -0,0 +1,284 @@
+//! Property tests checking that replaying the history of updates always
+//! gives the same state. Updates are generated from random scenarios, applied
+//! the way the update worker does it and then restored through the same
+//! encoding as the database rows.
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use ciborium::value::Value;
+use proptest::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::*;
+use crate::update::btc::BestBtcBlock;
+use crate::update::limit::LimitChangeUpd;
+use crate::update::misc::{InviteRec, SetLanguage};
+use crate::update::signup::{SignupAuth, SignupInfo};
+use crate::update::webhook::WebhookRegister;
+use crate::update::withdrawal::{FiatRates, WithdrawalRequestInfo};
+use crate::update::{StateUpdate, UpdateBody, UpdateTag, CURRENT_BODY_VERSION};
+use hexstody_api::domain::{BtcAddress, Currency, CurrencyAddress, Fiat, Language};
+use hexstody_api::types::{FeeTier, Invite, Limit, LimitSpan};
+
+const USERS: u8 = 4;
+const BTC_ADDRESSES: [&str; 3] = [
+    "bc1qpv8tczdsft9lmlz4nhz8058jdyl96velqqlwgj",
+    "bcrt1qz6sp8sr3sfwt8tpgwfmwd2pl4z06nd6dmwaxsm",
+    "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+];
+
+#[derive(Debug, Clone)]
+enum Action {
+    Signup(u8),
+    DepositAddress(u8, usize),
+    LimitRequest { user: u8, amount: u64, span: u8 },
+    /// Limit request as it was recorded before ID and time were in the body
+    LegacyLimitRequest { user: u8, amount: u64 },
+    CancelLimit(u8),
+    Withdraw { user: u8, amount: u64 },
+    Webhook(u8),
+    Language(u8, bool),
+    BtcBlock(u64),
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        (0..USERS).prop_map(Action::Signup),
+        (0..USERS, 0..BTC_ADDRESSES.len()).prop_map(|(u, a)| Action::DepositAddress(u, a)),
+        (0..USERS, 1..1_000_000u64, 0..3u8)
+            .prop_map(|(user, amount, span)| Action::LimitRequest { user, amount, span }),
+        (0..USERS, 1..1_000_000u64)
+            .prop_map(|(user, amount)| Action::LegacyLimitRequest { user, amount }),
+        (0..USERS).prop_map(Action::CancelLimit),
+        (0..USERS, 1..1_000_000u64).prop_map(|(user, amount)| Action::Withdraw { user, amount }),
+        (0..USERS).prop_map(Action::Webhook),
+        (0..USERS, any::<bool>()).prop_map(|(u, l)| Action::Language(u, l)),
+        (0..1_000_000u64).prop_map(Action::BtcBlock),
+    ]
+}
+
+fn user_name(user: u8) -> String {
+    format!("user{user}")
+}
+
+fn span(span: u8) -> LimitSpan {
+    match span {
+        0 => LimitSpan::Day,
+        1 => LimitSpan::Week,
+        _ => LimitSpan::Month,
+    }
+}
+
+/// History of updates built the same way the update worker records them:
+/// updates that fail to apply are not stored.
+struct History {
+    live: State,
+    updates: Vec<StateUpdate>,
+    seq: u128,
+    clock: NaiveDateTime,
+}
+
+impl History {
+    fn build(actions: &[Action]) -> Self {
+        let mut history = History {
+            live: State::new(Network::Regtest),
+            updates: vec![],
+            seq: 0,
+            clock: NaiveDate::from_ymd(2022, 1, 1).and_hms(0, 0, 0),
+        };
+        // Replay of an empty history starts from the wall clock, so make sure there is at least one update
+        history.signup(0);
+        for action in actions {
+            history.apply(action);
+        }
+        history
+    }
+
+    fn next_id(&mut self) -> Uuid {
+        self.seq += 1;
+        Uuid::from_u128(self.seq)
+    }
+
+    /// Milliseconds precision to match what survives a database round trip
+    fn next_time(&mut self) -> NaiveDateTime {
+        self.clock = self.clock + Duration::milliseconds(1500);
+        self.clock
+    }
+
+    fn push(&mut self, body: UpdateBody) {
+        let update = StateUpdate {
+            created: self.next_time(),
+            body,
+        };
+        let mut copy = self.live.clone();
+        if copy.apply_update(update.clone()).is_ok() {
+            self.live = copy;
+            self.updates.push(update);
+        }
+    }
+
+    fn signup(&mut self, user: u8) {
+        let invite = Invite {
+            invite: self.next_id(),
+        };
+        self.push(UpdateBody::GenInvite(InviteRec {
+            invite,
+            invitor: String::new(),
+            label: String::new(),
+        }));
+        self.push(UpdateBody::Signup(SignupInfo {
+            username: user_name(user),
+            invite,
+            auth: SignupAuth::Lightning,
+        }));
+    }
+
+    fn apply(&mut self, action: &Action) {
+        match action.clone() {
+            Action::Signup(user) => self.signup(user),
+            Action::DepositAddress(user, addr) => {
+                self.push(UpdateBody::DepositAddress(DepositAddress {
+                    user_id: user_name(user),
+                    address: CurrencyAddress::BTC(BtcAddress {
+                        addr: BTC_ADDRESSES[addr].to_owned(),
+                    }),
+                }))
+            }
+            Action::LimitRequest { user, amount, span: s } => {
+                let id = Some(self.next_id());
+                let created_at = Some(self.clock);
+                self.push(UpdateBody::LimitsChangeRequest(LimitChangeUpd {
+                    id,
+                    user: user_name(user),
+                    currency: Currency::BTC,
+                    limit: Limit {
+                        amount,
+                        span: span(s),
+                        fiat: None,
+                    },
+                    created_at,
+                }))
+            }
+            Action::LegacyLimitRequest { user, amount } => {
+                self.push(UpdateBody::LimitsChangeRequest(LimitChangeUpd {
+                    id: None,
+                    user: user_name(user),
+                    currency: Currency::BTC,
+                    limit: Limit {
+                        amount,
+                        span: LimitSpan::Day,
+                        fiat: None,
+                    },
+                    created_at: None,
+                }))
+            }
+            Action::CancelLimit(user) => {
+                let id = self.next_id();
+                self.push(UpdateBody::CancelLimitChange(LimitCancelData {
+                    id,
+                    user: user_name(user),
+                    currency: Currency::BTC,
+                }))
+            }
+            Action::Withdraw { user, amount } => {
+                let id = self.next_id();
+                let created_at = Some(self.clock);
+                self.push(UpdateBody::CreateWithdrawalRequest(WithdrawalRequestInfo {
+                    id,
+                    user: user_name(user),
+                    address: CurrencyAddress::BTC(BtcAddress {
+                        addr: BTC_ADDRESSES[0].to_owned(),
+                    }),
+                    amount,
+                    request_type: WithdrawalRequestType::OverLimit,
+                    created_at,
+                    rates: FiatRates::from([(Fiat::USD, Rate::from_integer(20_000))]),
+                    fee_tier: FeeTier::Normal,
+                    charged_fee: None,
+                }))
+            }
+            Action::Webhook(user) => {
+                let id = self.next_id();
+                let created_at = self.clock;
+                self.push(UpdateBody::RegisterWebhook(WebhookRegister {
+                    id,
+                    user: user_name(user),
+                    url: format!("https://example.com/{id}"),
+                    secret: "secret".to_owned(),
+                    created_at,
+                }))
+            }
+            Action::Language(user, english) => self.push(UpdateBody::SetLanguage(SetLanguage {
+                user: user_name(user),
+                language: if english {
+                    Language::English
+                } else {
+                    Language::Russian
+                },
+            })),
+            Action::BtcBlock(height) => self.push(UpdateBody::BestBtcBlock(BestBtcBlock {
+                height,
+                block_hash: format!("{height:064x}"),
+            })),
+        }
+    }
+}
+
+/// Restore updates through the database encoding and collect the state
+fn replay(updates: &[StateUpdate]) -> State {
+    let restored = updates.iter().map(|upd| {
+        let tag = upd.body.tag().to_string();
+        let body = upd.body.json().expect("Update is encoded");
+        StateUpdate {
+            created: upd.created,
+            body: UpdateTag::from_tag(&tag, CURRENT_BODY_VERSION, body).expect("Update is decoded"),
+        }
+    });
+    State::collect(Network::Regtest, restored).expect("History is replayed")
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut buf = vec![];
+    ciborium::ser::into_writer(value, &mut buf).expect("Value is encoded");
+    buf
+}
+
+/// Sort map entries by encoded keys, so the order of hash maps doesn't matter
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Map(entries) => {
+            let mut entries: Vec<(Vec<u8>, Value, Value)> = entries
+                .into_iter()
+                .map(|(k, v)| {
+                    let k = canonical(k);
+                    (encode(&k), k, canonical(v))
+                })
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Map(entries.into_iter().map(|(_, k, v)| (k, v)).collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonical).collect()),
+        Value::Tag(tag, inner) => Value::Tag(tag, Box::new(canonical(*inner))),
+        other => other,
+    }
+}
+
+/// Canonical CBOR encoding of the state
+fn state_bytes(state: &State) -> Vec<u8> {
+    let value: Value = ciborium::de::from_reader(encode(state).as_slice()).expect("State is decoded");
+    encode(&canonical(value))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_replay_is_deterministic(actions in prop::collection::vec(action(), 0..60)) {
+        let history = History::build(&actions);
+        let first = state_bytes(&replay(&history.updates));
+        let second = state_bytes(&replay(&history.updates));
+        prop_assert_eq!(&first, &second);
+        // Restarted node must see the same state as the one that applied the updates
+        prop_assert_eq!(&first, &state_bytes(&history.live));
+    }
+}
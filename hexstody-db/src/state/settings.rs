@@ -0,0 +1,134 @@
+use chrono::NaiveDateTime;
+use hexstody_api::types::{
+    SettingsChange, SettingsChangeRequest, SettingsChangeStatus, SettingsConfirmationData,
+    SignatureData, WithdrawalRequestDecisionType,
+};
+use p256::{ecdsa::Signature, PublicKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::CONFIRMATIONS_CONFIG;
+
+pub type SettingsChangeId = Uuid;
+
+/// Body of the settings change proposal. The proposer's signature counts as the first confirmation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SettingsChangeUpd {
+    pub id: SettingsChangeId,
+    pub change: SettingsChange,
+    /// API URL wich was used to send the proposal
+    pub url: String,
+    /// Proposer's digital signature
+    pub signature: Signature,
+    /// Nonce that was generated during the proposal
+    pub nonce: u64,
+    /// Proposer's public key corresponding to the signing private key
+    pub public_key: PublicKey,
+}
+
+/// Settings change waiting for operators' decision or already decided
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SettingsChangeRecord {
+    pub id: SettingsChangeId,
+    pub change: SettingsChange,
+    pub created_at: NaiveDateTime,
+    pub status: SettingsChangeStatus,
+    pub confirmations: Vec<SignatureData>,
+    pub rejections: Vec<SignatureData>,
+}
+
+impl SettingsChangeRecord {
+    /// New change confirmed by its proposer only
+    pub fn proposed(upd: SettingsChangeUpd, created_at: NaiveDateTime) -> Self {
+        SettingsChangeRecord {
+            id: upd.id,
+            change: upd.change,
+            created_at,
+            status: SettingsChangeStatus::InProgress {
+                confirmations_minus_rejections: 1,
+            },
+            confirmations: vec![SignatureData {
+                signature: upd.signature,
+                nonce: upd.nonce,
+                public_key: upd.public_key,
+            }],
+            rejections: Vec::new(),
+        }
+    }
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, SettingsChangeStatus::InProgress { .. })
+    }
+    /// Number of operators' confirmations the change takes effect after
+    pub fn required_confirmations(&self) -> i16 {
+        match self.change {
+            SettingsChange::AggregateLimits(_) => CONFIRMATIONS_CONFIG.change_limit,
+            _ => CONFIRMATIONS_CONFIG.settings,
+        }
+    }
+    pub fn has_confirmed(&self, pubkey: PublicKey) -> bool {
+        self.confirmations.iter().any(|sd| sd.public_key == pubkey)
+    }
+    pub fn has_rejected(&self, pubkey: PublicKey) -> bool {
+        self.rejections.iter().any(|sd| sd.public_key == pubkey)
+    }
+}
+
+impl From<SettingsChangeRecord> for SettingsChangeRequest {
+    fn from(rec: SettingsChangeRecord) -> Self {
+        let keys = |sigs: Vec<SignatureData>| sigs.into_iter().map(|sd| sd.public_key.to_string()).collect();
+        SettingsChangeRequest {
+            id: rec.id,
+            change: rec.change,
+            created_at: rec.created_at,
+            status: rec.status,
+            confirmations: keys(rec.confirmations),
+            rejections: keys(rec.rejections),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SettingsChangeDecision {
+    /// Settings change id
+    pub id: SettingsChangeId,
+    /// The change as the operator saw it, must match the proposed one
+    pub change: SettingsChange,
+    /// API URL wich was used to send the decision
+    pub url: String,
+    /// Operator's digital signature
+    pub signature: Signature,
+    /// Nonce that was generated during decision
+    pub nonce: u64,
+    /// Operator's public key corresponding to the signing private key
+    pub public_key: PublicKey,
+    /// Decision type: confirm or reject
+    pub decision: WithdrawalRequestDecisionType,
+}
+
+impl
+    From<(
+        SettingsConfirmationData,
+        SignatureData,
+        WithdrawalRequestDecisionType,
+        String,
+    )> for SettingsChangeDecision
+{
+    fn from(
+        value: (
+            SettingsConfirmationData,
+            SignatureData,
+            WithdrawalRequestDecisionType,
+            String,
+        ),
+    ) -> SettingsChangeDecision {
+        SettingsChangeDecision {
+            id: value.0.id,
+            change: value.0.change,
+            url: value.3,
+            signature: value.1.signature,
+            nonce: value.1.nonce,
+            public_key: value.1.public_key,
+            decision: value.2,
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use chrono::prelude::*;
+use hexstody_api::domain::Currency;
+use hexstody_api::types::{CurrencyStatement, Statement, StatementEntry, StatementEntryKind};
+
+use super::transaction::Transaction;
+use super::user::UserInfo;
+
+/// Balance change before the running balance is known
+struct Change {
+    time: NaiveDateTime,
+    kind: StatementEntryKind,
+    amount: u64,
+    reference: String,
+}
+
+impl Change {
+    fn is_credit(&self) -> bool {
+        matches!(
+            self.kind,
+            StatementEntryKind::Deposit | StatementEntryKind::ExchangeIn | StatementEntryKind::TransferIn
+        )
+    }
+
+    fn signed(&self) -> i128 {
+        if self.is_credit() {
+            self.amount as i128
+        } else {
+            -(self.amount as i128)
+        }
+    }
+}
+
+/// All changes of the currency balance, oldest first. Follows `UserCurrencyInfo::balance`:
+/// withdrawals, exchanges and transfers are debited when requested and returned when rejected or expired
+fn currency_changes(user: &UserInfo, currency: &Currency) -> Vec<Change> {
+    let mut changes = vec![];
+    if let Some(info) = user.currencies.get(currency) {
+        for tx in info.transactions.iter().filter(|t| !t.is_conflicted()) {
+            let (time, reference) = match tx {
+                Transaction::Btc(tx) => (tx.timestamp.naive_utc(), tx.txid.to_string()),
+                Transaction::Eth(tx) => (
+                    NaiveDateTime::from_timestamp(tx.timeStamp.parse().unwrap_or(0), 0),
+                    tx.hash.clone(),
+                ),
+            };
+            let kind = if tx.amount() < 0 {
+                StatementEntryKind::Withdrawal
+            } else {
+                StatementEntryKind::Deposit
+            };
+            changes.push(Change { time, kind, amount: tx.amount().unsigned_abs(), reference });
+        }
+        for w in info.withdrawal_requests.values().filter(|w| !w.is_rejected() && !w.is_cancelled()) {
+            let time = w.created_at.naive_utc();
+            changes.push(Change {
+                time,
+                kind: StatementEntryKind::Withdrawal,
+                amount: w.amount,
+                reference: w.id.to_string(),
+            });
+            if let Some(fee) = w.user_fee() {
+                changes.push(Change {
+                    time,
+                    kind: StatementEntryKind::WithdrawalFee,
+                    amount: fee,
+                    reference: w.id.to_string(),
+                });
+            }
+        }
+        for o in info.exchange_requests.values().filter(|o| o.is_pending() || o.is_finalized()) {
+            let time = if o.is_finalized() { o.completed_time() } else { o.created_time() };
+            changes.push(Change {
+                time: time.unwrap_or(user.created_at),
+                kind: StatementEntryKind::ExchangeOut,
+                amount: o.amount_from,
+                reference: o.id.to_string(),
+            });
+        }
+        for t in info.outgoing_transfers.values().filter(|t| !t.is_rejected()) {
+            changes.push(Change {
+                time: t.created_at,
+                kind: StatementEntryKind::TransferOut,
+                amount: t.amount,
+                reference: t.id.to_string(),
+            });
+        }
+        for t in info.incoming_transfers.values().filter(|t| t.is_completed()) {
+            changes.push(Change {
+                time: t.time(),
+                kind: StatementEntryKind::TransferIn,
+                amount: t.amount,
+                reference: t.id.to_string(),
+            });
+        }
+        if let Some(sweep) = &info.sweep {
+            changes.push(Change {
+                time: sweep.swept_at,
+                kind: StatementEntryKind::ClosureSweep,
+                amount: sweep.amount,
+                reference: user.username.clone(),
+            });
+        }
+    }
+    for o in user
+        .currencies
+        .values()
+        .flat_map(|info| info.exchange_requests.values())
+        .filter(|o| o.currency_to == *currency && o.is_finalized())
+    {
+        changes.push(Change {
+            time: o.completed_time().unwrap_or(user.created_at),
+            kind: StatementEntryKind::ExchangeIn,
+            amount: o.amount_to,
+            reference: o.id.to_string(),
+        });
+    }
+    changes.sort_by(|a, b| a.time.cmp(&b.time));
+    changes
+}
+
+fn clamp(balance: i128) -> u64 {
+    balance.clamp(0, u64::MAX as i128) as u64
+}
+
+/// Statement of the currency over `[from, until)`
+pub fn currency_statement(
+    user: &UserInfo,
+    currency: &Currency,
+    from: NaiveDateTime,
+    until: NaiveDateTime,
+) -> CurrencyStatement {
+    let changes = currency_changes(user, currency);
+    let mut balance: i128 = changes
+        .iter()
+        .take_while(|c| c.time < from)
+        .map(|c| c.signed())
+        .sum();
+    let opening_balance = clamp(balance);
+    let mut fees = 0;
+    let entries = changes
+        .into_iter()
+        .skip_while(|c| c.time < from)
+        .take_while(|c| c.time < until)
+        .map(|c| {
+            balance += c.signed();
+            if c.kind == StatementEntryKind::WithdrawalFee {
+                fees += c.amount;
+            }
+            let (credit, debit) = if c.is_credit() { (c.amount, 0) } else { (0, c.amount) };
+            StatementEntry {
+                time: c.time,
+                kind: c.kind,
+                credit,
+                debit,
+                balance: clamp(balance),
+                reference: c.reference,
+            }
+        })
+        .collect();
+    CurrencyStatement {
+        currency: currency.clone(),
+        opening_balance,
+        closing_balance: clamp(balance),
+        fees,
+        entries,
+    }
+}
+
+/// Statement of all user's currencies over `[from, until)`
+pub fn user_statement(user: &UserInfo, from: NaiveDateTime, until: NaiveDateTime) -> Statement {
+    let mut currencies: Vec<Currency> = user.currencies.keys().cloned().collect();
+    currencies.sort();
+    Statement {
+        user: user.username.clone(),
+        from,
+        until,
+        currencies: currencies
+            .iter()
+            .map(|c| currency_statement(user, c, from, until))
+            .collect(),
+    }
+}
@@ -0,0 +1,152 @@
+use chrono::NaiveDateTime;
+use hexstody_api::domain::Currency;
+use hexstody_api::types::{
+    SignatureData, TransferConfirmationData, TransferStatus, WithdrawalRequestDecisionType,
+};
+use p256::{ecdsa::Signature, PublicKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::withdraw::WithdrawalRequestType;
+use crate::update::signup::UserId;
+use crate::update::withdrawal::FiatRates;
+
+pub type TransferId = Uuid;
+
+/// Body of the internal transfer update
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct InternalTransferUpd {
+    pub id: TransferId,
+    /// Sender
+    pub from: UserId,
+    /// Recipient
+    pub to: UserId,
+    pub currency: Currency,
+    pub amount: u64,
+    pub created_at: NaiveDateTime,
+    /// Transfers under the sender's limits are executed without operators' decision
+    pub request_type: WithdrawalRequestType,
+    /// Prices of one coin in fiat at the request time, used for fiat limits
+    #[serde(default)]
+    pub rates: FiatRates,
+}
+
+/// Transfer as it is stored with both users. The sender holds the funds while it is pending,
+/// the recipient gets it only when it is completed.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct InternalTransfer {
+    pub id: TransferId,
+    pub from: UserId,
+    pub to: UserId,
+    pub currency: Currency,
+    pub amount: u64,
+    pub created_at: NaiveDateTime,
+    pub status: TransferStatus,
+    pub request_type: WithdrawalRequestType,
+    pub confirmations: Vec<SignatureData>,
+    pub rejections: Vec<SignatureData>,
+    /// When the funds were moved to the recipient
+    pub executed_at: Option<NaiveDateTime>,
+}
+
+impl InternalTransfer {
+    pub fn is_completed(&self) -> bool {
+        matches!(self.status, TransferStatus::Completed)
+    }
+    pub fn is_rejected(&self) -> bool {
+        matches!(self.status, TransferStatus::Rejected)
+    }
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, TransferStatus::InProgress { .. })
+    }
+    pub fn is_auto_executed(&self) -> bool {
+        self.is_completed() && self.request_type == WithdrawalRequestType::UnderLimit
+    }
+    /// When the transfer was completed, or created if it is not completed
+    pub fn time(&self) -> NaiveDateTime {
+        self.executed_at.unwrap_or(self.created_at)
+    }
+    pub fn has_confirmed(&self, pubkey: PublicKey) -> bool {
+        self.confirmations.iter().any(|sd| sd.public_key == pubkey)
+    }
+    pub fn has_rejected(&self, pubkey: PublicKey) -> bool {
+        self.rejections.iter().any(|sd| sd.public_key == pubkey)
+    }
+}
+
+impl From<InternalTransferUpd> for InternalTransfer {
+    fn from(upd: InternalTransferUpd) -> Self {
+        InternalTransfer {
+            id: upd.id,
+            from: upd.from,
+            to: upd.to,
+            currency: upd.currency,
+            amount: upd.amount,
+            created_at: upd.created_at,
+            status: TransferStatus::InProgress {
+                confirmations_minus_rejections: 0,
+            },
+            request_type: upd.request_type,
+            confirmations: Vec::new(),
+            rejections: Vec::new(),
+            executed_at: None,
+        }
+    }
+}
+
+impl From<InternalTransfer> for hexstody_api::types::InternalTransfer {
+    fn from(t: InternalTransfer) -> Self {
+        let auto_executed = t.is_auto_executed();
+        let InternalTransfer { id, from, to, currency, amount, created_at, status, .. } = t;
+        hexstody_api::types::InternalTransfer { id, from, to, currency, amount, created_at, status, auto_executed }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct InternalTransferDecision {
+    /// Transfer id
+    pub id: TransferId,
+    /// Sender of the transfer
+    pub from: UserId,
+    /// Currency of the transfer
+    pub currency: Currency,
+    /// API URL wich was used to send the decision
+    pub url: String,
+    /// Operator's digital signature
+    pub signature: Signature,
+    /// Nonce that was generated during decision
+    pub nonce: u64,
+    /// Operator's public key corresponding to the signing private key
+    pub public_key: PublicKey,
+    /// Decision type: confirm or reject
+    pub decision: WithdrawalRequestDecisionType,
+}
+
+impl
+    From<(
+        TransferConfirmationData,
+        SignatureData,
+        WithdrawalRequestDecisionType,
+        String,
+    )> for InternalTransferDecision
+{
+    fn from(
+        value: (
+            TransferConfirmationData,
+            SignatureData,
+            WithdrawalRequestDecisionType,
+            String,
+        ),
+    ) -> InternalTransferDecision {
+        InternalTransferDecision {
+            id: value.0.id,
+            from: value.0.from,
+            currency: value.0.currency,
+            url: value.3,
+            signature: value.1.signature,
+            nonce: value.1.nonce,
+            public_key: value.1.public_key,
+            decision: value.2,
+        }
+    }
+}
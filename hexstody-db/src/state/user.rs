@@ -1,25 +1,38 @@
 use super::exchange::ExchangeOrder;
 use super::exchange::ExchangeOrderId;
+use super::transfer::{InternalTransfer, TransferId};
 use super::transaction::*;
 use super::withdraw::*;
+use crate::update::address_book::AddressBookAdd;
 use crate::update::btc::BtcTxCancel;
 use crate::update::limit::LimitChangeData;
 use crate::update::signup::{SignupAuth, SignupInfo, UserId};
+use crate::update::totp::TotpEnroll;
+use crate::update::webauthn::WebauthnCredentialAdd;
+use crate::update::webhook::WebhookRegister;
+use crate::update::withdrawal::FiatRates;
 use chrono::prelude::*;
 use hexstody_api::domain::CurrencyTxId;
 use hexstody_api::domain::Email;
+use hexstody_api::domain::Fiat;
 use hexstody_api::domain::Language;
 use hexstody_api::domain::PhoneNumber;
+use hexstody_api::domain::{Rate, Rounding};
 use hexstody_api::domain::TgName;
 use hexstody_api::domain::Unit;
 use hexstody_api::domain::{Currency, CurrencyAddress};
+use hexstody_api::types::{AccountStatus, AddressBookEntry, WithdrawalAllowlistStatus};
 use hexstody_api::types::ExchangeFilter;
 use hexstody_api::types::Invite;
-use hexstody_api::types::LimitInfo;
+use hexstody_api::types::LimitChangeOpResponse;
+use hexstody_api::types::{Limit, LimitInfo, LimitSpan};
+use hexstody_api::types::WebauthnCredentialInfo;
+use hexstody_api::types::WebhookInfo;
 use p256::PublicKey;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct UserConfig {
@@ -57,7 +70,25 @@ pub struct UserInfo {
     /// User's config
     pub config: UserConfig,
     /// User's public key for public key authroization
-    pub public_key: Option<PublicKey>
+    pub public_key: Option<PublicKey>,
+    /// Registered endpoints for event delivery
+    pub webhooks: HashMap<Uuid, Webhook>,
+    /// TOTP second factor, if enabled
+    pub totp: Option<Totp>,
+    /// WebAuthn credentials by base64url encoded credential ID
+    pub webauthn_credentials: HashMap<String, WebauthnCredential>,
+    /// Fiat limits over withdrawals in all currencies, set by operators
+    #[serde(default)]
+    pub aggregate_limits: Vec<Limit>,
+    /// Saved withdrawal addresses
+    #[serde(default)]
+    pub address_book: HashMap<Uuid, SavedAddress>,
+    /// Withdrawals only to saved addresses
+    #[serde(default)]
+    pub allowlist: WithdrawalAllowlist,
+    /// Frozen and closed accounts can't sign in, withdraw or exchange
+    #[serde(default)]
+    pub status: AccountStatus,
 }
 
 impl UserInfo {
@@ -75,10 +106,96 @@ impl UserInfo {
                 .collect(),
             limit_change_requests: HashMap::new(),
             config: UserConfig::default(),
-            public_key: Option::default()
+            public_key: Option::default(),
+            webhooks: HashMap::new(),
+            totp: None,
+            webauthn_credentials: HashMap::new(),
+            aggregate_limits: Vec::new(),
+            address_book: HashMap::new(),
+            allowlist: WithdrawalAllowlist::default(),
+            status: AccountStatus::Active,
         }
     }
 
+    pub fn is_active(&self) -> bool {
+        self.status == AccountStatus::Active
+    }
+
+    /// Whether any withdrawal, exchange or transfer of the user still waits for a decision
+    /// or any deposit is not finalized yet
+    pub fn has_pending_operations(&self) -> bool {
+        self.currencies.values().any(|info| {
+            info.withdrawal_requests.values().any(|w| w.is_pending())
+                || info.exchange_requests.values().any(|o| o.is_pending())
+                || info.outgoing_transfers.values().any(|t| t.is_pending())
+                || info
+                    .unconfirmed_transactions()
+                    .any(|t| !t.is_withdraw() && !t.is_finalized())
+        })
+    }
+
+    /// Take all finalized balances off the account, see `UserCurrencyInfo::sweep`
+    pub fn sweep_balances(&mut self, now: NaiveDateTime) {
+        for info in self.currencies.values_mut() {
+            let amount = info.finalized_balance();
+            if amount > 0 {
+                info.sweep = Some(BalanceSweep { amount, swept_at: now });
+            }
+        }
+    }
+
+    /// Whether the user can withdraw to the address at the given time
+    pub fn can_withdraw_to(&self, address: &CurrencyAddress, now: NaiveDateTime) -> bool {
+        !self.allowlist.is_enforced(now)
+            || self
+                .address_book
+                .values()
+                .any(|a| a.address == *address && a.available_at <= now)
+    }
+
+    /// Fiat value of under limit withdrawals in all currencies made after the given time
+    pub fn aggregate_spent(&self, fiat: &Fiat, since: NaiveDateTime) -> Rate {
+        self.currencies
+            .values()
+            .fold(Rate::ZERO, |sum, cinfo| sum.saturating_add(cinfo.fiat_spent(fiat, since)))
+    }
+
+    /// State of each aggregate limit over its rolling window ending at the given time
+    pub fn aggregate_limit_infos(&self, now: NaiveDateTime) -> Vec<LimitInfo> {
+        self.aggregate_limits
+            .iter()
+            .filter_map(|limit| {
+                let fiat = limit.fiat.as_ref()?;
+                Some(LimitInfo {
+                    limit: limit.clone(),
+                    spent: self.aggregate_spent(fiat, now - limit.span.window()).ceil(),
+                })
+            })
+            .collect()
+    }
+
+    /// Check that the withdrawal fits into the aggregate limits at the given time
+    pub fn fits_aggregate_limits(
+        &self,
+        currency: &Currency,
+        amount: u64,
+        rates: &FiatRates,
+        now: NaiveDateTime,
+    ) -> bool {
+        let cinfo = match self.currencies.get(currency) {
+            Some(cinfo) => cinfo,
+            None => return false,
+        };
+        self.aggregate_limits.iter().all(|limit| {
+            let since = now - limit.span.window();
+            let total = limit.fiat.as_ref().and_then(|fiat| {
+                let value = cinfo.fiat_value(amount, *rates.get(fiat)?)?;
+                self.aggregate_spent(fiat, since).checked_add(value)
+            });
+            matches!(total, Some(total) if total <= Rate::from_integer(limit.amount))
+        })
+    }
+
     /// Return true if the user has given address as deposit address
     pub fn has_address(&self, address: &CurrencyAddress) -> bool {
         if let Some(cur_info) = self.currencies.get(&address.currency()) {
@@ -99,12 +216,30 @@ impl UserInfo {
             c.exchange_requests.values().filter_map(|eo| match filter {
                 ExchangeFilter::All => Some(eo.clone().into()),
                 ExchangeFilter::Completed => if eo.is_finalized() {Some(eo.clone().into())} else {None},
-                ExchangeFilter::Rejected => if eo.is_rejected() {Some(eo.clone().into())} else {None},
+                ExchangeFilter::Rejected => if eo.is_rejected() || eo.is_expired() {Some(eo.clone().into())} else {None},
                 ExchangeFilter::Pending => if eo.is_pending() {Some(eo.clone().into())} else {None},
             })
         ).collect()
     }
 
+    /// Limit change request as it is shown to operators
+    pub fn limit_change_op_response(&self, req: &LimitChangeData) -> LimitChangeOpResponse {
+        let current_limit = self
+            .currencies
+            .get(&req.currency)
+            .map(|cinfo| cinfo.current_limit(&req.limit.span))
+            .unwrap_or_else(|| LimitInfo::default().limit);
+        LimitChangeOpResponse {
+            id: req.id,
+            user: req.user.clone(),
+            created_at: req.created_at.clone(),
+            currency: req.currency.clone(),
+            current_limit,
+            requested_limit: req.limit.clone(),
+            status: req.status.clone(),
+        }
+    }
+
     pub fn get_unit_by_currency(&self, cur: Currency) -> Unit{
         self.currencies.get(&cur).map(|cinfo| cinfo.unit.clone()).unwrap_or(cur.default_unit())
     }
@@ -116,6 +251,59 @@ impl From<(NaiveDateTime, SignupInfo)> for UserInfo {
     }
 }
 
+/// User's endpoint for event delivery
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    /// Shared secret for HMAC signing of delivered events
+    pub secret: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<WebhookRegister> for Webhook {
+    fn from(req: WebhookRegister) -> Self {
+        Webhook {
+            id: req.id,
+            url: req.url,
+            secret: req.secret,
+            created_at: req.created_at,
+        }
+    }
+}
+
+impl From<Webhook> for WebhookInfo {
+    fn from(hook: Webhook) -> Self {
+        WebhookInfo {
+            id: hook.id,
+            url: hook.url,
+            created_at: hook.created_at,
+        }
+    }
+}
+
+fn default_limits() -> Vec<Limit> {
+    vec![LimitInfo::default().limit]
+}
+
+/// Under limit withdrawal counted against the limits
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct LimitSpend {
+    pub id: WithdrawalRequestId,
+    pub amount: u64,
+    pub spent_at: NaiveDateTime,
+    /// Prices of the coin at the withdrawal time
+    #[serde(default)]
+    pub rates: FiatRates,
+}
+
+/// Remaining balance of the closed account
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct BalanceSweep {
+    pub amount: u64,
+    pub swept_at: NaiveDateTime,
+}
+
 /// User data for specific currency
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct UserCurrencyInfo {
@@ -132,8 +320,25 @@ pub struct UserCurrencyInfo {
     pub exchange_requests: HashMap<ExchangeOrderId, ExchangeOrder>,
     /// Confirmed incoming exchange requests. We store only amounts for balance calculations
     pub incoming_exchange_requests: HashMap<ExchangeOrderId, u64>,
-    /// User's limit info. 
-    pub limit_info: LimitInfo,
+    /// Transfers to other users. Pending ones hold the funds until operators decide
+    #[serde(default)]
+    pub outgoing_transfers: HashMap<TransferId, InternalTransfer>,
+    /// Completed transfers from other users
+    #[serde(default)]
+    pub incoming_transfers: HashMap<TransferId, InternalTransfer>,
+    /// Withdrawal limits, at most one per span. Each of them is checked separately.
+    #[serde(default = "default_limits")]
+    pub limits: Vec<Limit>,
+    /// The only limit of snapshots made before several limits per currency.
+    /// Moved to `limits` when the snapshot is loaded, see `upgrade_legacy_limit`
+    #[serde(default, skip_serializing)]
+    limit_info: Option<LimitInfo>,
+    /// Under limit withdrawals that are still within the longest limit window
+    #[serde(default)]
+    pub limit_spends: Vec<LimitSpend>,
+    /// Balance taken off when the account was closed. Operators pay it out off the platform
+    #[serde(default)]
+    pub sweep: Option<BalanceSweep>,
     /// Unit used for this currency
     pub unit: Unit
 }
@@ -148,10 +353,122 @@ impl UserCurrencyInfo {
             withdrawal_requests: HashMap::new(),
             exchange_requests: HashMap::new(),
             incoming_exchange_requests: HashMap::new(),
-            limit_info: LimitInfo::default(),
+            outgoing_transfers: HashMap::new(),
+            incoming_transfers: HashMap::new(),
+            limits: default_limits(),
+            limit_info: None,
+            limit_spends: Vec::new(),
+            sweep: None,
+        }
+    }
+
+    /// Move the limit of the old snapshot to `limits`. The amount spent in the old
+    /// period is dropped, as it has no time to be counted in the rolling window.
+    pub fn upgrade_legacy_limit(&mut self) {
+        if let Some(info) = self.limit_info.take() {
+            self.limits = vec![info.limit];
+        }
+    }
+
+    fn spends_since(&self, since: NaiveDateTime) -> impl Iterator<Item = &LimitSpend> {
+        self.limit_spends.iter().filter(move |s| s.spent_at > since)
+    }
+
+    /// Value of the amount in fiat given the price of one coin, rounded up.
+    /// `None` if the value is out of range.
+    pub fn fiat_value(&self, amount: u64, rate: Rate) -> Option<Rate> {
+        rate.value_of(amount, self.currency.precision(), Rounding::Up)
+    }
+
+    /// Fiat value of under limit withdrawals made after the given time.
+    /// Spends without the recorded rate are not counted.
+    pub fn fiat_spent(&self, fiat: &Fiat, since: NaiveDateTime) -> Rate {
+        self.spends_since(since)
+            .filter_map(|s| self.fiat_value(s.amount, *s.rates.get(fiat)?))
+            .fold(Rate::ZERO, |sum, value| sum.saturating_add(value))
+    }
+
+    /// Amount spent within the rolling window of the limit, in units of the limit
+    pub fn limit_spent(&self, limit: &Limit, now: NaiveDateTime) -> u64 {
+        let since = now - limit.span.window();
+        match &limit.fiat {
+            None => self.spends_since(since).map(|s| s.amount).sum(),
+            Some(fiat) => self.fiat_spent(fiat, since).ceil(),
+        }
+    }
+
+    /// State of each limit over its rolling window ending at the given time
+    pub fn limit_infos(&self, now: NaiveDateTime) -> Vec<LimitInfo> {
+        self.limits
+            .iter()
+            .map(|limit| LimitInfo {
+                limit: limit.clone(),
+                spent: self.limit_spent(limit, now),
+            })
+            .collect()
+    }
+
+    /// The limit with the least remaining amount
+    pub fn limit_info(&self, now: NaiveDateTime) -> LimitInfo {
+        self.limit_infos(now)
+            .into_iter()
+            .min_by_key(|info| info.remaining())
+            .unwrap_or_default()
+    }
+
+    /// Current limit for the span. Falls back to the first limit if there is no such span.
+    pub fn current_limit(&self, span: &LimitSpan) -> Limit {
+        self.limits
+            .iter()
+            .find(|l| l.span == *span)
+            .or_else(|| self.limits.first())
+            .cloned()
+            .unwrap_or_else(|| LimitInfo::default().limit)
+    }
+
+    /// Check that the amount can be withdrawn at the given time without exceeding any limit.
+    /// The withdrawal is refused if a fiat limit is set and the rate of its fiat is missing.
+    pub fn fits_limits(&self, amount: u64, rates: &FiatRates, now: NaiveDateTime) -> bool {
+        self.limits.iter().all(|limit| {
+            let since = now - limit.span.window();
+            match &limit.fiat {
+                None => self.spends_since(since).map(|s| s.amount).sum::<u64>() + amount <= limit.amount,
+                Some(fiat) => {
+                    let total = rates
+                        .get(fiat)
+                        .and_then(|rate| self.fiat_value(amount, *rate))
+                        .and_then(|value| self.fiat_spent(fiat, since).checked_add(value));
+                    matches!(total, Some(total) if total <= Rate::from_integer(limit.amount))
+                }
+            }
+        })
+    }
+
+    /// Replace the limit with the same span and fiat or add a new one
+    pub fn set_limit(&mut self, limit: Limit) {
+        match self
+            .limits
+            .iter_mut()
+            .find(|l| l.span == limit.span && l.fiat == limit.fiat)
+        {
+            Some(old) => *old = limit,
+            None => self.limits.push(limit),
         }
     }
 
+    /// Count the withdrawal against the limits. Spends older than the longest window are dropped.
+    pub fn add_limit_spend(&mut self, spend: LimitSpend) {
+        // Aggregate limits of the user count spends of all currencies, so keep the longest possible window
+        let since = spend.spent_at - LimitSpan::Month.window();
+        self.limit_spends.retain(|s| s.spent_at > since);
+        self.limit_spends.push(spend);
+    }
+
+    /// Stop counting the withdrawal, e.g. when it was rejected by the node
+    pub fn remove_limit_spend(&mut self, id: &WithdrawalRequestId) {
+        self.limit_spends.retain(|s| s.id != *id);
+    }
+
     /// Includes unconfirmed transactions
     pub fn unconfirmed_transactions(&self) -> impl Iterator<Item = &Transaction> {
         self.transactions
@@ -171,25 +488,70 @@ impl UserCurrencyInfo {
                 }
             })
             .sum();
-        // Do not count rejected withdrawals
+        // Do not count rejected and cancelled withdrawals
         let pending_withdrawals: u64 = self.withdrawal_requests
             .iter()
             .map(|(_, w)|
-                if w.is_rejected() {0}
+                if w.is_rejected() || w.is_cancelled() {0}
                 else {
-                    w.amount + w.fee().unwrap_or(0)
+                    w.amount + w.user_fee().unwrap_or(0)
                 })
             .sum();
-        let incoming: u64 = self.incoming_exchange_requests.values().sum();
-        let outgoing: u64 = self.exchange_requests
-            .values()
-            .filter_map(|v| if v.is_rejected() {None} else {Some(v.amount_from)})
-            .sum();
+        let incoming: u64 = self.incoming_exchange_requests.values().sum::<u64>() + self.transferred_in();
+        let outgoing: u64 = self.exchanged_balance() + self.held_balance() + self.transferred_out() + self.swept();
         let val = (incoming as i64) - (pending_withdrawals as i64) - (outgoing as i64);
         // zero to prevent spreading overflow bug when in less then out
         0.max(tx_sum + val) as u64
     }
 
+    /// Funds held by pending exchange orders. Released when an order is rejected or expires
+    pub fn held_balance(&self) -> u64 {
+        self.exchange_requests
+            .values()
+            .filter_map(|v| if v.is_pending() {Some(v.amount_from)} else {None})
+            .sum()
+    }
+
+    /// Funds exchanged without operators' decision after the given time
+    pub fn auto_exchanged_since(&self, since: NaiveDateTime) -> u64 {
+        self.exchange_requests
+            .values()
+            .filter(|v| v.is_auto_executed() && v.executed_at.map_or(false, |t| t > since))
+            .map(|v| v.amount_from)
+            .sum()
+    }
+
+    /// Funds spent by completed exchange orders
+    pub fn exchanged_balance(&self) -> u64 {
+        self.exchange_requests
+            .values()
+            .filter_map(|v| if v.is_finalized() {Some(v.amount_from)} else {None})
+            .sum()
+    }
+
+    /// Funds sent to other users, including pending transfers
+    pub fn transferred_out(&self) -> u64 {
+        self.outgoing_transfers
+            .values()
+            .filter(|t| !t.is_rejected())
+            .map(|t| t.amount)
+            .sum()
+    }
+
+    /// Funds received from other users
+    pub fn transferred_in(&self) -> u64 {
+        self.incoming_transfers
+            .values()
+            .filter(|t| t.is_completed())
+            .map(|t| t.amount)
+            .sum()
+    }
+
+    /// Balance taken off when the account was closed
+    pub fn swept(&self) -> u64 {
+        self.sweep.as_ref().map_or(0, |s| s.amount)
+    }
+
     /// Include only finalized transactions
     pub fn finalized_balance(&self) -> u64 {
         let tx_sum: i64 = self
@@ -203,20 +565,17 @@ impl UserCurrencyInfo {
                 }
             })
             .sum();
-        // Do not count rejected withdrawals
+        // Do not count rejected and cancelled withdrawals
         let pending_withdrawals: u64 = self.withdrawal_requests
             .iter()
             .map(|(_, w)|
-                if w.is_rejected() {0}
+                if w.is_rejected() || w.is_cancelled() {0}
                 else {
-                    w.amount + w.fee().unwrap_or(0)
+                    w.amount + w.user_fee().unwrap_or(0)
                 })
             .sum();
-        let incoming: u64 = self.incoming_exchange_requests.values().sum();
-        let outgoing: u64 = self.exchange_requests
-            .values()
-            .filter_map(|v| if v.is_rejected() {None} else {Some(v.amount_from)})
-            .sum();
+        let incoming: u64 = self.incoming_exchange_requests.values().sum::<u64>() + self.transferred_in();
+        let outgoing: u64 = self.exchanged_balance() + self.held_balance() + self.transferred_out() + self.swept();
         let val = (incoming as i64) - (pending_withdrawals as i64) - (outgoing as i64);
         // zero to prevent spreading overflow bug when in less then out
         0.max(tx_sum + val) as u64
@@ -260,6 +619,20 @@ impl UserCurrencyInfo {
         }
     }
 
+    /// Add new ETH or token transaction or update confirmations of the known one
+    pub fn update_eth_tx(&mut self, upd_tx: &EthTransaction) {
+        for tx in self.transactions.iter_mut() {
+            match tx {
+                Transaction::Eth(eth_tx) if eth_tx.hash == upd_tx.hash => {
+                    *eth_tx = upd_tx.clone();
+                    return;
+                }
+                _ => (),
+            }
+        }
+        self.transactions.push(Transaction::Eth(upd_tx.clone()));
+    }
+
     pub fn cancel_btc_tx(&mut self, upd_tx: &BtcTxCancel) {
         let mut remove_i = None;
         for (i, tx) in self.transactions.iter().enumerate() {
@@ -276,3 +649,122 @@ impl UserCurrencyInfo {
         }
     }
 }
+
+/// Enabled TOTP second factor of the user
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Totp {
+    /// Base32 encoded shared secret
+    pub secret: String,
+    /// SHA-256 hashes of unused recovery codes
+    pub recovery_codes: Vec<String>,
+    pub require_for_withdrawal: bool,
+    pub require_for_api_keys: bool,
+    pub enrolled_at: NaiveDateTime,
+}
+
+impl From<TotpEnroll> for Totp {
+    fn from(req: TotpEnroll) -> Self {
+        Totp {
+            secret: req.secret,
+            recovery_codes: req.recovery_codes,
+            require_for_withdrawal: req.require_for_withdrawal,
+            require_for_api_keys: req.require_for_api_keys,
+            enrolled_at: req.enrolled_at,
+        }
+    }
+}
+
+/// Withdrawal address saved by the user
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SavedAddress {
+    pub id: Uuid,
+    pub address: CurrencyAddress,
+    pub label: String,
+    pub created_at: NaiveDateTime,
+    /// Newly added addresses get a cool-down before they can be used
+    pub available_at: NaiveDateTime,
+}
+
+impl From<AddressBookAdd> for SavedAddress {
+    fn from(req: AddressBookAdd) -> Self {
+        SavedAddress {
+            id: req.id,
+            address: req.address,
+            label: req.label,
+            created_at: req.created_at,
+            available_at: req.available_at,
+        }
+    }
+}
+
+impl From<SavedAddress> for AddressBookEntry {
+    fn from(a: SavedAddress) -> Self {
+        AddressBookEntry {
+            id: a.id,
+            address: a.address,
+            label: a.label,
+            created_at: a.created_at,
+            available_at: a.available_at,
+        }
+    }
+}
+
+/// Allow-list of withdrawal addresses. Switching it off is delayed like adding
+/// an address, so it can't be used to bypass the cool-down
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct WithdrawalAllowlist {
+    pub enabled: bool,
+    /// The allow-list is enforced until this time
+    pub disabled_at: Option<NaiveDateTime>,
+}
+
+impl WithdrawalAllowlist {
+    pub fn is_enforced(&self, now: NaiveDateTime) -> bool {
+        self.enabled && self.disabled_at.map_or(true, |t| now < t)
+    }
+
+    pub fn status(&self, now: NaiveDateTime) -> WithdrawalAllowlistStatus {
+        let enabled = self.is_enforced(now);
+        WithdrawalAllowlistStatus {
+            enabled,
+            disabled_at: if enabled { self.disabled_at } else { None },
+        }
+    }
+}
+
+/// Registered WebAuthn authenticator of the user
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct WebauthnCredential {
+    /// Base64url encoded credential ID
+    pub id: String,
+    pub name: String,
+    pub public_key: PublicKey,
+    /// Last seen signature counter
+    pub sign_count: u32,
+    pub created_at: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+impl From<WebauthnCredentialAdd> for WebauthnCredential {
+    fn from(req: WebauthnCredentialAdd) -> Self {
+        WebauthnCredential {
+            id: req.credential_id,
+            name: req.name,
+            public_key: req.public_key,
+            sign_count: req.sign_count,
+            created_at: req.created_at,
+            last_used: None,
+        }
+    }
+}
+
+impl From<WebauthnCredential> for WebauthnCredentialInfo {
+    fn from(cred: WebauthnCredential) -> Self {
+        WebauthnCredentialInfo {
+            id: cred.id,
+            name: cred.name,
+            created_at: cred.created_at,
+            last_used: cred.last_used,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crate::update::withdrawal::WithdrawalRequestInfo;
 use crate::update::{signup::UserId, withdrawal::WithdrawalRequestDecision};
 use hexstody_api::domain::{CurrencyAddress, CurrencyTxId};
 use hexstody_api::types::{
-    WithdrawalFilter, WithdrawalRequest as WithdrawalRequestApi,
+    FeeTier, WithdrawalFilter, WithdrawalRequest as WithdrawalRequestApi,
     WithdrawalRequestStatus as WithdrawalRequestStatusApi,
 };
 
@@ -39,6 +39,10 @@ pub enum WithdrawalRequestStatus {
         /// Node
         reason: String,
     },
+    /// Cancelled by the user before operators confirmed it
+    Cancelled {
+        cancelled_at: NaiveDateTime,
+    },
 }
 
 impl Into<WithdrawalRequestStatusApi> for WithdrawalRequestStatus {
@@ -67,6 +71,9 @@ impl Into<WithdrawalRequestStatusApi> for WithdrawalRequestStatus {
             WithdrawalRequestStatus::NodeRejected { reason } => {
                 WithdrawalRequestStatusApi::NodeRejected { reason }
             }
+            WithdrawalRequestStatus::Cancelled { cancelled_at } => {
+                WithdrawalRequestStatusApi::Cancelled { cancelled_at }
+            }
         }
     }
 }
@@ -97,6 +104,12 @@ pub struct WithdrawalRequest {
     pub rejections: Vec<WithdrawalRequestDecision>,
     /// Withdrawal request type
     pub request_type: WithdrawalRequestType,
+    /// Priority of the transaction chosen by the user
+    #[serde(default)]
+    pub fee_tier: FeeTier,
+    /// Fee charged by the fee policy. `None` if the network fee is charged
+    #[serde(default)]
+    pub charged_fee: Option<u64>,
 }
 
 impl From<(DateTime<Utc>, WithdrawalRequestInfo)> for WithdrawalRequest {
@@ -113,6 +126,8 @@ impl From<(DateTime<Utc>, WithdrawalRequestInfo)> for WithdrawalRequest {
             confirmations: vec![],
             rejections: vec![],
             request_type: value.1.request_type,
+            fee_tier: value.1.fee_tier,
+            charged_fee: value.1.charged_fee,
         }
     }
 }
@@ -127,6 +142,8 @@ impl Into<WithdrawalRequestApi> for WithdrawalRequest {
             created_at: self.created_at.to_string(),
             amount: self.amount,
             confirmation_status: confirmation_status,
+            fee_tier: self.fee_tier,
+            charged_fee: self.charged_fee,
         }
     }
 }
@@ -140,6 +157,30 @@ impl WithdrawalRequest {
         }
     }
 
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.status, WithdrawalRequestStatus::Cancelled { .. })
+    }
+
+    /// Waiting for operators or for the node
+    pub fn is_pending(&self) -> bool {
+        matches!(
+            self.status,
+            WithdrawalRequestStatus::InProgress { .. } | WithdrawalRequestStatus::Confirmed
+        )
+    }
+
+    /// Only requests waiting for operators can be cancelled.
+    /// Under limit requests are sent to the node at once.
+    pub fn is_cancellable(&self) -> bool {
+        matches!(self.status, WithdrawalRequestStatus::InProgress { .. })
+            && self.request_type == WithdrawalRequestType::OverLimit
+    }
+
+    /// Fee the user pays: the charged one or the network fee when the request is completed
+    pub fn user_fee(&self) -> Option<u64> {
+        self.charged_fee.or_else(|| self.fee())
+    }
+
     /// Get fee for completed withdrawals, 'None' for others
     pub fn fee(&self) -> Option<u64> {
         match self.status {
@@ -167,6 +208,9 @@ impl WithdrawalRequest {
                 WithdrawalRequestStatus::NodeRejected { .. } => {
                     matches!(filter, WithdrawalFilter::NodeRejected)
                 }
+                WithdrawalRequestStatus::Cancelled { .. } => {
+                    matches!(filter, WithdrawalFilter::Cancelled)
+                }
             }
         }
     }
@@ -0,0 +1,34 @@
+use chrono::NaiveDateTime;
+use hexstody_api::domain::CurrencyAddress;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::signup::UserId;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AddressBookAdd {
+    /// Entry ID
+    pub id: Uuid,
+    /// Owner of the address book
+    pub user: UserId,
+    pub address: CurrencyAddress,
+    pub label: String,
+    /// When the address was saved
+    pub created_at: NaiveDateTime,
+    /// Withdrawals to the address are allowed since this time
+    pub available_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AddressBookRemove {
+    pub id: Uuid,
+    pub user: UserId,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SetWithdrawalAllowlist {
+    pub user: UserId,
+    pub enabled: bool,
+    /// When the allow-list is switched off. Ignored when it is switched on
+    pub effective_at: NaiveDateTime,
+}
@@ -0,0 +1,32 @@
+use std::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use hexstody_api::types::ApiKeyScope;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::signup::UserId;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ApiKeyCreate {
+    /// API key ID
+    pub id: Uuid,
+    /// Owner of the key
+    pub user: UserId,
+    /// SHA-256 of the key. The key itself is never stored
+    pub key_hash: String,
+    /// First characters of the key to tell keys apart
+    pub prefix: String,
+    pub label: String,
+    pub scopes: Vec<ApiKeyScope>,
+    /// Empty list allows any address
+    pub allowed_ips: Vec<IpAddr>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ApiKeyRevoke {
+    pub id: Uuid,
+    pub user: UserId,
+}
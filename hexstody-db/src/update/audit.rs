@@ -0,0 +1,15 @@
+use hexstody_api::types::{OperatorActionType, SignatureData};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Operator's signed request recorded to the audit log
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct OperatorActionRec {
+    pub id: Uuid,
+    pub action: OperatorActionType,
+    /// Signed URL of the request
+    pub url: String,
+    pub signature_data: SignatureData,
+    /// Signed request body
+    pub payload: String,
+}
@@ -0,0 +1,14 @@
+use hexstody_api::domain::Currency;
+use serde::{Deserialize, Serialize};
+
+use super::signup::UserId;
+use crate::state::transaction::EthTransaction;
+
+/// Deposit to the user's ETH or token account seen by the ETH module,
+/// or new confirmations of a known one
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct EthDepositUpd {
+    pub user: UserId,
+    pub currency: Currency,
+    pub tx: EthTransaction,
+}
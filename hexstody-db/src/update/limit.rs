@@ -1,4 +1,5 @@
 use hexstody_api::{domain::Currency, types::{Limit, LimitChangeStatus, SignatureData, LimitChangeResponse, LimitChangeDecisionType, LimitConfirmationData, LimitChangeFilter}};
+use chrono::NaiveDateTime;
 use p256::{ecdsa::Signature, PublicKey};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
@@ -12,9 +13,24 @@ pub struct LimitCancelData{
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct LimitChangeUpd{
+    /// ID of the created request. Missing only in events recorded before it was
+    /// added to the body, see 'legacy_event_id'.
+    #[serde(default)]
+    pub id: Option<Uuid>,
     pub user: String,
     pub currency: Currency,
-    pub limit: Limit
+    pub limit: Limit,
+    /// Missing only in old events, the time of the event is used then
+    #[serde(default)]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// Limits over all currencies of the user, set by operators
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AggregateLimitsUpd{
+    pub user: String,
+    /// Fiat limits, the fiat is required
+    pub limits: Vec<Limit>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
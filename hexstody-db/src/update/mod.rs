@@ -1,27 +1,46 @@
 pub mod btc;
+pub mod eth;
 pub mod deposit;
 pub mod signup;
 pub mod withdrawal;
 pub mod results;
 pub mod misc;
 pub mod limit;
+pub mod webhook;
+pub mod api_key;
+pub mod totp;
+pub mod webauthn;
+pub mod address_book;
+pub mod audit;
 
+use bitcoin_hashes::{sha256, Hash as _};
 use chrono::prelude::*;
 use hexstody_api::domain::CurrencyAddress;
-use hexstody_api::types::LimitSpan;
+use hexstody_api::types::{CurrencyFeePolicy, ExchangeLimits, LimitSpan};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 use thiserror::Error;
+use uuid::Uuid;
 
-use crate::state::exchange::{ExchangeOrderUpd, ExchangeDecision};
+use crate::state::exchange::{ExchangeOrderUpd, ExchangeDecision, ExchangeExpire};
+use crate::state::transfer::{InternalTransferDecision, InternalTransferUpd};
+use crate::state::settings::{SettingsChangeDecision, SettingsChangeUpd};
+use crate::state::account::{AccountChangeDecision, AccountChangeUpd};
 
 use self::btc::{BestBtcBlock, BtcTxCancel};
+use self::eth::EthDepositUpd;
 use self::deposit::DepositAddress;
-use self::limit::{LimitChangeUpd, LimitCancelData, LimitChangeDecision};
+use self::limit::{AggregateLimitsUpd, LimitChangeUpd, LimitCancelData, LimitChangeDecision};
 use self::signup::SignupInfo;
-use self::withdrawal::{WithdrawalRequestDecisionInfo, WithdrawalRequestInfo, WithdrawCompleteInfo, WithdrawalRejectInfo};
+use self::withdrawal::{WithdrawalRequestDecisionInfo, WithdrawalRequestInfo, WithdrawCompleteInfo, WithdrawalRejectInfo, WithdrawalCancelInfo};
 use self::misc::{InviteRec, TokenUpdate, SetLanguage, ConfigUpdateData, PasswordChangeUpd, SetPublicKey, SetUnit};
+use self::webhook::{WebhookRegister, WebhookRemove};
+use self::api_key::{ApiKeyCreate, ApiKeyRevoke};
+use self::totp::{TotpDisable, TotpEnroll, TotpRecoveryCodeUsed, TotpRecoveryCodes};
+use self::webauthn::{WebauthnCredentialAdd, WebauthnCredentialRemove, WebauthnCredentialUse};
+use self::address_book::{AddressBookAdd, AddressBookRemove, SetWithdrawalAllowlist};
+use self::audit::OperatorActionRec;
 use super::state::transaction::BtcTransaction;
 use super::state::State;
 
@@ -43,6 +62,17 @@ impl StateUpdate {
     }
 }
 
+/// ID for an entity created by an event that was recorded before the ID became
+/// part of the event body. Derived from the event time and the discriminator,
+/// so every replay of the history gives the same ID.
+pub fn legacy_event_id(created: NaiveDateTime, discriminator: &str) -> Uuid {
+    let data = format!("{}:{}", created.timestamp_nanos(), discriminator);
+    let hash = sha256::Hash::hash(data.as_bytes()).into_inner();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash[..16]);
+    Uuid::from_bytes(bytes)
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum UpdateBody {
     /// Create new account for user
@@ -75,7 +105,8 @@ pub enum UpdateBody {
     CancelLimitChange(LimitCancelData),
     /// Limit change decision
     LimitChangeDecision(LimitChangeDecision),
-    /// Clear limits by span
+    /// Clear limits by span. Legacy: not issued anymore as limits are applied over
+    /// rolling windows, kept to read the history, see `is_legacy`
     ClearLimits(LimitSpan),
     /// Set language
     SetLanguage(SetLanguage),
@@ -92,10 +123,81 @@ pub enum UpdateBody {
     /// Set up exchange deposit address
     ExchangeAddress(CurrencyAddress),
     /// Set user's unit info
-    SetUnit(SetUnit)
+    SetUnit(SetUnit),
+    /// Register user's webhook
+    RegisterWebhook(WebhookRegister),
+    /// Remove user's webhook
+    RemoveWebhook(WebhookRemove),
+    /// Create user's API key
+    CreateApiKey(ApiKeyCreate),
+    /// Revoke user's API key
+    RevokeApiKey(ApiKeyRevoke),
+    /// Enable user's TOTP second factor
+    TotpEnroll(TotpEnroll),
+    /// Disable user's TOTP second factor
+    TotpDisable(TotpDisable),
+    /// Replace user's TOTP recovery codes
+    TotpRecoveryCodes(TotpRecoveryCodes),
+    /// Register user's WebAuthn credential
+    WebauthnCredentialAdd(WebauthnCredentialAdd),
+    /// Remove user's WebAuthn credential
+    WebauthnCredentialRemove(WebauthnCredentialRemove),
+    /// User signed in with WebAuthn credential
+    WebauthnCredentialUse(WebauthnCredentialUse),
+    /// Set limits over all currencies of the user.
+    /// Legacy: new limits go through `SettingsChangeRequest`, see `is_legacy`
+    SetAggregateLimits(AggregateLimitsUpd),
+    /// Pending exchange order passed its expiry time, the held funds are released
+    ExchangeExpired(ExchangeExpire),
+    /// Set limits for exchange orders executed without operators' decision.
+    /// Legacy: new limits go through `SettingsChangeRequest`, see `is_legacy`
+    SetExchangeLimits(ExchangeLimits),
+    /// Exchange order that is executed without operators' decision if it fits the exchange limits
+    ExchangeAutoExecuted(ExchangeOrderUpd),
+    /// Deposit to ETH or ERC20 account, or its new confirmations
+    EthDeposit(EthDepositUpd),
+    /// Move funds between two users of the custody
+    InternalTransfer(InternalTransferUpd),
+    /// Operator's decision on the internal transfer over the limits
+    InternalTransferDecision(InternalTransferDecision),
+    /// Save withdrawal address to the user's address book
+    AddressBookAdd(AddressBookAdd),
+    /// Remove address from the user's address book
+    AddressBookRemove(AddressBookRemove),
+    /// Allow withdrawals only to saved addresses or switch it off
+    SetWithdrawalAllowlist(SetWithdrawalAllowlist),
+    /// User cancelled the withdrawal request before operators confirmed it
+    CancelWithdrawalRequest(WithdrawalCancelInfo),
+    /// Operators set the withdrawal fee policy of the currency.
+    /// Legacy: new policies go through `SettingsChangeRequest`, see `is_legacy`
+    SetFeePolicy(CurrencyFeePolicy),
+    /// Operator's signed request recorded to the audit log
+    OperatorAction(OperatorActionRec),
+    /// Operator proposed new exchange margin or fee estimates
+    SettingsChangeRequest(SettingsChangeUpd),
+    /// Operator's decision on the proposed settings change
+    SettingsChangeDecision(SettingsChangeDecision),
+    /// Operator proposed to freeze, unfreeze or close the user's account
+    AccountChangeRequest(AccountChangeUpd),
+    /// Operator's decision on the proposed account change
+    AccountChangeDecision(AccountChangeDecision),
+    /// Removes the recovery code after it is used as the second factor
+    TotpRecoveryCodeUsed(TotpRecoveryCodeUsed),
 }
 
 impl UpdateBody {
+    /// Updates kept only to replay the recorded history. The changes they made
+    /// are confirmed by operators' quorum now, so the update worker refuses them.
+    pub fn is_legacy(&self) -> bool {
+        matches!(
+            self,
+            UpdateBody::ClearLimits(_)
+                | UpdateBody::SetExchangeLimits(_)
+                | UpdateBody::SetFeePolicy(_)
+                | UpdateBody::SetAggregateLimits(_)
+        )
+    }
+
     pub fn tag(&self) -> UpdateTag {
         match self {
             UpdateBody::Signup(_) => UpdateTag::Signup,
@@ -122,6 +224,34 @@ impl UpdateBody {
             UpdateBody::ExchangeDecision(_) => UpdateTag::ExchangeDecision,
             UpdateBody::ExchangeAddress(_) => UpdateTag::ExchangeAddress,
             UpdateBody::SetUnit(_) => UpdateTag::SetUnit,
+            UpdateBody::RegisterWebhook(_) => UpdateTag::RegisterWebhook,
+            UpdateBody::RemoveWebhook(_) => UpdateTag::RemoveWebhook,
+            UpdateBody::CreateApiKey(_) => UpdateTag::CreateApiKey,
+            UpdateBody::RevokeApiKey(_) => UpdateTag::RevokeApiKey,
+            UpdateBody::TotpEnroll(_) => UpdateTag::TotpEnroll,
+            UpdateBody::TotpDisable(_) => UpdateTag::TotpDisable,
+            UpdateBody::TotpRecoveryCodes(_) => UpdateTag::TotpRecoveryCodes,
+            UpdateBody::WebauthnCredentialAdd(_) => UpdateTag::WebauthnCredentialAdd,
+            UpdateBody::WebauthnCredentialRemove(_) => UpdateTag::WebauthnCredentialRemove,
+            UpdateBody::WebauthnCredentialUse(_) => UpdateTag::WebauthnCredentialUse,
+            UpdateBody::SetAggregateLimits(_) => UpdateTag::SetAggregateLimits,
+            UpdateBody::ExchangeExpired(_) => UpdateTag::ExchangeExpired,
+            UpdateBody::SetExchangeLimits(_) => UpdateTag::SetExchangeLimits,
+            UpdateBody::ExchangeAutoExecuted(_) => UpdateTag::ExchangeAutoExecuted,
+            UpdateBody::EthDeposit(_) => UpdateTag::EthDeposit,
+            UpdateBody::InternalTransfer(_) => UpdateTag::InternalTransfer,
+            UpdateBody::InternalTransferDecision(_) => UpdateTag::InternalTransferDecision,
+            UpdateBody::AddressBookAdd(_) => UpdateTag::AddressBookAdd,
+            UpdateBody::AddressBookRemove(_) => UpdateTag::AddressBookRemove,
+            UpdateBody::SetWithdrawalAllowlist(_) => UpdateTag::SetWithdrawalAllowlist,
+            UpdateBody::CancelWithdrawalRequest(_) => UpdateTag::CancelWithdrawalRequest,
+            UpdateBody::SetFeePolicy(_) => UpdateTag::SetFeePolicy,
+            UpdateBody::OperatorAction(_) => UpdateTag::OperatorAction,
+            UpdateBody::SettingsChangeRequest(_) => UpdateTag::SettingsChangeRequest,
+            UpdateBody::SettingsChangeDecision(_) => UpdateTag::SettingsChangeDecision,
+            UpdateBody::AccountChangeRequest(_) => UpdateTag::AccountChangeRequest,
+            UpdateBody::AccountChangeDecision(_) => UpdateTag::AccountChangeDecision,
+            UpdateBody::TotpRecoveryCodeUsed(_) => UpdateTag::TotpRecoveryCodeUsed,
         }
     }
 
@@ -151,6 +281,34 @@ impl UpdateBody {
             UpdateBody::ExchangeDecision(v) => serde_json::to_value(v),
             UpdateBody::ExchangeAddress(v) => serde_json::to_value(v),
             UpdateBody::SetUnit(v) => serde_json::to_value(v),
+            UpdateBody::RegisterWebhook(v) => serde_json::to_value(v),
+            UpdateBody::RemoveWebhook(v) => serde_json::to_value(v),
+            UpdateBody::CreateApiKey(v) => serde_json::to_value(v),
+            UpdateBody::RevokeApiKey(v) => serde_json::to_value(v),
+            UpdateBody::TotpEnroll(v) => serde_json::to_value(v),
+            UpdateBody::TotpDisable(v) => serde_json::to_value(v),
+            UpdateBody::TotpRecoveryCodes(v) => serde_json::to_value(v),
+            UpdateBody::WebauthnCredentialAdd(v) => serde_json::to_value(v),
+            UpdateBody::WebauthnCredentialRemove(v) => serde_json::to_value(v),
+            UpdateBody::WebauthnCredentialUse(v) => serde_json::to_value(v),
+            UpdateBody::SetAggregateLimits(v) => serde_json::to_value(v),
+            UpdateBody::ExchangeExpired(v) => serde_json::to_value(v),
+            UpdateBody::SetExchangeLimits(v) => serde_json::to_value(v),
+            UpdateBody::ExchangeAutoExecuted(v) => serde_json::to_value(v),
+            UpdateBody::EthDeposit(v) => serde_json::to_value(v),
+            UpdateBody::InternalTransfer(v) => serde_json::to_value(v),
+            UpdateBody::InternalTransferDecision(v) => serde_json::to_value(v),
+            UpdateBody::AddressBookAdd(v) => serde_json::to_value(v),
+            UpdateBody::AddressBookRemove(v) => serde_json::to_value(v),
+            UpdateBody::SetWithdrawalAllowlist(v) => serde_json::to_value(v),
+            UpdateBody::CancelWithdrawalRequest(v) => serde_json::to_value(v),
+            UpdateBody::SetFeePolicy(v) => serde_json::to_value(v),
+            UpdateBody::OperatorAction(v) => serde_json::to_value(v),
+            UpdateBody::SettingsChangeRequest(v) => serde_json::to_value(v),
+            UpdateBody::SettingsChangeDecision(v) => serde_json::to_value(v),
+            UpdateBody::AccountChangeRequest(v) => serde_json::to_value(v),
+            UpdateBody::AccountChangeDecision(v) => serde_json::to_value(v),
+            UpdateBody::TotpRecoveryCodeUsed(v) => serde_json::to_value(v),
         }
     }
 }
@@ -181,6 +339,34 @@ pub enum UpdateTag {
     ExchangeDecision,
     ExchangeAddress,
     SetUnit,
+    RegisterWebhook,
+    RemoveWebhook,
+    CreateApiKey,
+    RevokeApiKey,
+    TotpEnroll,
+    TotpDisable,
+    TotpRecoveryCodes,
+    WebauthnCredentialAdd,
+    WebauthnCredentialRemove,
+    WebauthnCredentialUse,
+    SetAggregateLimits,
+    ExchangeExpired,
+    SetExchangeLimits,
+    ExchangeAutoExecuted,
+    EthDeposit,
+    InternalTransfer,
+    InternalTransferDecision,
+    AddressBookAdd,
+    AddressBookRemove,
+    SetWithdrawalAllowlist,
+    CancelWithdrawalRequest,
+    SetFeePolicy,
+    OperatorAction,
+    SettingsChangeRequest,
+    SettingsChangeDecision,
+    AccountChangeRequest,
+    AccountChangeDecision,
+    TotpRecoveryCodeUsed,
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
@@ -221,6 +407,34 @@ impl fmt::Display for UpdateTag {
             UpdateTag::ExchangeDecision => write!(f, "exchange decision"),
             UpdateTag::ExchangeAddress => write!(f, "exchange address"),
             UpdateTag::SetUnit => write!(f, "set unit"),
+            UpdateTag::RegisterWebhook => write!(f, "register webhook"),
+            UpdateTag::RemoveWebhook => write!(f, "remove webhook"),
+            UpdateTag::CreateApiKey => write!(f, "create api key"),
+            UpdateTag::RevokeApiKey => write!(f, "revoke api key"),
+            UpdateTag::TotpEnroll => write!(f, "totp enroll"),
+            UpdateTag::TotpDisable => write!(f, "totp disable"),
+            UpdateTag::TotpRecoveryCodes => write!(f, "totp recovery codes"),
+            UpdateTag::WebauthnCredentialAdd => write!(f, "webauthn credential add"),
+            UpdateTag::WebauthnCredentialRemove => write!(f, "webauthn credential remove"),
+            UpdateTag::WebauthnCredentialUse => write!(f, "webauthn credential use"),
+            UpdateTag::SetAggregateLimits => write!(f, "set aggregate limits"),
+            UpdateTag::ExchangeExpired => write!(f, "exchange expired"),
+            UpdateTag::SetExchangeLimits => write!(f, "set exchange limits"),
+            UpdateTag::ExchangeAutoExecuted => write!(f, "exchange auto executed"),
+            UpdateTag::EthDeposit => write!(f, "eth deposit"),
+            UpdateTag::InternalTransfer => write!(f, "internal transfer"),
+            UpdateTag::InternalTransferDecision => write!(f, "internal transfer decision"),
+            UpdateTag::AddressBookAdd => write!(f, "address book add"),
+            UpdateTag::AddressBookRemove => write!(f, "address book remove"),
+            UpdateTag::SetWithdrawalAllowlist => write!(f, "set withdrawal allowlist"),
+            UpdateTag::CancelWithdrawalRequest => write!(f, "cancel withdrawal request"),
+            UpdateTag::SetFeePolicy => write!(f, "set fee policy"),
+            UpdateTag::OperatorAction => write!(f, "operator action"),
+            UpdateTag::SettingsChangeRequest => write!(f, "settings change request"),
+            UpdateTag::SettingsChangeDecision => write!(f, "settings change decision"),
+            UpdateTag::AccountChangeRequest => write!(f, "account change request"),
+            UpdateTag::AccountChangeDecision => write!(f, "account change decision"),
+            UpdateTag::TotpRecoveryCodeUsed => write!(f, "totp recovery code used"),
         }
     }
 }
@@ -254,6 +468,34 @@ impl FromStr for UpdateTag {
             "exchange decision" => Ok(UpdateTag::ExchangeDecision),
             "exchange address" => Ok(UpdateTag::ExchangeAddress),
             "set unit" => Ok(UpdateTag::SetUnit),
+            "register webhook" => Ok(UpdateTag::RegisterWebhook),
+            "remove webhook" => Ok(UpdateTag::RemoveWebhook),
+            "create api key" => Ok(UpdateTag::CreateApiKey),
+            "revoke api key" => Ok(UpdateTag::RevokeApiKey),
+            "totp enroll" => Ok(UpdateTag::TotpEnroll),
+            "totp disable" => Ok(UpdateTag::TotpDisable),
+            "totp recovery codes" => Ok(UpdateTag::TotpRecoveryCodes),
+            "webauthn credential add" => Ok(UpdateTag::WebauthnCredentialAdd),
+            "webauthn credential remove" => Ok(UpdateTag::WebauthnCredentialRemove),
+            "webauthn credential use" => Ok(UpdateTag::WebauthnCredentialUse),
+            "set aggregate limits" => Ok(UpdateTag::SetAggregateLimits),
+            "exchange expired" => Ok(UpdateTag::ExchangeExpired),
+            "set exchange limits" => Ok(UpdateTag::SetExchangeLimits),
+            "exchange auto executed" => Ok(UpdateTag::ExchangeAutoExecuted),
+            "eth deposit" => Ok(UpdateTag::EthDeposit),
+            "internal transfer" => Ok(UpdateTag::InternalTransfer),
+            "internal transfer decision" => Ok(UpdateTag::InternalTransferDecision),
+            "address book add" => Ok(UpdateTag::AddressBookAdd),
+            "address book remove" => Ok(UpdateTag::AddressBookRemove),
+            "set withdrawal allowlist" => Ok(UpdateTag::SetWithdrawalAllowlist),
+            "cancel withdrawal request" => Ok(UpdateTag::CancelWithdrawalRequest),
+            "set fee policy" => Ok(UpdateTag::SetFeePolicy),
+            "operator action" => Ok(UpdateTag::OperatorAction),
+            "settings change request" => Ok(UpdateTag::SettingsChangeRequest),
+            "settings change decision" => Ok(UpdateTag::SettingsChangeDecision),
+            "account change request" => Ok(UpdateTag::AccountChangeRequest),
+            "account change decision" => Ok(UpdateTag::AccountChangeDecision),
+            "totp recovery code used" => Ok(UpdateTag::TotpRecoveryCodeUsed),
             _ => Err(UnknownUpdateTag(s.to_owned())),
         }
     }
@@ -319,6 +561,34 @@ impl UpdateTag {
             UpdateTag::ExchangeDecision => Ok(UpdateBody::ExchangeDecision(serde_json::from_value(value)?)),
             UpdateTag::ExchangeAddress => Ok(UpdateBody::ExchangeAddress(serde_json::from_value(value)?)),
             UpdateTag::SetUnit => Ok(UpdateBody::SetUnit(serde_json::from_value(value)?)),
+            UpdateTag::RegisterWebhook => Ok(UpdateBody::RegisterWebhook(serde_json::from_value(value)?)),
+            UpdateTag::RemoveWebhook => Ok(UpdateBody::RemoveWebhook(serde_json::from_value(value)?)),
+            UpdateTag::CreateApiKey => Ok(UpdateBody::CreateApiKey(serde_json::from_value(value)?)),
+            UpdateTag::RevokeApiKey => Ok(UpdateBody::RevokeApiKey(serde_json::from_value(value)?)),
+            UpdateTag::TotpEnroll => Ok(UpdateBody::TotpEnroll(serde_json::from_value(value)?)),
+            UpdateTag::TotpDisable => Ok(UpdateBody::TotpDisable(serde_json::from_value(value)?)),
+            UpdateTag::TotpRecoveryCodes => Ok(UpdateBody::TotpRecoveryCodes(serde_json::from_value(value)?)),
+            UpdateTag::WebauthnCredentialAdd => Ok(UpdateBody::WebauthnCredentialAdd(serde_json::from_value(value)?)),
+            UpdateTag::WebauthnCredentialRemove => Ok(UpdateBody::WebauthnCredentialRemove(serde_json::from_value(value)?)),
+            UpdateTag::WebauthnCredentialUse => Ok(UpdateBody::WebauthnCredentialUse(serde_json::from_value(value)?)),
+            UpdateTag::SetAggregateLimits => Ok(UpdateBody::SetAggregateLimits(serde_json::from_value(value)?)),
+            UpdateTag::ExchangeExpired => Ok(UpdateBody::ExchangeExpired(serde_json::from_value(value)?)),
+            UpdateTag::SetExchangeLimits => Ok(UpdateBody::SetExchangeLimits(serde_json::from_value(value)?)),
+            UpdateTag::ExchangeAutoExecuted => Ok(UpdateBody::ExchangeAutoExecuted(serde_json::from_value(value)?)),
+            UpdateTag::EthDeposit => Ok(UpdateBody::EthDeposit(serde_json::from_value(value)?)),
+            UpdateTag::InternalTransfer => Ok(UpdateBody::InternalTransfer(serde_json::from_value(value)?)),
+            UpdateTag::InternalTransferDecision => Ok(UpdateBody::InternalTransferDecision(serde_json::from_value(value)?)),
+            UpdateTag::AddressBookAdd => Ok(UpdateBody::AddressBookAdd(serde_json::from_value(value)?)),
+            UpdateTag::AddressBookRemove => Ok(UpdateBody::AddressBookRemove(serde_json::from_value(value)?)),
+            UpdateTag::SetWithdrawalAllowlist => Ok(UpdateBody::SetWithdrawalAllowlist(serde_json::from_value(value)?)),
+            UpdateTag::CancelWithdrawalRequest => Ok(UpdateBody::CancelWithdrawalRequest(serde_json::from_value(value)?)),
+            UpdateTag::SetFeePolicy => Ok(UpdateBody::SetFeePolicy(serde_json::from_value(value)?)),
+            UpdateTag::OperatorAction => Ok(UpdateBody::OperatorAction(serde_json::from_value(value)?)),
+            UpdateTag::SettingsChangeRequest => Ok(UpdateBody::SettingsChangeRequest(serde_json::from_value(value)?)),
+            UpdateTag::SettingsChangeDecision => Ok(UpdateBody::SettingsChangeDecision(serde_json::from_value(value)?)),
+            UpdateTag::AccountChangeRequest => Ok(UpdateBody::AccountChangeRequest(serde_json::from_value(value)?)),
+            UpdateTag::AccountChangeDecision => Ok(UpdateBody::AccountChangeDecision(serde_json::from_value(value)?)),
+            UpdateTag::TotpRecoveryCodeUsed => Ok(UpdateBody::TotpRecoveryCodeUsed(serde_json::from_value(value)?)),
         }
     }
 }
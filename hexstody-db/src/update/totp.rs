@@ -0,0 +1,40 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::signup::UserId;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TotpEnroll {
+    pub user: UserId,
+    /// Base32 encoded shared secret
+    pub secret: String,
+    /// SHA-256 hashes of one-time recovery codes
+    pub recovery_codes: Vec<String>,
+    /// Ask for a code on each withdrawal request
+    pub require_for_withdrawal: bool,
+    /// Ask for a code on each API key creation
+    pub require_for_api_keys: bool,
+    pub enrolled_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TotpDisable {
+    pub user: UserId,
+}
+
+/// Replaces the whole set of recovery codes with regenerated ones
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TotpRecoveryCodes {
+    pub user: UserId,
+    /// SHA-256 hashes of one-time recovery codes
+    pub recovery_codes: Vec<String>,
+}
+
+/// Removes the used recovery code. Fails if the code is already used,
+/// so the same code can't pass two concurrent requests.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TotpRecoveryCodeUsed {
+    pub user: UserId,
+    /// SHA-256 hash of the code
+    pub code_hash: String,
+}
@@ -0,0 +1,35 @@
+use chrono::NaiveDateTime;
+use p256::PublicKey;
+use serde::{Deserialize, Serialize};
+
+use super::signup::UserId;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WebauthnCredentialAdd {
+    pub user: UserId,
+    /// Base64url encoded credential ID
+    pub credential_id: String,
+    /// Human readable name of the authenticator
+    pub name: String,
+    /// ES256 public key of the credential
+    pub public_key: PublicKey,
+    /// Signature counter reported at registration
+    pub sign_count: u32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WebauthnCredentialRemove {
+    pub user: UserId,
+    pub credential_id: String,
+}
+
+/// Successful sign in with the credential. Persists signature counter
+/// to detect cloned authenticators.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WebauthnCredentialUse {
+    pub user: UserId,
+    pub credential_id: String,
+    pub sign_count: u32,
+    pub used_at: NaiveDateTime,
+}
@@ -0,0 +1,25 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::signup::UserId;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WebhookRegister {
+    /// Webhook ID
+    pub id: Uuid,
+    /// Owner of the webhook
+    pub user: UserId,
+    /// Endpoint to deliver events to
+    pub url: String,
+    /// Shared secret for HMAC signing of delivered events
+    pub secret: String,
+    /// When the webhook was registered
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WebhookRemove {
+    pub id: Uuid,
+    pub user: UserId,
+}
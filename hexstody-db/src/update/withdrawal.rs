@@ -1,12 +1,14 @@
+use std::collections::HashMap;
+
 use chrono::NaiveDateTime;
 use p256::{ecdsa::Signature, PublicKey};
 use serde::{Deserialize, Serialize};
 
 use crate::state::withdraw::{WithdrawalRequestId, WithdrawalRequestType};
 use crate::update::signup::UserId;
-use hexstody_api::domain::{Currency, CurrencyAddress, CurrencyTxId};
+use hexstody_api::domain::{Currency, CurrencyAddress, CurrencyTxId, Fiat, Rate};
 use hexstody_api::types::{
-    ConfirmationData, SignatureData, WithdrawalRequestDecisionType,
+    ConfirmationData, FeeTier, SignatureData, WithdrawalRequestDecisionType,
 };
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -20,9 +22,28 @@ pub struct WithdrawalRequestInfo {
     /// Amount of tokens to transfer
     pub amount: u64,
     /// Withdrawal request type
-    pub request_type: WithdrawalRequestType 
+    pub request_type: WithdrawalRequestType,
+    /// Missing only in events recorded before it was added to the body,
+    /// the time of the event is used then
+    #[serde(default)]
+    pub created_at: Option<NaiveDateTime>,
+    /// Prices of one coin in fiat at the request time, used for fiat limits
+    #[serde(default)]
+    pub rates: FiatRates,
+    /// Priority of the transaction chosen by the user
+    #[serde(default)]
+    pub fee_tier: FeeTier,
+    /// Fee charged by the fee policy, separate from the network fee actually paid.
+    /// `None` if the network fee is charged, as for requests made before fee policies.
+    #[serde(default)]
+    pub charged_fee: Option<u64>,
 }
 
+/// Price of one whole coin of the currency in each fiat. Fixed point, so replaying
+/// the recorded rates gives the same limit checks. Old events with float rates are
+/// read by their shortest decimal representation.
+pub type FiatRates = HashMap<Fiat, Rate>;
+
 // This data type is used to create DB state update
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct WithdrawalRequestDecisionInfo {
@@ -125,4 +146,13 @@ pub struct WithdrawCompleteInfo {
 pub struct WithdrawalRejectInfo {
     pub id: WithdrawalRequestId,
     pub reason: String,
-}
\ No newline at end of file
+}
+
+/// Withdrawal request cancelled by the user while it waits for operators
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WithdrawalCancelInfo {
+    pub id: WithdrawalRequestId,
+    pub user: UserId,
+    pub currency: Currency,
+    pub cancelled_at: NaiveDateTime,
+}
@@ -10,6 +10,7 @@ use hexstody_btc_test::runner::run_regtest;
 use hexstody_db::state::{Network, CONFIRMATIONS_CONFIG};
 use hexstody_eth_client::client::EthClient;
 use hexstody_ticker_provider::client::TickerClient;
+use hexstody_ticker_provider::provider::{CoinGecko, CryptoCompare, StaticProvider, TickerProvider};
 use log::*;
 use runner::{run_hot_wallet, ApiConfig};
 use std::error::Error;
@@ -48,6 +49,15 @@ pub struct Args {
         env = "HEXSTODY_TICKER_PROVIDER"
     )]
     ticker_provider: String,
+    #[clap(
+        long,
+        default_value = "https://api.coingecko.com",
+        env = "HEXSTODY_COINGECKO_PROVIDER"
+    )]
+    coingecko_provider: String,
+    /// JSON file with fixed rates like `{"BTC": {"USD": "20000"}}`. Replaces online ticker providers
+    #[clap(long, env = "HEXSTODY_TICKER_RATES_FILE")]
+    ticker_rates_file: Option<PathBuf>,
     #[clap(long, default_value = "mainnet", env = "HEXSTODY_NETWORK")]
     network: Network,
     #[clap(long, env = "HEXSTODY_START_REGTEST")]
@@ -131,6 +141,17 @@ async fn run(
     }
 }
 
+fn make_ticker_client(args: &Args) -> TickerClient {
+    let providers: Vec<Arc<dyn TickerProvider>> = match &args.ticker_rates_file {
+        Some(path) => vec![Arc::new(StaticProvider::from_file(path).expect("ticker rates file"))],
+        None => vec![
+            Arc::new(CryptoCompare::new(&args.ticker_provider)),
+            Arc::new(CoinGecko::new(&args.coingecko_provider)),
+        ],
+    };
+    TickerClient::with_providers(providers)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
@@ -146,7 +167,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     args.operator_public_keys.clone(),
                     |(node1_port, _), (node2_port, _), (hbtc_url, btc_client)| {
                         let eth_client = EthClient::new(&args.eth_module);
-                        let ticker_client = TickerClient::new(&args.ticker_provider);
+                        let ticker_client = make_ticker_client(&args);
                         let mut args = args.clone();
                         args.network = Network::Regtest;
                         let start_notify = Arc::new(Notify::new());
@@ -178,7 +199,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             } else {
                 let btc_client = BtcClient::new(&args.btc_module);
                 let eth_client = EthClient::new(&args.eth_module);
-                let ticker_client = TickerClient::new(&args.ticker_provider);
+                let ticker_client = make_ticker_client(&args);
                 let start_notify = Arc::new(Notify::new());
                 run(btc_client, eth_client, ticker_client, &args, start_notify).await
             }
@@ -1,6 +1,8 @@
 use figment::Figment;
 use futures::future::{join3, AbortHandle, AbortRegistration, Abortable, Aborted};
 use futures::Future;
+use hexstody_api::types::OperatorEvent;
+use hexstody_auth::AuthStore;
 use hexstody_eth_client::client::EthClient;
 use hexstody_runtime_db::RuntimeState;
 use hexstody_ticker::worker::ticker_worker;
@@ -12,7 +14,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::{fmt, fs};
 use thiserror::Error;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::sync::{Mutex, Notify};
 
 use hexstody_btc_client::client::BtcClient;
@@ -21,6 +23,7 @@ use hexstody_db::*;
 use hexstody_db::{state::State, update::StateUpdate, Pool};
 use hexstody_operator;
 use hexstody_public;
+use hexstody_public::webhook::webhook_worker;
 
 use super::worker::*;
 use super::Args;
@@ -179,9 +182,12 @@ async fn serve_api(
     pool: Pool,
     state_mx: Arc<Mutex<State>>,
     runtime_state_mx: Arc<Mutex<RuntimeState>>,
+    auth_store_mx: Arc<Mutex<AuthStore>>,
     state_notify: Arc<Notify>,
     start_notify: Arc<Notify>,
     update_sender: mpsc::Sender<StateUpdate>,
+    applied_updates: broadcast::Sender<StateUpdate>,
+    operator_alerts: broadcast::Sender<OperatorEvent>,
     btc_client: BtcClient,
     eth_client: EthClient,
     ticker_client: TickerClient,
@@ -203,9 +209,11 @@ async fn serve_api(
                     pool.clone(),
                     state_mx.clone(),
                     runtime_state_mx.clone(),
+                    auth_store_mx.clone(),
                     state_notify.clone(),
                     start_notify.clone(),
                     update_sender.clone(),
+                    applied_updates.clone(),
                     btc_client,
                     eth_client,
                     ticker_client,
@@ -224,6 +232,8 @@ async fn serve_api(
                     state_notify.clone(),
                     start_notify.clone(),
                     update_sender.clone(),
+                    applied_updates.clone(),
+                    operator_alerts.clone(),
                     btc_client,
                     eth_client,
                     ticker_client,
@@ -239,11 +249,14 @@ pub async fn serve_apis(
     pool: Pool,
     state_mx: Arc<Mutex<State>>,
     runtime_state_mx: Arc<Mutex<RuntimeState>>,
+    auth_store_mx: Arc<Mutex<AuthStore>>,
     state_notify: Arc<Notify>,
     start_notify: Arc<Notify>,
     api_config: ApiConfig,
     api_abort: AbortRegistration,
     update_sender: mpsc::Sender<StateUpdate>,
+    applied_updates: broadcast::Sender<StateUpdate>,
+    operator_alerts: broadcast::Sender<OperatorEvent>,
     btc_client: BtcClient,
     eth_client: EthClient,
     ticker_client: TickerClient,
@@ -257,9 +270,12 @@ pub async fn serve_apis(
         pool.clone(),
         state_mx.clone(),
         runtime_state_mx.clone(),
+        auth_store_mx.clone(),
         state_notify.clone(),
         public_start.clone(),
         update_sender.clone(),
+        applied_updates.clone(),
+        operator_alerts.clone(),
         btc_client.clone(),
         eth_client.clone(),
         ticker_client.clone(),
@@ -273,9 +289,12 @@ pub async fn serve_apis(
         pool,
         state_mx,
         runtime_state_mx.clone(),
+        auth_store_mx,
         state_notify,
         operator_start.clone(),
         update_sender.clone(),
+        applied_updates,
+        operator_alerts,
         btc_client.clone(),
         eth_client.clone(),
         ticker_client.clone(),
@@ -326,15 +345,19 @@ pub async fn run_hot_wallet(
     let state = query_state(args.network, &pool).await?;
     let state_mx = Arc::new(Mutex::new(state));
     let runtime_state_mx = Arc::new(Mutex::new(RuntimeState::new()));
+    let auth_store_mx = Arc::new(Mutex::new(AuthStore::default()));
     let state_notify = Arc::new(Notify::new());
     let (update_sender, update_receiver) = mpsc::channel(1000);
     let (update_resp_sender, update_resp_receiver) = mpsc::channel(1000);
+    let (applied_sender, _) = broadcast::channel(1000);
+    let (operator_alerts, _) = broadcast::channel(100);
     let api_config = ApiConfig::parse_figment(args);
 
     let update_worker_hndl = tokio::spawn({
         let pool = pool.clone();
         let state_mx = state_mx.clone();
         let state_notify = state_notify.clone();
+        let applied_sender = applied_sender.clone();
         async move {
             update_worker(
                 pool,
@@ -342,6 +365,7 @@ pub async fn run_hot_wallet(
                 state_notify,
                 update_receiver,
                 update_resp_sender,
+                applied_sender,
             )
             .await;
         }
@@ -350,8 +374,9 @@ pub async fn run_hot_wallet(
         let state_mx = state_mx.clone();
         let btc_client = btc_client.clone();
         let update_sender = update_sender.clone();
+        let operator_alerts = operator_alerts.clone();
         async move {
-            btc_worker(btc_client, state_mx, update_sender).await;
+            btc_worker(btc_client, state_mx, update_sender, operator_alerts).await;
         }
     });
 
@@ -372,26 +397,59 @@ pub async fn run_hot_wallet(
         }
     });
 
-    let cron_workers_hndl = tokio::spawn({
+    let exchange_expiry_hndl = tokio::spawn({
+        let state_mx = state_mx.clone();
         let update_sender = update_sender.clone();
-        async move { cron_workers(update_sender).await }
+        async move { exchange_expiry_worker(state_mx, update_sender).await }
+    });
+
+    let eth_deposit_hndl = tokio::spawn({
+        let eth_client = eth_client.clone();
+        let state_mx = state_mx.clone();
+        let update_sender = update_sender.clone();
+        async move { eth_deposit_worker(eth_client, state_mx, update_sender).await }
     });
 
     let ticker_worker_hndl = tokio::spawn({
         let ticker_client = ticker_client.clone();
         let runtime_state_mx = runtime_state_mx.clone();
-        async move { ticker_worker(ticker_client, runtime_state_mx).await }
+        let pool = pool.clone();
+        async move { ticker_worker(ticker_client, runtime_state_mx, pool).await }
+    });
+
+    let settings_worker_hndl = tokio::spawn({
+        let state_mx = state_mx.clone();
+        let runtime_state_mx = runtime_state_mx.clone();
+        let applied_receiver = applied_sender.subscribe();
+        async move { settings_worker(state_mx, runtime_state_mx, applied_receiver).await }
+    });
+
+    let webhook_worker_hndl = tokio::spawn({
+        let pool = pool.clone();
+        let state_mx = state_mx.clone();
+        let applied_receiver = applied_sender.subscribe();
+        async move { webhook_worker(pool, state_mx, applied_receiver).await }
+    });
+
+    let session_worker_hndl = tokio::spawn({
+        let state_mx = state_mx.clone();
+        let auth_store_mx = auth_store_mx.clone();
+        let applied_receiver = applied_sender.subscribe();
+        async move { session_worker(state_mx, auth_store_mx, applied_receiver).await }
     });
 
     if let Err(Aborted) = serve_apis(
         pool,
         state_mx,
         runtime_state_mx,
+        auth_store_mx,
         state_notify,
         start_notify,
         api_config,
         api_abort_reg,
         update_sender,
+        applied_sender,
+        operator_alerts,
         btc_client,
         eth_client,
         ticker_client,
@@ -403,8 +461,12 @@ pub async fn run_hot_wallet(
         update_worker_hndl.abort();
         btc_worker_hndl.abort();
         update_response_hndl.abort();
-        cron_workers_hndl.abort();
+        exchange_expiry_hndl.abort();
+        eth_deposit_hndl.abort();
         ticker_worker_hndl.abort();
+        settings_worker_hndl.abort();
+        webhook_worker_hndl.abort();
+        session_worker_hndl.abort();
         Err(Error::Aborted)
     } else {
         Ok(())
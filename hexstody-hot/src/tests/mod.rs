@@ -31,6 +31,7 @@ async fn test_auth_email() {
             .signin_email(SigninEmail {
                 user: user.clone(),
                 password: password.clone(),
+                totp: None,
             })
             .await;
         assert!(!res.is_ok());
@@ -65,6 +66,7 @@ async fn test_auth_email() {
             .signin_email(SigninEmail {
                 user: user.clone(),
                 password: "wrong".to_owned(),
+                totp: None,
             })
             .await;
         assert!(!res.is_ok(), "Wrong password passes");
@@ -72,6 +74,7 @@ async fn test_auth_email() {
             .signin_email(SigninEmail {
                 user: user.clone(),
                 password: password.clone(),
+                totp: None,
             })
             .await
             .expect("Signin");
@@ -98,6 +98,8 @@ where
                         btc_module: "http://127.0.0.1:8180".to_owned(),
                         eth_module: eth_module,
                         ticker_provider: "https://min-api.cryptocompare.com".to_owned(),
+                        coingecko_provider: "https://api.coingecko.com".to_owned(),
+                        ticker_rates_file: None,
                         network: Network::Regtest,
                         start_regtest: true,
                         operator_public_keys: keys,
@@ -251,6 +253,7 @@ where
             .signin_email(SigninEmail {
                 user: user.clone(),
                 password: password.clone(),
+                totp: None,
             })
             .await
             .expect("Signin");
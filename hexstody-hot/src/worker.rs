@@ -1,7 +1,7 @@
 use chrono::Utc;
 use hexstody_api::{
-    domain::{BTCTxid, CurrencyTxId},
-    types::{ConfirmedWithdrawal, LimitSpan},
+    domain::{BTCTxid, Currency, CurrencyTxId},
+    types::{ConfirmedWithdrawal, Erc20HistUnitU, OperatorEvent},
     domain::currency::CurrencyAddress
 };
 use hexstody_btc_api::events::*;
@@ -9,19 +9,22 @@ use hexstody_btc_client::client::BtcClient;
 use hexstody_eth_client::client::EthClient;
 use hexstody_db::{
     state::State,
+    state::transaction::{EthTransaction, Transaction},
     update::{
         btc::BestBtcBlock,
+        eth::EthDepositUpd,
         results::UpdateResult,
         withdrawal::{WithdrawCompleteInfo, WithdrawalRejectInfo},
         StateUpdate, UpdateBody,
     },
 };
+use hexstody_auth::AuthStore;
+use hexstody_runtime_db::RuntimeState;
 use log::*;
-use std::{sync::Arc, vec};
+use std::{str::FromStr, sync::Arc, vec};
 use std::time::Duration;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex};
 use tokio::time::sleep;
-use tokio_cron_scheduler::*;
 
 pub async fn update_results_worker(
     btc_client: BtcClient,
@@ -58,6 +61,7 @@ pub async fn update_results_worker(
                             amount: req.amount,
                             confirmations,
                             rejections,
+                            fee_tier: req.fee_tier,
                         };
                         info!("=================DEBUG=================");
                         info!("===============<UPDATE>================");
@@ -154,6 +158,7 @@ pub async fn update_results_worker(
                         amount: wr.amount,
                         confirmations: vec![],
                         rejections: vec![],
+                        fee_tier: wr.fee_tier,
                     };
                     match btc_client.withdraw_under_limit(cw).await {
                         Ok(resp) => {
@@ -196,6 +201,7 @@ pub async fn btc_worker(
     btc_client: BtcClient,
     state_mx: Arc<Mutex<State>>,
     update_sender: mpsc::Sender<StateUpdate>,
+    operator_alerts: broadcast::Sender<OperatorEvent>,
 ) {
     trace!("Starting BTC worker");
     loop {
@@ -205,12 +211,215 @@ pub async fn btc_worker(
             }
             Err(e) => {
                 error!("BTC module error: {e}");
+                // Error means there are no subscribers at the moment, that's fine
+                let _ = operator_alerts.send(OperatorEvent::NodeError {
+                    node: "btc".to_owned(),
+                    message: e.to_string(),
+                });
                 sleep(Duration::from_secs(5)).await;
             }
         }
     }
 }
 
+/// Release funds of exchange orders that operators didn't decide on in time
+pub async fn exchange_expiry_worker(
+    state_mx: Arc<Mutex<State>>,
+    update_sender: mpsc::Sender<StateUpdate>,
+) {
+    trace!("Starting exchange expiry worker");
+    loop {
+        let overdue = {
+            let state = state_mx.lock().await;
+            state.overdue_exchanges(Utc::now().naive_utc())
+        };
+        for expire in overdue {
+            debug!("Exchange order {} has expired", expire.id);
+            let upd = StateUpdate::new(UpdateBody::ExchangeExpired(expire));
+            if let Err(e) = update_sender.send(upd).await {
+                error!("Failed to send exchange expiry update: {e}");
+            }
+        }
+        sleep(Duration::from_secs(10)).await;
+    }
+}
+
+async fn sync_settings(state_mx: &Mutex<State>, runtime_state_mx: &Mutex<RuntimeState>) {
+    let (margins, fee_estimates) = {
+        let state = state_mx.lock().await;
+        (state.margins.clone(), state.fee_estimates.clone())
+    };
+    runtime_state_mx
+        .lock()
+        .await
+        .sync_settings(&margins, &fee_estimates);
+}
+
+/// Keep the runtime copy of margins and fee estimates equal to the ones confirmed in the state
+pub async fn settings_worker(
+    state_mx: Arc<Mutex<State>>,
+    runtime_state_mx: Arc<Mutex<RuntimeState>>,
+    mut updates: broadcast::Receiver<StateUpdate>,
+) {
+    trace!("Starting settings worker");
+    sync_settings(&state_mx, &runtime_state_mx).await;
+    loop {
+        match updates.recv().await {
+            Ok(StateUpdate {
+                body: UpdateBody::SettingsChangeRequest(_) | UpdateBody::SettingsChangeDecision(_),
+                ..
+            }) => sync_settings(&state_mx, &runtime_state_mx).await,
+            Ok(_) => (),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Settings worker lagged behind, {n} updates are skipped");
+                sync_settings(&state_mx, &runtime_state_mx).await;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    info!("Settings worker exited!");
+}
+
+/// Close sessions of the user if the account can't be used anymore
+async fn revoke_inactive_sessions(
+    state_mx: &Mutex<State>,
+    auth_store_mx: &Mutex<AuthStore>,
+    user: &str,
+) {
+    let inactive = state_mx.lock().await.users.get(user).map_or(false, |u| !u.is_active());
+    if inactive {
+        let closed = auth_store_mx.lock().await.sessions.revoke_user_sessions(user, None);
+        debug!("Closed {closed} sessions of inactive account {user}");
+    }
+}
+
+/// Sign out users whose accounts are frozen or closed by operators.
+/// Sessions are not part of the state, so this is done once the change is applied.
+pub async fn session_worker(
+    state_mx: Arc<Mutex<State>>,
+    auth_store_mx: Arc<Mutex<AuthStore>>,
+    mut updates: broadcast::Receiver<StateUpdate>,
+) {
+    trace!("Starting session worker");
+    loop {
+        match updates.recv().await {
+            Ok(StateUpdate {
+                body: UpdateBody::AccountChangeRequest(upd),
+                ..
+            }) => revoke_inactive_sessions(&state_mx, &auth_store_mx, &upd.user).await,
+            Ok(StateUpdate {
+                body: UpdateBody::AccountChangeDecision(decision),
+                ..
+            }) => revoke_inactive_sessions(&state_mx, &auth_store_mx, &decision.user).await,
+            Ok(_) => (),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Session worker lagged behind, {n} updates are skipped");
+                let users: Vec<String> = state_mx.lock().await.users.keys().cloned().collect();
+                for user in users {
+                    revoke_inactive_sessions(&state_mx, &auth_store_mx, &user).await;
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    info!("Session worker exited!");
+}
+
+/// Delay between polls of the ETH module for deposits
+const ETH_DEPOSIT_PERIOD_SECS: u64 = 60;
+
+/// Record deposits seen by the ETH module in the state, so users' history doesn't depend on the module.
+/// Deposits are updated until they are finalized
+pub async fn eth_deposit_worker(
+    eth_client: EthClient,
+    state_mx: Arc<Mutex<State>>,
+    update_sender: mpsc::Sender<StateUpdate>,
+) {
+    trace!("Starting ETH deposit worker");
+    loop {
+        let users: Vec<String> = {
+            let state = state_mx.lock().await;
+            state
+                .users
+                .values()
+                .filter(|u| u.currencies.keys().any(|c| *c != Currency::BTC))
+                .map(|u| u.username.clone())
+                .collect()
+        };
+        for user in users {
+            let user_data = match eth_client.get_user_data(&user).await {
+                Ok(user_data) => user_data,
+                Err(e) => {
+                    warn!("Failed to get ETH data of {user}: {e}");
+                    continue;
+                }
+            };
+            let deposits = user_data
+                .data
+                .historyTokens
+                .iter()
+                .flat_map(|h| h.history.iter())
+                .chain(user_data.data.historyEth.iter())
+                // Withdrawals are tracked by withdrawal requests
+                .filter(|h| h.addr.to_uppercase() != h.from.to_uppercase());
+            for h in deposits {
+                let (currency, tx) = match (Currency::from_str(&h.tokenName), eth_transaction(h)) {
+                    (Ok(currency), Some(tx)) => (currency, tx),
+                    _ => {
+                        warn!("Skipping malformed ETH history entry {}", h.hash);
+                        continue;
+                    }
+                };
+                let is_new = {
+                    let state = state_mx.lock().await;
+                    state
+                        .users
+                        .get(&user)
+                        .and_then(|u| u.currencies.get(&currency))
+                        .map(|cinfo| {
+                            !cinfo.transactions.iter().any(|t| match t {
+                                Transaction::Eth(known) => {
+                                    known.hash == tx.hash
+                                        && (t.is_finalized() || known.confirmations == tx.confirmations)
+                                }
+                                _ => false,
+                            })
+                        })
+                        .unwrap_or(false)
+                };
+                if is_new {
+                    let upd = StateUpdate::new(UpdateBody::EthDeposit(EthDepositUpd {
+                        user: user.clone(),
+                        currency,
+                        tx,
+                    }));
+                    if let Err(e) = update_sender.send(upd).await {
+                        error!("Failed to send ETH deposit update: {e}");
+                    }
+                }
+            }
+        }
+        sleep(Duration::from_secs(ETH_DEPOSIT_PERIOD_SECS)).await;
+    }
+}
+
+fn eth_transaction(h: &Erc20HistUnitU) -> Option<EthTransaction> {
+    Some(EthTransaction {
+        blockNumber: h.blockNumber.clone(),
+        timeStamp: h.timeStamp.clone(),
+        hash: h.hash.clone(),
+        from: h.from.clone(),
+        to: h.to.clone(),
+        value: h.value.parse().ok()?,
+        tokenName: h.tokenName.clone(),
+        gas: h.gas.clone(),
+        gasPrice: h.gasPrice.clone(),
+        contractAddress: h.contractAddress.clone(),
+        confirmations: h.confirmations.parse().unwrap_or(0),
+        addr: h.addr.clone(),
+    })
+}
+
 pub async fn process_btc_events(
     state_mx: Arc<Mutex<State>>,
     update_sender: &mpsc::Sender<StateUpdate>,
@@ -249,55 +458,3 @@ pub async fn process_btc_events(
         }
     }
 }
-
-pub async fn cron_workers(
-    update_sender: mpsc::Sender<StateUpdate>,
-) {
-    info!("Starting cleanup worker");
-    let mut sched = JobScheduler::new().await.expect("Failed to create scheduler");
-
-    sched.shutdown_on_ctrl_c();
-    sched.set_shutdown_handler(Box::new(|| {
-        Box::pin(async move {
-          println!("Shut down done");
-        })
-      }));
-
-    let send_daily = update_sender.clone();
-    let send_weekly = update_sender.clone();
-
-    let _daily_id = sched.add(Job::new_async("0 0 0 * * *", move |_, _| {
-        let update_sender = send_daily.clone();
-        let upd = StateUpdate::new(UpdateBody::ClearLimits(LimitSpan::Day));
-        Box::pin(async move {
-            info!("Starting daily cleanup");
-            if let Err(e) = update_sender.send(upd.clone()).await{
-                error!("{:?}", e);
-            }
-        })
-    }).expect("Failed to create daily job"));
-
-    let _weekly_id = sched.add(Job::new_async("0 0 0 * * Mon *", move |_, _| {
-        let update_sender = send_weekly.clone();
-        let upd = StateUpdate::new(UpdateBody::ClearLimits(LimitSpan::Week));
-        Box::pin(async move {
-            info!("Starting weekly cleanup");
-            if let Err(e) = update_sender.send(upd.clone()).await{
-                error!("{:?}", e);
-            }
-        })
-    }).expect("Failed to create weekly job"));
-
-    let _monthly_id = sched.add(Job::new_async("0 0 0 1 * * *", move |_, _| {
-        let update_sender = update_sender.clone();
-        let upd = StateUpdate::new(UpdateBody::ClearLimits(LimitSpan::Month));
-        Box::pin(async move {
-            info!("Starting monthly cleanup");
-            if let Err(e) = update_sender.send(upd.clone()).await{
-                error!("{:?}", e);
-            }
-        })
-    }).expect("Failed to create monthly job"));
-
-    sched.start().await.expect("Some error in scheduling");
-}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{prelude::*, Duration};
+use hexstody_api::domain::error;
+use hexstody_api::types::{EventStreamToken, OperatorEvent, SignatureData};
+use hexstody_db::{state::State as HexstodyState, update::StateUpdate};
+use hexstody_sig::SignatureVerificationConfig;
+use log::*;
+use p256::PublicKey;
+use rocket::{
+    futures::stream::Stream,
+    response::stream::{Event, EventStream},
+    serde::json::Json,
+    tokio::select,
+    Shutdown, State as RocketState, {get, post, uri},
+};
+use rocket_okapi::openapi;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::helpers::guard_op_signature_nomsg;
+
+/// How long issued event stream token can be used to connect
+pub const EVENT_TOKEN_TTL_SECS: i64 = 60;
+
+/// Issued event stream tokens with operator's key and expiration time
+#[derive(Default)]
+pub struct EventTokens(Mutex<HashMap<String, (PublicKey, NaiveDateTime)>>);
+
+impl EventTokens {
+    async fn issue(&self, public_key: PublicKey) -> EventStreamToken {
+        let now = Utc::now().naive_utc();
+        let token = Uuid::new_v4().to_string();
+        let expires_at = now + Duration::seconds(EVENT_TOKEN_TTL_SECS);
+        let mut tokens = self.0.lock().await;
+        tokens.retain(|_, (_, expires)| *expires > now);
+        tokens.insert(token.clone(), (public_key, expires_at));
+        EventStreamToken { token, expires_at }
+    }
+
+    async fn is_valid(&self, token: &str) -> bool {
+        let now = Utc::now().naive_utc();
+        self.0
+            .lock()
+            .await
+            .get(token)
+            .map(|(_, expires)| *expires > now)
+            .unwrap_or(false)
+    }
+}
+
+/// Get short-lived token to open the event stream from a browser
+#[openapi(tag = "State update events")]
+#[post("/state-updates-events/token")]
+pub async fn get_events_token(
+    tokens: &RocketState<EventTokens>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<EventStreamToken>> {
+    let public_key = signature_data.public_key;
+    guard_op_signature_nomsg(
+        &config,
+        uri!(get_events_token).to_string(),
+        signature_data,
+    )?;
+    Ok(Json(tokens.issue(public_key).await))
+}
+
+/// Returns an infinite stream of server-sent events for operators.
+/// Requires either 'Signature-Data' header or a token from '/state-updates-events/token'.
+/// Each event is JSON encoded 'OperatorEvent' with SSE event name equal to its type,
+/// e.g. "withdrawalRequest", "limitChangeRequest", "nodeError".
+#[openapi(tag = "State update events")]
+#[get("/state-updates-events?<token>")]
+pub async fn events(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    operator_alerts: &RocketState<broadcast::Sender<OperatorEvent>>,
+    tokens: &RocketState<EventTokens>,
+    signature_data: Option<SignatureData>,
+    config: &RocketState<SignatureVerificationConfig>,
+    token: Option<String>,
+    mut end: Shutdown,
+) -> error::Result<EventStream<impl Stream<Item = Event>>> {
+    match (signature_data, token) {
+        (Some(signature_data), _) => guard_op_signature_nomsg(
+            &config,
+            uri!(events(_)).to_string(),
+            signature_data,
+        )?,
+        (None, Some(token)) if tokens.is_valid(&token).await => (),
+        _ => return Err(error::Error::SignatureError("Missing signature or token".to_owned()).into()),
+    }
+    let mut updates = applied_updates.subscribe();
+    let mut alerts = operator_alerts.subscribe();
+    let state = state.inner().clone();
+    Ok(EventStream! {
+        loop {
+            let events = select! {
+                upd = updates.recv() => match upd {
+                    Ok(update) => state.lock().await.operator_events(&update.body),
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Operator event stream lagged behind, {n} updates are skipped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                alert = alerts.recv() => match alert {
+                    Ok(alert) => vec![alert],
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Operator event stream lagged behind, {n} alerts are skipped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut end => break,
+            };
+            for event in events {
+                yield Event::json(&event).event(event.kind());
+            }
+        }
+    })
+}
@@ -1,16 +1,22 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use hexstody_api::domain::{error, CurrencyAddress, EthAccount, BtcAddress, Erc20Token, Erc20};
-use hexstody_api::{types::SignatureData, domain::Currency};
+use hexstody_api::{types::{OperatorActionType, SignatureData}, domain::Currency};
 use hexstody_btc_client::client::BtcClient;
+use hexstody_db::update::audit::OperatorActionRec;
 use hexstody_db::update::{StateUpdate, UpdateBody};
 use hexstody_eth_client::client::EthClient;
 use hexstody_sig::{SignatureVerificationData, SignatureVerificationConfig};
 use rocket::{serde::json, State};
 use serde::Serialize;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex};
 use hexstody_db::state::State as DbState;
 use log::*;
+use uuid::Uuid;
+
+/// How long to wait for the operator's update to be applied to the state
+const APPLY_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Guard operator handle from non-authorized user
 pub fn guard_op_signature<T: Serialize>(
@@ -52,17 +58,87 @@ pub fn guard_op_signature_nomsg(
         .map_err(|e| error::Error::SignatureError(format!("{:?}", e)).into())
 }
 
+/// Audit log record of the operator's request that passed `guard_op_signature`
+pub fn op_action<T: Serialize>(
+    config: &SignatureVerificationConfig,
+    uri: String,
+    signature_data: SignatureData,
+    action: OperatorActionType,
+    body: &T,
+) -> OperatorActionRec {
+    OperatorActionRec {
+        id: Uuid::new_v4(),
+        action,
+        url: [config.domain.clone(), uri].join(""),
+        signature_data,
+        payload: json::to_string(body).unwrap(),
+    }
+}
+
+/// Record the operator's request to the audit log
+pub async fn send_op_action(
+    updater: &mpsc::Sender<StateUpdate>,
+    action: OperatorActionRec,
+) -> error::Result<()> {
+    updater
+        .send(StateUpdate::new(UpdateBody::OperatorAction(action)))
+        .await
+        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+}
+
+/// Send the update caused by the operator's request and wait until it is
+/// applied, then record the request to the audit log. Rejected updates are
+/// never applied, so they leave no audit record.
+pub async fn send_op_update(
+    updater: &mpsc::Sender<StateUpdate>,
+    applied_updates: &broadcast::Sender<StateUpdate>,
+    update: StateUpdate,
+    action: OperatorActionRec,
+) -> error::Result<()> {
+    let mut applied = applied_updates.subscribe();
+    updater
+        .send(update.clone())
+        .await
+        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)))?;
+    let wait = async {
+        loop {
+            match applied.recv().await {
+                Ok(upd) if upd == update => return Ok(()),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(error::Error::InternalServerError(
+                        "State update channel is closed".to_owned(),
+                    ))
+                }
+            }
+        }
+    };
+    tokio::time::timeout(APPLY_TIMEOUT, wait)
+        .await
+        .map_err(|_| error::Error::InternalServerError("State update is not applied".to_owned()))??;
+    send_op_action(updater, action).await
+}
+
 static HEXSTODY_EXCHANGE_USER: &str = "hexstody-exchange"; 
 
+/// Deposit address of the exchange
+pub enum DepositAddress {
+    /// Address that is already known to the state
+    Known(CurrencyAddress),
+    /// New address that is to be recorded with `UpdateBody::ExchangeAddress`
+    Allocated(CurrencyAddress),
+}
+
 pub async fn get_deposit_address(
     btc_client: &State<BtcClient>,
     eth_client: &State<EthClient>,
-    updater: &State<mpsc::Sender<StateUpdate>>,
     state: &State<Arc<Mutex<DbState>>>,
     currency: Currency,
-) -> Result<CurrencyAddress, error::Error> {
+) -> Result<DepositAddress, error::Error> {
     match currency {
-        Currency::BTC => allocate_address(btc_client, eth_client, updater, currency).await,
+        Currency::BTC => allocate_address(btc_client, eth_client, currency)
+            .await
+            .map(DepositAddress::Allocated),
         Currency::ETH | Currency::ERC20(_) => {
             let db_state = state.lock().await;
             let deposit_addresses: Vec<CurrencyAddress> = db_state
@@ -75,9 +151,11 @@ pub async fn get_deposit_address(
                 .deposit_info
                 .clone();
             if deposit_addresses.is_empty() {
-                allocate_address(btc_client, eth_client, updater, currency.clone()).await
+                allocate_address(btc_client, eth_client, currency.clone())
+                    .await
+                    .map(DepositAddress::Allocated)
             } else {
-                Ok(deposit_addresses[0].clone())
+                Ok(DepositAddress::Known(deposit_addresses[0].clone()))
             }
         }
     }
@@ -86,62 +164,49 @@ pub async fn get_deposit_address(
 async fn allocate_address(
     btc_client: &State<BtcClient>,
     eth_client: &State<EthClient>,
-    updater: &State<mpsc::Sender<StateUpdate>>,
     currency: Currency,
 ) -> Result<CurrencyAddress, error::Error> {
     match currency {
-        Currency::BTC => allocate_btc_address(btc_client, updater).await,
-        Currency::ETH => allocate_eth_address(eth_client, updater).await,
-        Currency::ERC20(token) => allocate_erc20_address(eth_client, updater, token).await,
+        Currency::BTC => allocate_btc_address(btc_client).await,
+        Currency::ETH => allocate_eth_address(eth_client).await,
+        Currency::ERC20(token) => allocate_erc20_address(eth_client, token).await,
     }
 }
 
 async fn allocate_btc_address(
     btc: &State<BtcClient>,
-    updater: &State<mpsc::Sender<StateUpdate>>,
 ) -> Result<CurrencyAddress, error::Error> {
     let address = btc.deposit_address().await.map_err(|e| {
         error!("{}", e);
         error::Error::FailedGenAddress(Currency::BTC)
     })?;
-    let packed_address = CurrencyAddress::BTC(BtcAddress{addr: format!("{}", address)});
-    updater.send(StateUpdate::new(UpdateBody::ExchangeAddress(packed_address.clone())))
-        .await.map_err(|e| error::Error::GenericError(e.to_string()))?;
-    Ok(packed_address)
+    Ok(CurrencyAddress::BTC(BtcAddress{addr: format!("{}", address)}))
 }
 
 async fn allocate_eth_address(
     eth_client: &State<EthClient>,
-    updater: &State<mpsc::Sender<StateUpdate>>,
 ) -> Result<CurrencyAddress, error::Error> {
     let user_data = eth_client
         .get_user_data(HEXSTODY_EXCHANGE_USER)
         .await
         .map_err(|e| error::Error::FailedETHConnection(e.to_string()))?;
-    let packed_address = CurrencyAddress::ETH(EthAccount {
+    Ok(CurrencyAddress::ETH(EthAccount {
         account: user_data.address,
-    });
-    updater.send(StateUpdate::new(UpdateBody::ExchangeAddress(packed_address.clone())))
-        .await.map_err(|e| error::Error::GenericError(e.to_string()))?;
-    Ok(packed_address)
+    }))
 }
 
 async fn allocate_erc20_address(
     eth_client: &State<EthClient>,
-    updater: &State<mpsc::Sender<StateUpdate>>,
     token: Erc20Token,
 ) -> Result<CurrencyAddress, error::Error> {
     let user_data = eth_client
         .get_user_data(HEXSTODY_EXCHANGE_USER)
         .await
         .map_err(|e| error::Error::FailedETHConnection(e.to_string()))?;
-    let packed_address = CurrencyAddress::ERC20(Erc20 {
+    Ok(CurrencyAddress::ERC20(Erc20 {
         token: token,
         account: EthAccount {
             account: user_data.address,
         },
-    });
-    updater.send(StateUpdate::new(UpdateBody::ExchangeAddress(packed_address.clone())))
-        .await.map_err(|e| error::Error::GenericError(e.to_string()))?;
-    Ok(packed_address)
+    }))
 }
\ No newline at end of file
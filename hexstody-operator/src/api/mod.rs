@@ -1,41 +1,43 @@
 use figment::Figment;
-use hexstody_runtime_db::{RuntimeState, FeeEstimates};
+use hexstody_runtime_db::RuntimeState;
 use hexstody_ticker::api::ticker_api;
 use hexstody_ticker_provider::client::TickerClient;
 use qrcode_generator::QrCodeEcc;
 use rocket::{
     fairing::AdHoc,
     fs::{FileServer, NamedFile},
-    futures::stream::Stream,
-    response::stream::{Event, EventStream},
+    http::ContentType,
     serde::json::Json,
-    tokio::select,
-    Shutdown, State as RocketState, {get, post, routes, uri},
+    State as RocketState, {get, post, routes, uri},
 };
 
 use rocket_okapi::{openapi, openapi_get_routes, swagger_ui::*};
 use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 use std::{path::PathBuf, str, sync::Arc};
-use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::sync::{broadcast, mpsc, Mutex, Notify};
 use uuid::Uuid;
 
 use hexstody_api::{
     domain::{
         error,
-        Currency
+        Currency, Rate
     },
     types::{
-        ConfirmationData, ConfirmationsConfig, ExchangeAddress, ExchangeBalanceItem,
-        ExchangeConfirmationData, ExchangeFilter, HotBalanceResponse, Invite, InviteRequest,
-        InviteResp, LimitChangeDecisionType, LimitChangeFilter, LimitChangeOpResponse,
-        LimitConfirmationData, SignatureData, UserInfo, WithdrawalFilter, WithdrawalRequest,
-        WithdrawalRequestDecisionType,
+        AccountChangeProposal, AccountChangeRequest, AccountConfirmationData,
+        AggregateLimitsReq, ConfirmationData, ConfirmationsConfig, CurrencyFeePolicy, ExchangeAddress, ExchangeBalanceItem,
+        FeeEstimates,
+        ExchangeConfirmationData, ExchangeFilter, ExchangeLimits, HotBalanceResponse, InternalTransfer, Invite,
+        InviteRequest, InviteResp, LimitChangeDecisionType, LimitChangeFilter, LimitChangeOpResponse,
+        LimitConfirmationData, MarginData, OperatorActionType, OperatorAuditEntry, OperatorAuditFilter,
+        OperatorEvent, SettingsChange, SettingsChangeRequest, SettingsConfirmationData,
+        SignatureData, Statement, TransferConfirmationData, UserAccount, UserInfo,
+        UserStatementRequest, WithdrawalFilter, WithdrawalRequest, WithdrawalRequestDecisionType,
     },
 };
 use hexstody_btc_client::client::BtcClient;
 use hexstody_db::{
-    state::{exchange::ExchangeDecisionType, State as HexstodyState, CONFIRMATIONS_CONFIG},
+    state::{account::AccountChangeUpd, exchange::ExchangeDecisionType, settings::SettingsChangeUpd, statement::user_statement, State as HexstodyState, StateUpdateErr, CONFIRMATIONS_CONFIG},
     update::limit::LimitChangeData,
     update::{misc::InviteRec, StateUpdate, UpdateBody},
     Pool,
@@ -43,7 +45,9 @@ use hexstody_db::{
 use hexstody_eth_client::client::EthClient;
 use hexstody_sig::SignatureVerificationConfig;
 
+mod events;
 mod helpers;
+use events::*;
 use helpers::*;
 
 #[openapi(skip)]
@@ -109,9 +113,62 @@ async fn get_user_info(
         email: user.config.email.clone(),
         phone: user.config.phone.clone(),
         tg_name: user.config.tg_name.clone(),
+        status: user.status,
     }))
 }
 
+/// Build the statement of the user for the period
+async fn make_user_statement(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    req: UserStatementRequest,
+) -> error::Result<Statement> {
+    if req.from >= req.until {
+        return Err(error::Error::GenericError("Statement period is empty".to_owned()).into());
+    }
+    let state = state.lock().await;
+    let user = state.users.get(&req.user).ok_or(error::Error::NoUserFound)?;
+    Ok(user_statement(user, req.from, req.until))
+}
+
+/// Statement of any user, the same users get from '/statement'
+#[openapi(skip)]
+#[post("/user/statement", format = "json", data = "<req>")]
+async fn get_user_statement(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    req: Json<UserStatementRequest>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<Statement>> {
+    let req = req.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(get_user_statement).to_string(),
+        signature_data,
+        &req,
+    )?;
+    make_user_statement(state, req).await.map(Json)
+}
+
+/// The same as '/user/statement', but in CSV
+#[openapi(skip)]
+#[post("/user/statement/csv", format = "json", data = "<req>")]
+async fn get_user_statement_csv(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    req: Json<UserStatementRequest>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<(ContentType, String)> {
+    let req = req.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(get_user_statement_csv).to_string(),
+        signature_data,
+        &req,
+    )?;
+    let statement = make_user_statement(state, req).await?;
+    Ok((ContentType::CSV, statement.to_csv()))
+}
+
 /// # Hot wallet balance
 #[openapi(tag = "Hot wallet balance")]
 #[get("/hot-wallet-balance/<currency_name>")]
@@ -184,6 +241,7 @@ async fn list(
 #[post("/confirm", format = "json", data = "<confirmation_data>")]
 async fn confirm(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     confirmation_data: Json<ConfirmationData>,
     config: &RocketState<SignatureVerificationConfig>,
@@ -195,6 +253,13 @@ async fn confirm(
         signature_data,
         &confirmation_data,
     )?;
+    let action = op_action(
+        &config,
+        uri!(confirm).to_string(),
+        signature_data,
+        OperatorActionType::WithdrawalConfirm,
+        &confirmation_data,
+    );
     let url = [config.domain.clone(), uri!(confirm).to_string()].join("");
     let state_update = StateUpdate::new(UpdateBody::WithdrawalRequestDecision(
         (
@@ -205,10 +270,7 @@ async fn confirm(
         )
             .into(),
     ));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+    send_op_update(update_sender, applied_updates, state_update, action).await
 }
 
 /// # Reject withdrawal request
@@ -216,6 +278,7 @@ async fn confirm(
 #[post("/reject", format = "json", data = "<confirmation_data>")]
 async fn reject(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     confirmation_data: Json<ConfirmationData>,
     config: &RocketState<SignatureVerificationConfig>,
@@ -227,6 +290,13 @@ async fn reject(
         signature_data,
         &confirmation_data,
     )?;
+    let action = op_action(
+        &config,
+        uri!(reject).to_string(),
+        signature_data,
+        OperatorActionType::WithdrawalReject,
+        &confirmation_data,
+    );
     let url = [config.domain.clone(), uri!(reject).to_string()].join("");
     let state_update = StateUpdate::new(UpdateBody::WithdrawalRequestDecision(
         (
@@ -237,10 +307,7 @@ async fn reject(
         )
             .into(),
     ));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+    send_op_update(update_sender, applied_updates, state_update, action).await
 }
 
 /// Generate an invite
@@ -248,18 +315,27 @@ async fn reject(
 #[post("/invite/generate", format = "json", data = "<req>")]
 async fn gen_invite(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     state: &RocketState<Arc<Mutex<HexstodyState>>>,
     config: &RocketState<SignatureVerificationConfig>,
     signature_data: SignatureData,
     req: Json<InviteRequest>,
 ) -> error::Result<Json<InviteResp>> {
+    let req = req.into_inner();
     let label = req.label.clone();
     guard_op_signature(
         &config,
         uri!(gen_invite).to_string(),
         signature_data,
-        &req.into_inner(),
+        &req,
     )?;
+    let action = op_action(
+        &config,
+        uri!(gen_invite).to_string(),
+        signature_data,
+        OperatorActionType::GenInvite,
+        &req,
+    );
     let invitor = signature_data.public_key.to_string();
     let mut invite = Invite {
         invite: Uuid::new_v4(),
@@ -277,11 +353,8 @@ async fn gen_invite(
         invitor,
         label: label.clone(),
     }));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
-        .map(|_| Json(InviteResp { invite, label }))
+    send_op_update(update_sender, applied_updates, state_update, action).await?;
+    Ok(Json(InviteResp { invite, label }))
 }
 
 /// List operator's invites
@@ -346,9 +419,7 @@ async fn get_all_changes(
                     .currencies
                     .get(&currency)
                     .unwrap()
-                    .limit_info
-                    .limit
-                    .clone();
+                    .current_limit(&requested_limit.span);
                 LimitChangeOpResponse {
                     id,
                     user,
@@ -368,6 +439,7 @@ async fn get_all_changes(
 #[post("/limits/confirm", format = "json", data = "<confirmation_data>")]
 async fn confirm_limits(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     confirmation_data: Json<LimitConfirmationData>,
     config: &RocketState<SignatureVerificationConfig>,
@@ -379,6 +451,13 @@ async fn confirm_limits(
         signature_data,
         &confirmation_data,
     )?;
+    let action = op_action(
+        &config,
+        uri!(confirm_limits).to_string(),
+        signature_data,
+        OperatorActionType::LimitsConfirm,
+        &confirmation_data,
+    );
     let url = [config.domain.clone(), uri!(confirm_limits).to_string()].join("");
     let state_update = StateUpdate::new(UpdateBody::LimitChangeDecision(
         (
@@ -389,16 +468,57 @@ async fn confirm_limits(
         )
             .into(),
     ));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+/// Propose fiat limits over withdrawals in all currencies of the user.
+/// They take effect when enough operators confirm them
+#[openapi(skip)]
+#[post("/limits/aggregate", format = "json", data = "<req>")]
+async fn set_aggregate_limits(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    req: Json<AggregateLimitsReq>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let req = req.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(set_aggregate_limits).to_string(),
+        signature_data,
+        &req,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(set_aggregate_limits).to_string(),
+        signature_data,
+        OperatorActionType::SetAggregateLimits,
+        &req,
+    );
+    if req.limits.iter().any(|l| l.fiat.is_none()) {
+        return Err(error::Error::GenericError("Aggregate limits must be set in fiat".to_owned()).into());
+    }
+    if !state.lock().await.users.contains_key(&req.user) {
+        return Err(error::Error::NoUserFound.into());
+    }
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+        id: Uuid::new_v4(),
+        change: SettingsChange::AggregateLimits(req),
+        url: action.url.clone(),
+        signature: signature_data.signature,
+        nonce: signature_data.nonce,
+        public_key: signature_data.public_key,
+    }));
+    send_op_update(update_sender, applied_updates, state_update, action).await
 }
 
 #[openapi(skip)]
 #[post("/limits/reject", format = "json", data = "<confirmation_data>")]
 async fn reject_limits(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     confirmation_data: Json<LimitConfirmationData>,
     config: &RocketState<SignatureVerificationConfig>,
@@ -410,6 +530,13 @@ async fn reject_limits(
         signature_data,
         &confirmation_data,
     )?;
+    let action = op_action(
+        &config,
+        uri!(reject_limits).to_string(),
+        signature_data,
+        OperatorActionType::LimitsReject,
+        &confirmation_data,
+    );
     let url = [config.domain.clone(), uri!(reject_limits).to_string()].join("");
     let state_update = StateUpdate::new(UpdateBody::LimitChangeDecision(
         (
@@ -420,16 +547,14 @@ async fn reject_limits(
         )
             .into(),
     ));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+    send_op_update(update_sender, applied_updates, state_update, action).await
 }
 
 #[openapi(skip)]
 #[post("/exchange/confirm", data = "<confirmation_data>")]
 async fn confirm_exchange(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     confirmation_data: Json<ExchangeConfirmationData>,
     config: &RocketState<SignatureVerificationConfig>,
@@ -441,6 +566,13 @@ async fn confirm_exchange(
         signature_data,
         &confirmation_data,
     )?;
+    let action = op_action(
+        &config,
+        uri!(confirm_exchange).to_string(),
+        signature_data,
+        OperatorActionType::ExchangeConfirm,
+        &confirmation_data,
+    );
     let url = [config.domain.clone(), uri!(confirm_exchange).to_string()].join("");
     let state_update = StateUpdate::new(UpdateBody::ExchangeDecision(
         (
@@ -451,16 +583,14 @@ async fn confirm_exchange(
         )
             .into(),
     ));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+    send_op_update(update_sender, applied_updates, state_update, action).await
 }
 
 #[openapi(skip)]
 #[post("/exchange/reject", data = "<confirmation_data>")]
 async fn reject_exchange(
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     confirmation_data: Json<ExchangeConfirmationData>,
     config: &RocketState<SignatureVerificationConfig>,
@@ -472,6 +602,13 @@ async fn reject_exchange(
         signature_data,
         &confirmation_data,
     )?;
+    let action = op_action(
+        &config,
+        uri!(reject_exchange).to_string(),
+        signature_data,
+        OperatorActionType::ExchangeReject,
+        &confirmation_data,
+    );
     let url = [config.domain.clone(), uri!(reject_exchange).to_string()].join("");
     let state_update = StateUpdate::new(UpdateBody::ExchangeDecision(
         (
@@ -482,10 +619,92 @@ async fn reject_exchange(
         )
             .into(),
     ));
-    update_sender
-        .send(state_update)
-        .await
-        .map_err(|e| error::Error::InternalServerError(format!("{:?}", e)).into())
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+#[openapi(skip)]
+#[post("/transfer/confirm", data = "<confirmation_data>")]
+async fn confirm_transfer(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    confirmation_data: Json<TransferConfirmationData>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let confirmation_data = confirmation_data.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(confirm_transfer).to_string(),
+        signature_data,
+        &confirmation_data,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(confirm_transfer).to_string(),
+        signature_data,
+        OperatorActionType::TransferConfirm,
+        &confirmation_data,
+    );
+    let url = [config.domain.clone(), uri!(confirm_transfer).to_string()].join("");
+    let state_update = StateUpdate::new(UpdateBody::InternalTransferDecision(
+        (
+            confirmation_data,
+            signature_data,
+            WithdrawalRequestDecisionType::Confirm,
+            url,
+        )
+            .into(),
+    ));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+#[openapi(skip)]
+#[post("/transfer/reject", data = "<confirmation_data>")]
+async fn reject_transfer(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    confirmation_data: Json<TransferConfirmationData>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let confirmation_data = confirmation_data.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(reject_transfer).to_string(),
+        signature_data,
+        &confirmation_data,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(reject_transfer).to_string(),
+        signature_data,
+        OperatorActionType::TransferReject,
+        &confirmation_data,
+    );
+    let url = [config.domain.clone(), uri!(reject_transfer).to_string()].join("");
+    let state_update = StateUpdate::new(UpdateBody::InternalTransferDecision(
+        (
+            confirmation_data,
+            signature_data,
+            WithdrawalRequestDecisionType::Reject,
+            url,
+        )
+            .into(),
+    ));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+/// Internal transfers of all users, newest first
+#[openapi(skip)]
+#[get("/transfer/list")]
+async fn get_transfers(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<Vec<InternalTransfer>>> {
+    guard_op_signature_nomsg(&config, uri!(get_transfers).to_string(), signature_data)?;
+    let state = state.lock().await;
+    Ok(Json(state.get_internal_transfers()))
 }
 
 #[openapi(skip)]
@@ -530,6 +749,117 @@ async fn get_exchange_balances(
     Ok(Json(res))
 }
 
+#[openapi(skip)]
+#[get("/exchange/limits")]
+async fn get_exchange_limits(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<ExchangeLimits>> {
+    guard_op_signature_nomsg(
+        &config,
+        uri!(get_exchange_limits).to_string(),
+        signature_data,
+    )?;
+    let state = state.lock().await;
+    Ok(Json(state.exchange_state.limits.clone()))
+}
+
+/// Propose limits under which exchange orders are executed without operators' decision.
+/// They take effect when enough operators confirm them
+#[openapi(skip)]
+#[post("/exchange/limits", format = "json", data = "<req>")]
+async fn set_exchange_limits(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    req: Json<ExchangeLimits>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let req = req.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(set_exchange_limits).to_string(),
+        signature_data,
+        &req,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(set_exchange_limits).to_string(),
+        signature_data,
+        OperatorActionType::SetExchangeLimits,
+        &req,
+    );
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+        id: Uuid::new_v4(),
+        change: SettingsChange::ExchangeLimits(req),
+        url: action.url.clone(),
+        signature: signature_data.signature,
+        nonce: signature_data.nonce,
+        public_key: signature_data.public_key,
+    }));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+/// Withdrawal fee policies of all supported currencies
+#[openapi(skip)]
+#[get("/fee/policy")]
+async fn get_fee_policies(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<Vec<CurrencyFeePolicy>>> {
+    guard_op_signature_nomsg(
+        &config,
+        uri!(get_fee_policies).to_string(),
+        signature_data,
+    )?;
+    let state = state.lock().await;
+    let policies = Currency::supported()
+        .into_iter()
+        .map(|currency| CurrencyFeePolicy {
+            policy: state.fee_policy(&currency),
+            currency,
+        })
+        .collect();
+    Ok(Json(policies))
+}
+
+/// Propose withdrawal fee policy of the currency. It takes effect when enough operators confirm it
+#[openapi(skip)]
+#[post("/fee/policy", format = "json", data = "<req>")]
+async fn set_fee_policy(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    req: Json<CurrencyFeePolicy>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let req = req.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(set_fee_policy).to_string(),
+        signature_data,
+        &req,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(set_fee_policy).to_string(),
+        signature_data,
+        OperatorActionType::SetFeePolicy,
+        &req,
+    );
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+        id: Uuid::new_v4(),
+        change: SettingsChange::FeePolicy(req),
+        url: action.url.clone(),
+        signature: signature_data.signature,
+        nonce: signature_data.nonce,
+        public_key: signature_data.public_key,
+    }));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
 #[openapi(skip)]
 #[post("/exchange/address", data = "<currency>")]
 async fn get_exchange_address(
@@ -537,6 +867,7 @@ async fn get_exchange_address(
     btc_client: &RocketState<BtcClient>,
     eth_client: &RocketState<EthClient>,
     update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     config: &RocketState<SignatureVerificationConfig>,
     currency: Json<Currency>,
@@ -553,17 +884,27 @@ async fn get_exchange_address(
         state.exchange_state.addresses.get(&currency).cloned()
     };
     let address = match deposit_info {
-        Some(address) => Ok(address),
-        None => get_deposit_address(
-            btc_client,
-            eth_client,
-            update_sender,
-            state,
-            currency.clone(),
-        )
-        .await
-        .map_err(|_| error::Error::FailedGenAddress(currency.clone())),
-    }?;
+        Some(address) => address,
+        None => match get_deposit_address(btc_client, eth_client, state, currency.clone())
+            .await
+            .map_err(|_| error::Error::FailedGenAddress(currency.clone()))?
+        {
+            DepositAddress::Known(address) => address,
+            DepositAddress::Allocated(address) => {
+                let action = op_action(
+                    &config,
+                    uri!(get_exchange_address).to_string(),
+                    signature_data,
+                    OperatorActionType::GenExchangeAddress,
+                    &currency,
+                );
+                let state_update =
+                    StateUpdate::new(UpdateBody::ExchangeAddress(address.clone()));
+                send_op_update(update_sender, applied_updates, state_update, action).await?;
+                address
+            }
+        },
+    };
     let qr_code: Vec<u8> =
         qrcode_generator::to_png_to_vec(address.address(), QrCodeEcc::Low, 256).unwrap();
     let addr = ExchangeAddress {
@@ -578,7 +919,7 @@ async fn get_exchange_address(
 pub struct MarginSet {
     currency_from: Currency,
     currency_to: Currency,
-    /// Parse string and convert String -> f64 in the handler
+    /// Parse string and convert String -> Rate in the handler
     /// Js stringify drops .0 from floats
     /// Client signs and sends margin: 1,
     /// guard_op_signature runs json::to_string and gets 1.0
@@ -586,53 +927,52 @@ pub struct MarginSet {
     margin: String
 }
 
+/// Propose the exchange margin of the pair. It takes effect when enough operators confirm it
 #[openapi(skip)]
 #[post("/margin/set", data="<req>")]
 pub async fn set_margin(
-    rstate: &RocketState<Arc<Mutex<RuntimeState>>>,
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     config: &RocketState<SignatureVerificationConfig>,
     req: Json<MarginSet>
 ) -> error::Result<()> {
     let req = req.into_inner();
-    let margin = req.margin.parse::<f64>().map_err(|e| error::Error::MalformedMargin(e.to_string()))?;
+    let margin = req.margin.parse::<Rate>().map_err(|e| error::Error::MalformedMargin(e.to_string()))?;
     guard_op_signature(
         &config,
         uri!(set_margin).to_string(),
         signature_data,
         &req,
     )?;
-    let mut rstate = rstate.lock().await;
-    rstate.set_margin(req.currency_from.symbol(), req.currency_to.symbol(), margin);
-    Ok(())
-}
-
-// NOTE: this handler has no authorization, so it leaks information
-/// Returns an infinite stream of server-sent events.
-/// Each event is a notification that state on the server has changed.
-#[openapi(tag = "State update events")]
-#[get("/state-updates-events")]
-async fn events(
-    state_notify: &RocketState<Arc<Notify>>,
-    mut end: Shutdown,
-) -> EventStream<impl Stream<Item = Event> + '_> {
-    EventStream! {
-        loop {
-            select! {
-                _ = state_notify.notified() => {
-                    yield Event::data("");
-                }
-                _ = &mut end => break,
-            };
-        }
-    }
+    let action = op_action(
+        &config,
+        uri!(set_margin).to_string(),
+        signature_data,
+        OperatorActionType::SetMargin,
+        &req,
+    );
+    let change = SettingsChange::Margin(MarginData {
+        currency_from: req.currency_from,
+        currency_to: req.currency_to,
+        margin,
+    });
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+        id: Uuid::new_v4(),
+        change,
+        url: action.url.clone(),
+        signature: signature_data.signature,
+        nonce: signature_data.nonce,
+        public_key: signature_data.public_key,
+    }));
+    send_op_update(update_sender, applied_updates, state_update, action).await
 }
 
 /// Get fee estimate info
 #[openapi(skip)]
 #[get("/rstate/fees")]
 async fn get_fee_estimates(
-    rstate: &RocketState<Arc<Mutex<RuntimeState>>>,
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
     signature_data: SignatureData,
     config: &RocketState<SignatureVerificationConfig>,
 ) -> error::Result<Json<FeeEstimates>> {
@@ -641,15 +981,16 @@ async fn get_fee_estimates(
         uri!(get_fee_estimates).to_string(),
         signature_data,
     )?;
-    let est = rstate.lock().await.fee_estimates.clone();
+    let est = state.lock().await.fee_estimates.clone();
     Ok(Json(est))
 }
 
-/// Set fee estimates
+/// Propose fee estimates. They take effect when enough operators confirm them
 #[openapi(skip)]
 #[post("/rstate/fees/set", data="<estimates>")]
 async fn set_fee_estimates(
-    rstate: &RocketState<Arc<Mutex<RuntimeState>>>,
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
     signature_data: SignatureData,
     config: &RocketState<SignatureVerificationConfig>,
     estimates: Json<FeeEstimates>
@@ -661,9 +1002,291 @@ async fn set_fee_estimates(
         signature_data,
         &estimates,
     )?;
-    let mut rstate = rstate.lock().await;
-    rstate.fee_estimates = estimates;
-    Ok(())
+    let action = op_action(
+        &config,
+        uri!(set_fee_estimates).to_string(),
+        signature_data,
+        OperatorActionType::SetFeeEstimates,
+        &estimates,
+    );
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeRequest(SettingsChangeUpd {
+        id: Uuid::new_v4(),
+        change: SettingsChange::FeeEstimates(estimates),
+        url: action.url.clone(),
+        signature: signature_data.signature,
+        nonce: signature_data.nonce,
+        public_key: signature_data.public_key,
+    }));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+/// Proposed changes of margins, fee estimates, fee policies, exchange and aggregate limits, newest first
+#[openapi(skip)]
+#[get("/settings/changes")]
+async fn get_settings_changes(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<Vec<SettingsChangeRequest>>> {
+    guard_op_signature_nomsg(
+        &config,
+        uri!(get_settings_changes).to_string(),
+        signature_data,
+    )?;
+    let state = state.lock().await;
+    Ok(Json(state.get_settings_changes()))
+}
+
+#[openapi(skip)]
+#[post("/settings/confirm", format = "json", data = "<confirmation_data>")]
+async fn confirm_settings(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    confirmation_data: Json<SettingsConfirmationData>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let confirmation_data = confirmation_data.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(confirm_settings).to_string(),
+        signature_data,
+        &confirmation_data,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(confirm_settings).to_string(),
+        signature_data,
+        OperatorActionType::SettingsConfirm,
+        &confirmation_data,
+    );
+    let url = [config.domain.clone(), uri!(confirm_settings).to_string()].join("");
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeDecision(
+        (
+            confirmation_data,
+            signature_data,
+            WithdrawalRequestDecisionType::Confirm,
+            url,
+        )
+            .into(),
+    ));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+#[openapi(skip)]
+#[post("/settings/reject", format = "json", data = "<confirmation_data>")]
+async fn reject_settings(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    confirmation_data: Json<SettingsConfirmationData>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let confirmation_data = confirmation_data.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(reject_settings).to_string(),
+        signature_data,
+        &confirmation_data,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(reject_settings).to_string(),
+        signature_data,
+        OperatorActionType::SettingsReject,
+        &confirmation_data,
+    );
+    let url = [config.domain.clone(), uri!(reject_settings).to_string()].join("");
+    let state_update = StateUpdate::new(UpdateBody::SettingsChangeDecision(
+        (
+            confirmation_data,
+            signature_data,
+            WithdrawalRequestDecisionType::Reject,
+            url,
+        )
+            .into(),
+    ));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+/// Statuses of all users' accounts
+#[openapi(skip)]
+#[get("/account/list")]
+async fn get_user_accounts(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<Vec<UserAccount>>> {
+    guard_op_signature_nomsg(
+        &config,
+        uri!(get_user_accounts).to_string(),
+        signature_data,
+    )?;
+    let state = state.lock().await;
+    Ok(Json(state.get_user_accounts()))
+}
+
+/// Proposed freezes, unfreezes and closures of accounts, newest first
+#[openapi(skip)]
+#[get("/account/changes")]
+async fn get_account_changes(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<Json<Vec<AccountChangeRequest>>> {
+    guard_op_signature_nomsg(
+        &config,
+        uri!(get_account_changes).to_string(),
+        signature_data,
+    )?;
+    let state = state.lock().await;
+    Ok(Json(state.get_account_changes()))
+}
+
+/// Propose to freeze, unfreeze or close the user's account. It takes effect when enough operators confirm it
+#[openapi(skip)]
+#[post("/account/change", format = "json", data = "<req>")]
+async fn propose_account_change(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+    req: Json<AccountChangeProposal>,
+) -> error::Result<()> {
+    let req = req.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(propose_account_change).to_string(),
+        signature_data,
+        &req,
+    )?;
+    {
+        let state = state.lock().await;
+        if state.has_pending_account_change(&req.user) {
+            return Err(error::Error::AccountChangeNotAllowed(format!(
+                "account of {} already has a pending change",
+                req.user
+            ))
+            .into());
+        }
+        state
+            .check_account_change(&req.user, req.action)
+            .map_err(|e| match e {
+                StateUpdateErr::UserNotFound(_) => error::Error::NoUserFound,
+                e => error::Error::AccountChangeNotAllowed(e.to_string()),
+            })?;
+    }
+    let action = op_action(
+        &config,
+        uri!(propose_account_change).to_string(),
+        signature_data,
+        OperatorActionType::AccountChange,
+        &req,
+    );
+    let state_update = StateUpdate::new(UpdateBody::AccountChangeRequest(AccountChangeUpd {
+        id: Uuid::new_v4(),
+        user: req.user,
+        action: req.action,
+        reason: req.reason,
+        url: action.url.clone(),
+        signature: signature_data.signature,
+        nonce: signature_data.nonce,
+        public_key: signature_data.public_key,
+    }));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+#[openapi(skip)]
+#[post("/account/confirm", format = "json", data = "<confirmation_data>")]
+async fn confirm_account_change(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    confirmation_data: Json<AccountConfirmationData>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let confirmation_data = confirmation_data.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(confirm_account_change).to_string(),
+        signature_data,
+        &confirmation_data,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(confirm_account_change).to_string(),
+        signature_data,
+        OperatorActionType::AccountConfirm,
+        &confirmation_data,
+    );
+    let url = [config.domain.clone(), uri!(confirm_account_change).to_string()].join("");
+    let state_update = StateUpdate::new(UpdateBody::AccountChangeDecision(
+        (
+            confirmation_data,
+            signature_data,
+            WithdrawalRequestDecisionType::Confirm,
+            url,
+        )
+            .into(),
+    ));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+#[openapi(skip)]
+#[post("/account/reject", format = "json", data = "<confirmation_data>")]
+async fn reject_account_change(
+    update_sender: &RocketState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RocketState<broadcast::Sender<StateUpdate>>,
+    signature_data: SignatureData,
+    confirmation_data: Json<AccountConfirmationData>,
+    config: &RocketState<SignatureVerificationConfig>,
+) -> error::Result<()> {
+    let confirmation_data = confirmation_data.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(reject_account_change).to_string(),
+        signature_data,
+        &confirmation_data,
+    )?;
+    let action = op_action(
+        &config,
+        uri!(reject_account_change).to_string(),
+        signature_data,
+        OperatorActionType::AccountReject,
+        &confirmation_data,
+    );
+    let url = [config.domain.clone(), uri!(reject_account_change).to_string()].join("");
+    let state_update = StateUpdate::new(UpdateBody::AccountChangeDecision(
+        (
+            confirmation_data,
+            signature_data,
+            WithdrawalRequestDecisionType::Reject,
+            url,
+        )
+            .into(),
+    ));
+    send_op_update(update_sender, applied_updates, state_update, action).await
+}
+
+/// Operators' audit log, newest first
+#[openapi(skip)]
+#[post("/audit", format = "json", data = "<filter>")]
+async fn get_operator_audit(
+    state: &RocketState<Arc<Mutex<HexstodyState>>>,
+    signature_data: SignatureData,
+    config: &RocketState<SignatureVerificationConfig>,
+    filter: Json<OperatorAuditFilter>,
+) -> error::Result<Json<Vec<OperatorAuditEntry>>> {
+    let filter = filter.into_inner();
+    guard_op_signature(
+        &config,
+        uri!(get_operator_audit).to_string(),
+        signature_data,
+        &filter,
+    )?;
+    let state = state.lock().await;
+    Ok(Json(state.operator_audit(&filter)))
 }
 
 pub async fn serve_api(
@@ -673,6 +1296,8 @@ pub async fn serve_api(
     state_notify: Arc<Notify>,
     start_notify: Arc<Notify>,
     update_sender: mpsc::Sender<StateUpdate>,
+    applied_updates: broadcast::Sender<StateUpdate>,
+    operator_alerts: broadcast::Sender<OperatorEvent>,
     btc_client: BtcClient,
     eth_client: EthClient,
     ticker_client: TickerClient,
@@ -702,16 +1327,36 @@ pub async fn serve_api(
                 get_all_changes,            // GET:  /changes
                 confirm_limits,             // POST: /limits/confirm
                 reject_limits,              // POST: /limits/reject
+                set_aggregate_limits,       // POST: /limits/aggregate
                 confirm_exchange,           // POST: /exchange/confirm
                 reject_exchange,            // POST: /exchange/reject
                 get_exchange_requests,      // GET:  /exchange/list?filter= <all, pending, completed, rejected>
                 get_exchange_balances,      // GET:  /exchange/balances
+                get_exchange_limits,        // GET:  /exchange/limits
+                set_exchange_limits,        // POST: /exchange/limits
                 get_exchange_address,       // POST: /exchange/address
+                confirm_transfer,           // POST: /transfer/confirm
+                reject_transfer,            // POST: /transfer/reject
+                get_transfers,              // GET:  /transfer/list
                 get_user_info,              // GET:  /user/info/<user_id>
-                events,                     // GET:  /state-updates-events
+                get_user_statement,         // POST: /user/statement
+                get_user_statement_csv,     // POST: /user/statement/csv
+                get_events_token,           // POST: /state-updates-events/token
+                events,                     // GET:  /state-updates-events?token=
                 set_margin,                 // POST: /margin/set
                 get_fee_estimates,          // GET:  /rstate/fee
                 set_fee_estimates,          // POST: /rstate/fee/set
+                get_settings_changes,       // GET:  /settings/changes
+                confirm_settings,           // POST: /settings/confirm
+                reject_settings,            // POST: /settings/reject
+                get_fee_policies,           // GET:  /fee/policy
+                set_fee_policy,             // POST: /fee/policy
+                get_operator_audit,         // POST: /audit
+                get_user_accounts,          // GET:  /account/list
+                get_account_changes,        // GET:  /account/changes
+                propose_account_change,     // POST: /account/change
+                confirm_account_change,     // POST: /account/confirm
+                reject_account_change,      // POST: /account/reject
             ],
         )
         .mount("/ticker/", ticker_api)
@@ -731,6 +1376,9 @@ pub async fn serve_api(
         .manage(ticker_client)
         .manage(static_path)
         .manage(state_notify)
+        .manage(applied_updates)
+        .manage(operator_alerts)
+        .manage(EventTokens::default())
         .attach(AdHoc::config::<SignatureVerificationConfig>())
         .attach(on_ready)
         .launch()
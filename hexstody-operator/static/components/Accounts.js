@@ -0,0 +1,117 @@
+import {
+    getUserAccounts, getAccountChanges, proposeAccountChange, confirmAccountChange, rejectAccountChange
+} from "../scripts/common.js"
+
+export const Accounts = {
+    template:
+        /*html*/
+        `<div class="flex-column">
+            <h4>Pending changes</h4>
+            <table v-if="pendingChanges.length > 0" class="table">
+                <thead>
+                    <tr>
+                        <th>Created at</th>
+                        <th>User</th>
+                        <th>Action</th>
+                        <th>Reason</th>
+                        <th>Confirmations</th>
+                        <th>Actions</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    <tr v-for="change in pendingChanges" :key="change.id">
+                        <td>{{change.created_at}}</td>
+                        <td>{{change.user}}</td>
+                        <td>{{change.action}}</td>
+                        <td>{{change.reason}}</td>
+                        <td>{{change.confirmations.length}}/{{change.rejections.length}}</td>
+                        <td>
+                            <button class="button mr-1" @click="decide(change, true)">Confirm</button>
+                            <button class="button" @click="decide(change, false)">Reject</button>
+                        </td>
+                    </tr>
+                </tbody>
+            </table>
+            <span v-else>No pending changes</span>
+            <h4>Accounts</h4>
+            <div style="display: flex;">
+                <div class="mr-2em">
+                    <span>Reason:</span>
+                    <input type="text" id="account-reason-input" v-model="reason">
+                </div>
+            </div>
+            <div v-if="hasError" class="text-error">{{errorMessage}}</div>
+            <table class="table">
+                <thead>
+                    <tr>
+                        <th>User</th>
+                        <th>Created at</th>
+                        <th>Status</th>
+                        <th>Actions</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    <tr v-for="account in accounts" :key="account.user">
+                        <td>{{account.user}}</td>
+                        <td>{{account.created_at}}</td>
+                        <td>{{account.status}}</td>
+                        <td>
+                            <button v-if="account.status === 'active'" class="button mr-1" @click="propose(account, 'freeze')">Freeze</button>
+                            <button v-if="account.status === 'frozen'" class="button mr-1" @click="propose(account, 'unfreeze')">Unfreeze</button>
+                            <button v-if="account.status !== 'closed'" class="button" @click="propose(account, 'close')">Close</button>
+                        </td>
+                    </tr>
+                </tbody>
+            </table>
+        </div>`,
+    data() {
+        return {
+            accounts: [],
+            changes: [],
+            reason: "",
+            hasError: false,
+            errorMessage: "",
+        }
+    },
+    methods: {
+        async fetchData() {
+            this.accounts = await getUserAccounts(this.privateKeyJwk, this.publicKeyDer).then(r => r.json())
+            this.changes = await getAccountChanges(this.privateKeyJwk, this.publicKeyDer).then(r => r.json())
+        },
+        async propose(account, action) {
+            this.hasError = false
+            if (!this.reason) {
+                this.hasError = true
+                this.errorMessage = "Reason field is required"
+                return
+            }
+            const proposal = { user: account.user, action: action, reason: this.reason }
+            const response = await proposeAccountChange(this.privateKeyJwk, this.publicKeyDer, proposal)
+            if (response.ok) {
+                this.reason = ""
+            } else {
+                this.hasError = true
+                this.errorMessage = await response.json().then(e => e.message)
+            }
+            await this.fetchData()
+        },
+        async decide(change, confirm) {
+            const confirmationData = { id: change.id, user: change.user, action: change.action }
+            if (confirm) {
+                await confirmAccountChange(this.privateKeyJwk, this.publicKeyDer, confirmationData)
+            } else {
+                await rejectAccountChange(this.privateKeyJwk, this.publicKeyDer, confirmationData)
+            }
+            await this.fetchData()
+        }
+    },
+    computed: {
+        pendingChanges() {
+            return this.changes.filter(c => c.status.type === "InProgress")
+        }
+    },
+    async created() {
+        await this.fetchData()
+    },
+    inject: ['privateKeyJwk', 'publicKeyDer'],
+}
\ No newline at end of file
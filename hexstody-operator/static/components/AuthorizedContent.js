@@ -3,10 +3,11 @@ import { Invites } from "./Invites.js"
 import { WithdrawalLimits } from "./WithdrawalLimits.js"
 import { ExchangeRequests } from "./ExchangeRequests.js"
 import { MarginsTab } from "./Margins.js"
+import { Accounts } from "./Accounts.js"
 
 export const AuthorizedContent = {
     components: {
-        WithdrawalRequests, Invites, WithdrawalLimits, ExchangeRequests, MarginsTab
+        WithdrawalRequests, Invites, WithdrawalLimits, ExchangeRequests, MarginsTab, Accounts
     },
     template:
         /*html*/
@@ -25,7 +26,7 @@ export const AuthorizedContent = {
     data() {
         return {
             currentTab: 'WithdrawalRequests',
-            tabs: ['WithdrawalRequests', 'Invites', 'WithdrawalLimits', 'ExchangeRequests', 'MarginsTab']
+            tabs: ['WithdrawalRequests', 'Invites', 'WithdrawalLimits', 'ExchangeRequests', 'MarginsTab', 'Accounts']
         }
     },
     methods: {
@@ -47,6 +48,9 @@ export const AuthorizedContent = {
                 case 'MarginsTab':
                     tabName = 'Margins';
                     break
+                case 'Accounts':
+                    tabName = 'Accounts'
+                    break
                 default:
                     tabName = 'Undefined'
             };
@@ -1,4 +1,20 @@
-import { getSupportedCurrencies, getCurrencyName, formatCurrencyValue, getPairRate, getMargin, isNumeric, setMargin } from "../scripts/common.js"
+import {
+    getSupportedCurrencies, getCurrencyName, formatCurrencyValue, getPairRate, getMargin, isNumeric, setMargin,
+    getSettingsChanges, confirmSettingsChange, rejectSettingsChange
+} from "../scripts/common.js"
+
+function describeFeePolicy(policy) {
+    switch (policy.type) {
+        case "Flat":
+            return `flat ${policy.fee}`
+        case "Percent":
+            return `${policy.basis_points / 100}% of the amount`
+        case "NetworkFee":
+            return `network fee + ${policy.markup_basis_points / 100}%`
+        default:
+            return policy.type
+    }
+}
 
 export const MarginsTab = {
     template:
@@ -36,9 +52,32 @@ export const MarginsTab = {
                     <input type="text" id="margin-input" v-model="marginField">
                 </div>
                 <div v-if='canSet' style="display: flex;">
-                    <button class="button mt-auto" @click='setBtnClick()'>Set</button>
+                    <button class="button mt-auto" @click='setBtnClick()'>Propose</button>
                 </div>
             </div>
+            <h4>Pending changes</h4>
+            <table v-if="pendingChanges.length > 0" class="table">
+                <thead>
+                    <tr>
+                        <th>Created at</th>
+                        <th>Change</th>
+                        <th>Confirmations</th>
+                        <th>Actions</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    <tr v-for="change in pendingChanges" :key="change.id">
+                        <td>{{change.created_at}}</td>
+                        <td>{{describeChange(change.change)}}</td>
+                        <td>{{change.confirmations.length}}/{{change.rejections.length}}</td>
+                        <td>
+                            <button class="button mr-1" @click="decide(change, true)">Confirm</button>
+                            <button class="button" @click="decide(change, false)">Reject</button>
+                        </td>
+                    </tr>
+                </tbody>
+            </table>
+            <span v-else>No pending changes</span>
         </div>`,
     data() {
         return {
@@ -49,6 +88,7 @@ export const MarginsTab = {
             rate: null,
             margin: null,
             marginField: null,
+            changes: [],
         }
     },
     methods: {
@@ -72,6 +112,36 @@ export const MarginsTab = {
                 margin: margin.toFixed(1)
             }
             await setMargin(this.privateKeyJwk, this.publicKeyDer, req)
+            await this.loadChanges()
+        },
+        async loadChanges() {
+            this.changes = await getSettingsChanges(this.privateKeyJwk, this.publicKeyDer).then(r => r.json())
+        },
+        describeChange(change) {
+            switch (change.type) {
+                case "Margin":
+                    return `Margin ${getCurrencyName(change.currency_from)} → ${getCurrencyName(change.currency_to)}: ${change.margin}%`
+                case "FeeEstimates":
+                    return `Fee estimates: BTC ${change.btc_bytes_per_tx} bytes, ETH gas ${change.eth_tx_gas_limit}, ERC20 gas ${change.erc20_tx_gas_limit}`
+                case "FeePolicy":
+                    return `Fee policy ${getCurrencyName(change.currency)}: ${describeFeePolicy(change.policy)}`
+                case "AggregateLimits":
+                    return `Aggregate limits of ${change.user}: ${change.limits.map(l => `${l.amount} ${l.fiat} per ${l.span}`).join(", ")}`
+                case "ExchangeLimits":
+                    return `Exchange limits: ${change.pairs.length} pairs, ${change.user_daily.length} user daily, ${change.daily.length} daily`
+                default:
+                    return change.type
+            }
+        },
+        async decide(change, confirm) {
+            const confirmationData = { id: change.id, change: change.change }
+            if (confirm) {
+                await confirmSettingsChange(this.privateKeyJwk, this.publicKeyDer, confirmationData)
+            } else {
+                await rejectSettingsChange(this.privateKeyJwk, this.publicKeyDer, confirmationData)
+            }
+            await this.loadChanges()
+            await this.loadPairData()
         },
         async loadPairData() {
             this.isLoading = true
@@ -96,6 +166,9 @@ export const MarginsTab = {
         }
     },
     computed: {
+        pendingChanges() {
+            return this.changes.filter(c => c.status.type === "InProgress")
+        },
         isRateLoaded() {
             if (typeof this.rate === 'object' && this.rate !== null && "rate" in this.rate) {
                 return true
@@ -111,6 +184,7 @@ export const MarginsTab = {
     },
     async created() {
         await this.fetchData()
+        await this.loadChanges()
     },
     inject: ['privateKeyJwk', 'publicKeyDer'],
 }
\ No newline at end of file
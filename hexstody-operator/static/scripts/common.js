@@ -137,6 +137,8 @@ export function formatWithdrawalRequestStatus(status, requiredConfirmations) {
             return "Rejected by operators"
         case "NodeRejected":
             return "Rejected by node"
+        case "Cancelled":
+            return "Cancelled by user"
         case "Completed":
             return "Completed"
         default:
@@ -152,6 +154,8 @@ export function formatExchangeRequestStatus(status, requiredConfirmations) {
             return "Confirmed"
         case "Rejected":
             return "Rejected by operators"
+        case "Expired":
+            return "Expired"
         default:
             return "Unknown"
     };
@@ -340,3 +344,35 @@ export async function getMargin(currency_from, currency_to) {
 export async function setMargin(privateKeyJwk, publicKeyDer, req) {
     return await makeSignedRequest(privateKeyJwk, publicKeyDer, req, "margin/set", "POST")
 }
+
+export async function getSettingsChanges(privateKeyJwk, publicKeyDer) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, null, "settings/changes", "GET")
+}
+
+export async function confirmSettingsChange(privateKeyJwk, publicKeyDer, confirmationData) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, confirmationData, "settings/confirm", "POST")
+}
+
+export async function rejectSettingsChange(privateKeyJwk, publicKeyDer, confirmationData) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, confirmationData, "settings/reject", "POST")
+}
+
+export async function getUserAccounts(privateKeyJwk, publicKeyDer) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, null, "account/list", "GET")
+}
+
+export async function getAccountChanges(privateKeyJwk, publicKeyDer) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, null, "account/changes", "GET")
+}
+
+export async function proposeAccountChange(privateKeyJwk, publicKeyDer, proposal) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, proposal, "account/change", "POST")
+}
+
+export async function confirmAccountChange(privateKeyJwk, publicKeyDer, confirmationData) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, confirmationData, "account/confirm", "POST")
+}
+
+export async function rejectAccountChange(privateKeyJwk, publicKeyDer, confirmationData) {
+    return await makeSignedRequest(privateKeyJwk, publicKeyDer, confirmationData, "account/reject", "POST")
+}
@@ -1,5 +1,14 @@
 import { KeyfileComponent } from "../components/KeyfileInput.js"
 import { AuthorizedContent } from "../components/AuthorizedContent.js"
+import { makeSignedRequest } from "./common.js"
+
+const operatorEventTypes = [
+    "withdrawalRequest",
+    "limitChangeRequest",
+    "exchangeOrder",
+    "reconciliationAlert",
+    "nodeError"
+]
 
 const app = Vue.createApp({
     template:
@@ -12,30 +21,60 @@ const app = Vue.createApp({
         return {
             privateKeyJwk: null,
             publicKeyDer: null,
-            eventToggle: false
+            eventToggle: false,
+            lastEvent: null,
+            eventSource: null
         }
     },
     provide() {
         return {
             eventToggle: Vue.computed(() => this.eventToggle),
+            lastEvent: Vue.computed(() => this.lastEvent),
             privateKeyJwk: Vue.computed(() => this.privateKeyJwk),
             publicKeyDer: Vue.computed(() => this.publicKeyDer)
         }
     },
-    created() {
-        const eventSource = new EventSource('/state-updates-events')
-        eventSource.onmessage = (_event) => {
-            this.eventToggle = !this.eventToggle
-        }
-    },
     methods: {
         setKey(privateKeyJwk, publicKeyDer) {
             this.privateKeyJwk = privateKeyJwk
             this.publicKeyDer = publicKeyDer
+            this.subscribeEvents()
         },
         resetKey() {
             this.privateKeyJwk = null
             this.publicKeyDer = null
+            this.closeEvents()
+        },
+        async subscribeEvents() {
+            this.closeEvents()
+            const response = await makeSignedRequest(this.privateKeyJwk, this.publicKeyDer, null, "state-updates-events/token", 'POST')
+            if (!response.ok) {
+                return
+            }
+            const { token } = await response.json()
+            const eventSource = new EventSource(`/state-updates-events?token=${encodeURIComponent(token)}`)
+            for (const eventType of operatorEventTypes) {
+                eventSource.addEventListener(eventType, (event) => {
+                    this.lastEvent = { type: eventType, data: JSON.parse(event.data) }
+                    this.eventToggle = !this.eventToggle
+                })
+            }
+            // Token is short-lived, so get a new one instead of reconnecting with the old
+            eventSource.onerror = () => {
+                this.closeEvents()
+                setTimeout(() => {
+                    if (this.isAuthorized) {
+                        this.subscribeEvents()
+                    }
+                }, 5000)
+            }
+            this.eventSource = eventSource
+        },
+        closeEvents() {
+            if (this.eventSource) {
+                this.eventSource.close()
+                this.eventSource = null
+            }
         }
     },
     computed: {
@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use hexstody_api::domain::error;
+use hexstody_api::types::{
+    AddressBookEntry, AddressBookRequest, ApiKeyScope, WithdrawalAllowlistRequest,
+    WithdrawalAllowlistStatus,
+};
+use hexstody_auth::types::ApiKey;
+use hexstody_auth::{require_auth_user, require_auth_user_scoped, AuthStore};
+use hexstody_db::state::{Network, State as DbState};
+use hexstody_db::update::address_book::{AddressBookAdd, AddressBookRemove, SetWithdrawalAllowlist};
+use hexstody_db::update::{StateUpdate, UpdateBody};
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use rocket_okapi::openapi;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use uuid::Uuid;
+
+use super::totp::verify_second_factor;
+
+/// Maximum number of saved addresses per user
+const MAX_ADDRESS_BOOK_ENTRIES: usize = 100;
+/// Maximum length of the address label
+const MAX_LABEL_LEN: usize = 64;
+/// Delay before a new address can be used or the allow-list is switched off,
+/// unless the user confirms the change with TOTP
+const ADDRESS_COOLDOWN_HOURS: i64 = 24;
+
+#[openapi(tag = "address book")]
+#[get("/address-book")]
+pub async fn list_address_book(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<Vec<AddressBookEntry>>> {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        let mut entries: Vec<AddressBookEntry> =
+            user.address_book.into_values().map(|a| a.into()).collect();
+        entries.sort_by_key(|a| a.created_at);
+        Ok(Json(entries))
+    })
+    .await
+}
+
+/// Save the address. It can be used for withdrawals after the cool-down,
+/// or at once if the user confirms it with TOTP
+#[openapi(tag = "address book")]
+#[post("/address-book", data = "<request>")]
+pub async fn add_address_book_entry(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    network: &State<Network>,
+    request: Json<AddressBookRequest>,
+) -> error::Result<Json<AddressBookEntry>> {
+    let AddressBookRequest { address, label, totp } = request.into_inner();
+    address.validate(network.btc())?;
+    let label = label.trim().to_owned();
+    if label.is_empty() || label.chars().count() > MAX_LABEL_LEN {
+        return Err(error::Error::GenericError(format!(
+            "Label must be from 1 to {MAX_LABEL_LEN} characters long"
+        ))
+        .into());
+    }
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |_, user| async move {
+        if user.address_book.len() >= MAX_ADDRESS_BOOK_ENTRIES {
+            return Err(error::Error::GenericError(format!(
+                "Too many saved addresses, maximum is {MAX_ADDRESS_BOOK_ENTRIES}"
+            ))
+            .into());
+        }
+        if user.address_book.values().any(|a| a.address == address) {
+            return Err(error::Error::GenericError(format!("Address {address} is already saved")).into());
+        }
+        let now = Utc::now().naive_utc();
+        let available_at = match user.totp.as_ref() {
+            Some(t) => {
+                verify_second_factor(auth_store, updater, applied_updates, &user.username, t, totp.as_deref())
+                    .await?;
+                now
+            }
+            None => now + Duration::hours(ADDRESS_COOLDOWN_HOURS),
+        };
+        let add = AddressBookAdd {
+            id: Uuid::new_v4(),
+            user: user.username,
+            address,
+            label,
+            created_at: now,
+            available_at,
+        };
+        let entry = AddressBookEntry {
+            id: add.id,
+            address: add.address.clone(),
+            label: add.label.clone(),
+            created_at: add.created_at,
+            available_at: add.available_at,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::AddressBookAdd(add)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(entry))
+    })
+    .await
+}
+
+#[openapi(tag = "address book")]
+#[post("/address-book/remove", data = "<id>")]
+pub async fn remove_address_book_entry(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    id: Json<Uuid>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |_, user| async move {
+        if !user.address_book.contains_key(&id) {
+            return Err(error::Error::AddressBookEntryNotFound(id).into());
+        }
+        let remove = AddressBookRemove {
+            id,
+            user: user.username,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::AddressBookRemove(remove)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(())
+    })
+    .await
+}
+
+#[openapi(tag = "address book")]
+#[get("/address-book/allowlist")]
+pub async fn get_withdrawal_allowlist(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<WithdrawalAllowlistStatus>> {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        Ok(Json(user.allowlist.status(Utc::now().naive_utc())))
+    })
+    .await
+}
+
+/// Allow withdrawals only to saved addresses. Switching it off takes effect after the cool-down,
+/// or at once if the user confirms it with TOTP
+#[openapi(tag = "address book")]
+#[post("/address-book/allowlist", data = "<request>")]
+pub async fn set_withdrawal_allowlist(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    request: Json<WithdrawalAllowlistRequest>,
+) -> error::Result<Json<WithdrawalAllowlistStatus>> {
+    let WithdrawalAllowlistRequest { enabled, totp } = request.into_inner();
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |_, user| async move {
+        let now = Utc::now().naive_utc();
+        let effective_at = match (enabled, user.totp.as_ref()) {
+            (true, _) => now,
+            (false, Some(t)) => {
+                verify_second_factor(auth_store, updater, applied_updates, &user.username, t, totp.as_deref())
+                    .await?;
+                now
+            }
+            (false, None) => now + Duration::hours(ADDRESS_COOLDOWN_HOURS),
+        };
+        let mut allowlist = user.allowlist.clone();
+        if enabled {
+            allowlist.enabled = true;
+            allowlist.disabled_at = None;
+        } else if allowlist.enabled {
+            allowlist.disabled_at = Some(allowlist.disabled_at.map_or(effective_at, |t| t.min(effective_at)));
+        }
+        let upd = SetWithdrawalAllowlist {
+            user: user.username,
+            enabled,
+            effective_at,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::SetWithdrawalAllowlist(upd)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(allowlist.status(now)))
+    })
+    .await
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use hexstody_api::domain::error;
+use hexstody_api::types::{ApiKeyCreated, ApiKeyInfo, ApiKeyRequest};
+use hexstody_auth::{require_session, AuthStore};
+use hexstody_auth::types::{hash_api_key, ApiKey};
+use hexstody_db::state::{ApiKeyRecord, State as DbState};
+use hexstody_db::update::api_key::{ApiKeyCreate, ApiKeyRevoke};
+use hexstody_db::update::{StateUpdate, UpdateBody};
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use rocket_okapi::openapi;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use uuid::Uuid;
+
+use super::totp::verify_second_factor;
+
+/// Maximum number of API keys per user
+const MAX_USER_API_KEYS: usize = 20;
+/// Length of the key prefix shown in the key list
+const API_KEY_PREFIX_LEN: usize = 8;
+/// How long to wait for the update to be applied to the state
+const APPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Random key with 244 bits of entropy
+fn generate_api_key() -> String {
+    format!(
+        "hxs_{}{}",
+        Uuid::new_v4().to_simple(),
+        Uuid::new_v4().to_simple()
+    )
+}
+
+/// Send the update and wait until it is applied, so the change takes
+/// effect for the next request of the user.
+pub(super) async fn send_and_wait_applied(
+    updater: &mpsc::Sender<StateUpdate>,
+    applied_updates: &broadcast::Sender<StateUpdate>,
+    body: UpdateBody,
+) -> error::Result<()> {
+    let mut applied = applied_updates.subscribe();
+    updater
+        .send(StateUpdate::new(body.clone()))
+        .await
+        .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+    let wait = async {
+        loop {
+            match applied.recv().await {
+                Ok(upd) if upd.body == body => return Ok(()),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(error::Error::InternalServerError(
+                        "State update channel is closed".to_owned(),
+                    ))
+                }
+            }
+        }
+    };
+    tokio::time::timeout(APPLY_TIMEOUT, wait)
+        .await
+        .map_err(|_| error::Error::InternalServerError("State update is not applied in time".to_owned()))??;
+    Ok(())
+}
+
+#[openapi(tag = "api keys")]
+#[get("/profile/apikeys")]
+pub async fn list_api_keys(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<Vec<ApiKeyInfo>>> {
+    require_session(cookies, api_key, auth_store, state, |user_id| async move {
+        let keys = state.lock().await.user_api_keys(&user_id);
+        let store = auth_store.lock().await;
+        Ok(Json(
+            keys.into_iter()
+                .map(|k| {
+                    let last_used = store.api_key_usage.get(&k.id).cloned();
+                    (k, last_used).into()
+                })
+                .collect(),
+        ))
+    })
+    .await
+}
+
+/// Create new API key. The key is returned only once, only its hash is stored.
+#[openapi(tag = "api keys")]
+#[post("/profile/apikeys", data = "<request>")]
+pub async fn create_api_key(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    request: Json<ApiKeyRequest>,
+) -> error::Result<Json<ApiKeyCreated>> {
+    let ApiKeyRequest {
+        label,
+        mut scopes,
+        allowed_ips,
+        expires_at,
+        totp,
+    } = request.into_inner();
+    let now = Utc::now().naive_utc();
+    if label.trim().is_empty() {
+        return Err(error::Error::ApiKeyInvalid("Label is empty".to_owned()).into());
+    }
+    if expires_at.map(|t| t <= now).unwrap_or(false) {
+        return Err(error::Error::ApiKeyInvalid("Expiration time is in the past".to_owned()).into());
+    }
+    scopes.sort();
+    scopes.dedup();
+    require_session(cookies, api_key, auth_store, state, |user_id| async move {
+        let user_totp = state
+            .lock()
+            .await
+            .get_user_by_id(&user_id)
+            .and_then(|u| u.totp.clone())
+            .filter(|t| t.require_for_api_keys);
+        if let Some(user_totp) = user_totp {
+            verify_second_factor(auth_store, updater, applied_updates, &user_id, &user_totp, totp.as_deref())
+                .await?;
+        }
+        if state.lock().await.user_api_keys(&user_id).len() >= MAX_USER_API_KEYS {
+            return Err(error::Error::ApiKeyInvalid(format!(
+                "Too many API keys, maximum is {MAX_USER_API_KEYS}"
+            ))
+            .into());
+        }
+        let key = generate_api_key();
+        let create = ApiKeyCreate {
+            id: Uuid::new_v4(),
+            user: user_id,
+            key_hash: hash_api_key(&key),
+            prefix: key[..API_KEY_PREFIX_LEN].to_owned(),
+            label,
+            scopes,
+            allowed_ips,
+            created_at: now,
+            expires_at,
+        };
+        let info: ApiKeyInfo = (ApiKeyRecord::from(create.clone()), None).into();
+        send_and_wait_applied(updater, applied_updates, UpdateBody::CreateApiKey(create)).await?;
+        Ok(Json(ApiKeyCreated { key, info }))
+    })
+    .await
+}
+
+/// Revoke API key. The key is rejected as soon as the request returns.
+#[openapi(tag = "api keys")]
+#[post("/profile/apikeys/revoke", data = "<id>")]
+pub async fn revoke_api_key(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    id: Json<Uuid>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_session(cookies, api_key, auth_store, state, |user_id| async move {
+        if !state.lock().await.user_api_keys(&user_id).iter().any(|k| k.id == id) {
+            return Err(error::Error::ApiKeyNotFound(id).into());
+        }
+        let revoke = ApiKeyRevoke { id, user: user_id };
+        send_and_wait_applied(updater, applied_updates, UpdateBody::RevokeApiKey(revoke)).await?;
+        auth_store.lock().await.api_key_usage.remove(&id);
+        Ok(())
+    })
+    .await
+}
@@ -4,17 +4,17 @@ use hexstody_api::domain::error;
 use hexstody_api::types as api;
 use hexstody_api::types::PasswordChange;
 use hexstody_api::types::SignatureData;
-use hexstody_auth::AUTH_COOKIE;
-use hexstody_auth::require_auth_user;
-use hexstody_auth::types::ApiKey;
+use hexstody_auth::types::ClientInfo;
+use hexstody_auth::{require_auth_user, session_id, start_session, AuthStore};
 use hexstody_db::state::*;
 use hexstody_db::update::misc::PasswordChangeUpd;
 use hexstody_db::update::signup::*;
 use hexstody_db::update::*;
 use hexstody_eth_client::client::EthClient;
-use hexstody_runtime_db::RuntimeState;
+use hexstody_runtime_db::{ChallengePurpose, RuntimeState};
 use hexstody_sig::verify_signature;
 use hexstody_sig::SignatureVerificationConfig;
+use chrono::Duration;
 use pwhash::bcrypt;
 use rocket::get;
 use rocket::http::{Cookie, CookieJar};
@@ -28,10 +28,12 @@ use rocket_dyn_templates::{context, Template};
 use rocket_okapi::openapi;
 
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+use super::totp::verify_second_factor;
+
 pub struct IsTestFlag(pub bool);
 
 #[openapi(tag = "auth")]
@@ -97,7 +99,7 @@ pub async fn signup_email(
 pub async fn change_password(
     state: &RState<Arc<Mutex<State>>>,
     cookies: &CookieJar<'_>,
-    api_key: Option<ApiKey>,
+    auth_store: &RState<Arc<Mutex<AuthStore>>>,
     updater: &RState<mpsc::Sender<StateUpdate>>,
     data: Json<api::PasswordChange>,
 ) -> error::Result<()> {
@@ -105,7 +107,8 @@ pub async fn change_password(
         old_password,
         new_password,
     } = data.into_inner();
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    // Password can't be changed with an API key
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         if let UserInfo {
             auth: SignupAuth::Password(pass_hash),
             ..
@@ -123,10 +126,16 @@ pub async fn change_password(
         }
         let new_pass_hash = bcrypt::hash(&new_password).map_err(|e| error::Error::from(e))?;
         let upd = StateUpdate::new(UpdateBody::PasswordChange(PasswordChangeUpd {
-            user: user.username,
+            user: user.username.clone(),
             new_password: new_pass_hash,
         }));
         updater.send(upd).await.unwrap();
+        // Sign out other devices, they might be the reason of the change
+        auth_store
+            .lock()
+            .await
+            .sessions
+            .revoke_user_sessions(&user.username, session_id(cookies).as_ref());
         Ok(())
     })
     .await
@@ -136,8 +145,12 @@ pub async fn change_password(
 #[post("/signin/email", data = "<data>")]
 pub async fn signin_email(
     state: &RState<Arc<Mutex<State>>>,
+    updater: &RState<mpsc::Sender<StateUpdate>>,
+    applied_updates: &RState<broadcast::Sender<StateUpdate>>,
     data: Json<api::SigninEmail>,
     cookies: &CookieJar<'_>,
+    auth_store: &RState<Arc<Mutex<AuthStore>>>,
+    client: ClientInfo,
 ) -> error::Result<Json<()>> {
     if data.user.len() < error::MIN_USER_NAME_LEN {
         return Err(error::Error::UserNameTooShort.into());
@@ -151,23 +164,29 @@ pub async fn signin_email(
     if data.password.len() > error::MAX_USER_PASSWORD_LEN {
         return Err(error::Error::UserPasswordTooLong.into());
     }
-    {
+    let totp = {
         let mstate = state.lock().await;
         if let Some(UserInfo {
             auth: SignupAuth::Password(pass_hash),
+            totp,
             ..
         }) = mstate.users.get(&data.user)
         {
             if bcrypt::verify(&data.password, pass_hash) {
-                cookies.add_private(Cookie::new(AUTH_COOKIE, data.user.clone()));
-                Ok(Json(()))
+                totp.clone()
             } else {
-                Err(error::Error::SigninFailed.into())
+                return Err(error::Error::SigninFailed.into());
             }
         } else {
-            Err(error::Error::SigninFailed.into())
+            return Err(error::Error::SigninFailed.into());
         }
+    };
+    if let Some(totp) = totp {
+        verify_second_factor(auth_store, updater, applied_updates, &data.user, &totp, data.totp.as_deref())
+            .await?;
     }
+    start_session(cookies, auth_store, state, &data.user, client).await?;
+    Ok(Json(()))
 }
 
 #[openapi(skip)]
@@ -177,6 +196,7 @@ pub fn signin_page() -> Template {
     Template::render("signin", context)
 }
 
+/// Test only: drops the user without an update. Operators close accounts with account changes
 #[openapi(skip)]
 #[get("/removeuser/<user>")]
 pub async fn remove_user(
@@ -195,22 +215,40 @@ pub async fn remove_user(
     }
 }
 
+/// Private cookie with random ID that binds issued challenges to the browser session
+pub const CHALLENGE_SESSION_COOKIE: &str = "challenge_session";
+/// How long an issued sign in challenge can be redeemed
+pub const CHALLENGE_TTL_SECS: i64 = 120;
+
+/// Get challenge session ID of the browser, starting a new session if there is none
+pub fn challenge_session(cookies: &CookieJar<'_>) -> String {
+    match cookies.get_private(CHALLENGE_SESSION_COOKIE) {
+        Some(cookie) => cookie.value().to_owned(),
+        None => {
+            let session = Uuid::new_v4().to_string();
+            cookies.add_private(Cookie::new(CHALLENGE_SESSION_COOKIE, session.clone()));
+            session
+        }
+    }
+}
+
 #[openapi(skip)]
 #[post("/signin/challenge/get", data = "<user>")]
 pub async fn get_challenge(
     runtime_state: &RState<Arc<Mutex<RuntimeState>>>,
     state: &RState<Arc<Mutex<State>>>,
+    cookies: &CookieJar<'_>,
     user: Json<String>,
 ) -> error::Result<Json<String>> {
     let user = user.into_inner();
     let user_exist = state.lock().await.users.contains_key(&user);
     if user_exist {
-        let challenge = Uuid::new_v4().to_string();
-        runtime_state
-            .lock()
-            .await
-            .challenges
-            .insert(user, challenge.clone());
+        let session = challenge_session(cookies);
+        let challenge = runtime_state.lock().await.issue_challenge(
+            session,
+            ChallengePurpose::KeyLogin { user },
+            Duration::seconds(CHALLENGE_TTL_SECS),
+        );
         Ok(Json(challenge))
     } else {
         return Err(error::Error::NoUserFound.into());
@@ -223,44 +261,46 @@ pub async fn redeem_challenge(
     runtime_state: &RState<Arc<Mutex<RuntimeState>>>,
     state: &RState<Arc<Mutex<State>>>,
     cookies: &CookieJar<'_>,
+    auth_store: &RState<Arc<Mutex<AuthStore>>>,
     resp: Json<ChallengeResponse>,
     signature_data: SignatureData,
     config: &RState<SignatureVerificationConfig>,
+    client: ClientInfo,
 ) -> error::Result<()> {
     let url = [config.domain.clone(), uri!(redeem_challenge).to_string()].join("");
     let user = resp.user.clone();
     let challenge = resp.challenge.clone();
-    let user_exist = state.lock().await.users.contains_key(&user);
-    if user_exist {
-        let message = [url, serde::json::to_string(&resp.into_inner()).unwrap()].join(":");
-        let v = verify_signature(
-            None,
-            &signature_data.public_key,
-            &signature_data.nonce,
-            message,
-            &signature_data.signature,
-        );
-        match v {
-            Ok(_) => {
-                let mut rstate = runtime_state.lock().await;
-                match rstate.challenges.get(&user) {
-                    Some(stored_challenge) => {
-                        if stored_challenge.clone() == challenge {
-                            rstate.challenges.remove(&user);
-                            cookies.add_private(Cookie::new(AUTH_COOKIE, user.clone()));
-                            Ok(())
-                        } else {
-                            Err(error::Error::NoUserFound.into())
-                        }
-                    }
-                    None => Err(error::Error::NoUserFound.into()),
-                }
-            }
-            Err(e) => Err(error::Error::SignatureError(format!("{:?}", e)).into()),
-        }
-    } else {
-        Err(error::Error::NoUserFound.into())
+    let user_key = match state.lock().await.users.get(&user) {
+        Some(uinfo) => uinfo.public_key,
+        None => return Err(error::Error::NoUserFound.into()),
+    };
+    // Only the key the user has uploaded to the profile can be used to sign in
+    if user_key != Some(signature_data.public_key) {
+        return Err(error::Error::SignatureError("Unknown key".to_owned()).into());
+    }
+    let message = [url, serde::json::to_string(&resp.into_inner()).unwrap()].join(":");
+    verify_signature(
+        None,
+        &signature_data.public_key,
+        &signature_data.nonce,
+        message,
+        &signature_data.signature,
+    )
+    .map_err(|e| error::Error::SignatureError(format!("{:?}", e)))?;
+    // The challenge is consumed even if it doesn't match, so it can't be guessed
+    let session = cookies
+        .get_private(CHALLENGE_SESSION_COOKIE)
+        .ok_or(error::Error::ChallengeExpired)?;
+    let stored = runtime_state
+        .lock()
+        .await
+        .take_challenge(session.value())
+        .ok_or(error::Error::ChallengeExpired)?;
+    if stored.challenge != challenge || stored.purpose != (ChallengePurpose::KeyLogin { user: user.clone() }) {
+        return Err(error::Error::ChallengeExpired.into());
     }
+    start_session(cookies, auth_store, state, &user, client).await?;
+    Ok(())
 }
 
 /// Redirect to signin page
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use hexstody_api::domain::error;
+use hexstody_auth::{require_auth_user, AuthStore};
+use hexstody_auth::types::ApiKey;
+use hexstody_db::state::State as DbState;
+use hexstody_db::update::StateUpdate;
+use log::*;
+use rocket::futures::stream::Stream;
+use rocket::http::CookieJar;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::{get, Shutdown, State};
+use rocket_okapi::openapi;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Mutex;
+
+/// Returns an infinite stream of server-sent events related to the caller's account.
+/// Each event is JSON encoded 'UserEvent' with SSE event name equal to its type,
+/// e.g. "deposit", "balanceChanged", "withdrawalStatus".
+#[openapi(tag = "events")]
+#[get("/events")]
+pub async fn user_events(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    mut end: Shutdown,
+) -> error::Result<EventStream<impl Stream<Item = Event>>> {
+    let user_id = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        Ok(user.username)
+    })
+    .await?;
+    // Subscribe before the stream is returned so no updates are missed in between
+    let mut updates = applied_updates.subscribe();
+    let state = state.inner().clone();
+    Ok(EventStream! {
+        loop {
+            let update = select! {
+                upd = updates.recv() => match upd {
+                    Ok(update) => update,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Event stream of {user_id} lagged behind, {n} updates are skipped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut end => break,
+            };
+            let events = state.lock().await.user_events(&update.body);
+            for (event_user, event) in events {
+                if event_user == user_id {
+                    yield Event::json(&event).event(event.kind());
+                }
+            }
+        }
+    })
+}
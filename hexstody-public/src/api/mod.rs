@@ -1,12 +1,19 @@
+pub mod address_book;
+pub mod api_key;
 pub mod auth;
+pub mod events;
 pub mod helpers;
 pub mod profile;
+pub mod session;
+pub mod totp;
 pub mod wallet;
+pub mod webauthn;
+pub mod webhook;
 
 use base64;
 use figment::Figment;
 use hexstody_api::error::HexstodyError;
-use hexstody_auth::{require_auth, require_auth_user, AUTH_COOKIE};
+use hexstody_auth::{end_session, require_auth, require_auth_user, AuthStore};
 use hexstody_db::state::Network;
 use hexstody_runtime_db::RuntimeState;
 use hexstody_ticker::api::ticker_api;
@@ -17,12 +24,12 @@ use std::io::Read;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::sync::{Mutex, Notify};
 
 use rocket::fairing::AdHoc;
 use rocket::fs::FileServer;
-use rocket::http::{CookieJar, Cookie};
+use rocket::http::CookieJar;
 use rocket::response::Redirect;
 use rocket::serde::json::Json;
 use rocket::uri;
@@ -30,7 +37,10 @@ use rocket::{get, routes, State};
 use rocket_dyn_templates::{context, Template};
 use rocket_okapi::{openapi, openapi_get_routes, swagger_ui::*};
 
+use address_book::*;
+use api_key::*;
 use auth::*;
+use events::*;
 use hexstody_api::{
     domain::{Currency, Language, error},
     types::DepositInfo,
@@ -40,7 +50,11 @@ use hexstody_db::{state::State as DbState, update::*, Pool};
 use hexstody_eth_client::client::EthClient;
 use hexstody_sig::SignatureVerificationConfig;
 use profile::*;
+use session::*;
+use totp::*;
 use wallet::*;
+use webauthn::*;
+use webhook::*;
 
 struct StaticPath(PathBuf);
 
@@ -77,9 +91,10 @@ fn ping() -> Json<()> {
 #[get("/")]
 async fn index(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
 ) -> Redirect {
-    require_auth(cookies, None, state, |_| async { Ok(()) })
+    require_auth(cookies, None, auth_store, state, |_| async { Ok(()) })
         .await
         .map_or(goto_signin(), |_| Redirect::to(uri!(overview)))
 }
@@ -88,10 +103,11 @@ async fn index(
 #[get("/overview")]
 async fn overview(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     static_path: &State<StaticPath>,
 ) -> Result<Template, Redirect> {
-    require_auth_user(cookies, None, state, |_, user| async move {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         let page_title = match user.config.language {
             Language::English => "Overview",
             Language::Russian => "Главная",
@@ -132,11 +148,12 @@ async fn overview(
 #[get("/profile?<tab>")]
 async fn profile_page(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     static_path: &State<StaticPath>,
     tab: Option<String>,
 ) -> Result<Template, Redirect> {
-    require_auth_user(cookies, None, state, |_, user| async move {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         let page_title = match user.config.language {
             Language::English => "Profile",
             Language::Russian => "Профиль",
@@ -185,10 +202,11 @@ fn signup() -> Template {
 #[get("/logout")]
 pub async fn logout(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
 ) -> error::Result<Redirect> {
-    let resp = require_auth(cookies, None, state, |_| async move {
-        cookies.remove_private(Cookie::named(AUTH_COOKIE));
+    let resp = require_auth(cookies, None, auth_store, state, |_| async move {
+        end_session(cookies, auth_store).await;
         Ok(Json(()))
     })
     .await;
@@ -210,6 +228,7 @@ pub async fn logout(
 #[get("/deposit?<tab>")]
 async fn deposit(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     static_path: &State<StaticPath>,
     btc_client: &State<BtcClient>,
@@ -217,7 +236,7 @@ async fn deposit(
     update_sender: &State<mpsc::Sender<StateUpdate>>,
     tab: Option<String>,
 ) -> Result<error::Result<Template>, Redirect> {
-    let resp = require_auth_user(cookies, None, state, |_, user| async move {
+    let resp = require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         let page_title = match user.config.language {
             Language::English => "Deposit",
             Language::Russian => "Депозит",
@@ -300,11 +319,12 @@ async fn deposit(
 #[get("/withdraw?<tab>")]
 async fn withdraw(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     static_path: &State<StaticPath>,
     tab: Option<String>,
 ) -> Result<error::Result<Template>, Redirect> {
-    let resp = require_auth_user(cookies, None, state, |_, user| async move {
+    let resp = require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         let page_title = match user.config.language {
             Language::English => "Withdraw",
             Language::Russian => "Вывод",
@@ -365,11 +385,12 @@ async fn withdraw(
 #[get("/translations/<path..>")]
 async fn get_dict(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     static_path: &State<StaticPath>,
     path: PathBuf,
 ) -> error::Result<serde_json::Value> {
-    require_auth_user(cookies, None, state, |_, user| async move {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         get_dict_json(static_path.inner(), user.config.language, path)
     })
     .await
@@ -379,10 +400,11 @@ async fn get_dict(
 #[get("/swap")]
 async fn swap(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     static_path: &State<StaticPath>,
 ) -> Result<Template, Redirect> {
-    require_auth_user(cookies, None, state, |_, user| async move {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
         let mut currencies: Vec<Currency> = user.currencies.keys().cloned().collect();
         currencies.sort();
         let currencies: Vec<String> = currencies.into_iter().map(|c| c.symbol().symbol()).collect();
@@ -421,9 +443,11 @@ pub async fn serve_api(
     pool: Pool,
     state: Arc<Mutex<DbState>>,
     runtime_state: Arc<Mutex<RuntimeState>>,
+    auth_store: Arc<Mutex<AuthStore>>,
     _state_notify: Arc<Notify>,
     start_notify: Arc<Notify>,
     update_sender: mpsc::Sender<StateUpdate>,
+    applied_updates: broadcast::Sender<StateUpdate>,
     btc_client: BtcClient,
     eth_client: EthClient,
     ticker_client: TickerClient,
@@ -449,8 +473,14 @@ pub async fn serve_api(
                 get_user_data,
                 get_fee,
                 get_history,
+                get_history_page,
+                get_statement,
+                get_statement_csv,
                 withdraw_eth,
+                get_withdrawal_fees,
                 post_withdraw,
+                cancel_withdrawal,
+                post_transfer,
                 signup_email,
                 signin_email,
                 logout,
@@ -459,6 +489,7 @@ pub async fn serve_api(
                 disable_token,
                 remove_user,
                 get_user_limits,
+                get_user_aggregate_limits,
                 request_new_limits,
                 get_user_limit_changes,
                 cancel_user_change,
@@ -471,12 +502,41 @@ pub async fn serve_api(
                 get_challenge,
                 redeem_challenge,
                 get_deposit_address_handle,
-                order_exchange,
+                quote_exchange,
+                accept_exchange,
                 list_my_orders,
                 get_network,
                 set_unit,
                 get_unit,
-                get_all_units
+                get_all_units,
+                list_webhooks,
+                register_webhook,
+                remove_webhook,
+                list_webhook_deliveries,
+                replay_webhook_delivery,
+                list_address_book,
+                add_address_book_entry,
+                remove_address_book_entry,
+                get_withdrawal_allowlist,
+                set_withdrawal_allowlist,
+                user_events,
+                list_api_keys,
+                create_api_key,
+                revoke_api_key,
+                get_totp_status,
+                setup_totp,
+                enable_totp,
+                disable_totp,
+                regenerate_recovery_codes,
+                webauthn_register_begin,
+                webauthn_register_finish,
+                list_webauthn_credentials,
+                remove_webauthn_credential,
+                webauthn_login_begin,
+                webauthn_login_finish,
+                list_sessions,
+                revoke_session,
+                revoke_other_sessions
             ],
         )
         .mount("/ticker/", ticker_api)
@@ -503,9 +563,11 @@ pub async fn serve_api(
         .manage(state)
         .manage(pool)
         .manage(update_sender)
+        .manage(applied_updates)
         .manage(btc_client)
         .manage(eth_client)
         .manage(runtime_state)
+        .manage(auth_store)
         .manage(ticker_client)
         .manage(IsTestFlag(is_test))
         .manage(StaticPath(static_path))
@@ -1,13 +1,15 @@
 use std::{sync::Arc, fmt::Debug};
 use base64;
-use hexstody_api::{types::{LimitApiResp, LimitChangeReq, LimitChangeResponse, ConfigChangeRequest, LimitChangeFilter}, domain::{Currency, Language, Email, PhoneNumber, TgName, Unit, CurrencyUnit, UnitInfo, UserUnitInfo}};
-use hexstody_auth::{types::ApiKey, require_auth_user};
+use hexstody_api::{types::{LimitApiResp, LimitChangeReq, LimitInfo, LimitChangeResponse, ConfigChangeRequest, LimitChangeFilter}, domain::{Currency, Language, Email, PhoneNumber, TgName, Unit, CurrencyUnit, UnitInfo, UserUnitInfo}};
+use hexstody_auth::{types::ApiKey, require_auth_user, AuthStore};
 use rocket::{get, http::CookieJar, State, serde::json::Json, response::Redirect, post};
 use rocket_okapi::openapi;
 use tokio::sync::{Mutex, mpsc};
 use hexstody_db::{state::{State as DbState, UserConfig}, update::{StateUpdate, limit::{LimitChangeUpd, LimitCancelData}, UpdateBody, misc::{SetLanguage, ConfigUpdateData, SetPublicKey, SetUnit}}};
 use hexstody_api::domain::error;
 use p256::{pkcs8::DecodePublicKey, PublicKey};
+use chrono::Utc;
+use uuid::Uuid;
 
 use super::auth::goto_signin;
 
@@ -15,13 +17,16 @@ use super::auth::goto_signin;
 #[get("/profile/limits/get")]
 pub async fn get_user_limits(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
 ) -> Result<Json<Vec<LimitApiResp>>, Redirect>{
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        let now = Utc::now().naive_utc();
         let mut infos: Vec<LimitApiResp> = user.currencies.values().map(|cur_info| 
             LimitApiResp{ 
-                limit_info: cur_info.limit_info.clone(), 
+                limit_info: cur_info.limit_info(now), 
+                limits: cur_info.limit_infos(now),
                 currency: cur_info.currency.clone() 
             }).collect();
         infos.sort();
@@ -29,27 +34,44 @@ pub async fn get_user_limits(
     }).await.map_err(|_| goto_signin())
 }
 
+/// Fiat limits over withdrawals in all currencies. They are set by operators.
+#[openapi(tag = "profile")]
+#[get("/profile/limits/aggregate")]
+pub async fn get_user_aggregate_limits(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<Vec<LimitInfo>>> {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        Ok(Json(user.aggregate_limit_infos(Utc::now().naive_utc())))
+    }).await
+}
+
 #[openapi(tag = "profile")]
 #[post("/profile/limits", data="<new_limits>")]
 pub async fn request_new_limits(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     new_limits: Json<Vec<LimitChangeReq>>
 ) -> Result<error::Result<()>, Redirect> {
     let new_limits = new_limits.into_inner();
-    let resp = require_auth_user(cookies, api_key, state, |_, user| async move {
+    let resp = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let filtered_limits : Vec<LimitChangeUpd> = new_limits.into_iter().filter_map(|l| {
             match user.currencies.get(&l.currency) {
                 None => None,
-                Some(ci) => if ci.limit_info.limit == l.limit{
+                Some(ci) => if ci.limits.contains(&l.limit){
                     None
                 } else {
                     Some(LimitChangeUpd{
+                        id: Some(Uuid::new_v4()),
                         user: user.username.clone(),
                         currency: l.currency.clone(),
                         limit: l.limit.clone(),
+                        created_at: Some(Utc::now().naive_utc()),
                     })
                 }
             }
@@ -79,12 +101,13 @@ pub async fn request_new_limits(
 #[get("/profile/limits/changes?<filter>")]
 pub async fn get_user_limit_changes(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     filter: Option<LimitChangeFilter>
 ) -> Result<Json<Vec<LimitChangeResponse>>, Redirect>{
     let filter = filter.unwrap_or(LimitChangeFilter::All);
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let changes = user.limit_change_requests
             .values()
             .filter_map(|v| if v.matches_filter(filter) { Some(v.clone().into()) } else {None})
@@ -97,12 +120,13 @@ pub async fn get_user_limit_changes(
 #[post("/profile/limits/cancel", data="<currency>")]
 pub async fn cancel_user_change(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     currency: Json<Currency>
 ) -> Result<error::Result<()>, Redirect>{
-    let resp = require_auth_user(cookies, api_key, state, |_, user| async move {
+    let resp = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         match user.limit_change_requests.get(&currency){
             Some(v) => {
                 let state_update = StateUpdate::new(UpdateBody::CancelLimitChange(
@@ -128,13 +152,14 @@ pub async fn cancel_user_change(
 #[post("/profile/language", data="<lang>")]
 pub async fn set_language(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     lang: Json<Language>
 ) -> error::Result<()> {
     let lang = lang.into_inner();
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         if user.config.language == lang {
             Err(error::Error::LimitsNoChanges.into())
         } else {
@@ -148,10 +173,11 @@ pub async fn set_language(
 #[get("/profile/settings/config")]
 pub async fn get_user_config(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
 ) -> error::Result<Json<UserConfig>>{
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         Ok(Json(user.config))
     }).await
 }
@@ -159,12 +185,13 @@ pub async fn get_user_config(
 #[post("/profile/settings/config", data="<request>")]
 pub async fn set_user_config(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     request: Json<ConfigChangeRequest>
 ) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let req = request.into_inner();
         let mut upd_data = ConfigUpdateData::default();
         upd_data.user = user.username;
@@ -205,12 +232,13 @@ E: Debug
 #[post("/profile/key", data="<key_b64>")]
 pub async fn set_user_public_key(
     cookies: &CookieJar<'_>,
-    api_key: Option<ApiKey>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     key_b64: Option<Json<String>>
 ) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    // Signing key can't be changed with an API key
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
     let mut upd = SetPublicKey { user: user.username, public_key: None };
     if let Some(key_bytes) = key_b64 {
         match base64::decode(key_bytes.into_inner()){
@@ -234,13 +262,14 @@ pub async fn set_user_public_key(
 #[post("/profile/unit/set", data="<unit_reqs>")]
 pub async fn set_unit(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     unit_reqs: Json<Vec<Unit>>
 ) -> error::Result<()> {
     let unit_reqs = unit_reqs.into_inner();
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         for unit_req in unit_reqs {
             let cur = unit_req.currency().ok_or(error::Error::UnknownCurrency(unit_req.name()))?;
             let cinfo = user.currencies.get(&cur).ok_or(error::Error::NoUserCurrency(cur))?;
@@ -256,12 +285,13 @@ pub async fn set_unit(
 #[post("/profile/unit/get", data="<currency>")]
 pub async fn get_unit(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     currency: Json<Currency>
 ) -> error::Result<Json<Unit>> {
     let currency = currency.into_inner();
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let cinfo = user.currencies.get(&currency).ok_or(error::Error::NoUserCurrency(currency))?;
         Ok(Json(cinfo.unit.clone()))
     }).await
@@ -271,10 +301,11 @@ pub async fn get_unit(
 #[get("/profile/unit/all")]
 pub async fn get_all_units(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
 ) -> error::Result<Json<Vec<UserUnitInfo>>> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let units: Vec<UnitInfo> =  user.currencies.values()
             .filter_map(|cinfo|
                 if cinfo.unit.is_generic() {
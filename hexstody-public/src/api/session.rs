@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use hexstody_api::domain::error;
+use hexstody_api::types::SessionInfo;
+use hexstody_auth::types::ApiKey;
+use hexstody_auth::{require_session, session_id, AuthStore};
+use hexstody_db::state::State as DbState;
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use rocket_okapi::openapi;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Active sessions of the user, newest first
+#[openapi(tag = "sessions")]
+#[get("/profile/sessions")]
+pub async fn list_sessions(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<Vec<SessionInfo>>> {
+    require_session(cookies, api_key, auth_store, state, |user_id| async move {
+        let current = session_id(cookies);
+        let sessions = auth_store
+            .lock()
+            .await
+            .sessions
+            .user_sessions(&user_id, Utc::now().naive_utc());
+        Ok(Json(
+            sessions
+                .into_iter()
+                .map(|s| SessionInfo {
+                    id: s.id,
+                    created_at: s.created_at,
+                    last_seen: s.last_seen,
+                    user_agent: s.user_agent,
+                    ip: s.ip,
+                    current: Some(s.id) == current,
+                })
+                .collect(),
+        ))
+    })
+    .await
+}
+
+/// Close the session, e.g. a forgotten one on another device
+#[openapi(tag = "sessions")]
+#[post("/profile/sessions/revoke", data = "<id>")]
+pub async fn revoke_session(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    id: Json<Uuid>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_session(cookies, api_key, auth_store, state, |user_id| async move {
+        let mut store = auth_store.lock().await;
+        match store.sessions.get(&id) {
+            Some(session) if session.user == user_id => {
+                store.sessions.remove(&id);
+                Ok(())
+            }
+            _ => Err(error::Error::GenericError(format!("Session {id} is not found")).into()),
+        }
+    })
+    .await
+}
+
+/// Close all sessions of the user except the current one. Returns number of closed sessions.
+#[openapi(tag = "sessions")]
+#[post("/profile/sessions/revoke-others")]
+pub async fn revoke_other_sessions(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<usize>> {
+    require_session(cookies, api_key, auth_store, state, |user_id| async move {
+        let current = session_id(cookies);
+        let closed = auth_store
+            .lock()
+            .await
+            .sessions
+            .revoke_user_sessions(&user_id, current.as_ref());
+        Ok(Json(closed))
+    })
+    .await
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use hexstody_api::domain::error;
+use hexstody_api::types::{
+    TotpCode, TotpEnableRequest, TotpRecoveryCodesResponse, TotpSetup, TotpStatus,
+};
+use hexstody_auth::{require_auth_user, AuthStore};
+use hexstody_auth::types::ApiKey;
+use hexstody_db::state::{State as DbState, Totp};
+use hexstody_db::update::signup::UserId;
+use hexstody_db::update::totp::{TotpDisable, TotpEnroll, TotpRecoveryCodeUsed, TotpRecoveryCodes};
+use hexstody_db::update::{StateUpdate, UpdateBody};
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use rocket_okapi::openapi;
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+use super::api_key::send_and_wait_applied;
+use crate::totp::{
+    generate_recovery_codes, generate_secret, hash_recovery_code, otpauth_url, verify_code,
+};
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+/// Check TOTP or recovery code of the user. Used recovery code is removed.
+/// The user is locked out for a while after several wrong codes.
+pub async fn verify_second_factor(
+    auth_store: &Mutex<AuthStore>,
+    updater: &mpsc::Sender<StateUpdate>,
+    applied_updates: &broadcast::Sender<StateUpdate>,
+    user: &UserId,
+    totp: &Totp,
+    code: Option<&str>,
+) -> error::Result<()> {
+    let code = code.ok_or(error::Error::TotpRequired)?;
+    let now = Utc::now().naive_utc();
+    let hash = hash_recovery_code(code);
+    {
+        let mut store = auth_store.lock().await;
+        if let Some(left) = store.second_factor_failures.locked_for(user, now) {
+            return Err(error::Error::TotpLocked(left.num_minutes() + 1).into());
+        }
+        if verify_code(&totp.secret, code, unix_now()) {
+            store.second_factor_failures.reset(user);
+            return Ok(());
+        }
+        if !totp.recovery_codes.contains(&hash) {
+            store.second_factor_failures.record_failure(user, now);
+            return Err(error::Error::TotpInvalid.into());
+        }
+    }
+    let used = TotpRecoveryCodeUsed {
+        user: user.clone(),
+        code_hash: hash,
+    };
+    // The update is not applied if a concurrent request has already used the code
+    send_and_wait_applied(updater, applied_updates, UpdateBody::TotpRecoveryCodeUsed(used))
+        .await
+        .map_err(|_| error::Error::TotpInvalid)?;
+    auth_store.lock().await.second_factor_failures.reset(user);
+    Ok(())
+}
+
+#[openapi(tag = "totp")]
+#[get("/profile/totp")]
+pub async fn get_totp_status(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<TotpStatus>> {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        let status = match user.totp {
+            Some(totp) => TotpStatus {
+                enabled: true,
+                require_for_withdrawal: totp.require_for_withdrawal,
+                require_for_api_keys: totp.require_for_api_keys,
+                recovery_codes_left: totp.recovery_codes.len(),
+            },
+            None => TotpStatus {
+                enabled: false,
+                require_for_withdrawal: false,
+                require_for_api_keys: false,
+                recovery_codes_left: 0,
+            },
+        };
+        Ok(Json(status))
+    })
+    .await
+}
+
+/// Generate new secret. TOTP is enabled only after the secret is confirmed
+/// with '/profile/totp/enable'.
+#[openapi(tag = "totp")]
+#[post("/profile/totp/setup")]
+pub async fn setup_totp(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<TotpSetup>> {
+    // Second factor can't be managed with an API key
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        if user.totp.is_some() {
+            return Err(error::Error::TotpAlreadyEnabled.into());
+        }
+        let secret = generate_secret();
+        Ok(Json(TotpSetup {
+            otpauth_url: otpauth_url(&secret, &user.username),
+            secret,
+        }))
+    })
+    .await
+}
+
+#[openapi(tag = "totp")]
+#[post("/profile/totp/enable", data = "<request>")]
+pub async fn enable_totp(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    request: Json<TotpEnableRequest>,
+) -> error::Result<Json<TotpRecoveryCodesResponse>> {
+    let TotpEnableRequest {
+        secret,
+        code,
+        require_for_withdrawal,
+        require_for_api_keys,
+    } = request.into_inner();
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        if user.totp.is_some() {
+            return Err(error::Error::TotpAlreadyEnabled.into());
+        }
+        if !verify_code(&secret, &code, unix_now()) {
+            return Err(error::Error::TotpInvalid.into());
+        }
+        let (codes, recovery_codes) = generate_recovery_codes();
+        let upd = TotpEnroll {
+            user: user.username,
+            secret,
+            recovery_codes,
+            require_for_withdrawal,
+            require_for_api_keys,
+            enrolled_at: Utc::now().naive_utc(),
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::TotpEnroll(upd)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(TotpRecoveryCodesResponse { codes }))
+    })
+    .await
+}
+
+#[openapi(tag = "totp")]
+#[post("/profile/totp/disable", data = "<code>")]
+pub async fn disable_totp(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    code: Json<TotpCode>,
+) -> error::Result<()> {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        let totp = user.totp.ok_or(error::Error::TotpNotEnabled)?;
+        verify_second_factor(auth_store, updater, applied_updates, &user.username, &totp, Some(&code.code))
+            .await?;
+        updater
+            .send(StateUpdate::new(UpdateBody::TotpDisable(TotpDisable {
+                user: user.username,
+            })))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()).into())
+    })
+    .await
+}
+
+/// Replace all recovery codes with new ones
+#[openapi(tag = "totp")]
+#[post("/profile/totp/recovery", data = "<code>")]
+pub async fn regenerate_recovery_codes(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    code: Json<TotpCode>,
+) -> error::Result<Json<TotpRecoveryCodesResponse>> {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        let totp = user.totp.ok_or(error::Error::TotpNotEnabled)?;
+        verify_second_factor(auth_store, updater, applied_updates, &user.username, &totp, Some(&code.code))
+            .await?;
+        let (codes, recovery_codes) = generate_recovery_codes();
+        let upd = TotpRecoveryCodes {
+            user: user.username,
+            recovery_codes,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::TotpRecoveryCodes(upd)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(TotpRecoveryCodesResponse { codes }))
+    })
+    .await
+}
@@ -1,48 +1,56 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use chrono::prelude::*;
+use chrono::Duration;
 use hexstody_api::domain::{
-    filter_tokens, BtcAddress, Currency, CurrencyAddress, CurrencyTxId, ETHTxid, Erc20, Erc20Token,
-    EthAccount, Symbol, error as error, CurrencyUnit
+    filter_tokens, BtcAddress, Currency, CurrencyAddress, Erc20, Erc20Token, EthAccount, Fiat, Rounding, Symbol, error as error, CurrencyUnit
 };
 use hexstody_api::types::{
-    self as api, BalanceItem, Erc20HistUnitU, ExchangeFilter, ExchangeRequest, GetTokensResponse,
+    self as api, ApiKeyScope, BalanceItem, ExchangeFilter, ExchangeQuote, ExchangeRequest, FeeTier, GetTokensResponse,
     TokenActionRequest, TokenInfo, WithdrawalFilter, EthFeeResp, UnitTickedAmount
 };
-use hexstody_auth::require_auth_user;
+use hexstody_auth::{require_auth_user, require_auth_user_scoped, AuthStore};
 use hexstody_auth::types::ApiKey;
 use hexstody_btc_client::client::{BtcClient, BTC_BYTES_PER_TRANSACTION};
 use hexstody_db::state::exchange::ExchangeOrderUpd;
+use hexstody_db::state::history::{history_page, user_history};
+use hexstody_db::state::statement::user_statement;
+use hexstody_db::state::transfer::InternalTransferUpd;
 use hexstody_db::state::{Network, State as DbState, WithdrawalRequestType};
-use hexstody_db::state::{Transaction, WithdrawalRequest, CONFIRMATIONS_CONFIG};
+use hexstody_db::state::CONFIRMATIONS_CONFIG;
 use hexstody_db::update::deposit::DepositAddress;
 use hexstody_db::update::misc::{TokenAction, TokenUpdate};
-use hexstody_db::update::withdrawal::WithdrawalRequestInfo;
+use hexstody_db::update::withdrawal::{FiatRates, WithdrawalCancelInfo, WithdrawalRequestInfo};
 use hexstody_db::update::{StateUpdate, UpdateBody};
+use hexstody_db::{queries::query_rate_at, Pool};
 use hexstody_eth_client::client::EthClient;
 use hexstody_runtime_db::RuntimeState;
 use hexstody_ticker_provider::client::TickerClient;
 use log::*;
 use reqwest;
-use rocket::http::CookieJar;
+use rocket::http::{ContentType, CookieJar};
 use rocket::serde::json::Json;
 use rocket::{get, post, State};
 use rocket_okapi::openapi;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex};
 use uuid::Uuid;
 
+use super::totp::verify_second_factor;
+
 #[openapi(tag = "wallet")]
 #[get("/balance")]
 pub async fn get_balance(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     rstate: &State<Arc<Mutex<RuntimeState>>>,
     eth_client: &State<EthClient>,
     ticker_client: &State<TickerClient>
 ) -> error::Result<Json<api::Balance>> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let user_data_resp = eth_client.get_user_data(&user.username).await;
         if let Err(e) = user_data_resp {
             return Err(error::Error::FailedETHConnection(e.to_string()).into());
@@ -69,7 +77,7 @@ pub async fn get_balance(
             let bal = api::BalanceItem {
                 currency: cur.clone(),
                 value: (bal, &info.unit).into(),
-                limit_info: info.limit_info.clone(),
+                limit_info: info.limit_info(Utc::now().naive_utc()),
                 ticker,
             };
             balances.push(bal);
@@ -85,6 +93,7 @@ pub async fn get_balance(
 #[post("/balance", data = "<currency>")]
 pub async fn get_balance_by_currency(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     rstate: &State<Arc<Mutex<RuntimeState>>>,
@@ -95,10 +104,10 @@ pub async fn get_balance_by_currency(
     let cur = currency.into_inner();
     let currency = cur.clone();
     let nofound_err = Err(error::Error::NoUserCurrency(cur.clone()).into());
-    let resp = require_auth_user(cookies, api_key, state, |_, user| async move {
+    let resp = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         match user.currencies.get(&cur) {
             Some(info) => {
-                let limit_info = info.limit_info.clone();
+                let limit_info = info.limit_info(Utc::now().naive_utc());
                 let unit = info.unit.clone();
                 if cur == Currency::BTC {
                     return Ok(((info.balance(), &unit).into(), limit_info));
@@ -150,11 +159,12 @@ pub async fn get_balance_by_currency(
 #[get("/userdata")]
 pub async fn get_user_data(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     eth_client: &State<EthClient>,
 ) -> error::Result<Json<api::UserEth>> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         eth_client
             .get_user_data(&user.username)
             .await
@@ -180,6 +190,7 @@ pub async fn get_eth_fee() -> reqwest::Result<api::EthGasPrice>{
 #[post("/fee/get?<ticker>", data="<currency>")]
 pub async fn get_fee(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     rstate: &State<Arc<Mutex<RuntimeState>>>,
@@ -191,7 +202,7 @@ pub async fn get_fee(
     let currency = currency.into_inner();
     // symbol is used for fee ticker. For Eth and Erc20 we use Eth ticker
     let symbol = if matches!(currency, Currency::BTC) {Symbol::BTC} else {Symbol::ETH};
-    let (fee, unit) = require_auth_user(cookies, api_key, state, |_, user| async move {
+    let (fee, unit) = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         if matches!(currency, Currency::BTC){
             let bytes_estimate = rstate.lock().await.fee_estimates.btc_bytes_per_tx;
             let btc_fee_per_kilobyte = &btc_client
@@ -223,137 +234,158 @@ pub async fn get_fee(
     Ok(Json(UnitTickedAmount{ amount: fee, name: unit.name(), mul: unit.mul(), prec: unit.precision(), ticker: t }))
 }
 
+/// Deposits and withdrawals, newest first. Kept for the web UI, see '/history' for all items with filters
 #[openapi(tag = "history")]
 #[get("/history/<skip>/<take>?<filter>")]
 pub async fn get_history(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
-    eth_client: &State<EthClient>,
+    pool: &State<Pool>,
     skip: usize,
     take: usize,
     filter: Option<WithdrawalFilter>,
 ) -> error::Result<Json<api::History>> {
     let filter = filter.unwrap_or(WithdrawalFilter::All);
-    fn to_deposit_history_item(deposit: &Transaction) -> api::HistoryItem {
-        match deposit {
-            Transaction::Btc(btc_deposit) => api::HistoryItem::Deposit(api::DepositHistoryItem {
-                currency: Currency::BTC,
-                date: btc_deposit.timestamp,
-                number_of_confirmations: btc_deposit.confirmations,
-                value: btc_deposit.amount.abs() as u64,
-                to_address: CurrencyAddress::from(btc_deposit.address.clone()),
-                txid: CurrencyTxId::from(btc_deposit.txid),
-            }),
-            Transaction::Eth(_) => todo!("Eth deposit history mapping"),
-        }
-    }
+    let mut history_items = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        let withdrawals: HashMap<String, bool> = user
+            .currencies
+            .values()
+            .flat_map(|info| info.withdrawal_requests.values())
+            .map(|w| (w.id.to_string(), w.matches_filter(filter)))
+            .collect();
+        Ok(user_history(&user)
+            .into_iter()
+            .filter(|item| match item {
+                api::HistoryItem::Deposit(_) => true,
+                api::HistoryItem::Withdrawal(w) => withdrawals.get(&w.id).copied().unwrap_or(false),
+                api::HistoryItem::Exchange(_) | api::HistoryItem::Transfer(_) => false,
+            })
+            .skip(skip)
+            .take(take)
+            .collect::<Vec<_>>())
+    })
+    .await?;
+    fill_fiat_values(pool, &mut history_items).await;
+    Ok(Json(api::History {
+        confirmations_config: CONFIRMATIONS_CONFIG,
+        history_items,
+    }))
+}
 
-    fn to_withdrawal_history_item(
-        currency: &Currency,
-        withdrawal: &WithdrawalRequest,
-    ) -> api::HistoryItem {
-        let withdrawal_status = withdrawal.status.clone().into();
-
-        api::HistoryItem::Withdrawal(api::WithdrawalHistoryItem {
-            currency: currency.to_owned(),
-            date: withdrawal.created_at,
-            status: withdrawal_status,
-            value: withdrawal.amount,
-            txid: None,
-        })
-    }
+/// Deposits, withdrawals and exchanges matching the filter, newest first.
+/// Pass `next_cursor` of the page to get the next one
+#[openapi(tag = "history")]
+#[post("/history", data = "<req>")]
+pub async fn get_history_page(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    pool: &State<Pool>,
+    req: Json<api::HistoryRequest>,
+) -> error::Result<Json<api::HistoryPage>> {
+    let api::HistoryRequest { filter, cursor, limit } = req.into_inner();
+    let after = cursor
+        .map(|c| api::HistoryCursor::from_str(&c))
+        .transpose()
+        .map_err(error::Error::GenericError)?;
+    let limit = limit.min(MAX_HISTORY_PAGE);
+    let (mut items, next) = require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        Ok(history_page(&user, &filter, after.as_ref(), limit))
+    })
+    .await?;
+    fill_fiat_values(pool, &mut items).await;
+    Ok(Json(api::HistoryPage {
+        confirmations_config: CONFIRMATIONS_CONFIG,
+        items,
+        next_cursor: next.map(|c| c.to_string()),
+    }))
+}
 
-    fn to_eth_history(h: &Erc20HistUnitU) -> api::HistoryItem {
-        let curr = Currency::from_str(&h.tokenName).unwrap();
-        let time = Utc.timestamp(h.timeStamp.parse().unwrap(), 0);
-        let val = h.value.parse().unwrap_or(u64::MAX); // MAX for strange entries with value bigger than u64
-        if h.addr.to_uppercase() != h.from.to_ascii_uppercase() {
-            api::HistoryItem::Deposit(api::DepositHistoryItem {
-                currency: curr,
-                date: time,
-                number_of_confirmations: 0,
-                value: val,
-                to_address: CurrencyAddress::ETH(EthAccount {
-                    account: h.addr.to_owned(),
-                }),
-                txid: CurrencyTxId::ETH(ETHTxid {
-                    txid: h.hash.to_owned(),
-                }),
-            })
-        } else {
-            api::HistoryItem::Withdrawal(api::WithdrawalHistoryItem {
-                currency: curr,
-                date: time,
-                status: api::WithdrawalRequestStatus::InProgress {
-                    confirmations_minus_rejections: 0,
-                },
-                value: val,
-                txid: Some(CurrencyTxId::ETH(ETHTxid {
-                    txid: h.hash.to_owned(),
-                })),
-            })
+/// Max number of items on a history page
+const MAX_HISTORY_PAGE: usize = 500;
+
+/// Set fiat values of deposits and withdrawals by the recorded rates
+async fn fill_fiat_values(pool: &Pool, items: &mut [api::HistoryItem]) {
+    for item in items.iter_mut() {
+        match item {
+            api::HistoryItem::Deposit(d) => {
+                d.fiat_value = historical_fiat_value(pool, &d.currency, d.value, d.date.naive_utc()).await
+            }
+            api::HistoryItem::Withdrawal(w) => {
+                w.fiat_value = historical_fiat_value(pool, &w.currency, w.value, w.date.naive_utc()).await
+            }
+            api::HistoryItem::Exchange(_) | api::HistoryItem::Transfer(_) => (),
         }
     }
+}
 
-    require_auth_user(cookies, api_key, state, |_, user| async move {
-        let mut history = user
-            .currencies
-            .iter()
-            .flat_map(|(currency, info)| {
-                let deposits = info.unconfirmed_transactions();
-                let deposit_history = deposits.map(to_deposit_history_item);
-                let withdrawals: Vec<_> = info
-                    .withdrawal_requests
-                    .values()
-                    .filter(|w| w.matches_filter(filter))
-                    .collect();
-                let withdrawal_history = withdrawals
-                    .iter()
-                    .map(|withdrawal| to_withdrawal_history_item(currency, withdrawal));
-
-                withdrawal_history
-                    .chain(deposit_history)
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>();
-        let user_data: Json<api::UserEth> = eth_client
-            .get_user_data(&user.username)
-            .await
-            .map(|user_data| Json(user_data))
-            .unwrap();
-        let mut eth_and_tokens_history = user_data
-            .data
-            .historyTokens
-            .iter()
-            .flat_map(|h| h.history.iter())
-            .chain(user_data.data.historyEth.iter())
-            .map(|h| to_eth_history(h))
-            .collect();
-        history.append(&mut eth_and_tokens_history);
-        history.sort_by(|a, b| api::history_item_time(b).cmp(api::history_item_time(a)));
+/// Deposits, withdrawals with fees and exchanges in the period with opening and closing balances
+#[openapi(tag = "history")]
+#[post("/statement", data = "<req>")]
+pub async fn get_statement(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    req: Json<api::StatementRequest>,
+) -> error::Result<Json<api::Statement>> {
+    let api::StatementRequest { from, until } = req.into_inner();
+    if from >= until {
+        return Err(error::Error::GenericError("Statement period is empty".to_owned()).into());
+    }
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        Ok(Json(user_statement(&user, from, until)))
+    })
+    .await
+}
 
-        let history_slice = history.iter().skip(skip).take(take).cloned().collect();
+/// The same as '/statement', but in CSV
+#[openapi(tag = "history")]
+#[post("/statement/csv", data = "<req>")]
+pub async fn get_statement_csv(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    req: Json<api::StatementRequest>,
+) -> error::Result<(ContentType, String)> {
+    let Json(statement) = get_statement(cookies, auth_store, api_key, state, req).await?;
+    Ok((ContentType::CSV, statement.to_csv()))
+}
 
-        Ok(Json(api::History {
-            confirmations_config: CONFIRMATIONS_CONFIG,
-            history_items: history_slice,
-        }))
+/// Value of the amount in each supported fiat by the rates recorded at the given time.
+/// `None` if some rate wasn't recorded yet
+pub async fn historical_fiat_value(
+    pool: &Pool,
+    currency: &Currency,
+    value: u64,
+    at: NaiveDateTime,
+) -> Option<api::TickerUsdRub> {
+    let fiat = |to: Symbol| async move {
+        let record = query_rate_at(pool, &currency.symbol(), &to, at).await.ok()??;
+        Some((value as f64 / currency.precision() as f64 * record.rate.to_f64()) as f32)
+    };
+    Some(api::TickerUsdRub {
+        USD: fiat(Symbol::USD).await?,
+        RUB: fiat(Symbol::RUB).await?,
     })
-    .await
 }
 
 #[openapi(tag = "history")]
 #[get("/withdraweth/<addr>/<amount>")]
 pub async fn withdraw_eth(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     eth_client: &State<EthClient>,
     addr: String,
     amount: String,
 ) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, _| async move {
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |_, _| async move {
         eth_client
             .send_tx("testlogin", &addr, &amount)
             .await
@@ -362,17 +394,117 @@ pub async fn withdraw_eth(
     .await
 }
 
+/// Prices of one coin in each supported fiat. Empty if the ticker is not available,
+/// then withdrawals don't pass fiat limits.
+async fn fiat_rates(
+    rstate: &Mutex<RuntimeState>,
+    ticker_client: &TickerClient,
+    currency: &Currency,
+) -> FiatRates {
+    let rates = rstate
+        .lock()
+        .await
+        .symbol_to_symbols(ticker_client, currency.symbol(), Symbol::supported_fiats())
+        .await;
+    match rates {
+        Ok(rates) => rates
+            .into_iter()
+            .filter_map(|(symbol, rate)| Some((Fiat::from_symbol(symbol)?, rate)))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to get fiat rates of {}: {}", currency.ticker(), e);
+            FiatRates::new()
+        }
+    }
+}
+
+/// Estimate of the BTC network fee for a withdrawal with the given priority
+async fn btc_network_fee(btc: &BtcClient, tier: FeeTier) -> error::Result<u64> {
+    let fee_per_kilobyte = btc
+        .get_fees_for(tier.conf_target())
+        .await
+        .map_err(|_| error::Error::FailedGetFee(Currency::BTC))?
+        .fee_rate;
+    Ok((fee_per_kilobyte * BTC_BYTES_PER_TRANSACTION) / 1024)
+}
+
+/// Fees of the withdrawal for each priority according to the fee policy of the currency.
+/// Only BTC fees depend on the priority.
+#[openapi(tag = "withdraw")]
+#[post("/withdraw/fee", data = "<req>")]
+pub async fn get_withdrawal_fees(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    btc: &State<BtcClient>,
+    state: &State<Arc<Mutex<DbState>>>,
+    req: Json<api::WithdrawalFeeRequest>,
+) -> error::Result<Json<Vec<api::WithdrawalFee>>> {
+    let api::WithdrawalFeeRequest { currency, amount } = req.into_inner();
+    let currency_ref = &currency;
+    let policy = require_auth_user(cookies, api_key, auth_store, state, |mstate, _| async move {
+        Ok(mstate.fee_policy(currency_ref))
+    })
+    .await?;
+    let mut fees = vec![];
+    if currency == Currency::BTC {
+        for tier in FeeTier::all() {
+            let network_fee = btc_network_fee(btc, tier).await?;
+            fees.push(api::WithdrawalFee {
+                tier,
+                blocks: tier.conf_target(),
+                network_fee: Some(network_fee),
+                fee: policy.charge(amount, Some(network_fee)),
+            });
+        }
+    } else {
+        let tier = FeeTier::default();
+        fees.push(api::WithdrawalFee {
+            tier,
+            blocks: tier.conf_target(),
+            network_fee: None,
+            fee: policy.charge(amount, None),
+        });
+    }
+    Ok(Json(fees))
+}
+
 #[openapi(tag = "withdraw")]
 #[post("/withdraw", data = "<withdraw_request>")]
 pub async fn post_withdraw(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     btc: &State<BtcClient>,
+    rstate: &State<Arc<Mutex<RuntimeState>>>,
+    ticker_client: &State<TickerClient>,
     updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
     state: &State<Arc<Mutex<DbState>>>,
+    network: &State<Network>,
     withdraw_request: Json<api::UserWithdrawRequest>,
 ) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    withdraw_request.address.validate(network.btc())?;
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |mstate, user| async move {
+        if !user.is_active() {
+            return Err(error::Error::AccountNotActive(user.status.to_string()).into());
+        }
+        let policy = mstate.fee_policy(&withdraw_request.address.currency());
+        drop(mstate);
+        if let Some(totp) = user.totp.as_ref().filter(|t| t.require_for_withdrawal) {
+            verify_second_factor(
+                auth_store,
+                updater,
+                applied_updates,
+                &user.username,
+                totp,
+                withdraw_request.totp.as_deref(),
+            )
+            .await?;
+        }
+        if !user.can_withdraw_to(&withdraw_request.address, Utc::now().naive_utc()) {
+            return Err(error::Error::AddressNotAllowed(withdraw_request.address.to_string()).into());
+        }
         match &withdraw_request.address {
             CurrencyAddress::ETH(_) => {
                 let withdrawal_request = WithdrawalRequestInfo {
@@ -381,6 +513,10 @@ pub async fn post_withdraw(
                     address: withdraw_request.address.to_owned(),
                     amount: withdraw_request.amount,
                     request_type: WithdrawalRequestType::OverLimit,
+                    created_at: Some(Utc::now().naive_utc()),
+                    rates: FiatRates::new(),
+                    fee_tier: withdraw_request.fee_tier,
+                    charged_fee: policy.charge(withdraw_request.amount, None),
                 };
                 let state_update =
                     StateUpdate::new(UpdateBody::CreateWithdrawalRequest(withdrawal_request));
@@ -397,6 +533,10 @@ pub async fn post_withdraw(
                     address: withdraw_request.address.to_owned(),
                     amount: withdraw_request.amount,
                     request_type: WithdrawalRequestType::OverLimit,
+                    created_at: Some(Utc::now().naive_utc()),
+                    rates: FiatRates::new(),
+                    fee_tier: withdraw_request.fee_tier,
+                    charged_fee: policy.charge(withdraw_request.amount, None),
                 };
                 let state_update =
                     StateUpdate::new(UpdateBody::CreateWithdrawalRequest(withdrawal_request));
@@ -413,17 +553,17 @@ pub async fn post_withdraw(
                     .get(&btc_cur)
                     .ok_or(error::Error::NoUserCurrency(btc_cur.clone()))?;
                 let btc_balance = btc_info.finalized_balance();
-                let spent = btc_info.limit_info.spent;
-                let limit = btc_info.limit_info.limit.amount;
-                let btc_fee_per_kilobyte = &btc
-                    .get_fees()
-                    .await
-                    .map_err(|_| error::Error::FailedGetFee(Currency::BTC))?
-                    .fee_rate;
-                let required_amount = withdraw_request.amount
-                    + (btc_fee_per_kilobyte * BTC_BYTES_PER_TRANSACTION) / 1024;
+                let network_fee = btc_network_fee(btc, withdraw_request.fee_tier).await?;
+                let fee = policy
+                    .charge(withdraw_request.amount, Some(network_fee))
+                    .unwrap_or(network_fee);
+                let required_amount = withdraw_request.amount + fee;
                 if required_amount <= btc_balance {
-                    let req_type = if limit - spent >= required_amount {
+                    let now = Utc::now().naive_utc();
+                    let rates = fiat_rates(rstate, ticker_client, &btc_cur).await;
+                    let req_type = if btc_info.fits_limits(required_amount, &rates, now)
+                        && user.fits_aggregate_limits(&btc_cur, required_amount, &rates, now)
+                    {
                         WithdrawalRequestType::UnderLimit
                     } else {
                         WithdrawalRequestType::OverLimit
@@ -435,6 +575,10 @@ pub async fn post_withdraw(
                         address: withdraw_request.address.to_owned(),
                         amount: withdraw_request.amount,
                         request_type: req_type,
+                        created_at: Some(now),
+                        rates,
+                        fee_tier: withdraw_request.fee_tier,
+                        charged_fee: Some(fee),
                     };
                     let state_update =
                         StateUpdate::new(UpdateBody::CreateWithdrawalRequest(withdrawal_request));
@@ -453,18 +597,125 @@ pub async fn post_withdraw(
     .map_err(|e| e.into())
 }
 
+/// Cancel the withdrawal request that still waits for operators' decision.
+/// The amount returns to the balance
+#[openapi(tag = "withdraw")]
+#[post("/withdraw/cancel", data = "<id>")]
+pub async fn cancel_withdrawal(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    id: Json<Uuid>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |_, user| async move {
+        let (currency, req) = user
+            .currencies
+            .iter()
+            .find_map(|(currency, info)| info.withdrawal_requests.get(&id).map(|req| (currency, req)))
+            .ok_or(error::Error::WithdrawalRequestNotFound(id))?;
+        if !req.is_cancellable() {
+            return Err(error::Error::WithdrawalNotCancellable(id).into());
+        }
+        let cancel = WithdrawalCancelInfo {
+            id,
+            user: user.username.clone(),
+            currency: currency.clone(),
+            cancelled_at: Utc::now().naive_utc(),
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::CancelWithdrawalRequest(cancel)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(())
+    })
+    .await
+}
+
+/// Move funds to another user of the custody without an on-chain transaction.
+/// Transfers over the user's limits wait for operators' decision. Returns ID of the transfer
+#[openapi(tag = "transfer")]
+#[post("/transfer", data = "<req>")]
+pub async fn post_transfer(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    rstate: &State<Arc<Mutex<RuntimeState>>>,
+    ticker_client: &State<TickerClient>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    applied_updates: &State<broadcast::Sender<StateUpdate>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    req: Json<api::TransferRequest>,
+) -> error::Result<Json<Uuid>> {
+    let req = req.into_inner();
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Withdraw, |mstate, user| async move {
+        if req.to == user.username {
+            return Err(error::Error::TransferToSelf.into());
+        }
+        let has_recipient = mstate
+            .users
+            .get(&req.to)
+            .map_or(false, |r| r.status != api::AccountStatus::Closed && r.currencies.contains_key(&req.currency));
+        if !has_recipient {
+            return Err(error::Error::TransferRecipientNotFound(req.to, req.currency).into());
+        }
+        // The state must be unlocked, the second factor check sends an update
+        drop(mstate);
+        if let Some(totp) = user.totp.as_ref().filter(|t| t.require_for_withdrawal) {
+            verify_second_factor(auth_store, updater, applied_updates, &user.username, totp, req.totp.as_deref())
+                .await?;
+        }
+        let cinfo = user
+            .currencies
+            .get(&req.currency)
+            .ok_or(error::Error::NoUserCurrency(req.currency.clone()))?;
+        if cinfo.finalized_balance() < req.amount {
+            return Err(error::Error::InsufficientFunds(req.currency).into());
+        }
+        let now = Utc::now().naive_utc();
+        let rates = fiat_rates(rstate, ticker_client, &req.currency).await;
+        let request_type = if cinfo.fits_limits(req.amount, &rates, now)
+            && user.fits_aggregate_limits(&req.currency, req.amount, &rates, now)
+        {
+            WithdrawalRequestType::UnderLimit
+        } else {
+            WithdrawalRequestType::OverLimit
+        };
+        let id = Uuid::new_v4();
+        let transfer = InternalTransferUpd {
+            id,
+            from: user.username,
+            to: req.to,
+            currency: req.currency,
+            amount: req.amount,
+            created_at: now,
+            request_type,
+            rates,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::InternalTransfer(transfer)))
+            .await
+            .map_err(|e| error::Error::GenericError(e.to_string()))?;
+        Ok(Json(id))
+    })
+    .await
+}
+
 #[openapi(tag = "deposit")]
 #[post("/deposit/address", data = "<currency>")]
 pub async fn get_deposit_address_handle(
     btc_client: &State<BtcClient>,
     eth_client: &State<EthClient>,
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     currency: Json<Currency>,
 ) -> error::Result<Json<CurrencyAddress>> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let currency = currency.into_inner();
         get_deposit_address(
             btc_client,
@@ -601,10 +852,11 @@ async fn allocate_erc20_address(
 #[get("/profile/tokens/list")]
 pub async fn list_tokens(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
 ) -> error::Result<Json<GetTokensResponse>> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let mut info: Vec<TokenInfo> = Currency::supported_tokens()
             .into_iter()
             .map(
@@ -634,13 +886,14 @@ pub async fn list_tokens(
 #[post("/profile/tokens/enable", data = "<req>")]
 pub async fn enable_token(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     eth_client: &State<EthClient>,
     req: Json<TokenActionRequest>,
 ) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let token = req.into_inner().token;
         let c = Currency::ERC20(token.clone());
         match user.currencies.get(&c) {
@@ -673,13 +926,14 @@ pub async fn enable_token(
 #[post("/profile/tokens/disable", data = "<req>")]
 pub async fn disable_token(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     updater: &State<mpsc::Sender<StateUpdate>>,
     eth_client: &State<EthClient>,
     req: Json<TokenActionRequest>,
 ) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let token = req.into_inner().token;
         let cur = Currency::ERC20(token.clone());
         match user.currencies.get(&cur) {
@@ -727,18 +981,27 @@ pub async fn disable_token(
     .await
 }
 
+/// How long an issued exchange quote can be accepted
+pub const EXCHANGE_QUOTE_TTL_SECS: i64 = 30;
+/// How long operators have to decide on an accepted exchange order before the funds are released
+pub const EXCHANGE_ORDER_TTL_MINS: i64 = 60;
+/// Rounding of quoted prices and amounts. Rounding down is in the house's favor
+pub const EXCHANGE_ROUNDING: Rounding = Rounding::Down;
+/// Oldest ticker rate that can be used for a quote
+pub const EXCHANGE_RATE_MAX_AGE_SECS: i64 = 300;
+
 #[openapi(tag = "wallet")]
-#[post("/exchange/order", data = "<req>")]
-pub async fn order_exchange(
+#[post("/exchange/quote", data = "<req>")]
+pub async fn quote_exchange(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
-    updater: &State<mpsc::Sender<StateUpdate>>,
     ticker_client: &State<TickerClient>,
     rstate: &State<Arc<Mutex<RuntimeState>>>,
     req: Json<ExchangeRequest>,
-) -> error::Result<()> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+) -> error::Result<Json<ExchangeQuote>> {
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Exchange, |_, user| async move {
         let ExchangeRequest {
             currency_from,
             currency_to,
@@ -748,36 +1011,101 @@ pub async fn order_exchange(
             .currencies
             .get(&currency_from)
             .ok_or(error::Error::NoUserCurrency(currency_from.clone()))?;
-        let balance = cinfo.balance();
+        if cinfo.finalized_balance() < amount_from {
+            return Err(error::Error::InsufficientFunds(currency_from).into());
+        }
         let mut rstate = rstate.lock().await;
         let from_symbol = currency_from.symbol();
         let to_symbol = currency_to.symbol();
+        let margin = rstate.get_margin(&from_symbol, &to_symbol);
         let rate = rstate
-            .symbol_to_symbol_adjusted(ticker_client, from_symbol.to_owned(), to_symbol.to_owned())
+            .fresh_rate(ticker_client, from_symbol.to_owned(), to_symbol.to_owned(), Duration::seconds(EXCHANGE_RATE_MAX_AGE_SECS))
             .await
-            .map_err(|e| error::Error::GenericError(e.to_string()))?;
-        let amount_to =
-            (amount_from as f64 / from_symbol.exponent() * rate * to_symbol.exponent()) as u64;
-        if balance < amount_from {
+            .map_err(|_| error::Error::ExchangeRateStale(from_symbol.symbol(), to_symbol.symbol()))?
+            .rate;
+        // We receive and store margins in whole percents
+        let adjusted_rate = rate.sub_percent(margin, EXCHANGE_ROUNDING);
+        let amount_to = adjusted_rate
+            .convert(amount_from, currency_from.precision(), currency_to.precision(), EXCHANGE_ROUNDING)
+            .ok_or_else(|| error::Error::GenericError("Exchanged amount is out of range".to_owned()))?;
+        let quote = ExchangeQuote {
+            id: Uuid::new_v4(),
+            currency_from,
+            currency_to,
+            amount_from,
+            amount_to,
+            rate,
+            margin,
+            expires_at: Utc::now().naive_utc() + Duration::seconds(EXCHANGE_QUOTE_TTL_SECS),
+        };
+        rstate.issue_quote(user.username, quote.clone());
+        Ok(Json(quote))
+    })
+    .await
+}
+
+#[openapi(tag = "wallet")]
+#[post("/exchange/accept", data = "<quote_id>")]
+pub async fn accept_exchange(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    rstate: &State<Arc<Mutex<RuntimeState>>>,
+    quote_id: Json<Uuid>,
+) -> error::Result<()> {
+    require_auth_user_scoped(cookies, api_key, auth_store, state, ApiKeyScope::Exchange, |mstate, user| async move {
+        if !user.is_active() {
+            return Err(error::Error::AccountNotActive(user.status.to_string()).into());
+        }
+        // Don't hold the state while waiting for the runtime state and the updater
+        drop(mstate);
+        let ExchangeQuote {
+            id,
+            currency_from,
+            currency_to,
+            amount_from,
+            amount_to,
+            rate,
+            margin,
+            ..
+        } = rstate
+            .lock()
+            .await
+            .take_quote(&quote_id.into_inner(), &user.username)
+            .ok_or(error::Error::ExchangeQuoteExpired)?;
+        let cinfo = user
+            .currencies
+            .get(&currency_from)
+            .ok_or(error::Error::NoUserCurrency(currency_from.clone()))?;
+        // The balance could have been spent since the quote was issued
+        if cinfo.finalized_balance() < amount_from {
             return Err(error::Error::InsufficientFunds(currency_from).into());
-        } else {
-            let id = Uuid::new_v4();
-            let created_at = chrono::offset::Utc::now().to_string();
-            let req = ExchangeOrderUpd {
-                user: user.username,
-                currency_from,
-                currency_to,
-                amount_from,
-                amount_to,
-                id,
-                created_at,
-            };
-            let upd = StateUpdate::new(UpdateBody::ExchangeRequest(req));
-            updater
-                .send(upd)
-                .await
-                .map_err(|e| error::Error::GenericError(e.to_string()).into())
         }
+        let now = Utc::now();
+        let req = ExchangeOrderUpd {
+            user: user.username,
+            currency_from,
+            currency_to,
+            amount_from,
+            amount_to,
+            id,
+            created_at: now.to_string(),
+            rate: Some(rate),
+            margin: Some(margin),
+            expires_at: Some(now.naive_utc() + Duration::minutes(EXCHANGE_ORDER_TTL_MINS)),
+        };
+        let auto = state.lock().await.can_auto_execute(&req, now.naive_utc());
+        let upd = if auto {
+            StateUpdate::new(UpdateBody::ExchangeAutoExecuted(req))
+        } else {
+            StateUpdate::new(UpdateBody::ExchangeRequest(req))
+        };
+        updater
+            .send(upd)
+            .await
+            .map_err(|e| error::Error::GenericError(e.to_string()).into())
     })
     .await
 }
@@ -786,11 +1114,12 @@ pub async fn order_exchange(
 #[get("/exchange/list?<filter>")]
 pub async fn list_my_orders(
     cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
     api_key: Option<ApiKey>,
     state: &State<Arc<Mutex<DbState>>>,
     filter: ExchangeFilter,
 ) -> error::Result<Json<Vec<hexstody_api::types::ExchangeOrder>>> {
-    require_auth_user(cookies, api_key, state, |_, user| async move {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
         let res = user.get_exchange_requests(filter);
         Ok(Json(res))
     })
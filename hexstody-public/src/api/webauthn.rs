@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use hexstody_api::domain::error;
+use hexstody_api::types::{
+    WebauthnAuthenticatorSelection, WebauthnCredDescriptor, WebauthnCredParam,
+    WebauthnCredentialInfo, WebauthnLoginOptions, WebauthnLoginRequest, WebauthnRegisterOptions,
+    WebauthnRegisterRequest, WebauthnRelyingParty, WebauthnUser,
+};
+use hexstody_auth::types::ClientInfo;
+use hexstody_auth::{require_auth_user, start_session, AuthStore};
+use hexstody_db::state::State as DbState;
+use hexstody_db::update::webauthn::{
+    WebauthnCredentialAdd, WebauthnCredentialRemove, WebauthnCredentialUse,
+};
+use hexstody_db::update::{StateUpdate, UpdateBody};
+use hexstody_runtime_db::{ChallengePurpose, RuntimeState};
+use hexstody_sig::SignatureVerificationConfig;
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use rocket_okapi::openapi;
+use tokio::sync::{mpsc, Mutex};
+
+use super::auth::{challenge_session, CHALLENGE_SESSION_COOKIE, CHALLENGE_TTL_SECS};
+use crate::webauthn::{
+    decode_b64url, encode_b64url, verify_assertion, verify_registration, RelyingParty,
+    COSE_ALG_ES256,
+};
+
+/// Maximum number of WebAuthn credentials per user
+const MAX_USER_CREDENTIALS: usize = 10;
+/// Maximum length of the authenticator name
+const MAX_CREDENTIAL_NAME_LEN: usize = 64;
+
+fn relying_party(config: &SignatureVerificationConfig) -> error::Result<RelyingParty> {
+    RelyingParty::from_domain(&config.domain).ok_or_else(|| {
+        error::Error::InternalServerError(format!("Invalid domain {}", config.domain)).into()
+    })
+}
+
+fn webauthn_error(e: crate::webauthn::Error) -> error::ErrorMessage {
+    error::Error::WebauthnFailed(e.to_string()).into()
+}
+
+/// Take the challenge of the browser session. Fails if the challenge was issued for another purpose.
+async fn take_session_challenge(
+    runtime_state: &Mutex<RuntimeState>,
+    cookies: &CookieJar<'_>,
+    purpose: ChallengePurpose,
+) -> error::Result<String> {
+    let session = cookies
+        .get_private(CHALLENGE_SESSION_COOKIE)
+        .ok_or(error::Error::ChallengeExpired)?;
+    let challenge = runtime_state
+        .lock()
+        .await
+        .take_challenge(session.value())
+        .filter(|c| c.purpose == purpose)
+        .ok_or(error::Error::ChallengeExpired)?;
+    Ok(challenge.challenge)
+}
+
+/// Start registration of a new passkey. Returns options for 'navigator.credentials.create'.
+#[openapi(tag = "webauthn")]
+#[post("/webauthn/register/begin")]
+pub async fn webauthn_register_begin(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    runtime_state: &State<Arc<Mutex<RuntimeState>>>,
+    config: &State<SignatureVerificationConfig>,
+) -> error::Result<Json<WebauthnRegisterOptions>> {
+    let rp = relying_party(config)?;
+    // Passkeys can be managed only from a signed in browser
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        if user.webauthn_credentials.len() >= MAX_USER_CREDENTIALS {
+            return Err(error::Error::GenericError(format!(
+                "Too many passkeys, maximum is {MAX_USER_CREDENTIALS}"
+            ))
+            .into());
+        }
+        let session = challenge_session(cookies);
+        let challenge = runtime_state.lock().await.issue_challenge(
+            session,
+            ChallengePurpose::WebauthnRegister {
+                user: user.username.clone(),
+            },
+            Duration::seconds(CHALLENGE_TTL_SECS),
+        );
+        Ok(Json(WebauthnRegisterOptions {
+            challenge,
+            rp: WebauthnRelyingParty {
+                id: rp.id,
+                name: "Hexstody".to_owned(),
+            },
+            user: WebauthnUser {
+                id: encode_b64url(user.username.as_bytes()),
+                name: user.username.clone(),
+                display_name: user.username.clone(),
+            },
+            pub_key_cred_params: vec![WebauthnCredParam {
+                cred_type: "public-key".to_owned(),
+                alg: COSE_ALG_ES256,
+            }],
+            timeout: CHALLENGE_TTL_SECS as u64 * 1000,
+            attestation: "none".to_owned(),
+            exclude_credentials: user
+                .webauthn_credentials
+                .keys()
+                .map(|id| WebauthnCredDescriptor {
+                    cred_type: "public-key".to_owned(),
+                    id: id.clone(),
+                })
+                .collect(),
+            authenticator_selection: WebauthnAuthenticatorSelection {
+                resident_key: "required".to_owned(),
+                user_verification: "required".to_owned(),
+            },
+        }))
+    })
+    .await
+}
+
+/// Finish passkey registration with the result of 'navigator.credentials.create'
+#[openapi(tag = "webauthn")]
+#[post("/webauthn/register/finish", data = "<request>")]
+pub async fn webauthn_register_finish(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    runtime_state: &State<Arc<Mutex<RuntimeState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    config: &State<SignatureVerificationConfig>,
+    request: Json<WebauthnRegisterRequest>,
+) -> error::Result<Json<WebauthnCredentialInfo>> {
+    let rp = relying_party(config)?;
+    let request = request.into_inner();
+    let name = request.name.trim().to_owned();
+    if name.is_empty() || name.len() > MAX_CREDENTIAL_NAME_LEN {
+        return Err(error::Error::GenericError(format!(
+            "Passkey name must be from 1 to {MAX_CREDENTIAL_NAME_LEN} characters"
+        ))
+        .into());
+    }
+    require_auth_user(cookies, None, auth_store, state, |mstate, user| async move {
+        // The state is locked again below to check the credential is not taken
+        drop(mstate);
+        let challenge = take_session_challenge(
+            runtime_state,
+            cookies,
+            ChallengePurpose::WebauthnRegister {
+                user: user.username.clone(),
+            },
+        )
+        .await?;
+        let client_data_json = decode_b64url(&request.client_data_json).map_err(webauthn_error)?;
+        let attestation_object =
+            decode_b64url(&request.attestation_object).map_err(webauthn_error)?;
+        let cred = verify_registration(&rp, &challenge, &client_data_json, &attestation_object)
+            .map_err(webauthn_error)?;
+        let credential_id = encode_b64url(&cred.id);
+        if state.lock().await.find_webauthn_credential(&credential_id).is_some() {
+            return Err(error::Error::WebauthnFailed(
+                "Passkey is already registered".to_owned(),
+            )
+            .into());
+        }
+        let add = WebauthnCredentialAdd {
+            user: user.username,
+            credential_id,
+            name,
+            public_key: cred.public_key,
+            sign_count: cred.sign_count,
+            created_at: Utc::now().naive_utc(),
+        };
+        let info = WebauthnCredentialInfo {
+            id: add.credential_id.clone(),
+            name: add.name.clone(),
+            created_at: add.created_at,
+            last_used: None,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::WebauthnCredentialAdd(add)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(info))
+    })
+    .await
+}
+
+#[openapi(tag = "webauthn")]
+#[get("/webauthn/credentials")]
+pub async fn list_webauthn_credentials(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<Vec<WebauthnCredentialInfo>>> {
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        let mut creds: Vec<WebauthnCredentialInfo> = user
+            .webauthn_credentials
+            .into_values()
+            .map(|c| c.into())
+            .collect();
+        creds.sort_by_key(|c| c.created_at);
+        Ok(Json(creds))
+    })
+    .await
+}
+
+#[openapi(tag = "webauthn")]
+#[post("/webauthn/credentials/remove", data = "<id>")]
+pub async fn remove_webauthn_credential(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    id: Json<String>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_auth_user(cookies, None, auth_store, state, |_, user| async move {
+        if !user.webauthn_credentials.contains_key(&id) {
+            return Err(error::Error::WebauthnCredentialNotFound(id).into());
+        }
+        let remove = WebauthnCredentialRemove {
+            user: user.username,
+            credential_id: id,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::WebauthnCredentialRemove(remove)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(())
+    })
+    .await
+}
+
+/// Start passkey sign in. Returns options for 'navigator.credentials.get'.
+/// Passkeys are discoverable, so the user name is not required.
+#[openapi(tag = "webauthn")]
+#[post("/webauthn/login/begin")]
+pub async fn webauthn_login_begin(
+    cookies: &CookieJar<'_>,
+    runtime_state: &State<Arc<Mutex<RuntimeState>>>,
+    config: &State<SignatureVerificationConfig>,
+) -> error::Result<Json<WebauthnLoginOptions>> {
+    let rp = relying_party(config)?;
+    let session = challenge_session(cookies);
+    let challenge = runtime_state.lock().await.issue_challenge(
+        session,
+        ChallengePurpose::WebauthnLogin,
+        Duration::seconds(CHALLENGE_TTL_SECS),
+    );
+    Ok(Json(WebauthnLoginOptions {
+        challenge,
+        rp_id: rp.id,
+        timeout: CHALLENGE_TTL_SECS as u64 * 1000,
+        user_verification: "required".to_owned(),
+    }))
+}
+
+/// Finish passkey sign in with the result of 'navigator.credentials.get'.
+/// Passkey with user verification is already two factors, so TOTP is not asked.
+#[openapi(tag = "webauthn")]
+#[post("/webauthn/login/finish", data = "<request>")]
+pub async fn webauthn_login_finish(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    state: &State<Arc<Mutex<DbState>>>,
+    runtime_state: &State<Arc<Mutex<RuntimeState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    config: &State<SignatureVerificationConfig>,
+    client: ClientInfo,
+    request: Json<WebauthnLoginRequest>,
+) -> error::Result<()> {
+    let rp = relying_party(config)?;
+    let request = request.into_inner();
+    let challenge =
+        take_session_challenge(runtime_state, cookies, ChallengePurpose::WebauthnLogin).await?;
+    let (user, cred) = state
+        .lock()
+        .await
+        .find_webauthn_credential(&request.id)
+        .map(|(u, c)| (u.username.clone(), c.clone()))
+        .ok_or_else(|| error::Error::WebauthnCredentialNotFound(request.id.clone()))?;
+    let client_data_json = decode_b64url(&request.client_data_json).map_err(webauthn_error)?;
+    let auth_data = decode_b64url(&request.authenticator_data).map_err(webauthn_error)?;
+    let signature = decode_b64url(&request.signature).map_err(webauthn_error)?;
+    let sign_count = verify_assertion(
+        &rp,
+        &challenge,
+        &cred.public_key,
+        cred.sign_count,
+        &client_data_json,
+        &auth_data,
+        &signature,
+    )
+    .map_err(webauthn_error)?;
+    let upd = WebauthnCredentialUse {
+        user: user.clone(),
+        credential_id: cred.id,
+        sign_count,
+        used_at: Utc::now().naive_utc(),
+    };
+    updater
+        .send(StateUpdate::new(UpdateBody::WebauthnCredentialUse(upd)))
+        .await
+        .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+    start_session(cookies, auth_store, state, &user, client).await?;
+    Ok(())
+}
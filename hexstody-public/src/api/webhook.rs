@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use chrono::prelude::*;
+use hexstody_api::domain::error;
+use hexstody_api::types::{WebhookDelivery, WebhookDeliveryStatus, WebhookInfo, WebhookRequest};
+use hexstody_auth::types::ApiKey;
+use hexstody_auth::{require_auth, require_auth_user, AuthStore};
+use hexstody_db::queries::{query_webhook_deliveries, query_webhook_delivery, upsert_webhook_delivery};
+use hexstody_db::state::State as DbState;
+use hexstody_db::update::webhook::{WebhookRegister, WebhookRemove};
+use hexstody_db::update::{StateUpdate, UpdateBody};
+use hexstody_db::Pool;
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use rocket::{get, post, State};
+use rocket_okapi::openapi;
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use super::auth::IsTestFlag;
+use crate::webhook::deliver;
+
+/// Maximum number of webhooks per user
+const MAX_USER_WEBHOOKS: usize = 10;
+
+fn validate_webhook_url(url: &str, is_test: bool) -> error::Result<()> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| error::Error::WebhookInvalidUrl(format!("{url}: {e}")))?;
+    // Plain HTTP is allowed only for local receivers in tests
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if is_test => Ok(()),
+        _ => Err(error::Error::WebhookInvalidUrl(format!("{url}: only https is supported")).into()),
+    }
+}
+
+#[openapi(tag = "webhook")]
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+) -> error::Result<Json<Vec<WebhookInfo>>> {
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        let mut hooks: Vec<WebhookInfo> = user.webhooks.into_values().map(|h| h.into()).collect();
+        hooks.sort_by_key(|h| h.created_at);
+        Ok(Json(hooks))
+    })
+    .await
+}
+
+#[openapi(tag = "webhook")]
+#[post("/webhooks", data = "<request>")]
+pub async fn register_webhook(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    is_test: &State<IsTestFlag>,
+    request: Json<WebhookRequest>,
+) -> error::Result<Json<WebhookInfo>> {
+    let WebhookRequest { url, secret } = request.into_inner();
+    validate_webhook_url(&url, is_test.0)?;
+    if secret.is_empty() {
+        return Err(error::Error::GenericError("Webhook secret is empty".to_owned()).into());
+    }
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        if user.webhooks.len() >= MAX_USER_WEBHOOKS {
+            return Err(error::Error::GenericError(format!(
+                "Too many webhooks, maximum is {MAX_USER_WEBHOOKS}"
+            ))
+            .into());
+        }
+        let register = WebhookRegister {
+            id: Uuid::new_v4(),
+            user: user.username,
+            url,
+            secret,
+            created_at: Utc::now().naive_utc(),
+        };
+        let info = WebhookInfo {
+            id: register.id,
+            url: register.url.clone(),
+            created_at: register.created_at,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::RegisterWebhook(register)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(info))
+    })
+    .await
+}
+
+#[openapi(tag = "webhook")]
+#[post("/webhooks/remove", data = "<id>")]
+pub async fn remove_webhook(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    updater: &State<mpsc::Sender<StateUpdate>>,
+    id: Json<Uuid>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_auth_user(cookies, api_key, auth_store, state, |_, user| async move {
+        if !user.webhooks.contains_key(&id) {
+            return Err(error::Error::WebhookNotFound(id).into());
+        }
+        let remove = WebhookRemove {
+            id,
+            user: user.username,
+        };
+        updater
+            .send(StateUpdate::new(UpdateBody::RemoveWebhook(remove)))
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(())
+    })
+    .await
+}
+
+#[openapi(tag = "webhook")]
+#[get("/webhooks/deliveries?<skip>&<take>")]
+pub async fn list_webhook_deliveries(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    pool: &State<Pool>,
+    skip: Option<u32>,
+    take: Option<u32>,
+) -> error::Result<Json<Vec<WebhookDelivery>>> {
+    let skip = skip.unwrap_or(0) as i64;
+    let take = take.unwrap_or(50).min(500) as i64;
+    require_auth(cookies, api_key, auth_store, state, |user_id| async move {
+        let deliveries = query_webhook_deliveries(pool, &user_id, skip, take)
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        Ok(Json(deliveries))
+    })
+    .await
+}
+
+/// Send recorded delivery once again. Useful when the receiver was down
+/// longer than the retry schedule covers.
+#[openapi(tag = "webhook")]
+#[post("/webhooks/deliveries/replay", data = "<id>")]
+pub async fn replay_webhook_delivery(
+    cookies: &CookieJar<'_>,
+    auth_store: &State<Arc<Mutex<AuthStore>>>,
+    api_key: Option<ApiKey>,
+    state: &State<Arc<Mutex<DbState>>>,
+    pool: &State<Pool>,
+    id: Json<Uuid>,
+) -> error::Result<()> {
+    let id = id.into_inner();
+    require_auth(cookies, api_key, auth_store, state, |user_id| async move {
+        let mut delivery = query_webhook_delivery(pool, id)
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?
+            .filter(|d| d.user == user_id)
+            .ok_or(error::Error::WebhookDeliveryNotFound(id))?;
+        let webhook = state
+            .lock()
+            .await
+            .get_user_by_id(&user_id)
+            .and_then(|u| u.webhooks.get(&delivery.webhook_id).cloned())
+            .ok_or(error::Error::WebhookNotFound(delivery.webhook_id))?;
+        delivery.status = WebhookDeliveryStatus::Pending;
+        delivery.updated = Utc::now().naive_utc();
+        upsert_webhook_delivery(pool, &delivery)
+            .await
+            .map_err(|e| error::Error::InternalServerError(e.to_string()))?;
+        tokio::spawn(deliver(
+            reqwest::Client::new(),
+            pool.inner().clone(),
+            webhook,
+            delivery,
+        ));
+        Ok(())
+    })
+    .await
+}
@@ -1,3 +1,6 @@
 pub mod api;
+pub mod totp;
+pub mod webauthn;
+pub mod webhook;
 #[cfg(test)]
 mod tests;
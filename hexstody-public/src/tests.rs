@@ -16,6 +16,7 @@ use hexstody_client::client::HexstodyClient;
 use rocket::fs::relative;
 use std::panic::AssertUnwindSafe;
 use hexstody_runtime_db::RuntimeState;
+use hexstody_auth::AuthStore;
 
 const SERVICE_TEST_PORT: u16 = 8000;
 const SERVICE_TEST_HOST: &str = "127.0.0.1";
@@ -33,11 +34,13 @@ where
 
     let (sender, receiver) = tokio::sync::oneshot::channel();
     let (update_sender, _) = tokio::sync::mpsc::channel(1000);
+    let (applied_updates, _) = tokio::sync::broadcast::channel(1000);
     let pub_keys : Vec<PublicKey> = vec![];
     let btc_client = BtcClient::new("127.0.0.1");
     let eth_client = EthClient::new("http://127.0.0.1");
     let ticker_client = TickerClient::new("https://min-api.cryptocompare.com");
     let runtime_state = Arc::new(Mutex::new(RuntimeState::new()));
+    let auth_store = Arc::new(Mutex::new(AuthStore::default()));
     let api_config = rocket::Config::figment()
         .merge(("port", SERVICE_TEST_PORT))
         .merge(("static_path", relative!("static")))
@@ -54,9 +57,11 @@ where
                 pool,
                 state,
                 runtime_state,
+                auth_store,
                 state_notify,
                 start_notify,
                 update_sender,
+                applied_updates,
                 btc_client,
                 eth_client,
                 ticker_client,
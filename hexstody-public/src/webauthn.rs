@@ -0,0 +1,449 @@
+use std::io::Cursor;
+
+use ciborium::value::Value;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use p256::PublicKey;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// User touched the authenticator
+pub const FLAG_USER_PRESENT: u8 = 0x01;
+/// Authenticator data contains attested credential
+pub const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+/// COSE algorithm ID of ECDSA with P-256 and SHA-256. The only one we accept,
+/// so the keys can be verified the same way as other signatures in the system.
+pub const COSE_ALG_ES256: i64 = -7;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum Error {
+    #[error("Malformed base64url value")]
+    Base64,
+    #[error("Malformed client data: {0}")]
+    ClientData(String),
+    #[error("Unexpected ceremony type: {0}")]
+    CeremonyType(String),
+    #[error("Challenge doesn't match")]
+    ChallengeMismatch,
+    #[error("Origin {0} doesn't match")]
+    OriginMismatch(String),
+    #[error("Malformed authenticator data")]
+    AuthData,
+    #[error("Relying party ID doesn't match")]
+    RpIdMismatch,
+    #[error("User presence flag is not set")]
+    UserNotPresent,
+    #[error("Malformed attestation object: {0}")]
+    Attestation(String),
+    #[error("Unsupported attestation format: {0}")]
+    UnsupportedAttestation(String),
+    #[error("Unsupported credential key, only ES256 is accepted")]
+    UnsupportedKey,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Signature counter didn't increase, the authenticator might be cloned")]
+    CounterRegression,
+}
+
+/// Alias for a `Result` with the error type `self::Error`.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Relying party the credentials are bound to
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    /// Host name of the wallet, e.g. "wallet.example.com"
+    pub id: String,
+    /// Full origin, e.g. "https://wallet.example.com"
+    pub origin: String,
+}
+
+impl RelyingParty {
+    /// Relying party for the public API domain from the config
+    pub fn from_domain(domain: &str) -> Option<Self> {
+        let url = reqwest::Url::parse(domain).ok()?;
+        Some(RelyingParty {
+            id: url.host_str()?.to_owned(),
+            origin: url.origin().ascii_serialization(),
+        })
+    }
+}
+
+pub fn decode_b64url(value: &str) -> Result<Vec<u8>> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD).map_err(|_| Error::Base64)
+}
+
+pub fn encode_b64url(value: &[u8]) -> String {
+    base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+fn verify_client_data(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    ceremony: &str,
+    challenge: &str,
+) -> Result<()> {
+    let data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|e| Error::ClientData(e.to_string()))?;
+    if data.ceremony != ceremony {
+        return Err(Error::CeremonyType(data.ceremony));
+    }
+    // Browsers may encode the challenge with or without padding
+    if data.challenge.trim_end_matches('=') != challenge {
+        return Err(Error::ChallengeMismatch);
+    }
+    if data.origin != rp.origin {
+        return Err(Error::OriginMismatch(data.origin));
+    }
+    Ok(())
+}
+
+struct AuthData {
+    sign_count: u32,
+    /// Credential ID and its key, present only at registration
+    credential: Option<(Vec<u8>, PublicKey)>,
+}
+
+fn parse_auth_data(rp: &RelyingParty, data: &[u8]) -> Result<AuthData> {
+    if data.len() < 37 {
+        return Err(Error::AuthData);
+    }
+    if data[..32] != Sha256::digest(rp.id.as_bytes())[..] {
+        return Err(Error::RpIdMismatch);
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(Error::UserNotPresent);
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // AAGUID (16 bytes) is followed by 2 bytes of credential ID length
+        let rest = data.get(37 + 16..).ok_or(Error::AuthData)?;
+        if rest.len() < 2 {
+            return Err(Error::AuthData);
+        }
+        let id_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let id = rest.get(2..2 + id_len).ok_or(Error::AuthData)?.to_vec();
+        let key: Value = ciborium::de::from_reader(Cursor::new(&rest[2 + id_len..]))
+            .map_err(|_| Error::AuthData)?;
+        Some((id, parse_cose_key(&key)?))
+    } else {
+        None
+    };
+    Ok(AuthData {
+        sign_count,
+        credential,
+    })
+}
+
+fn map_get<'a>(map: &'a [(Value, Value)], key: i64) -> Option<&'a Value> {
+    map.iter().find_map(|(k, v)| match k {
+        Value::Integer(i) if i128::from(*i) == key as i128 => Some(v),
+        _ => None,
+    })
+}
+
+fn as_int(value: Option<&Value>) -> Option<i128> {
+    match value {
+        Some(Value::Integer(i)) => Some(i128::from(*i)),
+        _ => None,
+    }
+}
+
+fn as_bytes(value: Option<&Value>) -> Option<&Vec<u8>> {
+    match value {
+        Some(Value::Bytes(b)) => Some(b),
+        _ => None,
+    }
+}
+
+/// Decode EC2 P-256 key in COSE format
+fn parse_cose_key(key: &Value) -> Result<PublicKey> {
+    let map = match key {
+        Value::Map(map) => map,
+        _ => return Err(Error::UnsupportedKey),
+    };
+    let kty = as_int(map_get(map, 1));
+    let alg = as_int(map_get(map, 3));
+    let crv = as_int(map_get(map, -1));
+    if kty != Some(2) || alg != Some(COSE_ALG_ES256 as i128) || crv != Some(1) {
+        return Err(Error::UnsupportedKey);
+    }
+    let x = as_bytes(map_get(map, -2)).ok_or(Error::UnsupportedKey)?;
+    let y = as_bytes(map_get(map, -3)).ok_or(Error::UnsupportedKey)?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(Error::UnsupportedKey);
+    }
+    let sec1 = [&[0x04], x.as_slice(), y.as_slice()].concat();
+    PublicKey::from_sec1_bytes(&sec1).map_err(|_| Error::UnsupportedKey)
+}
+
+fn verify_signature(key: &PublicKey, auth_data: &[u8], client_data_json: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::from_der(signature).map_err(|_| Error::InvalidSignature)?;
+    let message = [auth_data, &Sha256::digest(client_data_json)[..]].concat();
+    VerifyingKey::from(key)
+        .verify(&message, &signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// Credential that passed registration checks
+#[derive(Debug, PartialEq)]
+pub struct NewCredential {
+    pub id: Vec<u8>,
+    pub public_key: PublicKey,
+    pub sign_count: u32,
+}
+
+/// Verify result of 'navigator.credentials.create'. We request "none" attestation,
+/// so the only accepted formats are "none" and self-attested "packed".
+pub fn verify_registration(
+    rp: &RelyingParty,
+    challenge: &str,
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Result<NewCredential> {
+    verify_client_data(rp, client_data_json, "webauthn.create", challenge)?;
+    let attestation: Value = ciborium::de::from_reader(attestation_object)
+        .map_err(|e| Error::Attestation(e.to_string()))?;
+    let fields = match &attestation {
+        Value::Map(map) => map,
+        _ => return Err(Error::Attestation("not a map".to_owned())),
+    };
+    let field = |name: &str| {
+        fields.iter().find_map(|(k, v)| match k {
+            Value::Text(t) if t == name => Some(v),
+            _ => None,
+        })
+    };
+    let fmt = match field("fmt") {
+        Some(Value::Text(fmt)) => fmt.clone(),
+        _ => return Err(Error::Attestation("missing fmt".to_owned())),
+    };
+    let auth_data_raw = as_bytes(field("authData"))
+        .ok_or_else(|| Error::Attestation("missing authData".to_owned()))?;
+    let auth_data = parse_auth_data(rp, auth_data_raw)?;
+    let (id, public_key) = auth_data
+        .credential
+        .ok_or_else(|| Error::Attestation("missing attested credential".to_owned()))?;
+    match fmt.as_str() {
+        "none" => (),
+        "packed" => {
+            let stmt = match field("attStmt") {
+                Some(Value::Map(stmt)) => stmt,
+                _ => return Err(Error::Attestation("missing attStmt".to_owned())),
+            };
+            let stmt_field = |name: &str| {
+                stmt.iter().find_map(|(k, v)| match k {
+                    Value::Text(t) if t == name => Some(v),
+                    _ => None,
+                })
+            };
+            // Full attestation with a certificate chain is not requested
+            if stmt_field("x5c").is_some() {
+                return Err(Error::UnsupportedAttestation("packed with x5c".to_owned()));
+            }
+            if as_int(stmt_field("alg")) != Some(COSE_ALG_ES256 as i128) {
+                return Err(Error::UnsupportedKey);
+            }
+            let sig = as_bytes(stmt_field("sig"))
+                .ok_or_else(|| Error::Attestation("missing sig".to_owned()))?;
+            verify_signature(&public_key, auth_data_raw, client_data_json, sig)?;
+        }
+        other => return Err(Error::UnsupportedAttestation(other.to_owned())),
+    }
+    Ok(NewCredential {
+        id,
+        public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Verify result of 'navigator.credentials.get'. Returns new signature counter.
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    challenge: &str,
+    public_key: &PublicKey,
+    stored_sign_count: u32,
+    client_data_json: &[u8],
+    auth_data: &[u8],
+    signature: &[u8],
+) -> Result<u32> {
+    verify_client_data(rp, client_data_json, "webauthn.get", challenge)?;
+    let parsed = parse_auth_data(rp, auth_data)?;
+    verify_signature(public_key, auth_data, client_data_json, signature)?;
+    // Authenticators without a counter always report zero
+    if (parsed.sign_count != 0 || stored_sign_count != 0) && parsed.sign_count <= stored_sign_count {
+        return Err(Error::CounterRegression);
+    }
+    Ok(parsed.sign_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::value::Integer;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+    use rand_core::OsRng;
+
+    fn test_rp() -> RelyingParty {
+        RelyingParty::from_domain("https://wallet.example.com").unwrap()
+    }
+
+    fn int(v: i64) -> Value {
+        Value::Integer(Integer::from(v))
+    }
+
+    fn cose_key(key: &PublicKey) -> Value {
+        let point = key.to_encoded_point(false);
+        Value::Map(vec![
+            (int(1), int(2)),
+            (int(3), int(COSE_ALG_ES256)),
+            (int(-1), int(1)),
+            (int(-2), Value::Bytes(point.x().unwrap().to_vec())),
+            (int(-3), Value::Bytes(point.y().unwrap().to_vec())),
+        ])
+    }
+
+    fn auth_data(rp: &RelyingParty, sign_count: u32, credential: Option<(&[u8], &PublicKey)>) -> Vec<u8> {
+        let mut data = Sha256::digest(rp.id.as_bytes()).to_vec();
+        let mut flags = FLAG_USER_PRESENT;
+        if credential.is_some() {
+            flags |= FLAG_ATTESTED_CREDENTIAL;
+        }
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        if let Some((id, key)) = credential {
+            data.extend_from_slice(&[0u8; 16]);
+            data.extend_from_slice(&(id.len() as u16).to_be_bytes());
+            data.extend_from_slice(id);
+            ciborium::ser::into_writer(&cose_key(key), &mut data).unwrap();
+        }
+        data
+    }
+
+    fn client_data(ceremony: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": ceremony,
+            "challenge": challenge,
+            "origin": origin,
+        }))
+        .unwrap()
+    }
+
+    fn attestation_object(fmt: &str, auth_data: Vec<u8>, stmt: Vec<(Value, Value)>) -> Vec<u8> {
+        let obj = Value::Map(vec![
+            (Value::Text("fmt".to_owned()), Value::Text(fmt.to_owned())),
+            (Value::Text("attStmt".to_owned()), Value::Map(stmt)),
+            (Value::Text("authData".to_owned()), Value::Bytes(auth_data)),
+        ]);
+        let mut buf = vec![];
+        ciborium::ser::into_writer(&obj, &mut buf).unwrap();
+        buf
+    }
+
+    fn sign(key: &SigningKey, auth_data: &[u8], client_data_json: &[u8]) -> Vec<u8> {
+        let message = [auth_data, &Sha256::digest(client_data_json)[..]].concat();
+        let signature: Signature = key.sign(&message);
+        signature.to_der().as_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_registration_and_assertion() {
+        let rp = test_rp();
+        let signing_key = SigningKey::random(&mut OsRng);
+        let public_key = PublicKey::from(signing_key.verifying_key());
+        let cred_id = b"credential-1".to_vec();
+
+        let client_json = client_data("webauthn.create", "reg-challenge", &rp.origin);
+        let att = attestation_object(
+            "none",
+            auth_data(&rp, 0, Some((&cred_id, &public_key))),
+            vec![],
+        );
+        let cred = verify_registration(&rp, "reg-challenge", &client_json, &att).unwrap();
+        assert_eq!(
+            cred,
+            NewCredential {
+                id: cred_id,
+                public_key,
+                sign_count: 0
+            }
+        );
+
+        let client_json = client_data("webauthn.get", "login-challenge", &rp.origin);
+        let data = auth_data(&rp, 1, None);
+        let sig = sign(&signing_key, &data, &client_json);
+        let count = verify_assertion(&rp, "login-challenge", &public_key, 0, &client_json, &data, &sig);
+        assert_eq!(count, Ok(1));
+        // Replay of the same assertion doesn't increase the counter
+        let count = verify_assertion(&rp, "login-challenge", &public_key, 1, &client_json, &data, &sig);
+        assert_eq!(count, Err(Error::CounterRegression));
+    }
+
+    #[test]
+    fn test_packed_self_attestation() {
+        let rp = test_rp();
+        let signing_key = SigningKey::random(&mut OsRng);
+        let public_key = PublicKey::from(signing_key.verifying_key());
+        let client_json = client_data("webauthn.create", "challenge", &rp.origin);
+        let data = auth_data(&rp, 0, Some((b"id", &public_key)));
+        let sig = sign(&signing_key, &data, &client_json);
+        let stmt = vec![
+            (Value::Text("alg".to_owned()), int(COSE_ALG_ES256)),
+            (Value::Text("sig".to_owned()), Value::Bytes(sig)),
+        ];
+        let att = attestation_object("packed", data.clone(), stmt);
+        assert!(verify_registration(&rp, "challenge", &client_json, &att).is_ok());
+
+        let other_key = SigningKey::random(&mut OsRng);
+        let stmt = vec![
+            (Value::Text("alg".to_owned()), int(COSE_ALG_ES256)),
+            (Value::Text("sig".to_owned()), Value::Bytes(sign(&other_key, &data, &client_json))),
+        ];
+        let att = attestation_object("packed", data, stmt);
+        assert_eq!(
+            verify_registration(&rp, "challenge", &client_json, &att),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_client_data_checks() {
+        let rp = test_rp();
+        let signing_key = SigningKey::random(&mut OsRng);
+        let public_key = PublicKey::from(signing_key.verifying_key());
+        let att = attestation_object("none", auth_data(&rp, 0, Some((b"id", &public_key))), vec![]);
+
+        let wrong_origin = client_data("webauthn.create", "challenge", "https://evil.example.com");
+        assert_eq!(
+            verify_registration(&rp, "challenge", &wrong_origin, &att),
+            Err(Error::OriginMismatch("https://evil.example.com".to_owned()))
+        );
+        let wrong_challenge = client_data("webauthn.create", "other", &rp.origin);
+        assert_eq!(
+            verify_registration(&rp, "challenge", &wrong_challenge, &att),
+            Err(Error::ChallengeMismatch)
+        );
+        let wrong_type = client_data("webauthn.get", "challenge", &rp.origin);
+        assert_eq!(
+            verify_registration(&rp, "challenge", &wrong_type, &att),
+            Err(Error::CeremonyType("webauthn.get".to_owned()))
+        );
+
+        let other_rp = RelyingParty::from_domain("https://other.example.com").unwrap();
+        let client_json = client_data("webauthn.create", "challenge", &rp.origin);
+        let att = attestation_object("none", auth_data(&other_rp, 0, Some((b"id", &public_key))), vec![]);
+        assert_eq!(
+            verify_registration(&rp, "challenge", &client_json, &att),
+            Err(Error::RpIdMismatch)
+        );
+    }
+}
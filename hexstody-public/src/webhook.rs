@@ -0,0 +1,308 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use hexstody_api::types::{WebhookDelivery, WebhookDeliveryStatus, WebhookPayload};
+use hexstody_db::queries::{query_pending_webhook_deliveries, upsert_webhook_delivery};
+use hexstody_db::state::{State, Webhook};
+use hexstody_db::update::StateUpdate;
+use hexstody_db::Pool;
+use hmac::{Hmac, Mac};
+use log::*;
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+use uuid::Uuid;
+
+/// Header with hex encoded HMAC-SHA256 of the raw request body
+pub const SIGNATURE_HEADER: &str = "X-Hexstody-Signature";
+/// Header with delivery ID, the same as 'id' field of the payload
+pub const DELIVERY_HEADER: &str = "X-Hexstody-Delivery";
+/// Number of delivery attempts before the delivery is marked as failed
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 6;
+/// Delay before the first retry. Doubled after each failed attempt
+pub const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Timeout of a single delivery attempt
+pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Requesting endpoint error: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("JSON encoding/decoding error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Alias for a `Result` with the error type `self::Error`.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Hex encoded HMAC-SHA256 of the body with the webhook secret
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Make single delivery attempt of the payload to the endpoint
+pub async fn send_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    payload: &WebhookPayload,
+) -> Result<()> {
+    let body = serde_json::to_vec(payload)?;
+    let signature = sign_payload(secret, &body);
+    let request = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header(SIGNATURE_HEADER, format!("sha256={signature}"))
+        .header(DELIVERY_HEADER, payload.id.to_string())
+        .timeout(DELIVERY_TIMEOUT)
+        .body(body)
+        .build()?;
+    client.execute(request).await?.error_for_status()?;
+    Ok(())
+}
+
+/// Deliver the payload with exponential backoff. Each attempt is recorded
+/// to the database, so the delivery can be inspected, replayed later and
+/// resumed after restart.
+pub async fn deliver(
+    client: reqwest::Client,
+    pool: Pool,
+    webhook: Webhook,
+    mut delivery: WebhookDelivery,
+) {
+    let mut delay = INITIAL_RETRY_DELAY;
+    for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+        delivery.attempts += 1;
+        match send_webhook(&client, &webhook.url, &webhook.secret, &delivery.payload).await {
+            Ok(_) => {
+                delivery.status = WebhookDeliveryStatus::Delivered;
+                delivery.last_error = None;
+            }
+            Err(e) => {
+                debug!("Webhook delivery {} attempt {attempt} failed: {e}", delivery.id);
+                delivery.last_error = Some(e.to_string());
+                delivery.status = if attempt == MAX_DELIVERY_ATTEMPTS {
+                    WebhookDeliveryStatus::Failed
+                } else {
+                    WebhookDeliveryStatus::Pending
+                };
+            }
+        }
+        delivery.updated = Utc::now().naive_utc();
+        if let Err(e) = upsert_webhook_delivery(&pool, &delivery).await {
+            error!("Failed to record webhook delivery {}: {e}", delivery.id);
+        }
+        if delivery.status != WebhookDeliveryStatus::Pending {
+            break;
+        }
+        sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Find webhooks of users touched by the update and prepare deliveries for them
+fn collect_deliveries(state: &State, update: &StateUpdate) -> Vec<(Webhook, WebhookDelivery)> {
+    let now = Utc::now().naive_utc();
+    let mut deliveries = vec![];
+    for (user_id, event) in state.user_events(&update.body) {
+        if let Some(user) = state.users.get(&user_id) {
+            for webhook in user.webhooks.values() {
+                // Delivery ID is shared by the record and the payload
+                let id = Uuid::new_v4();
+                let delivery = WebhookDelivery {
+                    id,
+                    webhook_id: webhook.id,
+                    user: user_id.clone(),
+                    url: webhook.url.clone(),
+                    payload: WebhookPayload {
+                        id,
+                        created: update.created,
+                        event: event.clone(),
+                    },
+                    status: WebhookDeliveryStatus::Pending,
+                    attempts: 0,
+                    last_error: None,
+                    created: now,
+                    updated: now,
+                };
+                deliveries.push((webhook.clone(), delivery));
+            }
+        }
+    }
+    deliveries
+}
+
+/// Reschedule deliveries that were pending when the service stopped.
+/// Deliveries of removed webhooks are marked as failed.
+async fn resume_deliveries(client: &reqwest::Client, pool: &Pool, state: &Mutex<State>) {
+    let deliveries = match query_pending_webhook_deliveries(pool).await {
+        Ok(deliveries) => deliveries,
+        Err(e) => {
+            error!("Failed to query pending webhook deliveries: {e}");
+            return;
+        }
+    };
+    if !deliveries.is_empty() {
+        info!("Resuming {} pending webhook deliveries", deliveries.len());
+    }
+    for mut delivery in deliveries {
+        let webhook = state
+            .lock()
+            .await
+            .get_user_by_id(&delivery.user)
+            .and_then(|u| u.webhooks.get(&delivery.webhook_id).cloned());
+        match webhook {
+            Some(webhook) => {
+                tokio::spawn(deliver(client.clone(), pool.clone(), webhook, delivery));
+            }
+            None => {
+                delivery.status = WebhookDeliveryStatus::Failed;
+                delivery.last_error = Some("Webhook is removed".to_owned());
+                delivery.updated = Utc::now().naive_utc();
+                if let Err(e) = upsert_webhook_delivery(pool, &delivery).await {
+                    error!("Failed to record webhook delivery {}: {e}", delivery.id);
+                }
+            }
+        }
+    }
+}
+
+/// Listens applied state updates and delivers related events to users' webhooks
+pub async fn webhook_worker(
+    pool: Pool,
+    state: Arc<Mutex<State>>,
+    mut updates: broadcast::Receiver<StateUpdate>,
+) {
+    info!("Webhook worker started");
+    let client = reqwest::Client::new();
+    resume_deliveries(&client, &pool, &state).await;
+    loop {
+        match updates.recv().await {
+            Ok(update) => {
+                let deliveries = {
+                    let state = state.lock().await;
+                    collect_deliveries(&state, &update)
+                };
+                for (webhook, delivery) in deliveries {
+                    // Record the delivery before the first attempt, so it is
+                    // resumed if the service stops in the middle of retries
+                    if let Err(e) = upsert_webhook_delivery(&pool, &delivery).await {
+                        error!("Failed to record webhook delivery {}: {e}", delivery.id);
+                    }
+                    tokio::spawn(deliver(client.clone(), pool.clone(), webhook, delivery));
+                }
+            }
+            Err(RecvError::Lagged(n)) => {
+                warn!("Webhook worker lagged behind, {n} updates are skipped");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    info!("Webhook worker exited!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hexstody_api::domain::{BTCTxid, BtcAddress, Currency, CurrencyAddress, CurrencyTxId};
+    use hexstody_api::types::UserEvent;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Accept single HTTP request and reply with the given status.
+    /// Returns headers and body of the received request.
+    async fn receive_one(listener: TcpListener, status: &'static str) -> (String, Vec<u8>) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = vec![];
+        let mut chunk = [0u8; 4096];
+        let (head, body_start, content_length) = loop {
+            let n = socket.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&buf[..pos]).to_string();
+                let content_length = head
+                    .lines()
+                    .find_map(|l| {
+                        let (name, value) = l.split_once(':')?;
+                        if name.eq_ignore_ascii_case("content-length") {
+                            value.trim().parse::<usize>().ok()
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap_or(0);
+                break (head, pos + 4, content_length);
+            }
+        };
+        while buf.len() < body_start + content_length {
+            let n = socket.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        socket.write_all(response.as_bytes()).await.unwrap();
+        (head, buf[body_start..body_start + content_length].to_vec())
+    }
+
+    fn test_payload() -> WebhookPayload {
+        WebhookPayload {
+            id: Uuid::new_v4(),
+            created: Utc::now().naive_utc(),
+            event: UserEvent::Deposit {
+                currency: Currency::BTC,
+                txid: CurrencyTxId::BTC(BTCTxid {
+                    txid: "2ec2a0b0a9d3c3b1d4c3c1d9f6c4ad9f1b7d6e2b1d0e2c4f1a7c3d5e6b7a8c9d".to_owned(),
+                }),
+                address: CurrencyAddress::BTC(BtcAddress {
+                    addr: "bcrt1qz6sp8sr3sfwt8tpgwfmwd2pl4z06nd6dmwaxsm".to_owned(),
+                }),
+                amount: 10_000,
+                confirmations: 1,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_signed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = tokio::spawn(receive_one(listener, "200 OK"));
+        let payload = test_payload();
+        let client = reqwest::Client::new();
+        send_webhook(&client, &url, "secret", &payload)
+            .await
+            .expect("Delivered");
+
+        let (head, body) = receiver.await.unwrap();
+        let signature = head
+            .lines()
+            .find_map(|l| {
+                let (name, value) = l.split_once(':')?;
+                if name.eq_ignore_ascii_case(SIGNATURE_HEADER) {
+                    Some(value.trim().to_owned())
+                } else {
+                    None
+                }
+            })
+            .expect("Signature header");
+        assert_eq!(signature, format!("sha256={}", sign_payload("secret", &body)));
+        let received: WebhookPayload = serde_json::from_slice(&body).unwrap();
+        assert_eq!(received, payload);
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_error_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = tokio::spawn(receive_one(listener, "500 Internal Server Error"));
+        let client = reqwest::Client::new();
+        let res = send_webhook(&client, &url, "secret", &test_payload()).await;
+        receiver.await.unwrap();
+        assert!(res.is_err());
+    }
+}
@@ -63,6 +63,8 @@ export function localizeWithdrawalStatus(status) {
                 return "Rejected by operators"
             case "NodeRejected":
                 return "Rejected by node"
+            case "Cancelled":
+                return "Cancelled"
             default:
                 return "Unknown"
         };
@@ -77,6 +79,8 @@ export function localizeWithdrawalStatus(status) {
                 return "Отклонено оператором"
             case "NodeRejected":
                 return "Отклонено нодой"
+            case "Cancelled":
+                return "Отменено"
             default:
                 return "Unknown"
         };
@@ -105,6 +105,8 @@ export function formatWithdrawStatus(status) {
             return dict.opRejected
         case "NodeRejected":
             return `${dict.nodeRejected}: ${status.reason}`
+        case "Cancelled":
+            return dict.cancelled
         default:
             return dict.unknownStatus
     };
@@ -119,6 +121,10 @@ async function loadHistory() {
         let unitVal = Object.assign({}, curBalance.value)
         unitVal.amount = historyItem.value
         const valueDisplay = displayUnitTickerAmount(unitVal)
+        // Fiat value at the time of the operation, if the rate was recorded
+        const fiatValue = historyItem.fiat_value
+            ? Intl.NumberFormat('en', { style: 'currency', currency: 'USD' }).format(historyItem.fiat_value.USD)
+            : null
         if (isDeposit) {
             let explorerLink
             switch (currencyName) {
@@ -132,6 +138,7 @@ async function loadHistory() {
             return {
                 timeStamp: timeStamp,
                 valueToShow: `+${valueDisplay}`,
+                fiatValue: fiatValue,
                 txid: historyItem.txid.txid,
                 status: formatDepositStatus(historyItem.number_of_confirmations),
                 explorerLink: explorerLink,
@@ -153,6 +160,7 @@ async function loadHistory() {
             return {
                 timeStamp: timeStamp,
                 valueToShow: `-${valueDisplay}`,
+                fiatValue: fiatValue,
                 txid: isCompleted ? historyItem.status.txid : null,
                 status: formatWithdrawStatus(historyItem.status),
                 explorerLink: explorerLink,
@@ -1,6 +1,7 @@
 import { loadTemplate, initTabs, initCollapsibles, getUserName, chunkifyTransposed, indexArrayFromOne, convertToUnitJson } from "../common.js";
 import { localizeChangeStatus, localizeSpan, getLanguage } from "../localize.js";
 import { hasKeyPairStored, generateKeyPair, privateKeyToMnemonic, mnemonicToPrivateKey, retrievePrivateKey, removeStoredKeyPair, storePrivateKey } from "../crypto.js";
+import { isWebauthnSupported, getPasskeys, registerPasskey, removePasskey } from "../webauthn.js";
 
 const errorBox = document.getElementById("error-box")
 
@@ -518,15 +519,55 @@ async function restoreMnemonic() {
     }
 }
 
+async function getSessions() {
+    return await fetch("/profile/sessions").then(r => r.json())
+}
+
+async function revokeSession(event) {
+    await fetch("/profile/sessions/revoke", { method: "POST", body: JSON.stringify(event.target.dataset.id) })
+    await loadSecurityTab()
+}
+
+async function revokeOtherSessions() {
+    await fetch("/profile/sessions/revoke-others", { method: "POST" })
+    await loadSecurityTab()
+}
+
+async function addPasskey() {
+    const name = document.getElementById("passkey-name").value
+    await registerPasskey(name)
+        .then(() => loadSecurityTab())
+        .catch(err => displayError(err))
+}
+
+async function removePasskeyHandler(event) {
+    await removePasskey(event.target.dataset.id)
+        .then(() => loadSecurityTab())
+        .catch(err => displayError(err))
+}
+
 async function loadSecurityTab() {
     const name = getUserName()
     const hasKey = hasKeyPairStored(name)
-    const keyDrawUpdate = securityTemplate({ hasKey: hasKey, lang: dict.security })
+    const hasPasskeys = isWebauthnSupported()
+    const passkeys = hasPasskeys ? await getPasskeys() : []
+    const sessions = await getSessions()
+    const keyDrawUpdate = securityTemplate({ hasKey: hasKey, hasPasskeys: hasPasskeys, passkeys: passkeys, sessions: sessions, lang: dict.security })
     const securityEl = document.getElementById("security-tab-body")
     securityEl.style.width = "100%"
     securityEl.innerHTML = keyDrawUpdate
     initCollapsibles()
     document.getElementById("password-change-btn").onclick = performPasswordChange
+    document.getElementById("revoke-other-sessions-btn").onclick = revokeOtherSessions
+    for (const btn of document.getElementsByClassName("revoke-session-btn")) {
+        btn.onclick = revokeSession
+    }
+    if (hasPasskeys) {
+        document.getElementById("add-passkey-btn").onclick = addPasskey
+        for (const btn of document.getElementsByClassName("remove-passkey-btn")) {
+            btn.onclick = removePasskeyHandler
+        }
+    }
     if (hasKey) {
         const showMnemBtn = document.getElementById("show-mnemonic-btn")
         showMnemBtn.onclick = showMnemonic
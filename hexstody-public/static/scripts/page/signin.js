@@ -1,6 +1,7 @@
 import { listUsers, retrievePrivateKey } from "../crypto.js";
 import { getBrowserLanguage } from "../localize.js";
 import { loadTemplate, initDropDowns } from "../common.js";
+import { isWebauthnSupported, signInWithPasskey } from "../webauthn.js";
 
 var emailEl = null;
 var passwordEl = null;
@@ -11,18 +12,30 @@ var signinTemplate = null;
 var headerTranslations = null;
 var signInPageTranslations = null;
 
-async function postSignIn(email, password) {
+// Error id returned when the user has TOTP enabled and no code is given
+const TOTP_REQUIRED_ERROR = "hexstody_api:35";
+
+async function postSignIn(email, password, totp) {
     return await fetch("/signin/email",
         {
             method: "POST",
-            body: JSON.stringify({ user: email, password: password })
+            body: JSON.stringify({ user: email, password: password, totp: totp })
         })
 };
 
 async function trySubmit() {
     const email = emailEl.value;
     const password = passwordEl.value;
-    const signInResult = await postSignIn(email, password);
+    let signInResult = await postSignIn(email, password, null);
+    if (!signInResult.ok) {
+        const error = await signInResult.json().catch(() => ({}));
+        if (error.id === TOTP_REQUIRED_ERROR) {
+            const totp = window.prompt(signInPageTranslations.totp);
+            if (totp) {
+                signInResult = await postSignIn(email, password, totp);
+            }
+        }
+    }
     if (signInResult.ok) {
         window.location.href = "/overview";
     } else {
@@ -117,6 +130,13 @@ async function loginViaKey() {
         .catch(_err => displayErr(signInPageTranslations.incorrect));
 }
 
+async function loginViaPasskey() {
+    hideError()
+    await signInWithPasskey()
+        .then(() => window.location.href = "/overview")
+        .catch(err => displayErr(err));
+}
+
 async function handleLangChange(lang, hasKeyOverride) {
     await initTemplates(hasKeyOverride, lang);
 }
@@ -144,7 +164,7 @@ async function initTemplates(hasKeyOverride, lang) {
     document.title = signInPageTranslations.pageTitle;
 
     const headerDraw = headerTemplate({ selected_lang: lang.toUpperCase(), lang: headerTranslations });
-    const singinDraw = signinTemplate({ viaKey: viaKey, hasKey: hasKeys, keys: keys, lang: signInPageTranslations });
+    const singinDraw = signinTemplate({ viaKey: viaKey, hasKey: hasKeys, hasPasskeys: isWebauthnSupported(), keys: keys, lang: signInPageTranslations });
     document.getElementById("header").innerHTML = headerDraw;
     initDropDowns();
 
@@ -167,6 +187,9 @@ async function initTemplates(hasKeyOverride, lang) {
         passwordEl = document.getElementById("signInPassword");
         emailEl.addEventListener("keyup", trySubmitOnEnter);
         passwordEl.addEventListener("keyup", trySubmitOnEnter);
+        if (isWebauthnSupported()) {
+            document.getElementById("signin-passkey").onclick = loginViaPasskey
+        }
         if (hasKeys) {
             document.getElementById("signin-key").onclick = async function () { await initTemplates(true, lang) }
         }
@@ -5,6 +5,7 @@ let balanceTo = null;
 let pairRate = null;
 let amountFrom = null;
 let balances = null;
+let quote = null;
 
 function displayError(error) {
     const validationDisplay = document.getElementById("validation-error");
@@ -16,8 +17,12 @@ async function getBalances() {
     return await fetch("/balance").then(r => r.json());
 }
 
-async function postOrderExchange(request) {
-    return fetch("/exchange/order", { method: "POST", body: JSON.stringify(request) });
+async function postQuoteExchange(request) {
+    return fetch("/exchange/quote", { method: "POST", body: JSON.stringify(request) });
+}
+
+async function postAcceptExchange(quoteId) {
+    return fetch("/exchange/accept", { method: "POST", body: JSON.stringify(quoteId) });
 }
 
 async function getAdjustedRate(from, to) {
@@ -35,6 +40,7 @@ function wipeEnv(){
     pairRate = null;
     amountFrom = null;
     document.getElementById("from-avaliable").hidden = true;
+    hideQuote();
 }
 
 async function initEnv(){
@@ -78,6 +84,26 @@ async function displayEnv(){
     document.getElementById("from_value").onkeyup = fromChangedHandler;
     document.getElementById("to_value").onkeyup = toChangedHandler;
     document.getElementById("swap").onclick = handleSwapButton;
+    document.getElementById("accept").onclick = handleAcceptButton;
+}
+
+function hideQuote(){
+    quote = null;
+    document.getElementById("quote").hidden = true;
+}
+
+function displayQuote(){
+    const amountTo = quote.amount_to / balanceTo.value.mul;
+    document.getElementById("quote-amount").innerText = `${amountTo} ${balanceTo.value.name}`;
+    // Expiry time is sent in UTC without the offset
+    const expiresAt = new Date(quote.expires_at + "Z");
+    document.getElementById("quote-expires").innerText = expiresAt.toLocaleTimeString();
+    document.getElementById("quote").hidden = false;
+    setTimeout(() => {
+        if (quote && new Date() >= expiresAt) {
+            hideQuote();
+        }
+    }, expiresAt - new Date());
 }
 
 function displayExchangeRate(rate){
@@ -95,6 +121,7 @@ function maxAmountBtn(){
 }
 
 function fromChangedHandler(){
+    hideQuote();
     const rawValue = document.getElementById("from_value").value;
     const convertedAmount = Math.floor(rawValue * balanceFrom.value.mul)
     let val = validateAmount(balanceFrom.currency, convertedAmount);
@@ -113,6 +140,7 @@ function fromChangedHandler(){
 }
 
 function toChangedHandler(){
+    hideQuote();
     const rawValue = document.getElementById("to_value").value;
     const convertedAmount = Math.floor(rawValue * balanceTo.value.mul)
     let val = validateAmount(balanceTo.currency, convertedAmount);
@@ -136,9 +164,10 @@ async function handleSwapButton(){
             currency_to: balanceTo.currency,
             amount_from: val.value
         }
-        const result = await postOrderExchange(request);
+        const result = await postQuoteExchange(request);
         if (result.ok) {
-            window.location.href = "/overview"
+            quote = await result.json();
+            displayQuote();
         } else {
             displayError((await result.json()).message);
         }
@@ -147,4 +176,17 @@ async function handleSwapButton(){
     }
 }
 
+async function handleAcceptButton(){
+    if (!quote) {
+        return;
+    }
+    const result = await postAcceptExchange(quote.id);
+    if (result.ok) {
+        window.location.href = "/overview"
+    } else {
+        hideQuote();
+        displayError((await result.json()).message);
+    }
+}
+
 document.addEventListener("DOMContentLoaded", initEnv);
\ No newline at end of file
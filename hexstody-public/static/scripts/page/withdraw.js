@@ -15,6 +15,9 @@ let activeCurrencyName;
 let balance;
 let fee;
 
+// Error id returned when the user asked for TOTP on withdrawals and no code is given
+const TOTP_REQUIRED_ERROR = "hexstody_api:35";
+
 function networkToBtcNetwork() {
     switch (network) {
         case "Mainnet":
@@ -28,7 +31,7 @@ function networkToBtcNetwork() {
     }
 }
 
-async function postWithdrawRequest(currency, address, amount) {
+async function postWithdrawRequest(currency, address, amount, totp) {
     let body
     switch (currency.toUpperCase()) {
         case "BTC":
@@ -51,6 +54,7 @@ async function postWithdrawRequest(currency, address, amount) {
                 amount: amount
             }
     }
+    body.totp = totp
     return await fetch("/withdraw",
         {
             method: "POST",
@@ -156,7 +160,16 @@ async function trySubmit(currency, address, amount) {
     }
 
     try {
-        const response = await postWithdrawRequest(currency, addressValidationResult.value, amountValidationResult.value)
+        let response = await postWithdrawRequest(currency, addressValidationResult.value, amountValidationResult.value, null)
+        if (!response.ok) {
+            const error = await response.clone().json().catch(() => ({}))
+            if (error.id === TOTP_REQUIRED_ERROR) {
+                const totp = window.prompt(withdrawTranslations.totp)
+                if (totp) {
+                    response = await postWithdrawRequest(currency, addressValidationResult.value, amountValidationResult.value, totp)
+                }
+            }
+        }
         if (response.ok) {
             window.location.href = "/overview"
         } else {
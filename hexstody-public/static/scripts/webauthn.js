@@ -0,0 +1,63 @@
+// Helpers for passkey registration and sign in. The server sends and
+// expects binary fields of WebAuthn structures as base64url strings.
+
+function fromBase64Url(value) {
+    const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+    const padded = base64 + "=".repeat((4 - base64.length % 4) % 4);
+    return Uint8Array.from(atob(padded), c => c.charCodeAt(0)).buffer;
+}
+
+function toBase64Url(buffer) {
+    const bytes = new Uint8Array(buffer);
+    let binary = "";
+    bytes.forEach(b => binary += String.fromCharCode(b));
+    return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+export function isWebauthnSupported() {
+    return window.PublicKeyCredential !== undefined;
+}
+
+async function postJson(url, body) {
+    const params = body === undefined ? { method: "POST" } : { method: "POST", body: JSON.stringify(body) };
+    const resp = await fetch(url, params);
+    if (!resp.ok) {
+        const error = await resp.json().catch(() => ({}));
+        throw error.message || resp.statusText;
+    }
+    return resp;
+}
+
+export async function getPasskeys() {
+    return await fetch("/webauthn/credentials").then(r => r.json());
+}
+
+export async function removePasskey(id) {
+    await postJson("/webauthn/credentials/remove", id);
+}
+
+export async function registerPasskey(name) {
+    const options = await postJson("/webauthn/register/begin").then(r => r.json());
+    options.challenge = fromBase64Url(options.challenge);
+    options.user.id = fromBase64Url(options.user.id);
+    options.excludeCredentials = options.excludeCredentials.map(c => ({ ...c, id: fromBase64Url(c.id) }));
+    const credential = await navigator.credentials.create({ publicKey: options });
+    return await postJson("/webauthn/register/finish", {
+        name: name,
+        id: credential.id,
+        clientDataJson: toBase64Url(credential.response.clientDataJSON),
+        attestationObject: toBase64Url(credential.response.attestationObject),
+    }).then(r => r.json());
+}
+
+export async function signInWithPasskey() {
+    const options = await postJson("/webauthn/login/begin").then(r => r.json());
+    options.challenge = fromBase64Url(options.challenge);
+    const credential = await navigator.credentials.get({ publicKey: options });
+    await postJson("/webauthn/login/finish", {
+        id: credential.id,
+        clientDataJson: toBase64Url(credential.response.clientDataJSON),
+        authenticatorData: toBase64Url(credential.response.authenticatorData),
+        signature: toBase64Url(credential.response.signature),
+    });
+}
@@ -1,46 +1,57 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 
-use hexstody_api::domain::Symbol;
-use hexstody_ticker_provider::client::TickerClient;
+use chrono::{Duration, NaiveDateTime, Utc};
+use hexstody_api::domain::{Rate, Rounding, Symbol};
+use hexstody_api::types::{ExchangeQuote, MarginData};
+pub use hexstody_api::types::FeeEstimates;
+use hexstody_ticker_provider::client::{TickerClient, TimedRate};
 use hexstody_ticker_provider::client::Result as TickerResult;
-use serde::Deserialize;
-use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 use serde_json::Map;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FeeEstimates {
-    /// Estimate of bytes per tx for BTC
-    pub btc_bytes_per_tx: u64,
-    /// Gas limit for Ethereum transfer transaction
-    pub eth_tx_gas_limit: u64,
-    /// Gas limit for ERC20 transfer transaction
-    pub erc20_tx_gas_limit: u64
+/// What the challenge is issued for
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChallengePurpose {
+    /// Sign in with the key uploaded through '/profile/key'
+    KeyLogin { user: String },
+    /// Register new WebAuthn credential for the user
+    WebauthnRegister { user: String },
+    /// Sign in with a WebAuthn credential
+    WebauthnLogin,
 }
 
-impl FeeEstimates {
-    pub fn new() -> Self{
-        FeeEstimates{
-            btc_bytes_per_tx: 1024,
-            eth_tx_gas_limit: 21_000,
-            erc20_tx_gas_limit: 150_000,
-        }
-    }
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub challenge: String,
+    pub purpose: ChallengePurpose,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Exchange quote issued to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub user: String,
+    pub quote: ExchangeQuote,
 }
 
 pub struct RuntimeState {
-    /// Runtime cache of challenges to log in with a key
-    pub challenges: HashMap<String, String>,
+    /// Runtime cache of sign in and WebAuthn challenges by session ID.
+    /// Each session holds at most one challenge.
+    pub challenges: HashMap<String, Challenge>,
+    /// Exchange quotes that are not accepted yet, by quote ID
+    pub quotes: HashMap<uuid::Uuid, Quote>,
     /// Cached ticker info
     /// We store tikers refering by Symbol
     /// since we want to uniformely store both Crypto and Fiat tickers in the same map 
-    pub cached_tickers: HashMap<Symbol, HashMap<Symbol, f64>>,
+    /// Each rate remembers when it was fetched, so consumers can refuse outdated ones
+    pub cached_tickers: HashMap<Symbol, HashMap<Symbol, TimedRate>>,
     /// Exchange margins, applied to cached_tickers
     /// Store separately to make storing tickers easier and allow ops to see original rates
-    pub margins: HashMap<Symbol, HashMap<Symbol, f64>>,
-    /// Fee config
+    /// Copy of the margins confirmed by operators in the persistent state
+    pub margins: HashMap<Symbol, HashMap<Symbol, Rate>>,
+    /// Fee config. Copy of the persistent state
     pub fee_estimates: FeeEstimates
 }
 
@@ -48,78 +59,118 @@ impl RuntimeState {
     pub fn new() -> Self{
         RuntimeState{
             challenges: HashMap::new(),
+            quotes: HashMap::new(),
             cached_tickers: HashMap::new(),
             margins: HashMap::new(),
             fee_estimates: FeeEstimates::new()
         }
     }
 
-    pub async fn symbol_to_symbol(&mut self, client: &TickerClient, from: Symbol, to: Symbol) -> TickerResult<f64>{
+    /// Cached rate of any age, fetched if missing
+    pub async fn symbol_to_symbol(&mut self, client: &TickerClient, from: Symbol, to: Symbol) -> TickerResult<Rate>{
         let mrate = self.cached_tickers.get(&from).map(|sm| sm.get(&to)).flatten();
         match mrate {
-            Some(rate) => Ok(rate.clone()),
-            None => {
-                let rate = client.symbol_to_symbol(&from, &to).await?;
-                self.cached_tickers.entry(from).and_modify(|cm| {cm.insert(to, rate);});
-                Ok(rate)
-            },
+            Some(timed) => Ok(timed.rate),
+            None => self.fetch_rate(client, from, to).await.map(|timed| timed.rate),
         }
     }
 
+    /// Rate fetched not earlier than `max_age` ago. Outdated cached rate is refetched
+    pub async fn fresh_rate(&mut self, client: &TickerClient, from: Symbol, to: Symbol, max_age: Duration) -> TickerResult<TimedRate>{
+        let now = Utc::now().naive_utc();
+        let mrate = self.cached_tickers.get(&from).map(|sm| sm.get(&to)).flatten();
+        match mrate {
+            Some(timed) if timed.fetched_at + max_age >= now => Ok(*timed),
+            _ => self.fetch_rate(client, from, to).await,
+        }
+    }
+
+    async fn fetch_rate(&mut self, client: &TickerClient, from: Symbol, to: Symbol) -> TickerResult<TimedRate>{
+        let timed = client.symbol_to_symbol(&from, &to).await?;
+        self.cached_tickers.entry(from).or_default().insert(to, timed);
+        Ok(timed)
+    }
+
+    /// Update cached rates with the fetched ones. Rates missing from `rates` are kept as is
+    pub fn merge_tickers(&mut self, from: Symbol, rates: HashMap<Symbol, TimedRate>) {
+        self.cached_tickers.entry(from).or_default().extend(rates);
+    }
+
+    /// Rates are passed as numbers, since generic targets like `TickerUsdRub` are used for display only
     pub async fn symbol_to_symbols_generic<T>(&mut self, client: &TickerClient, from: Symbol, to: Vec<Symbol>) -> TickerResult<T>
     where T: DeserializeOwned + Debug 
     {
-        let mut vals: Map<String, Value> = Map::new();
-        let mut missing: Vec<Symbol> = Vec::new();
-        let submap = self.cached_tickers.get(&from);
-        match submap {
-            None => {
-                let res: HashMap<Symbol, f64> = client.symbol_to_symbols(&from, &to).await?;
-                self.cached_tickers.insert(from.clone(), res.clone());
-                vals = res.iter().map(|(k,v)| (k.symbol(), serde_json::to_value(v).unwrap())).collect();
-            },
-            Some(submap) => {
-                to.iter().for_each(|t| match submap.get(&t) {
-                    None => missing.push(t.clone()),
-                    Some(rate) => {vals.insert(t.symbol(), serde_json::to_value(rate).unwrap());},
-                })
-            },
-        }
-        if missing.len() != 0 {
-            let res: HashMap<Symbol, f64> = client.symbol_to_symbols(&from, &missing).await?;
-            self.cached_tickers.insert(from, res.clone());
-            vals = res.iter().map(|(k,v)| (k.symbol(), serde_json::to_value(v).unwrap())).collect();
-        }
+        let vals: Map<String, Value> = self
+            .symbol_to_symbols(client, from, to)
+            .await?
+            .into_iter()
+            .map(|(k, v)| (k.symbol(), serde_json::to_value(v.to_f64()).unwrap()))
+            .collect();
         serde_json::from_value(vals.into()).map_err(|e| e.into())
     }
 
-    pub async fn symbol_to_symbols(&mut self, client: &TickerClient, from: Symbol, to: Vec<Symbol>) -> TickerResult<HashMap<Symbol, f64>>{
-        let mut result: HashMap<Symbol, f64> = HashMap::new();
+    pub async fn symbol_to_symbols(&mut self, client: &TickerClient, from: Symbol, to: Vec<Symbol>) -> TickerResult<HashMap<Symbol, Rate>>{
+        let mut result: HashMap<Symbol, Rate> = HashMap::new();
         let mut missing: Vec<Symbol> = Vec::new();
         let submap = self.cached_tickers.get(&from);
-        match submap {
-            None => {
-                let res = client.symbol_to_symbols(&from, &to).await?;
-                self.cached_tickers.insert(from.clone(), res.clone());
-                return Ok(res) ;
-            },
-            Some(submap) => {
-                to.iter().for_each(|t| match submap.get(&t) {
-                    None => missing.push(t.clone()),
-                    Some(rate) => {result.insert(t.clone(), rate.clone());},
-                })
-            },
-        }
+        to.iter().for_each(|t| match submap.map(|sm| sm.get(t)).flatten() {
+            None => missing.push(t.clone()),
+            Some(timed) => {result.insert(t.clone(), timed.rate);},
+        });
         if missing.len() != 0 {
             let res = client.symbol_to_symbols(&from, &missing).await?;
-            self.cached_tickers.insert(from, res.clone());
-            res.into_iter().for_each(|(k,v)| {
-                result.insert(k, v);
+            res.iter().for_each(|(k, v)| {
+                result.insert(k.clone(), v.rate);
             });
+            self.merge_tickers(from, res);
         }
         Ok(result)
     }
 
+    /// Issue new challenge for the session replacing the previous one
+    pub fn issue_challenge(&mut self, session: String, purpose: ChallengePurpose, ttl: Duration) -> String {
+        let now = Utc::now().naive_utc();
+        self.challenges.retain(|_, c| c.expires_at > now);
+        let challenge = base64::encode_config(
+            [uuid::Uuid::new_v4().as_bytes().as_slice(), uuid::Uuid::new_v4().as_bytes().as_slice()].concat(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        self.challenges.insert(session, Challenge {
+            challenge: challenge.clone(),
+            purpose,
+            expires_at: now + ttl,
+        });
+        challenge
+    }
+
+    /// Take the challenge of the session. Each challenge can be taken only once.
+    /// Expired challenges are not returned.
+    pub fn take_challenge(&mut self, session: &str) -> Option<Challenge> {
+        let now = Utc::now().naive_utc();
+        self.challenges.remove(session).filter(|c| c.expires_at > now)
+    }
+
+    /// Remember the quote until it is accepted or expires
+    pub fn issue_quote(&mut self, user: String, quote: ExchangeQuote) {
+        let now = Utc::now().naive_utc();
+        self.quotes.retain(|_, q| q.quote.expires_at > now);
+        self.quotes.insert(quote.id, Quote { user, quote });
+    }
+
+    /// Take the user's quote. Each quote can be accepted only once.
+    /// Expired quotes and quotes of other users are not returned.
+    pub fn take_quote(&mut self, id: &uuid::Uuid, user: &str) -> Option<ExchangeQuote> {
+        let now = Utc::now().naive_utc();
+        match self.quotes.get(id) {
+            Some(q) if q.user == user => self
+                .quotes
+                .remove(id)
+                .map(|q| q.quote)
+                .filter(|q| q.expires_at > now),
+            _ => None,
+        }
+    }
+
     pub fn tracked_pairs(&self) -> HashMap<Symbol, Vec<Symbol>>{
         self
             .cached_tickers
@@ -129,13 +180,12 @@ impl RuntimeState {
     }
 
     /// Returns 0 if the margin is not set
-    pub fn get_margin(&self, from: &Symbol, to: &Symbol) -> f64 {
-        self.margins.get(from).map(|m| m.get(to)).flatten().cloned().unwrap_or(0.0)
+    pub fn get_margin(&self, from: &Symbol, to: &Symbol) -> Rate {
+        self.margins.get(from).map(|m| m.get(to)).flatten().cloned().unwrap_or(Rate::ZERO)
     }
 
     /// Sets pair's margin
-    pub fn set_margin(&mut self, from: Symbol, to:Symbol, margin: f64) {
-        let margin = margin.abs();
+    pub fn set_margin(&mut self, from: Symbol, to:Symbol, margin: Rate) {
         self.margins.entry(from).and_modify(|m| {
             m.insert(to.clone(), margin);
         }).or_insert(
@@ -143,12 +193,20 @@ impl RuntimeState {
         );
     }
 
-    /// Get pair rate, adjusted for margin
-    pub async fn symbol_to_symbol_adjusted(&mut self, client: &TickerClient, from: Symbol, to: Symbol) -> TickerResult<f64> {
+    /// Replace margins and fee estimates with the ones from the persistent state
+    pub fn sync_settings(&mut self, margins: &[MarginData], fee_estimates: &FeeEstimates) {
+        self.margins.clear();
+        for m in margins {
+            self.set_margin(m.currency_from.symbol(), m.currency_to.symbol(), m.margin);
+        }
+        self.fee_estimates = fee_estimates.clone();
+    }
+
+    /// Get pair rate, adjusted for margin. Rounded down in the house's favor
+    pub async fn symbol_to_symbol_adjusted(&mut self, client: &TickerClient, from: Symbol, to: Symbol) -> TickerResult<Rate> {
         let margin = self.get_margin(&from, &to);
         let rate = self.symbol_to_symbol(client, from, to).await?;
-        // We receive and store margins in whole percents, so we have to divide by 100
-        let adjusted_rate = rate * (1.0 - margin / 100.0);
-        Ok(adjusted_rate)
+        // We receive and store margins in whole percents
+        Ok(rate.sub_percent(margin, Rounding::Down))
     }
 }
\ No newline at end of file
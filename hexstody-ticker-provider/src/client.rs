@@ -1,10 +1,15 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
-use hexstody_api::domain::Symbol;
+use chrono::{NaiveDateTime, Utc};
+use futures::future::join_all;
+use hexstody_api::domain::{Rate, Rounding, Symbol};
 use log::*;
 use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
 use thiserror::Error;
 
+use crate::provider::{CryptoCompare, TickerProvider};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Requesting server error: {0}")]
@@ -20,87 +25,121 @@ pub enum Error {
 /// Alias for a `Result` with the error type `self::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Default allowed deviation of a source from the median in percents
+pub const DEFAULT_MAX_DEVIATION: u64 = 5;
+
+/// Aggregated rate with the time it was fetched at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedRate {
+    pub rate: Rate,
+    pub fetched_at: NaiveDateTime,
+}
+
+/// Queries all providers and aggregates their answers
 #[derive(Clone)]
 pub struct TickerClient {
-    pub client: reqwest::Client,
-    pub server: String,
-    pub api_key: Option<String>
+    pub providers: Vec<Arc<dyn TickerProvider>>,
+    /// Rates deviating from the median more than that many percents are dropped as outliers
+    pub max_deviation: Rate,
 }
 
 impl TickerClient {
+    /// Client with a single cryptocompare source
     pub fn new(url: &str) -> Self {
+        TickerClient::with_providers(vec![Arc::new(CryptoCompare::new(url))])
+    }
+
+    pub fn with_providers(providers: Vec<Arc<dyn TickerProvider>>) -> Self {
         TickerClient {
-            client: reqwest::Client::new(),
-            server: url.to_owned(),
-            api_key: Some("6b55695f17dbb4244531f989c93de2f448ab8eba5804afea248fa051f183ce14".to_string()),
+            providers,
+            max_deviation: Rate::from_integer(DEFAULT_MAX_DEVIATION),
         }
     }
 
     /// Symbol to Symbol ticker 
-    pub async fn symbol_to_symbol(&self, from: &Symbol, to: &Symbol) -> Result<f64> {
-        let path = "data/price";
-        let endpoint = format!("{}/{}?fsym={}&tsyms={}",self.server, path, from.symbol(), to.symbol());
-        let endpoint = self.api_key.as_ref()
-            .map(|api_key| format!("{}&api_key={}", endpoint, api_key))
-            .unwrap_or(endpoint);
-        let request = self.client.get(endpoint).build()?;
-        let response = self.client.execute(request)
+    pub async fn symbol_to_symbol(&self, from: &Symbol, to: &Symbol) -> Result<TimedRate> {
+        self.symbol_to_symbols(from, &vec![to.clone()])
             .await?
-            .error_for_status()?
-            .json::<HashMap<String, f64>>()
-            .await?;
-        debug!("Response {path}: {:?}", response);
-        response
-            .get(&to.symbol())
+            .remove(to)
             .ok_or(Error::ResponseMissing(to.symbol()))
-            .cloned()
     }
 
-    /// Concrete symbol to many symbols request.
-    pub async fn symbol_to_symbols(&self, from: &Symbol, to: &Vec<Symbol>) -> Result<HashMap<Symbol, f64>>
+    /// Concrete symbol to many symbols request. Symbols no source knows are omitted
+    pub async fn symbol_to_symbols(&self, from: &Symbol, to: &Vec<Symbol>) -> Result<HashMap<Symbol, TimedRate>>
     {
-        let tsyms = to.iter().map(|f| f.symbol()).collect::<Vec<String>>().join(",");
-        let path = "data/price";
-        let endpoint = format!("{}/{}?fsym={}&tsyms={}",self.server, path, from.symbol(), tsyms);
-        let endpoint = self.api_key.as_ref()
-            .map(|api_key| format!("{}&api_key={}", endpoint, api_key))
-            .unwrap_or(endpoint);
-        let request = self.client.get(endpoint).build()?;
-        let response = self.client.execute(request)
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
-        debug!("Response {path}: {:?}", response);
-        Ok(response)
+        let responses = join_all(self.providers.iter().map(|p| p.symbol_to_symbols(from, to))).await;
+        let fetched_at = Utc::now().naive_utc();
+        let mut quotes: HashMap<Symbol, Vec<Rate>> = HashMap::new();
+        let mut last_error = None;
+        for (provider, response) in self.providers.iter().zip(responses) {
+            match response {
+                Ok(rates) => rates.into_iter().for_each(|(k, v)| quotes.entry(k).or_default().push(v)),
+                Err(e) => {
+                    warn!("Ticker source {} failed for {}: {}", provider.name(), from.symbol(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if quotes.is_empty() {
+            if let Some(e) = last_error {
+                return Err(e);
+            }
+        }
+        let mut result = HashMap::new();
+        for (symbol, rates) in quotes {
+            match aggregate(rates, self.max_deviation) {
+                Some(rate) => {
+                    result.insert(symbol, TimedRate { rate, fetched_at });
+                }
+                None => warn!("Ticker sources disagree on {}/{}, skipping", from.symbol(), symbol.symbol()),
+            }
+        }
+        Ok(result)
     }
 
     /// Get multiple values: Symbol to multiple Symbols with generic return. Catch all is HashMap<String, f64>
     pub async fn symbol_to_symbols_generic<T>(&self, from: &Symbol, to: &Vec<Symbol>) -> Result<T>
     where T: DeserializeOwned + Debug
     {
-        let tsyms = to.iter().map(|f| f.symbol()).collect::<Vec<String>>().join(",");
-        let path = "data/price";
-        let endpoint = format!("{}/{}?fsym={}&tsyms={}",self.server, path, from.symbol(), tsyms);
-        let endpoint = self.api_key.as_ref()
-            .map(|api_key| format!("{}&api_key={}", endpoint, api_key))
-            .unwrap_or(endpoint);
-        let request = self.client.get(endpoint).build()?;
-        let response = self.client.execute(request)
+        let vals: Map<String, Value> = self.symbol_to_symbols(from, to)
             .await?
-            .error_for_status()?
-            .json::<T>()
-            .await?;
-        debug!("Response {path}: {:?}", response);
-        Ok(response)
+            .into_iter()
+            .map(|(k, v)| (k.symbol(), serde_json::to_value(v.rate.to_f64()).unwrap()))
+            .collect();
+        serde_json::from_value(vals.into()).map_err(|e| e.into())
+    }
+}
+
+fn median(sorted: &[Rate]) -> Option<Rate> {
+    let n = sorted.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(sorted[n / 2]),
+        _ => Some(sorted[n / 2 - 1].midpoint(sorted[n / 2])),
     }
 }
 
+/// Median of the rates that are within `max_deviation` percents from the median of all rates.
+/// Returns `None` when every rate is an outlier
+pub fn aggregate(mut rates: Vec<Rate>, max_deviation: Rate) -> Option<Rate> {
+    rates.sort();
+    let center = median(&rates)?;
+    let kept: Vec<Rate> = rates
+        .into_iter()
+        .filter(|r| match r.abs_diff(center).percent_of(center, Rounding::Up) {
+            Some(deviation) => deviation <= max_deviation,
+            None => r.is_zero(),
+        })
+        .collect();
+    median(&kept)
+}
+
 #[cfg(test)]
 mod tests {
     use std::{future::Future, panic::AssertUnwindSafe};
     use futures::FutureExt;
     use hexstody_api::types::TickerUsdRub;
+    use crate::provider::StaticProvider;
     use super::*;
     async fn run_test<F, Fut>(test_body: F)
     where
@@ -146,7 +185,48 @@ mod tests {
             let resp = client.symbol_to_symbol(&Symbol::BTC, &Symbol::BTC).await;
             assert!(resp.is_ok());
             let v = resp.unwrap();
-            assert_eq!(v, 1.0);
+            assert_eq!(v.rate, Rate::ONE);
         }).await;
     }
+
+    fn rate(s: &str) -> Rate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_aggregate_median() {
+        let max = Rate::from_integer(DEFAULT_MAX_DEVIATION);
+        assert_eq!(aggregate(vec![], max), None);
+        assert_eq!(aggregate(vec![rate("100")], max), Some(rate("100")));
+        assert_eq!(aggregate(vec![rate("101"), rate("100"), rate("99")], max), Some(rate("100")));
+        assert_eq!(aggregate(vec![rate("100"), rate("102")], max), Some(rate("101")));
+    }
+
+    #[test]
+    fn test_aggregate_drops_outliers() {
+        let max = Rate::from_integer(DEFAULT_MAX_DEVIATION);
+        // 150 is far from the median 101, the median of the rest is taken
+        assert_eq!(aggregate(vec![rate("100"), rate("150"), rate("101"), rate("102")], max), Some(rate("101")));
+        // Two sources that disagree give no rate at all
+        assert_eq!(aggregate(vec![rate("100"), rate("200")], max), None);
+    }
+
+    #[tokio::test]
+    async fn test_static_providers() {
+        let provider = |name: &str, btc_usd: &str| -> Arc<dyn TickerProvider> {
+            Arc::new(StaticProvider::new(name, HashMap::from([
+                (Symbol::BTC, HashMap::from([(Symbol::USD, rate(btc_usd))])),
+            ])))
+        };
+        let client = TickerClient::with_providers(vec![
+            provider("a", "20000"),
+            provider("b", "20100"),
+            provider("c", "35000"),
+        ]);
+        let resp = client.symbol_to_symbols(&Symbol::BTC, &vec![Symbol::USD, Symbol::BTC, Symbol::RUB]).await.unwrap();
+        assert_eq!(resp[&Symbol::USD].rate, rate("20050"));
+        assert_eq!(resp[&Symbol::BTC].rate, Rate::ONE);
+        assert!(!resp.contains_key(&Symbol::RUB));
+        assert!(client.symbol_to_symbol(&Symbol::ETH, &Symbol::USD).await.is_err());
+    }
 }
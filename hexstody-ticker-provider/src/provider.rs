@@ -0,0 +1,168 @@
+use std::{collections::HashMap, path::Path};
+
+use async_trait::async_trait;
+use hexstody_api::domain::{Rate, Symbol};
+use log::*;
+
+use crate::client::{Error, Result};
+
+/// Source of exchange rates
+#[async_trait]
+pub trait TickerProvider: Send + Sync {
+    /// Name of the source used in logs
+    fn name(&self) -> &str;
+
+    /// Price of one `from` in each of `to`. Pairs the source doesn't know are omitted
+    async fn symbol_to_symbols(&self, from: &Symbol, to: &[Symbol]) -> Result<HashMap<Symbol, Rate>>;
+}
+
+/// cryptocompare.com price API
+pub struct CryptoCompare {
+    pub client: reqwest::Client,
+    pub server: String,
+    pub api_key: Option<String>,
+}
+
+impl CryptoCompare {
+    pub fn new(url: &str) -> Self {
+        CryptoCompare {
+            client: reqwest::Client::new(),
+            server: url.to_owned(),
+            api_key: Some("6b55695f17dbb4244531f989c93de2f448ab8eba5804afea248fa051f183ce14".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl TickerProvider for CryptoCompare {
+    fn name(&self) -> &str {
+        "cryptocompare"
+    }
+
+    async fn symbol_to_symbols(&self, from: &Symbol, to: &[Symbol]) -> Result<HashMap<Symbol, Rate>> {
+        let tsyms = to.iter().map(|f| f.symbol()).collect::<Vec<String>>().join(",");
+        let path = "data/price";
+        let endpoint = format!("{}/{}?fsym={}&tsyms={}", self.server, path, from.symbol(), tsyms);
+        let endpoint = self.api_key.as_ref()
+            .map(|api_key| format!("{}&api_key={}", endpoint, api_key))
+            .unwrap_or(endpoint);
+        let request = self.client.get(endpoint).build()?;
+        let response = self.client.execute(request)
+            .await?
+            .error_for_status()?
+            .json::<HashMap<String, Rate>>()
+            .await?;
+        debug!("Response {path}: {:?}", response);
+        Ok(response.into_iter().map(|(k, v)| (Symbol::from_symbol(&k), v)).collect())
+    }
+}
+
+/// coingecko.com simple price API. Knows only the coins listed in `coin_id`
+pub struct CoinGecko {
+    pub client: reqwest::Client,
+    pub server: String,
+}
+
+impl CoinGecko {
+    pub fn new(url: &str) -> Self {
+        CoinGecko {
+            client: reqwest::Client::new(),
+            server: url.to_owned(),
+        }
+    }
+
+    /// CoinGecko identifier of the coin, used as the base of the pair
+    fn coin_id(symbol: &Symbol) -> Option<&'static str> {
+        match symbol {
+            Symbol::BTC => Some("bitcoin"),
+            Symbol::ETH => Some("ethereum"),
+            Symbol::ERC20(ticker) => match ticker.as_str() {
+                "USDT" => Some("tether"),
+                "CRV" => Some("curve-dao-token"),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl TickerProvider for CoinGecko {
+    fn name(&self) -> &str {
+        "coingecko"
+    }
+
+    async fn symbol_to_symbols(&self, from: &Symbol, to: &[Symbol]) -> Result<HashMap<Symbol, Rate>> {
+        let id = CoinGecko::coin_id(from).ok_or_else(|| Error::ResponseMissing(from.symbol()))?;
+        let vs = to.iter().map(|t| t.symbol().to_lowercase()).collect::<Vec<String>>().join(",");
+        let path = "api/v3/simple/price";
+        let endpoint = format!("{}/{}?ids={}&vs_currencies={}", self.server, path, id, vs);
+        let request = self.client.get(endpoint).build()?;
+        let mut response = self.client.execute(request)
+            .await?
+            .error_for_status()?
+            .json::<HashMap<String, HashMap<String, Rate>>>()
+            .await?;
+        debug!("Response {path}: {:?}", response);
+        let prices = response.remove(id).ok_or_else(|| Error::ResponseMissing(from.symbol()))?;
+        Ok(to
+            .iter()
+            .filter_map(|t| {
+                if t == from {
+                    Some((t.clone(), Rate::ONE))
+                } else {
+                    prices.get(&t.symbol().to_lowercase()).map(|r| (t.clone(), *r))
+                }
+            })
+            .collect())
+    }
+}
+
+/// Fixed rates, for tests and offline setups
+pub struct StaticProvider {
+    pub name: String,
+    pub rates: HashMap<Symbol, HashMap<Symbol, Rate>>,
+}
+
+impl StaticProvider {
+    pub fn new(name: &str, rates: HashMap<Symbol, HashMap<Symbol, Rate>>) -> Self {
+        StaticProvider { name: name.to_owned(), rates }
+    }
+
+    /// Read rates from a JSON file like `{"BTC": {"USD": "20000"}}`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Error::GenericError(format!("Failed to read {}: {}", path.display(), e)))?;
+        let rates: HashMap<String, HashMap<String, Rate>> = serde_json::from_str(&content)?;
+        let rates = rates
+            .into_iter()
+            .map(|(from, prices)| {
+                let prices = prices.into_iter().map(|(k, v)| (Symbol::from_symbol(&k), v)).collect();
+                (Symbol::from_symbol(&from), prices)
+            })
+            .collect();
+        Ok(StaticProvider::new(&path.display().to_string(), rates))
+    }
+}
+
+#[async_trait]
+impl TickerProvider for StaticProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn symbol_to_symbols(&self, from: &Symbol, to: &[Symbol]) -> Result<HashMap<Symbol, Rate>> {
+        let prices = self.rates.get(from).ok_or_else(|| Error::ResponseMissing(from.symbol()))?;
+        Ok(to
+            .iter()
+            .filter_map(|t| {
+                if t == from {
+                    Some((t.clone(), Rate::ONE))
+                } else {
+                    prices.get(t).map(|r| (t.clone(), *r))
+                }
+            })
+            .collect())
+    }
+}
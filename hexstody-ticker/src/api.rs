@@ -1,10 +1,11 @@
 use std::sync::Arc;
 
 use hexstody_api::{
-    types::{TickerUsdRub, MarginData},
-    domain::{Currency, Symbol}
+    types::{TickerUsdRub, MarginData, RateHistoryRequest, RateRecord},
+    domain::{Currency, Rate, Symbol}
 };
 use hexstody_api::domain::error as error;
+use hexstody_db::{queries::query_rate_history, Pool};
 use hexstody_runtime_db::RuntimeState;
 use hexstody_ticker_provider::client::TickerClient;
 use rocket::{post, State, Route, serde::json::Json};
@@ -17,7 +18,8 @@ pub fn ticker_api() -> Vec<Route> {
         ticker,
         ticker_pair,
         get_margin,
-        get_pair_with_margin
+        get_pair_with_margin,
+        rate_history
     ]
 }
 
@@ -47,7 +49,7 @@ pub struct CurrencyPair {
 pub struct CurrencyPairResponse{
     from: Currency,
     to: Currency,
-    rate: f64
+    rate: Rate
 }
 
 #[openapi(tag = "ticker")]
@@ -91,4 +93,21 @@ pub async fn get_pair_with_margin(
         .await
         .map_err(|e| error::Error::GenericError(e.to_string()))?;
     Ok(Json(CurrencyPairResponse{ from, to, rate }))
-}
\ No newline at end of file
+}
+
+/// Max number of records returned by a single history request
+const RATE_HISTORY_LIMIT: i64 = 10_000;
+
+/// Recorded rates of the pair in the period, oldest first
+#[openapi(tag = "ticker")]
+#[post("/history", data = "<req>")]
+pub async fn rate_history(
+    pool: &State<Pool>,
+    req: Json<RateHistoryRequest>,
+) -> error::Result<Json<Vec<RateRecord>>> {
+    let RateHistoryRequest{ from, to, since, until } = req.into_inner();
+    let records = query_rate_history(pool, &from, &to, since, until, RATE_HISTORY_LIMIT)
+        .await
+        .map_err(|e| error::Error::GenericError(e.to_string()))?;
+    Ok(Json(records))
+}
@@ -1,8 +1,10 @@
-use std::{sync::Arc, collections::HashMap};
+use std::sync::Arc;
 
+use hexstody_api::types::RateRecord;
+use hexstody_db::{queries::insert_rates, Pool};
 use hexstody_runtime_db::RuntimeState;
 use hexstody_ticker_provider::client::TickerClient;
-use log::{debug, info};
+use log::{error, info, warn};
 use tokio::sync::Mutex;
 
 /// Delay between refreshes in seconds
@@ -10,22 +12,35 @@ static REFRESH_PERIOD: u64 = 120;
 
 pub async fn ticker_worker(
     ticker_client: TickerClient,
-    rstate: Arc<Mutex<RuntimeState>>
+    rstate: Arc<Mutex<RuntimeState>>,
+    pool: Pool,
 ){
     info!("Started ticker worker with period {}s", REFRESH_PERIOD);
     let mut period = tokio::time::interval(tokio::time::Duration::from_secs(REFRESH_PERIOD));
     loop {
         period.tick().await;
         let pairs = {rstate.lock().await.tracked_pairs()};
-        let mut new_cache = HashMap::new();
         for (from, to) in pairs.into_iter() {
+            // Keep previous rates on failure, their timestamps tell consumers how old they are
             let resp = ticker_client.symbol_to_symbols(&from, &to).await;
             match resp {
-                Err(e) => debug!("Error requesting tickers: {}", e.to_string()),
-                Ok(vals) => {new_cache.insert(from, vals);}
+                Err(e) => warn!("Failed to refresh {} tickers: {}", from.symbol(), e),
+                Ok(vals) => {
+                    let records: Vec<RateRecord> = vals
+                        .iter()
+                        .map(|(to, timed)| RateRecord {
+                            from: from.clone(),
+                            to: to.clone(),
+                            rate: timed.rate,
+                            time: timed.fetched_at,
+                        })
+                        .collect();
+                    if let Err(e) = insert_rates(&pool, &records).await {
+                        error!("Failed to store {} rate history: {}", from.symbol(), e);
+                    }
+                    rstate.lock().await.merge_tickers(from, vals);
+                }
             }
         }
-        let mut rstate = rstate.lock().await;
-        rstate.cached_tickers = new_cache;
     }
 } 